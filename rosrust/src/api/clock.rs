@@ -1,11 +1,12 @@
-use crate::time::{Duration, Time};
+use crate::time::{Duration, SteadyTime, Time};
 use crate::util::FAILED_TO_LOCK;
 use crossbeam::sync::{Parker, Unparker};
 use std::cell::Cell;
 use std::cmp;
 use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread::sleep;
+use std::thread::{self, sleep};
 use std::time::{Duration as StdDuration, SystemTime, UNIX_EPOCH};
 
 static BEFORE_EPOCH: &'static str = "Requested time is before UNIX epoch.";
@@ -33,7 +34,7 @@ pub struct Rate {
 
 impl Rate {
     pub fn new(clock: Arc<Clock>, delay: Duration) -> Rate {
-        let start = clock.now();
+        let start = clock.schedule_now();
         Rate {
             clock,
             next: Cell::new(start),
@@ -42,9 +43,93 @@ impl Rate {
     }
 
     pub fn sleep(&self) {
-        let new_time = self.next.get() + self.delay;
+        let now = self.clock.schedule_now();
+        let mut start = self.next.get();
+        // The clock jumped backwards, for example because a bag file looped,
+        // so the schedule restarts from the current time
+        if now < start {
+            start = now;
+        }
+        let new_time = start + self.delay;
+        // Falling behind by over a whole period restarts the schedule,
+        // instead of rushing through all the missed cycles
+        if now > new_time + self.delay {
+            self.next.set(now);
+            return;
+        }
         self.next.set(new_time);
-        self.clock.wait_until(new_time);
+        self.clock.wait_until_scheduled(new_time);
+    }
+}
+
+/// Times of a single timer callback, as told by the node's clock.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimerEvent {
+    /// When the callback was due.
+    pub expected: Time,
+    /// When the callback got called.
+    pub actual: Time,
+}
+
+/// Calls a callback periodically on a thread of its own, until dropped.
+///
+/// A callback that overruns the period makes the timer skip the ticks it
+/// missed, so later ticks stay on the original schedule instead of
+/// catching up. If time jumps backwards, the schedule restarts from the
+/// new time.
+pub struct Timer {
+    stopped: Arc<AtomicBool>,
+    unparker: Unparker,
+}
+
+impl Timer {
+    /// # Panics
+    ///
+    /// Panics if the period is not positive.
+    pub fn new<F>(clock: Arc<dyn Clock>, period: Duration, mut callback: F) -> Self
+    where
+        F: FnMut(TimerEvent) + Send + 'static,
+    {
+        assert!(
+            period > Duration::default(),
+            "Timer period must be positive"
+        );
+        let stopped = Arc::new(AtomicBool::new(false));
+        let timer_stopped = Arc::clone(&stopped);
+        let parker = Parker::new();
+        let unparker = parker.unparker().clone();
+        thread::spawn(move || {
+            let mut expected = clock.schedule_now() + period;
+            loop {
+                clock.park_until_scheduled(expected, &parker);
+                if timer_stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                let now = clock.schedule_now();
+                // Only a clock jumping backwards ends the wait early, besides stopping
+                if now < expected {
+                    expected = now + period;
+                    continue;
+                }
+                let actual = clock.now();
+                callback(TimerEvent {
+                    expected: actual - (now - expected),
+                    actual,
+                });
+                let behind = clock.schedule_now() - expected;
+                let skipped = behind.nanos() / period.nanos();
+                expected = expected + Duration::from_nanos((skipped + 1) * period.nanos());
+            }
+        });
+        Self { stopped, unparker }
+    }
+}
+
+impl Drop for Timer {
+    /// Stops calling the callback, waking up the thread so it exits right away.
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.unparker.unpark();
     }
 }
 
@@ -53,6 +138,27 @@ pub trait Clock: Send + Sync {
     fn sleep(&self, d: Duration);
     fn wait_until(&self, t: Time);
     fn await_init(&self) {}
+
+    /// Current time of the scale that rates keep their schedule in.
+    ///
+    /// The real clock uses steady time here, so adjustments of the system
+    /// clock cannot disturb rates.
+    fn schedule_now(&self) -> Time {
+        self.now()
+    }
+
+    /// Waits until the given time of the scale used by `schedule_now`.
+    fn wait_until_scheduled(&self, t: Time) {
+        self.wait_until(t)
+    }
+
+    /// Waits like `wait_until_scheduled`, unless the parker gets unparked first.
+    ///
+    /// Clocks that cannot be interrupted ignore the parker, which keeps
+    /// stopped timers around until their next tick.
+    fn park_until_scheduled(&self, t: Time, _parker: &Parker) {
+        self.wait_until_scheduled(t)
+    }
 }
 
 #[derive(Clone, Default)]
@@ -82,6 +188,23 @@ impl Clock for RealClock {
     fn wait_until(&self, t: Time) {
         self.sleep(t - self.now());
     }
+
+    #[inline]
+    fn schedule_now(&self) -> Time {
+        Time::from_nanos(SteadyTime::now().nanos())
+    }
+
+    #[inline]
+    fn wait_until_scheduled(&self, t: Time) {
+        self.sleep(t - self.schedule_now());
+    }
+
+    fn park_until_scheduled(&self, t: Time, parker: &Parker) {
+        let d = t - self.schedule_now();
+        if d > Duration::default() {
+            parker.park_timeout(StdDuration::from_nanos(d.nanos() as u64));
+        }
+    }
 }
 
 struct Timeout {
@@ -123,14 +246,32 @@ pub struct SimData {
     timeouts: BinaryHeap<Timeout>,
 }
 
+/// Clock whose time only changes when told to.
+///
+/// Nodes using simulated time drive it with messages from `/clock`. Tests
+/// can drive it manually, for deterministic behavior of rates and timers.
 #[derive(Default)]
 pub struct SimulatedClock {
     pub data: Mutex<SimData>,
 }
 
 impl SimulatedClock {
-    pub fn trigger(&self, time: Time) {
+    /// Moves the clock forward, waking up everything waiting for the time in between.
+    pub fn advance(&self, d: Duration) {
         let mut data = self.data.lock().expect(FAILED_TO_LOCK);
+        let time = data.current + d;
+        Self::set_time(&mut data, time);
+    }
+
+    pub fn trigger(&self, time: Time) {
+        Self::set_time(&mut self.data.lock().expect(FAILED_TO_LOCK), time);
+    }
+
+    fn set_time(data: &mut SimData, time: Time) {
+        // Waiters are woken up when time jumps backwards, like they are in roscpp
+        if time < data.current {
+            data.timeouts.clear();
+        }
         data.current = time;
         loop {
             match data.timeouts.peek() {
@@ -160,17 +301,20 @@ impl Clock for SimulatedClock {
 
     #[inline]
     fn wait_until(&self, timestamp: Time) {
-        let parker = Parker::new();
-        let unparker = parker.unparker().clone();
+        self.park_until_scheduled(timestamp, &Parker::new())
+    }
+
+    fn park_until_scheduled(&self, timestamp: Time, parker: &Parker) {
         {
-            self.data
-                .lock()
-                .expect(FAILED_TO_LOCK)
-                .timeouts
-                .push(Timeout {
-                    timestamp,
-                    unparker,
-                });
+            let mut data = self.data.lock().expect(FAILED_TO_LOCK);
+            // Waiting for a time that has already passed would block until the next trigger
+            if timestamp <= data.current {
+                return;
+            }
+            data.timeouts.push(Timeout {
+                timestamp,
+                unparker: parker.unparker().clone(),
+            });
         }
         parker.park()
     }
@@ -181,3 +325,261 @@ impl Clock for SimulatedClock {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    #[test]
+    fn simulated_clock_starts_at_zero() {
+        let clock = SimulatedClock::default();
+        assert_eq!(Time::default(), clock.now());
+    }
+
+    #[test]
+    fn simulated_clock_tracks_triggered_time() {
+        let clock = SimulatedClock::default();
+        clock.trigger(Time { sec: 5, nsec: 10 });
+        assert_eq!(Time { sec: 5, nsec: 10 }, clock.now());
+        clock.trigger(Time { sec: 7, nsec: 0 });
+        assert_eq!(Time { sec: 7, nsec: 0 }, clock.now());
+    }
+
+    #[test]
+    fn simulated_clock_wakes_waiters_once_time_passes() {
+        let clock = Arc::new(SimulatedClock::default());
+        clock.trigger(Time { sec: 1, nsec: 0 });
+        let (tx, rx) = channel();
+        let waiting_clock = Arc::clone(&clock);
+        thread::spawn(move || {
+            waiting_clock.wait_until(Time { sec: 3, nsec: 0 });
+            tx.send(waiting_clock.now()).unwrap();
+        });
+
+        let short_wait = StdDuration::from_millis(50);
+        assert!(rx.recv_timeout(short_wait).is_err());
+        clock.trigger(Time { sec: 2, nsec: 0 });
+        assert!(rx.recv_timeout(short_wait).is_err());
+        clock.trigger(Time { sec: 3, nsec: 0 });
+        let woken_at = rx.recv_timeout(StdDuration::from_secs(5)).unwrap();
+        assert_eq!(Time { sec: 3, nsec: 0 }, woken_at);
+    }
+
+    fn wait_for_sleepers(clock: &SimulatedClock, count: usize) {
+        while clock.data.lock().expect(FAILED_TO_LOCK).timeouts.len() < count {
+            thread::sleep(StdDuration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn rate_wakes_at_simulated_instants() {
+        let clock = Arc::new(SimulatedClock::default());
+        clock.trigger(Time { sec: 10, nsec: 0 });
+        let (tx, rx) = channel();
+        let rate_clock = Arc::clone(&clock);
+        thread::spawn(move || {
+            let rate = Rate::new(
+                Arc::clone(&rate_clock) as Arc<dyn Clock>,
+                Duration::from_seconds(1),
+            );
+            loop {
+                rate.sleep();
+                if tx.send(rate_clock.now()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let timeout = StdDuration::from_secs(5);
+        let short_wait = StdDuration::from_millis(50);
+        wait_for_sleepers(&clock, 1);
+        clock.trigger(Time {
+            sec: 10,
+            nsec: 500_000_000,
+        });
+        assert!(rx.recv_timeout(short_wait).is_err());
+        clock.trigger(Time { sec: 11, nsec: 0 });
+        assert_eq!(Time { sec: 11, nsec: 0 }, rx.recv_timeout(timeout).unwrap());
+        wait_for_sleepers(&clock, 1);
+        clock.trigger(Time { sec: 12, nsec: 0 });
+        assert_eq!(Time { sec: 12, nsec: 0 }, rx.recv_timeout(timeout).unwrap());
+
+        // Jumping backwards wakes the sleeper, which then keeps the period from the new time
+        wait_for_sleepers(&clock, 1);
+        clock.trigger(Time { sec: 5, nsec: 0 });
+        assert_eq!(Time { sec: 5, nsec: 0 }, rx.recv_timeout(timeout).unwrap());
+        wait_for_sleepers(&clock, 1);
+        clock.trigger(Time {
+            sec: 5,
+            nsec: 500_000_000,
+        });
+        assert!(rx.recv_timeout(short_wait).is_err());
+        clock.trigger(Time { sec: 6, nsec: 0 });
+        assert_eq!(Time { sec: 6, nsec: 0 }, rx.recv_timeout(timeout).unwrap());
+    }
+
+    #[test]
+    fn simulated_clock_does_not_wait_for_past_time() {
+        let clock = SimulatedClock::default();
+        clock.trigger(Time { sec: 3, nsec: 0 });
+        clock.wait_until(Time { sec: 2, nsec: 0 });
+        clock.sleep(Duration::default());
+    }
+
+    #[test]
+    fn steady_time_ignores_simulated_time_jumps() {
+        let clock = SimulatedClock::default();
+        clock.trigger(Time { sec: 100, nsec: 0 });
+        let before = SteadyTime::now();
+        clock.trigger(Time { sec: 5, nsec: 0 });
+        let after = SteadyTime::now();
+        assert_eq!(Time { sec: 5, nsec: 0 }, clock.now());
+        assert!(after >= before);
+    }
+
+    #[test]
+    fn timer_fires_periodically_until_dropped() {
+        let clock = Arc::new(RealClock::default());
+        let (tx, rx) = channel();
+        let tx = Mutex::new(tx);
+        let timer = Timer::new(clock, Duration::from_nanos(20_000_000), move |event| {
+            tx.lock().unwrap().send(event).unwrap();
+        });
+        thread::sleep(StdDuration::from_millis(300));
+        drop(timer);
+        thread::sleep(StdDuration::from_millis(50));
+
+        let events = rx.try_iter().collect::<Vec<_>>();
+        assert!(!events.is_empty() && events.len() <= 16, "{}", events.len());
+        for event in &events {
+            assert!(event.actual >= event.expected);
+        }
+        // Expected times are converted from steady time, which adds jitter,
+        // and ticks can get skipped under load
+        for pair in events.windows(2) {
+            let period = (pair[1].expected - pair[0].expected).nanos();
+            let ticks = (period + 10_000_000) / 20_000_000;
+            assert!(ticks >= 1, "{}", period);
+            assert!(
+                (period - ticks * 20_000_000).abs() < 5_000_000,
+                "{}",
+                period
+            );
+        }
+        assert!(rx.recv_timeout(StdDuration::from_millis(100)).is_err());
+    }
+
+    fn assert_released(callback_data: &Arc<()>) {
+        let start = std::time::Instant::now();
+        while Arc::strong_count(callback_data) > 1 {
+            assert!(start.elapsed() < StdDuration::from_secs(5));
+            thread::sleep(StdDuration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn dropping_timer_ends_its_thread_before_next_tick() {
+        let callback_data = Arc::new(());
+        let timer_data = Arc::clone(&callback_data);
+        let timer = Timer::new(
+            Arc::new(RealClock::default()),
+            Duration::from_seconds(3600),
+            move |_| {
+                let _data = &timer_data;
+            },
+        );
+        drop(timer);
+        assert_released(&callback_data);
+
+        // A paused simulated clock never reaches the next tick
+        let clock = Arc::new(SimulatedClock::default());
+        clock.trigger(Time { sec: 10, nsec: 0 });
+        let timer_data = Arc::clone(&callback_data);
+        let timer = Timer::new(
+            Arc::clone(&clock) as Arc<dyn Clock>,
+            Duration::from_seconds(1),
+            move |_| {
+                let _data = &timer_data;
+            },
+        );
+        wait_for_sleepers(&clock, 1);
+        drop(timer);
+        assert_released(&callback_data);
+    }
+
+    #[test]
+    fn timer_skips_ticks_missed_by_slow_callback() {
+        let clock = Arc::new(SimulatedClock::default());
+        clock.trigger(Time { sec: 10, nsec: 0 });
+        let (tx, rx) = channel();
+        let tx = Mutex::new(tx);
+        let callback_clock = Arc::clone(&clock);
+        let _timer = Timer::new(
+            Arc::clone(&clock) as Arc<dyn Clock>,
+            Duration::from_seconds(1),
+            move |event| {
+                // The first callback overruns the following two ticks
+                if event.expected == (Time { sec: 11, nsec: 0 }) {
+                    callback_clock.trigger(Time {
+                        sec: 13,
+                        nsec: 500_000_000,
+                    });
+                }
+                tx.lock().unwrap().send(event).unwrap();
+            },
+        );
+
+        let timeout = StdDuration::from_secs(5);
+        wait_for_sleepers(&clock, 1);
+        clock.trigger(Time { sec: 11, nsec: 0 });
+        let first = rx.recv_timeout(timeout).unwrap();
+        assert_eq!(Time { sec: 11, nsec: 0 }, first.expected);
+        wait_for_sleepers(&clock, 1);
+        clock.trigger(Time { sec: 14, nsec: 0 });
+        let second = rx.recv_timeout(timeout).unwrap();
+        assert_eq!(Time { sec: 14, nsec: 0 }, second.expected);
+        assert_eq!(Time { sec: 14, nsec: 0 }, second.actual);
+    }
+
+    #[test]
+    fn advancing_clock_drives_timer() {
+        let clock = Arc::new(SimulatedClock::default());
+        clock.trigger(Time { sec: 10, nsec: 0 });
+        let (tx, rx) = channel();
+        let tx = Mutex::new(tx);
+        let _timer = Timer::new(
+            Arc::clone(&clock) as Arc<dyn Clock>,
+            Duration::from_nanos(100_000_000),
+            move |event| tx.lock().unwrap().send(event).unwrap(),
+        );
+
+        let timeout = StdDuration::from_secs(5);
+        let short_wait = StdDuration::from_millis(50);
+        for tick in 1..=3 {
+            wait_for_sleepers(&clock, 1);
+            clock.advance(Duration::from_nanos(60_000_000));
+            assert!(rx.recv_timeout(short_wait).is_err());
+            wait_for_sleepers(&clock, 1);
+            clock.advance(Duration::from_nanos(40_000_000));
+            let expected = Time { sec: 10, nsec: 0 } + Duration::from_nanos(tick * 100_000_000);
+            let event = rx.recv_timeout(timeout).unwrap();
+            assert_eq!(expected, event.expected);
+            assert_eq!(expected, event.actual);
+        }
+    }
+
+    #[test]
+    fn real_clock_rate_keeps_pace_in_steady_time() {
+        let clock = Arc::new(RealClock::default());
+        let rate = Rate::new(clock, Duration::from_nanos(20_000_000));
+        let start = SteadyTime::now();
+        for _ in 0..3 {
+            rate.sleep();
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_nanos(60_000_000), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_seconds(1), "{:?}", elapsed);
+    }
+}
@@ -4,6 +4,7 @@ pub use crate::api::naming::error as naming;
 pub use crate::rosxmlrpc::error as rosxmlrpc;
 pub use crate::rosxmlrpc::ResponseError;
 pub use crate::tcpros::error as tcpros;
+use crate::util::ports::AddrInUse;
 
 error_chain! {
     foreign_links {
@@ -12,6 +13,7 @@ error_chain! {
         FromUTF8(::std::string::FromUtf8Error);
         Response(ResponseError);
         SigintOverride(::ctrlc::Error);
+        SetLogger(::log::SetLoggerError);
     }
     links {
         XmlRpc(rosxmlrpc::Error, rosxmlrpc::ErrorKind);
@@ -40,6 +42,28 @@ error_chain! {
             description("Failure in communication with ROS API")
             display("Failure in communication with ROS API: {}", details)
         }
+        MessageRejected(details: String) {
+            description("Message was rejected by the publisher's validator")
+            display("Message was rejected by the publisher's validator: {}", details)
+        }
+    }
+}
+
+impl AddrInUse for ErrorKind {
+    fn is_addr_in_use(&self) -> bool {
+        match *self {
+            ErrorKind::Io(ref err) => err.is_addr_in_use(),
+            ErrorKind::XmlRpc(ref kind) => kind.is_addr_in_use(),
+            ErrorKind::Tcpros(ref kind) => kind.is_addr_in_use(),
+            _ => false,
+        }
+    }
+}
+
+impl AddrInUse for Error {
+    #[inline]
+    fn is_addr_in_use(&self) -> bool {
+        self.kind().is_addr_in_use()
     }
 }
 
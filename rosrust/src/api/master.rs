@@ -1,7 +1,25 @@
-use super::super::rosxmlrpc::{self, Response as Result};
+use super::super::rosxmlrpc::{self, Response as Result, RetryPolicy};
+use crate::util::FAILED_TO_LOCK;
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
 use xml_rpc;
 
+lazy_static! {
+    static ref RETRY_POLICY: Mutex<RetryPolicy> = Mutex::new(RetryPolicy::default());
+}
+
+/// Sets how calls to the master get retried by nodes created afterwards.
+///
+/// Setting it before `init` allows nodes to start before the master does.
+pub fn set_master_retry_policy(policy: RetryPolicy) {
+    *RETRY_POLICY.lock().expect(FAILED_TO_LOCK) = policy;
+}
+
+pub fn master_retry_policy() -> RetryPolicy {
+    RETRY_POLICY.lock().expect(FAILED_TO_LOCK).clone()
+}
+
 pub struct Master {
     client: rosxmlrpc::Client,
     client_id: String,
@@ -35,12 +53,17 @@ impl Master {
         caller_api: &str,
     ) -> rosxmlrpc::error::Result<Master> {
         Ok(Master {
-            client: rosxmlrpc::Client::new(master_uri)?,
+            client: rosxmlrpc::Client::new(master_uri)?.with_retry_policy(master_retry_policy()),
             client_id: client_id.to_owned(),
             caller_api: caller_api.to_owned(),
         })
     }
 
+    /// Waits for the master to come up, for the retry policy's startup timeout.
+    pub fn wait_until_reachable(&self) -> Result<()> {
+        self.client.wait_until_reachable()
+    }
+
     pub fn register_service(&self, service: &str, service_api: &str) -> Result<i32> {
         request!(self; registerService; service, service_api, &self.caller_api)
     }
@@ -65,13 +88,11 @@ impl Master {
         request!(self; unregisterPublisher; topic, &self.caller_api)
     }
 
-    #[allow(dead_code)]
     pub fn lookup_node(&self, node_name: &str) -> Result<String> {
         request!(self; lookupNode; node_name)
     }
 
-    #[allow(dead_code)]
-    pub fn get_published_topics(&self, subgraph: &str) -> Result<Vec<(String, String)>> {
+    pub fn get_published_topics(&self, subgraph: &str) -> Result<Vec<TopicTuple>> {
         request!(self; getPublishedTopics; subgraph)
     }
 
@@ -113,10 +134,6 @@ impl Master {
             .and(Ok(()))
     }
 
-    pub fn get_param<'a, T: Deserialize<'a>>(&self, key: &str) -> Result<T> {
-        request!(self; getParam; key)
-    }
-
     pub fn get_param_any(&self, key: &str) -> Result<xml_rpc::Value> {
         request_tree!(self; getParam; key)
     }
@@ -130,12 +147,10 @@ impl Master {
         request!(self; subscribeParam; &self.caller_api, key)
     }
 
-    #[allow(dead_code)]
     pub fn subscribe_param_any(&self, key: &str) -> Result<xml_rpc::Value> {
         request_tree!(self; subscribeParam; &self.caller_api, key)
     }
 
-    #[allow(dead_code)]
     pub fn unsubscribe_param(&self, key: &str) -> Result<i32> {
         request!(self; unsubscribeParam; &self.caller_api, key)
     }
@@ -206,3 +221,12 @@ pub struct Topic {
     pub name: String,
     pub datatype: String,
 }
+
+impl From<crate::tcpros::Topic> for Topic {
+    fn from(topic: crate::tcpros::Topic) -> Topic {
+        Topic {
+            name: topic.name,
+            datatype: topic.msg_type,
+        }
+    }
+}
@@ -1,7 +1,12 @@
-pub use self::clock::{Clock, Delay, Rate};
-pub use self::master::{SystemState, Topic};
+pub use self::clock::{Clock, Delay, Rate, RealClock, SimulatedClock, Timer, TimerEvent};
+pub use self::master::{master_retry_policy, set_master_retry_policy, SystemState, Topic};
+pub(crate) use self::ros::{spin_for, spin_once};
 pub use self::ros::{Parameter, Ros};
+pub use self::slave::ProtocolParams;
+pub use crate::rosxmlrpc::RetryPolicy;
+use crate::util::FAILED_TO_LOCK;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
 
 mod clock;
 pub mod error;
@@ -11,15 +16,23 @@ pub mod raii;
 pub mod resolve;
 mod ros;
 mod slave;
+mod statistics;
 
 pub struct ShutdownManager {
     should_shutdown: AtomicBool,
+    reason: Mutex<Option<String>>,
+    // Requests are made while holding the lock, so waiting threads cannot miss them
+    waiting: Mutex<()>,
+    wakeup: Condvar,
 }
 
 impl Default for ShutdownManager {
     fn default() -> Self {
         Self {
             should_shutdown: AtomicBool::new(false),
+            reason: Mutex::new(None),
+            waiting: Mutex::new(()),
+            wakeup: Condvar::new(),
         }
     }
 }
@@ -30,6 +43,68 @@ impl ShutdownManager {
     }
 
     pub fn shutdown(&self) {
-        self.should_shutdown.store(true, Ordering::Relaxed)
+        let _waiting = self.waiting.lock().expect(FAILED_TO_LOCK);
+        self.should_shutdown.store(true, Ordering::Relaxed);
+        self.wakeup.notify_all();
+    }
+
+    /// Blocks until shutdown gets requested, or the timeout passes.
+    ///
+    /// Returns whether shutdown was requested.
+    pub fn wait_for_shutdown(&self, timeout: Option<std::time::Duration>) -> bool {
+        let waiting = self.waiting.lock().expect(FAILED_TO_LOCK);
+        let running = |_: &mut ()| !self.awaiting_shutdown();
+        match timeout {
+            Some(timeout) => {
+                let result = self.wakeup.wait_timeout_while(waiting, timeout, running);
+                drop(result.expect(FAILED_TO_LOCK));
+            }
+            None => {
+                let result = self.wakeup.wait_while(waiting, running);
+                drop(result.expect(FAILED_TO_LOCK));
+            }
+        }
+        self.awaiting_shutdown()
+    }
+
+    /// Shuts down, keeping the reason unless an earlier one was given.
+    pub fn shutdown_with_reason(&self, reason: &str) {
+        self.reason
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .get_or_insert_with(|| reason.into());
+        self.shutdown()
+    }
+
+    /// Reason given for the shutdown, like the message of the master's `shutdown` call.
+    pub fn reason(&self) -> Option<String> {
+        self.reason.lock().expect(FAILED_TO_LOCK).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn shutdown_wakes_up_waiting_threads() {
+        let manager = Arc::new(ShutdownManager::default());
+        assert!(!manager.wait_for_shutdown(Some(Duration::from_millis(10))));
+
+        let (done_tx, done_rx) = channel();
+        let waiting_manager = Arc::clone(&manager);
+        thread::spawn(move || {
+            let waited = waiting_manager.wait_for_shutdown(Some(Duration::from_secs(60)));
+            done_tx.send(waited).unwrap();
+        });
+        assert!(done_rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        manager.shutdown();
+        assert!(done_rx.recv_timeout(Duration::from_secs(5)).unwrap());
+        assert!(manager.wait_for_shutdown(None));
     }
 }
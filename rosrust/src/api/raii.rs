@@ -1,22 +1,34 @@
 use super::clock::Clock;
-use super::error::Result;
+use super::error::{ErrorKind, Result};
 use super::master::Master;
+use super::ros::decode_param;
 use super::slave::Slave;
-use crate::rosxmlrpc::Response;
-use crate::tcpros::{Message, PublisherStream, ServicePair, ServiceResult};
+use crate::rosxmlrpc::{Response, ResponseError};
+use crate::tcpros::{Message, MessageEvent, MessageType, PublisherStream, ServicePair};
+use crate::util::FAILED_TO_LOCK;
 use log::error;
-use std::sync::atomic::AtomicUsize;
-use std::sync::Arc;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+type Validator<T> = Arc<dyn Fn(&T) -> std::result::Result<(), String> + Send + Sync>;
 
 #[derive(Clone)]
 pub struct Publisher<T: Message> {
     clock: Arc<Clock>,
     seq: Arc<AtomicUsize>,
     stream: PublisherStream<T>,
+    validator: Option<Validator<T>>,
     _raii: Arc<InteractorRaii<PublisherInfo>>,
 }
 
 impl<T: Message> Publisher<T> {
+    #[inline]
     pub(crate) fn new(
         master: Arc<Master>,
         slave: Arc<Slave>,
@@ -25,7 +37,29 @@ impl<T: Message> Publisher<T> {
         name: &str,
         queue_size: usize,
     ) -> Result<Self> {
-        let stream = slave.add_publication::<T>(hostname, name, queue_size)?;
+        let message_type = MessageType::of::<T>();
+        Self::with_type(
+            master,
+            slave,
+            clock,
+            hostname,
+            name,
+            queue_size,
+            message_type,
+        )
+    }
+
+    pub(crate) fn with_type(
+        master: Arc<Master>,
+        slave: Arc<Slave>,
+        clock: Arc<dyn Clock>,
+        hostname: &str,
+        name: &str,
+        queue_size: usize,
+        message_type: MessageType,
+    ) -> Result<Self> {
+        let msg_type = message_type.msg_type.clone();
+        let stream = slave.add_publication::<T>(hostname, name, queue_size, message_type)?;
 
         let raii = Arc::new(InteractorRaii::new(PublisherInfo {
             master,
@@ -35,7 +69,7 @@ impl<T: Message> Publisher<T> {
 
         raii.interactor
             .master
-            .register_publisher(name, &T::msg_type())
+            .register_publisher(name, &msg_type)
             .map_err(|err| {
                 error!("Failed to register publisher for topic '{}': {}", name, err);
                 err
@@ -45,6 +79,7 @@ impl<T: Message> Publisher<T> {
             stream,
             clock,
             seq: Arc::new(AtomicUsize::new(0)),
+            validator: None,
             _raii: raii,
         })
     }
@@ -64,18 +99,87 @@ impl<T: Message> Publisher<T> {
         self.stream.set_latching(latching);
     }
 
+    /// Sets whether compression is offered to subscribers that connect afterwards.
+    ///
+    /// The default comes from `rosrust::set_transport_compression`.
+    #[inline]
+    pub fn set_transport_compression(&self, enabled: bool) {
+        self.stream.set_transport_compression(enabled);
+    }
+
+    #[inline]
+    pub fn on_connect<F: Fn(&str) + Send + Sync + 'static>(&self, callback: F) {
+        self.stream.on_connect(callback);
+    }
+
+    #[inline]
+    pub fn on_disconnect<F: Fn(&str) + Send + Sync + 'static>(&self, callback: F) {
+        self.stream.on_disconnect(callback);
+    }
+
+    #[inline]
+    pub fn on_publish_error<F>(&self, callback: F)
+    where
+        F: Fn(&str, &io::Error) + Send + Sync + 'static,
+    {
+        self.stream.on_publish_error(callback);
+    }
+
     #[inline]
     pub fn set_queue_size(&mut self, queue_size: usize) {
         self.stream.set_queue_size(queue_size);
     }
 
+    /// Sets a check that every message has to pass before getting published.
+    ///
+    /// Rejected messages are not sent, and fail the call with the returned reason.
+    /// The validator only applies to this publisher and its later clones.
+    pub fn set_validator<F>(&mut self, validator: F)
+    where
+        F: Fn(&T) -> std::result::Result<(), String> + Send + Sync + 'static,
+    {
+        self.validator = Some(Arc::new(validator));
+    }
+
+    fn validate(&self, message: &T) -> Result<()> {
+        match self.validator {
+            Some(ref validator) => {
+                validator(message).map_err(|details| ErrorKind::MessageRejected(details).into())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Publishes the message, returning the number of subscribers it was queued for.
+    ///
+    /// Broken subscriber connections are dropped in the background instead of
+    /// failing the call.
     #[inline]
-    pub fn send(&self, mut message: T) -> Result<()> {
+    pub fn send(&self, mut message: T) -> Result<usize> {
+        self.validate(&message)?;
         message.set_header(&self.clock, &self.seq);
         self.stream.send(&message).map_err(Into::into)
     }
+
+    /// Publishes the messages in order, locking the connections only once.
+    ///
+    /// Headers get sequence numbers just like with separate `send` calls.
+    /// The batch takes up a single entry of the queue, so it is either
+    /// delivered or dropped as a whole. A single rejected message fails the
+    /// whole batch.
+    pub fn send_batch<I: IntoIterator<Item = T>>(&self, messages: I) -> Result<usize> {
+        let mut messages = messages.into_iter().collect::<Vec<_>>();
+        for message in &messages {
+            self.validate(message)?;
+        }
+        for message in &mut messages {
+            message.set_header(&self.clock, &self.seq);
+        }
+        self.stream.send_batch(&messages).map_err(Into::into)
+    }
 }
 
+#[derive(Clone)]
 struct PublisherInfo {
     master: Arc<Master>,
     slave: Arc<Slave>,
@@ -83,37 +187,74 @@ struct PublisherInfo {
 }
 
 impl Interactor for PublisherInfo {
-    fn unregister(&mut self) -> Response<()> {
+    fn release(&mut self) {
         self.slave.remove_publication(&self.name);
+    }
+
+    fn unregister(&mut self) -> Response<()> {
         self.master.unregister_publisher(&self.name).map(|_| ())
     }
 }
 
+/// Tells a subscriber whether its callback wants further messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallbackControl {
+    Continue,
+    /// Stops delivering messages, and unregisters the subscriber.
+    Unsubscribe,
+}
+
 #[derive(Clone)]
 pub struct Subscriber {
     info: Arc<InteractorRaii<SubscriberInfo>>,
 }
 
 impl Subscriber {
-    pub(crate) fn new<T: Message, F: Fn(T, &str) + Send + 'static>(
+    #[inline]
+    pub(crate) fn new<T: Message, F: Fn(Arc<T>, &MessageEvent) + Send + 'static>(
+        master: Arc<Master>,
+        slave: Arc<Slave>,
+        clock: Arc<dyn Clock>,
+        name: &str,
+        queue_size: usize,
+        callback: F,
+    ) -> Result<Self> {
+        let message_type = MessageType::of::<T>();
+        Self::with_type(
+            master,
+            slave,
+            clock,
+            name,
+            queue_size,
+            message_type,
+            callback,
+        )
+    }
+
+    pub(crate) fn with_type<T: Message, F: Fn(Arc<T>, &MessageEvent) + Send + 'static>(
         master: Arc<Master>,
         slave: Arc<Slave>,
+        clock: Arc<dyn Clock>,
         name: &str,
         queue_size: usize,
+        message_type: MessageType,
         callback: F,
     ) -> Result<Self> {
-        slave.add_subscription::<T, F>(name, queue_size, callback)?;
+        let msg_type = message_type.msg_type.clone();
+        let id = slave.add_subscription::<T, F>(name, queue_size, clock, message_type, callback)?;
 
         let info = Arc::new(InteractorRaii::new(SubscriberInfo {
             master,
             slave,
             name: name.into(),
+            id,
+            last_handle: false,
         }));
 
         let publishers = info
             .interactor
             .master
-            .register_subscriber(name, &T::msg_type())?;
+            .register_subscriber(name, &msg_type)?;
 
         if let Err(err) = info
             .interactor
@@ -129,6 +270,55 @@ impl Subscriber {
         Ok(Self { info })
     }
 
+    /// Creates a subscriber that unsubscribes once the callback asks it to.
+    ///
+    /// The callback is not called again afterwards, even while other
+    /// handles to the subscriber are still alive.
+    pub(crate) fn new_until<T, F>(
+        master: Arc<Master>,
+        slave: Arc<Slave>,
+        clock: Arc<dyn Clock>,
+        name: &str,
+        queue_size: usize,
+        callback: F,
+    ) -> Result<Self>
+    where
+        T: Message,
+        F: Fn(Arc<T>, &MessageEvent) -> CallbackControl + Send + 'static,
+    {
+        let finished = Arc::new(AtomicBool::new(false));
+        let handle = Arc::new(Mutex::new(Weak::<InteractorRaii<SubscriberInfo>>::new()));
+        let subscriber = {
+            let finished = Arc::clone(&finished);
+            let handle = Arc::clone(&handle);
+            Self::new(
+                master,
+                slave,
+                clock,
+                name,
+                queue_size,
+                move |data, event| {
+                    if finished.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    if callback(data, event) == CallbackControl::Unsubscribe {
+                        finished.store(true, Ordering::SeqCst);
+                        let info = handle.lock().expect(FAILED_TO_LOCK).upgrade();
+                        if let Some(info) = info {
+                            info.release();
+                        }
+                    }
+                },
+            )?
+        };
+        *handle.lock().expect(FAILED_TO_LOCK) = Arc::downgrade(&subscriber.info);
+        // A callback that finished before the handle was stored could not release it
+        if finished.load(Ordering::SeqCst) {
+            subscriber.info.release();
+        }
+        Ok(subscriber)
+    }
+
     #[inline]
     pub fn publisher_count(&self) -> usize {
         self.info
@@ -144,18 +334,63 @@ impl Subscriber {
             .slave
             .get_publisher_uris_of_subscription(&self.info.interactor.name)
     }
+
+    /// Number of messages discarded because the callback could not keep up
+    /// and the queue of incoming messages was full.
+    #[inline]
+    pub fn dropped_message_count(&self) -> usize {
+        self.info
+            .interactor
+            .slave
+            .get_dropped_message_count_of_subscription(&self.info.interactor.name)
+    }
+
+    /// Sets whether compression is offered to publishers connected to afterwards.
+    ///
+    /// The setting is shared by all subscribers of the topic within the node.
+    /// The default comes from `rosrust::set_transport_compression`.
+    #[inline]
+    pub fn set_transport_compression(&self, enabled: bool) {
+        self.info
+            .interactor
+            .slave
+            .set_transport_compression_of_subscription(&self.info.interactor.name, enabled)
+    }
+
+    /// Sets whether UDPROS is asked for when connecting to publishers afterwards.
+    ///
+    /// Publishers that only support TCPROS still get connected to over TCP.
+    /// Messages sent over UDP get lost along with any of their datagrams.
+    /// The setting is shared by all subscribers of the topic within the node.
+    #[inline]
+    pub fn set_udp_transport(&self, enabled: bool) {
+        self.info
+            .interactor
+            .slave
+            .set_udp_transport_of_subscription(&self.info.interactor.name, enabled)
+    }
 }
 
+#[derive(Clone)]
 struct SubscriberInfo {
     master: Arc<Master>,
     slave: Arc<Slave>,
     name: String,
+    id: usize,
+    last_handle: bool,
 }
 
 impl Interactor for SubscriberInfo {
+    fn release(&mut self) {
+        self.last_handle = self.slave.remove_subscription(&self.name, self.id);
+    }
+
     fn unregister(&mut self) -> Response<()> {
-        self.slave.remove_subscription(&self.name);
-        self.master.unregister_subscriber(&self.name).map(|_| ())
+        if self.last_handle {
+            self.master.unregister_subscriber(&self.name).map(|_| ())
+        } else {
+            Ok(())
+        }
     }
 }
 
@@ -165,19 +400,24 @@ pub struct Service {
 }
 
 impl Service {
-    pub(crate) fn new<T, F>(
+    pub(crate) fn new<T, E, F>(
         master: Arc<Master>,
         slave: Arc<Slave>,
         hostname: &str,
         bind_address: &str,
         name: &str,
+        workers: usize,
         handler: F,
     ) -> Result<Self>
     where
         T: ServicePair,
-        F: Fn(T::Request) -> ServiceResult<T::Response> + Send + Sync + 'static,
+        E: fmt::Display,
+        F: Fn(T::Request, &HashMap<String, String>) -> std::result::Result<T::Response, E>
+            + Send
+            + Sync
+            + 'static,
     {
-        let api = slave.add_service::<T, F>(hostname, bind_address, name, handler)?;
+        let api = slave.add_service::<T, E, F>(hostname, bind_address, name, workers, handler)?;
 
         let raii = Arc::new(InteractorRaii::new(ServiceInfo {
             master,
@@ -193,6 +433,7 @@ impl Service {
     }
 }
 
+#[derive(Clone)]
 struct ServiceInfo {
     master: Arc<Master>,
     slave: Arc<Slave>,
@@ -201,32 +442,394 @@ struct ServiceInfo {
 }
 
 impl Interactor for ServiceInfo {
-    fn unregister(&mut self) -> Response<()> {
+    fn release(&mut self) {
         self.slave.remove_service(&self.name);
+    }
+
+    fn unregister(&mut self) -> Response<()> {
         self.master
             .unregister_service(&self.name, &self.api)
             .map(|_| ())
     }
 }
 
-trait Interactor {
+/// Parameter whose value is kept up to date by the master.
+///
+/// Reads are served from a local cache that the master refreshes through
+/// `paramUpdate` calls, so they never perform a network round-trip.
+#[derive(Clone)]
+pub struct CachedParameter {
+    info: Arc<InteractorRaii<CachedParameterInfo>>,
+}
+
+impl CachedParameter {
+    pub(crate) fn new(master: Arc<Master>, slave: Arc<Slave>, name: &str) -> Response<Self> {
+        let first = slave.add_cached_parameter(name);
+
+        let info = Arc::new(InteractorRaii::new(CachedParameterInfo {
+            master,
+            slave,
+            name: name.into(),
+            last_handle: false,
+        }));
+
+        if first {
+            let value = info.interactor.master.subscribe_param_any(name)?;
+            info.interactor.slave.set_cached_parameter(name, value);
+        }
+
+        Ok(Self { info })
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.info.interactor.name
+    }
+
+    pub fn get<'b, T: Deserialize<'b>>(&self) -> Response<T> {
+        let value = self.get_raw().ok_or_else(|| {
+            ResponseError::Client(format!("Parameter '{}' is not set", self.name()))
+        })?;
+        decode_param(self.name(), value)
+    }
+
+    #[inline]
+    pub fn get_raw(&self) -> Option<xml_rpc::Value> {
+        self.info.interactor.slave.get_cached_parameter(self.name())
+    }
+}
+
+#[derive(Clone)]
+struct CachedParameterInfo {
+    master: Arc<Master>,
+    slave: Arc<Slave>,
+    name: String,
+    last_handle: bool,
+}
+
+impl Interactor for CachedParameterInfo {
+    fn release(&mut self) {
+        self.last_handle = self.slave.remove_cached_parameter(&self.name);
+    }
+
+    fn unregister(&mut self) -> Response<()> {
+        if self.last_handle {
+            self.master.unsubscribe_param(&self.name).map(|_| ())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Upper bound on how long dropping a handle waits for the master to
+/// acknowledge deregistration.
+const UNREGISTER_TIMEOUT: Duration = Duration::from_secs(1);
+
+trait Interactor: Clone + Send + 'static {
+    /// Tears down the node's own side of the registration.
+    fn release(&mut self);
+
+    /// Removes the registration from the master.
     fn unregister(&mut self) -> Response<()>;
 }
 
 struct InteractorRaii<I: Interactor> {
     pub interactor: I,
+    released: AtomicBool,
 }
 
 impl<I: Interactor> InteractorRaii<I> {
     pub fn new(interactor: I) -> InteractorRaii<I> {
-        Self { interactor }
+        Self {
+            interactor,
+            released: AtomicBool::new(false),
+        }
+    }
+
+    /// Tears down the registration, which otherwise happens on drop.
+    fn release(&self) {
+        if self.released.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let mut interactor = self.interactor.clone();
+        interactor.release();
+
+        // The master call is made on a separate thread, so an unreachable
+        // master cannot stall the drop indefinitely.
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let result = interactor.unregister();
+            // References held by the interactor are gone once the drop returns
+            drop(interactor);
+            // The receiver is gone if the drop timed out already
+            let _ = tx.send(result);
+        });
+        match rx.recv_timeout(UNREGISTER_TIMEOUT) {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("Error while unloading: {:?}", e),
+            Err(_) => error!("Timed out while unregistering from master"),
+        }
     }
 }
 
 impl<I: Interactor> Drop for InteractorRaii<I> {
     fn drop(&mut self) {
-        if let Err(e) = self.interactor.unregister() {
-            error!("Error while unloading: {:?}", e);
+        self.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::clock::RealClock;
+    use crate::api::ShutdownManager;
+    use crate::msg::rosgraph_msgs::Clock as ClockMsg;
+    use crate::msg::test_msgs::geometry_msgs::Vector3;
+    use crate::rosxmlrpc::mock::MockServer;
+    use crate::util::ports::ANY_PORT;
+    use std::sync::Mutex;
+    use std::time::Instant;
+    use xml_rpc::Value;
+
+    fn mock_master(unregister_delay: Duration) -> (String, Arc<Mutex<Vec<String>>>) {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let recorded = Arc::clone(&calls);
+        let mut server = MockServer::default();
+        server.on("registerSubscriber", |_args| Ok(Value::Array(vec![])));
+        server.on("registerPublisher", |_args| Ok(Value::Array(vec![])));
+        server.on("unregisterSubscriber", move |args| {
+            thread::sleep(unregister_delay);
+            if let Some(Value::String(topic)) = args.get(1) {
+                recorded.lock().unwrap().push(topic.clone());
+            }
+            Ok(Value::Int(1))
+        });
+        let uri = server.serve();
+        (uri, calls)
+    }
+
+    fn connect(master_uri: &str, name: &str) -> (Arc<Master>, Arc<Slave>) {
+        let slave = Arc::new(
+            Slave::new(
+                master_uri,
+                "127.0.0.1",
+                "127.0.0.1",
+                ANY_PORT,
+                name,
+                Arc::new(ShutdownManager::default()),
+            )
+            .unwrap(),
+        );
+        let master = Arc::new(Master::new(master_uri, name, slave.uri()).unwrap());
+        (master, slave)
+    }
+
+    fn subscribe(master_uri: &str) -> (Subscriber, Arc<Slave>) {
+        let (master, slave) = connect(master_uri, "/listener");
+        let subscriber = Subscriber::new::<ClockMsg, _>(
+            master,
+            Arc::clone(&slave),
+            Arc::new(RealClock::default()),
+            "/clock",
+            1,
+            |_, _| {},
+        )
+        .unwrap();
+        (subscriber, slave)
+    }
+
+    #[test]
+    fn dropping_subscriber_unregisters_from_master() {
+        let (uri, calls) = mock_master(Duration::from_millis(0));
+        let (subscriber, slave) = subscribe(&uri);
+        assert!(calls.lock().unwrap().is_empty());
+
+        drop(subscriber);
+
+        assert_eq!(vec!["/clock".to_owned()], *calls.lock().unwrap());
+        assert!(slave.subscriptions.get_topics::<Vec<_>>().is_empty());
+    }
+
+    #[test]
+    fn subscription_stays_until_last_subscriber_drops() {
+        let (uri, calls) = mock_master(Duration::from_millis(0));
+        let (first, slave) = subscribe(&uri);
+        let second = Subscriber::new::<ClockMsg, _>(
+            Arc::clone(&first.info.interactor.master),
+            Arc::clone(&slave),
+            Arc::new(RealClock::default()),
+            "/clock",
+            1,
+            |_, _| {},
+        )
+        .unwrap();
+
+        drop(first);
+        assert!(calls.lock().unwrap().is_empty());
+        assert_eq!(1, slave.subscriptions.get_topics::<Vec<_>>().len());
+
+        drop(second);
+        assert_eq!(vec!["/clock".to_owned()], *calls.lock().unwrap());
+        assert!(slave.subscriptions.get_topics::<Vec<_>>().is_empty());
+    }
+
+    #[test]
+    fn callback_can_unsubscribe_itself() {
+        let (uri, calls) = mock_master(Duration::from_millis(0));
+        let (master, talker) = connect(&uri, "/talker");
+        let publisher = Publisher::<ClockMsg>::new(
+            master,
+            Arc::clone(&talker),
+            Arc::new(RealClock::default()),
+            "127.0.0.1",
+            "/clock",
+            10,
+        )
+        .unwrap();
+
+        let (master, listener) = connect(&uri, "/listener");
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&received);
+        let subscriber = Subscriber::new_until::<ClockMsg, _>(
+            master,
+            Arc::clone(&listener),
+            Arc::new(RealClock::default()),
+            "/clock",
+            10,
+            move |_, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                CallbackControl::Unsubscribe
+            },
+        )
+        .unwrap();
+        listener
+            .add_publishers_to_subscription("/clock", vec![talker.uri().to_owned()].into_iter())
+            .unwrap();
+        let start = Instant::now();
+        while publisher.subscriber_count() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        publisher.send(ClockMsg::default()).unwrap();
+        while received.load(Ordering::SeqCst) == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+        for _ in 0..3 {
+            publisher.send(ClockMsg::default()).unwrap();
+        }
+        thread::sleep(Duration::from_millis(200));
+
+        assert_eq!(1, received.load(Ordering::SeqCst));
+        assert_eq!(vec!["/clock".to_owned()], *calls.lock().unwrap());
+        assert!(listener.subscriptions.get_topics::<Vec<_>>().is_empty());
+        assert_eq!(0, subscriber.publisher_count());
+    }
+
+    #[test]
+    fn slow_master_does_not_block_drop() {
+        let (uri, _calls) = mock_master(Duration::from_secs(5));
+        let (subscriber, slave) = subscribe(&uri);
+
+        let start = Instant::now();
+        drop(subscriber);
+
+        assert!(start.elapsed() < UNREGISTER_TIMEOUT + Duration::from_millis(500));
+        assert!(slave.subscriptions.get_topics::<Vec<_>>().is_empty());
+    }
+
+    #[test]
+    fn publisher_fills_in_message_headers() {
+        use crate::msg::rosgraph_msgs::Log;
+        use crate::rosmsg::RosMsg;
+        use crate::tcpros::header;
+        use crate::time::Time;
+        use std::net::TcpStream;
+
+        let (uri, _calls) = mock_master(Duration::from_millis(0));
+        let (master, slave) = connect(&uri, "/talker");
+        let publisher = Publisher::<Log>::new(
+            master,
+            Arc::clone(&slave),
+            Arc::new(RealClock::default()),
+            "127.0.0.1",
+            "/rosout",
+            1,
+        )
+        .unwrap();
+
+        let port = slave.publications.get_port("/rosout").unwrap() as u16;
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut fields = HashMap::<String, String>::new();
+        fields.insert("callerid".into(), "/listener".into());
+        fields.insert("topic".into(), "/rosout".into());
+        fields.insert("md5sum".into(), Log::md5sum());
+        fields.insert("type".into(), Log::msg_type());
+        header::encode(&mut stream, &fields).unwrap();
+        header::decode(&mut stream).unwrap();
+        let start = Instant::now();
+        while publisher.subscriber_count() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut receive = || {
+            // Messages are prefixed with their length
+            u32::decode(&mut stream).unwrap();
+            Log::decode(&mut stream).unwrap()
+        };
+        let mut received = vec![];
+        for _ in 0..3 {
+            publisher.send(Log::default()).unwrap();
+            received.push(receive());
+        }
+        let mut stamped = Log::default();
+        stamped.header.stamp = Time { sec: 7, nsec: 0 };
+        publisher.send(stamped).unwrap();
+        received.push(receive());
+
+        let seqs = received.iter().map(|v| v.header.seq).collect::<Vec<_>>();
+        assert_eq!(vec![0, 1, 2, 3], seqs);
+        assert!(received[..3].iter().all(|v| v.header.stamp.nanos() != 0));
+        assert_eq!(Time { sec: 7, nsec: 0 }, received[3].header.stamp);
+    }
+
+    #[test]
+    fn validator_rejects_messages_before_publishing() {
+        let (uri, _calls) = mock_master(Duration::from_secs(0));
+        let (master, slave) = connect(&uri, "/controller");
+        let mut publisher = Publisher::<Vector3>::new(
+            master,
+            slave,
+            Arc::new(RealClock::default()),
+            "127.0.0.1",
+            "/cmd_vel",
+            1,
+        )
+        .unwrap();
+        publisher.set_validator(|message| {
+            if message.x.is_nan() {
+                Err("velocity is NaN".into())
+            } else {
+                Ok(())
+            }
+        });
+
+        let velocity = |x| Vector3 {
+            x,
+            ..Default::default()
+        };
+        assert_eq!(0, publisher.send(velocity(0.5)).unwrap());
+        let err = publisher.send(velocity(f64::NAN)).unwrap_err();
+        match err.kind() {
+            ErrorKind::MessageRejected(details) => assert_eq!("velocity is NaN", details),
+            kind => panic!("Unexpected error: {}", kind),
         }
+        let batch = vec![velocity(1.0), velocity(f64::NAN)];
+        assert!(publisher.send_batch(batch).is_err());
     }
 }
@@ -1,4 +1,6 @@
-use std::{self, env};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{self, env, process};
 
 pub fn master() -> String {
     if let Some(v) = find_with_prefix("__master:=") {
@@ -7,6 +9,11 @@ pub fn master() -> String {
     env::var("ROS_MASTER_URI").unwrap_or_else(|_| String::from("http://localhost:11311/"))
 }
 
+/// Hostname advertised to other nodes.
+///
+/// The `__hostname` and `__ip` arguments take precedence over `ROS_HOSTNAME`,
+/// which takes precedence over `ROS_IP`, as in `roscpp` and `rospy`. Without
+/// any of them the system hostname is used.
 pub fn hostname() -> String {
     if let Some(v) = find_with_prefix("__hostname:=") {
         return v;
@@ -34,6 +41,31 @@ pub fn name(default: &str) -> String {
     find_with_prefix("__name:=").unwrap_or_else(|| String::from(default))
 }
 
+/// Node name made unique by appending the process ID and a millisecond timestamp.
+///
+/// As in `rospy`, a name set with the `__name` argument is used unchanged.
+/// The timestamp never repeats within the process, so each call yields a new name.
+pub fn anonymous_name(default: &str) -> String {
+    static LAST_STAMP: AtomicU64 = AtomicU64::new(0);
+
+    if let Some(v) = find_with_prefix("__name:=") {
+        return v;
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.as_millis() as u64)
+        .unwrap_or_default();
+    let mut last = LAST_STAMP.load(Ordering::Relaxed);
+    let stamp = loop {
+        let stamp = now.max(last + 1);
+        match LAST_STAMP.compare_exchange_weak(last, stamp, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => break stamp,
+            Err(v) => last = v,
+        }
+    };
+    format!("{}_{}_{}", default, process::id(), stamp)
+}
+
 pub fn mappings() -> Vec<(String, String)> {
     args()
         .skip(1)
@@ -107,28 +139,25 @@ fn args() -> std::env::Args {
 #[cfg(test)]
 #[inline]
 fn args() -> std::vec::IntoIter<String> {
-    tests::args_mock()
+    test_args::args_mock()
 }
 
 #[cfg(test)]
-mod tests {
-    use lazy_static::lazy_static;
-
-    use super::*;
+pub(crate) mod test_args {
     use crate::util::FAILED_TO_LOCK;
+    use lazy_static::lazy_static;
     use std::sync::Mutex;
-    use std::{self, env};
 
     lazy_static! {
         static ref DATA: Mutex<Vec<String>> = Mutex::new(Vec::new());
-        static ref TESTCASE: Mutex<()> = Mutex::new(());
+        pub(crate) static ref TESTCASE: Mutex<()> = Mutex::new(());
     }
 
     pub fn args_mock() -> std::vec::IntoIter<String> {
         DATA.lock().expect(FAILED_TO_LOCK).clone().into_iter()
     }
 
-    fn set_args(args: &Vec<&str>) {
+    pub(crate) fn set_args(args: &Vec<&str>) {
         let mut data = DATA.lock().expect(FAILED_TO_LOCK);
         data.clear();
         data.push(String::from("IGNORE"));
@@ -136,6 +165,14 @@ mod tests {
             data.push(String::from(arg));
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_args::{set_args, TESTCASE};
+    use super::*;
+    use crate::util::FAILED_TO_LOCK;
+    use std::{self, env};
 
     #[test]
     #[allow(unused_variables)]
@@ -262,6 +299,21 @@ mod tests {
         assert_eq!(String::from("othername"), name("myname"));
     }
 
+    #[test]
+    #[allow(unused_variables)]
+    fn anonymous_names_are_distinct() {
+        let testcase = TESTCASE.lock().expect(FAILED_TO_LOCK);
+        set_args(&vec![]);
+        let first = anonymous_name("myname");
+        let second = anonymous_name("myname");
+        assert_ne!(first, second);
+        let prefix = format!("myname_{}_", process::id());
+        assert!(first.starts_with(&prefix));
+        assert!(second.starts_with(&prefix));
+        set_args(&vec!["__name:=othername"]);
+        assert_eq!(String::from("othername"), anonymous_name("myname"));
+    }
+
     #[test]
     #[allow(unused_variables)]
     fn namespace_uses_empty_string_by_default() {
@@ -1,24 +1,39 @@
-use super::super::rosxmlrpc::Response;
-use super::clock::{Clock, Rate, RealClock, SimulatedClock};
+use super::super::rosxmlrpc::{Response, ResponseError};
+use super::clock::{Clock, Rate, RealClock, SimulatedClock, Timer, TimerEvent};
 use super::error::{ErrorKind, Result, ResultExt};
 use super::master::{self, Master, Topic};
 use super::naming::{self, Resolver};
-use super::raii::{Publisher, Service, Subscriber};
+use super::raii::{CachedParameter, CallbackControl, Publisher, Service, Subscriber};
 use super::resolve;
 use super::slave::Slave;
+use super::statistics::{self, StatisticsPublisher};
 use crate::api::clock::Delay;
 use crate::api::ShutdownManager;
 use crate::msg::rosgraph_msgs::{Clock as ClockMsg, Log};
 use crate::msg::std_msgs::Header;
-use crate::tcpros::{Client, Message, ServicePair, ServiceResult};
-use crate::time::{Duration, Time};
-use log::error;
+use crate::tcpros::{
+    Client, Message, MessageEvent, MessageType, RawMessage, ServicePair, ServiceResult,
+    TcpKeepalive, DEFAULT_SERVICE_WORKERS,
+};
+use crate::time::{Duration, SteadyTime, Time};
+use crate::util::ports::ANY_PORT;
+use crate::util::FAILED_TO_LOCK;
+use log::{error, warn};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use std::thread::sleep;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use std::thread;
 use xml_rpc;
 use yaml_rust::{Yaml, YamlLoader};
 
+const SERVICE_POLL_INITIAL_DELAY: std::time::Duration = std::time::Duration::from_millis(10);
+const SERVICE_POLL_MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+const SPIN_PERIOD: std::time::Duration = std::time::Duration::from_millis(100);
+const ROSOUT_RETRY_INITIAL_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+const ROSOUT_RETRY_MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(30);
+
 pub struct Ros {
     master: Arc<Master>,
     slave: Arc<Slave>,
@@ -28,20 +43,71 @@ pub struct Ros {
     name: String,
     clock: Arc<Clock>,
     static_subs: Vec<Subscriber>,
-    logger: Option<Publisher<Log>>,
+    logger: Arc<Mutex<Option<Publisher<Log>>>>,
+    statistics: Option<StatisticsPublisher>,
     shutdown_manager: Arc<ShutdownManager>,
 }
 
 impl Ros {
     pub fn new(name: &str) -> Result<Ros> {
+        Ros::new_with_master(name, &resolve::master())
+    }
+
+    /// Creates a node with a unique name, derived from the given one.
+    ///
+    /// This allows running several instances of a node at once.
+    /// See `resolve::anonymous_name` for how the name is built.
+    pub fn new_anonymous(name: &str) -> Result<Ros> {
+        Ros::new(&resolve::anonymous_name(name))
+    }
+
+    /// Creates a node that connects to the given master, instead of the one
+    /// set by `ROS_MASTER_URI` or the `__master` argument.
+    ///
+    /// This allows nodes connected to different masters within one process.
+    pub fn new_with_master(name: &str, master_uri: &str) -> Result<Ros> {
+        Ros::new_with_master_and_hostname(name, master_uri, &resolve::hostname())
+    }
+
+    /// Creates a node that connects to the given master, and advertises
+    /// itself to other nodes under the given hostname.
+    pub fn new_with_master_and_hostname(
+        name: &str,
+        master_uri: &str,
+        hostname: &str,
+    ) -> Result<Ros> {
+        Ros::new_configured(name, master_uri, hostname, ANY_PORT)
+    }
+
+    /// Creates a node whose XML-RPC server, topic publishers and services
+    /// only bind to ports from the given range.
+    ///
+    /// Each of them takes the first free port, so the range needs to be large
+    /// enough for all of them. Running out of ports fails with an error.
+    pub fn new_with_port_range(name: &str, ports: RangeInclusive<u16>) -> Result<Ros> {
+        Ros::new_configured(name, &resolve::master(), &resolve::hostname(), ports)
+    }
+
+    fn new_configured(
+        name: &str,
+        master_uri: &str,
+        hostname: &str,
+        ports: RangeInclusive<u16>,
+    ) -> Result<Ros> {
         let mut namespace = resolve::namespace();
         if !namespace.starts_with('/') {
             namespace = format!("/{}", namespace);
         }
-        let master_uri = resolve::master();
-        let hostname = resolve::hostname();
         let name = resolve::name(name);
-        let mut ros = Ros::new_raw(&master_uri, &hostname, &namespace, &name)?;
+        let mut ros = Ros::new_raw(master_uri, hostname, &namespace, &name, ports)?;
+        ros.master.wait_until_reachable()?;
+        // Registering makes the master shut down any node already using the name
+        if let Ok(uri) = ros.master.lookup_node(&ros.name) {
+            warn!(
+                "Node name '{}' is already used by the node at {}, which will get shut down",
+                ros.name, uri
+            );
+        }
         for (src, dest) in resolve::mappings() {
             ros.map(&src, &dest)?;
         }
@@ -73,12 +139,46 @@ impl Ros {
             ros.clock = ros_clock;
         }
 
-        ros.logger = Some(ros.publish("/rosout", 100)?);
+        ros.start_rosout();
+
+        if ros
+            .param("/enable_statistics")
+            .and_then(|v| v.get().ok())
+            .unwrap_or(false)
+        {
+            let window = ros
+                .param("/statistics_window_min_size")
+                .and_then(|v| v.get::<i32>().ok())
+                .filter(|&seconds| seconds > 0)
+                .map_or(statistics::DEFAULT_WINDOW, |seconds| {
+                    std::time::Duration::from_secs(seconds as u64)
+                });
+            ros.start_statistics(window)?;
+        }
 
         Ok(ros)
     }
 
-    fn new_raw(master_uri: &str, hostname: &str, namespace: &str, name: &str) -> Result<Ros> {
+    /// Starts publishing statistics about subscriptions to `/statistics`, once per window.
+    fn start_statistics(&mut self, window: std::time::Duration) -> Result<()> {
+        let publisher = self.publish("/statistics", 100)?;
+        self.statistics = Some(StatisticsPublisher::new(
+            publisher,
+            Arc::clone(&self.slave),
+            Arc::clone(&self.clock),
+            &self.name,
+            window,
+        ));
+        Ok(())
+    }
+
+    fn new_raw(
+        master_uri: &str,
+        hostname: &str,
+        namespace: &str,
+        name: &str,
+        ports: RangeInclusive<u16>,
+    ) -> Result<Ros> {
         let namespace = namespace.trim_end_matches('/');
 
         if name.contains('/') {
@@ -104,7 +204,7 @@ impl Ros {
             master_uri,
             hostname,
             bind_host,
-            0,
+            ports,
             &name,
             Arc::clone(&shutdown_manager),
         )?;
@@ -119,11 +219,70 @@ impl Ros {
             name,
             clock: Arc::new(RealClock::default()),
             static_subs: Vec::new(),
-            logger: None,
+            logger: Arc::new(Mutex::new(None)),
+            statistics: None,
             shutdown_manager,
         })
     }
 
+    /// Starts publishing log messages to `/rosout`.
+    ///
+    /// If advertising fails, it keeps getting retried in the background with
+    /// a growing delay, until it succeeds or the node shuts down.
+    /// Until then log messages only go to the terminal.
+    fn start_rosout(&self) {
+        let advertise = |master, slave, clock: Arc<dyn Clock>, bind_address: &str| {
+            Publisher::new(master, slave, clock, bind_address, "/rosout", 100)
+        };
+        let result = advertise(
+            Arc::clone(&self.master),
+            Arc::clone(&self.slave),
+            Arc::clone(&self.clock),
+            &self.bind_address,
+        );
+        match result {
+            Ok(publisher) => {
+                *self.logger.lock().expect(FAILED_TO_LOCK) = Some(publisher);
+                return;
+            }
+            Err(err) => warn!(
+                "Failed to advertise /rosout, retrying in the background: {}",
+                err
+            ),
+        }
+        // Weak references let the node get dropped while retries are pending
+        let logger = Arc::downgrade(&self.logger);
+        let master = Arc::downgrade(&self.master);
+        let slave = Arc::downgrade(&self.slave);
+        let clock = Arc::downgrade(&self.clock);
+        let shutdown_manager = Arc::clone(&self.shutdown_manager);
+        let bind_address = self.bind_address.clone();
+        thread::spawn(move || {
+            let mut delay = ROSOUT_RETRY_INITIAL_DELAY;
+            loop {
+                if shutdown_manager.wait_for_shutdown(Some(delay)) {
+                    return;
+                }
+                let (logger, master, slave, clock) = match (
+                    logger.upgrade(),
+                    master.upgrade(),
+                    slave.upgrade(),
+                    clock.upgrade(),
+                ) {
+                    (Some(logger), Some(master), Some(slave), Some(clock)) => {
+                        (logger, master, slave, clock)
+                    }
+                    _ => return,
+                };
+                if let Ok(publisher) = advertise(master, slave, clock, &bind_address) {
+                    *logger.lock().expect(FAILED_TO_LOCK) = Some(publisher);
+                    return;
+                }
+                delay = (delay * 2).min(ROSOUT_RETRY_MAX_DELAY);
+            }
+        });
+    }
+
     fn map(&mut self, source: &str, destination: &str) -> Result<()> {
         self.resolver.map(source, destination).map_err(Into::into)
     }
@@ -143,6 +302,12 @@ impl Ros {
         &self.hostname
     }
 
+    /// Resolves a graph name against the node's namespace and private
+    /// namespace, applying command line remappings.
+    pub fn resolve_name(&self, name: &str) -> Result<String> {
+        self.resolver.translate(name).map_err(Into::into)
+    }
+
     #[inline]
     pub fn bind_address(&self) -> &str {
         &self.bind_address
@@ -153,12 +318,40 @@ impl Ros {
         self.clock.now()
     }
 
+    /// Replaces the clock that tells the node's time.
+    ///
+    /// Publishers, subscribers, rates and timers created afterwards use the
+    /// new clock, while ones created before keep the old one. Tests can pass a
+    /// `SimulatedClock` to control time.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Time of a monotonic clock, for measuring elapsed time.
+    ///
+    /// Unlike `now`, it is unaffected by system clock adjustments and simulated time.
+    #[inline]
+    pub fn steady_now(&self) -> SteadyTime {
+        SteadyTime::now()
+    }
+
     #[inline]
     pub fn delay(&self, d: Duration) -> Delay {
         self.clock.await_init();
         Delay::new(Arc::clone(&self.clock), d)
     }
 
+    /// Calls the callback every `period` of the node's clock, until the timer gets dropped.
+    ///
+    /// See `Timer` for how overrunning callbacks are handled.
+    pub fn create_timer<F>(&self, period: Duration, callback: F) -> Timer
+    where
+        F: FnMut(TimerEvent) + Send + 'static,
+    {
+        self.clock.await_init();
+        Timer::new(Arc::clone(&self.clock), period, callback)
+    }
+
     #[inline]
     pub fn shutdown_sender(&self) -> Arc<ShutdownManager> {
         Arc::clone(&self.shutdown_manager)
@@ -175,6 +368,15 @@ impl Ros {
         !self.shutdown_manager.awaiting_shutdown()
     }
 
+    /// Why the node got shut down, if a reason was given.
+    ///
+    /// This holds the message of the master's `shutdown` call, which is sent
+    /// when another node registers with the same name, for example.
+    #[inline]
+    pub fn shutdown_reason(&self) -> Option<String> {
+        self.shutdown_manager.reason()
+    }
+
     #[inline]
     pub fn spin(&self) -> Spinner {
         Spinner {
@@ -182,6 +384,27 @@ impl Ros {
         }
     }
 
+    /// Waits for a single spin period, returning whether the node should keep running.
+    ///
+    /// Callbacks are handled on their own threads, so there is nothing to process here.
+    /// It lets loops that do their own work notice shutdown requests in small steps.
+    #[inline]
+    pub fn spin_once(&self) -> bool {
+        spin_once(&self.shutdown_manager)
+    }
+
+    /// Spins until the duration passes on the node's clock, or shutdown gets requested.
+    ///
+    /// Returns whether the node should keep running, so later spins can follow.
+    pub fn spin_for(&self, d: Duration) -> bool {
+        self.clock.await_init();
+        spin_for(&self.shutdown_manager, self.clock.as_ref(), d)
+    }
+
+    pub(crate) fn clock(&self) -> Arc<dyn Clock> {
+        Arc::clone(&self.clock)
+    }
+
     pub fn param(&self, name: &str) -> Option<Parameter> {
         self.resolver.translate(name).ok().map(|v| Parameter {
             master: Arc::clone(&self.master),
@@ -189,6 +412,36 @@ impl Ros {
         })
     }
 
+    /// Parameter handle that fetches the value once and then follows
+    /// updates pushed by the master.
+    pub fn param_cached(&self, name: &str) -> Result<CachedParameter> {
+        let name = self.resolver.translate(name)?;
+        CachedParameter::new(Arc::clone(&self.master), Arc::clone(&self.slave), &name)
+            .map_err(Into::into)
+    }
+
+    /// Whether the parameter is set, checked without fetching its value.
+    pub fn param_exists(&self, name: &str) -> Result<bool> {
+        let name = self.resolver.translate(name)?;
+        self.master.has_param(&name).map_err(Into::into)
+    }
+
+    /// Removes the parameter, failing if it was not set.
+    pub fn delete_param(&self, name: &str) -> Result<()> {
+        let name = self.resolver.translate(name)?;
+        self.master.delete_param(&name)?;
+        Ok(())
+    }
+
+    /// Loads a YAML document onto the parameter server under `namespace`,
+    /// like `rosparam load`.
+    ///
+    /// Nested maps become nested namespaces, and each leaf is set separately.
+    pub fn load_params_from_yaml(&self, namespace: &str, yaml: &str) -> Result<()> {
+        let namespace = self.resolver.translate(namespace)?;
+        load_yaml_params(&self.master, &namespace, yaml)
+    }
+
     pub fn parameters(&self) -> Response<Vec<String>> {
         self.master.get_param_names()
     }
@@ -203,54 +456,161 @@ impl Ros {
             .map(|v| v.into_iter().map(Into::into).collect())
     }
 
+    /// Topics with at least one publisher, limited to the given namespace.
+    ///
+    /// An empty subgraph covers all namespaces.
+    pub fn published_topics_on_master(&self, subgraph: &str) -> Response<Vec<Topic>> {
+        self.master
+            .get_published_topics(subgraph)
+            .map(|v| v.into_iter().map(Into::into).collect())
+    }
+
+    /// XML-RPC URI of the node with the given name.
+    pub fn lookup_node(&self, name: &str) -> Response<String> {
+        self.master.lookup_node(name)
+    }
+
+    /// Topics this node currently publishes, with their message types.
+    pub fn published_topics(&self) -> Vec<Topic> {
+        self.slave
+            .publications
+            .get_topics::<Vec<_>>()
+            .into_iter()
+            .map(Into::into)
+            .collect()
+    }
+
+    /// Topics this node is currently subscribed to, with their message types.
+    pub fn subscribed_topics(&self) -> Vec<Topic> {
+        self.slave
+            .subscriptions
+            .get_topics::<Vec<_>>()
+            .into_iter()
+            .map(Into::into)
+            .collect()
+    }
+
+    /// Keepalive of TCP connections opened afterwards by topics and services.
+    ///
+    /// Connections already open keep their previous setting.
+    pub fn set_tcp_keepalive(&self, keepalive: Option<TcpKeepalive>) {
+        self.slave.set_tcp_keepalive(keepalive)
+    }
+
+    /// Creates a client of the service's current provider.
+    ///
+    /// If that provider cannot be reached, the master is asked for the
+    /// service again, and the request fails over to the new provider.
     pub fn client<T: ServicePair>(&self, service: &str) -> Result<Client<T>> {
         let name = self.resolver.translate(service)?;
         let uri = self.master.lookup_service(&name)?;
-        Ok(Client::new(&self.name, &uri, &name))
+        Ok(Client::new(&self.name, &uri, &name)
+            .with_failover(self.service_lookup(name))
+            .with_tcp_keepalive(self.slave.tcp_keepalive()))
+    }
+
+    pub fn persistent_client<T: ServicePair>(&self, service: &str) -> Result<Client<T>> {
+        let name = self.resolver.translate(service)?;
+        let uri = self.master.lookup_service(&name)?;
+        Ok(Client::new_persistent(&self.name, &uri, &name)
+            .with_failover(self.service_lookup(name))
+            .with_tcp_keepalive(self.slave.tcp_keepalive()))
     }
 
+    fn service_lookup(&self, name: String) -> impl Fn() -> Option<String> + Send + Sync {
+        let master = Arc::clone(&self.master);
+        move || master.lookup_service(&name).ok()
+    }
+
+    /// Blocks until the service is registered with the master.
+    ///
+    /// The master is polled with a growing delay between attempts, and both
+    /// the delay and the timeout follow the node's clock, so simulated time
+    /// is respected.
     pub fn wait_for_service(
         &self,
         service: &str,
         timeout: Option<std::time::Duration>,
     ) -> Result<()> {
-        use crate::rosxmlrpc::ResponseError;
-
         let name = self.resolver.translate(service)?;
-        let now = std::time::Instant::now();
-        loop {
-            let e = match self.master.lookup_service(&name) {
-                Ok(_) => return Ok(()),
-                Err(e) => e,
-            };
-            match e {
-                ResponseError::Client(ref m) if m == "no provider" => {
-                    if let Some(ref timeout) = timeout {
-                        if now.elapsed() > *timeout {
-                            return Err(ErrorKind::TimeoutError.into());
-                        }
-                    }
-                    sleep(std::time::Duration::from_millis(100));
-                    continue;
-                }
-                _ => {}
-            }
-            return Err(e.into());
-        }
+        wait_for_service_on(&self.master, &*self.clock, &name, timeout)
     }
 
+    #[inline]
     pub fn service<T, F>(&self, service: &str, handler: F) -> Result<Service>
     where
         T: ServicePair,
         F: Fn(T::Request) -> ServiceResult<T::Response> + Send + Sync + 'static,
+    {
+        self.service_with_workers::<T, F>(service, DEFAULT_SERVICE_WORKERS, handler)
+    }
+
+    /// Advertises a service whose handler fails with its own error type.
+    ///
+    /// The error's `Display` output is sent to the client as the failure message,
+    /// which lets handlers use `?` on their own error enums.
+    pub fn service_with_error<T, E, F>(&self, service: &str, handler: F) -> Result<Service>
+    where
+        T: ServicePair,
+        E: fmt::Display,
+        F: Fn(T::Request) -> std::result::Result<T::Response, E> + Send + Sync + 'static,
+    {
+        self.advertise_service::<T, E, _>(service, DEFAULT_SERVICE_WORKERS, move |req, _| {
+            handler(req)
+        })
+    }
+
+    /// Advertises a service whose handler also receives the connection header
+    /// sent by the client, which holds fields like `callerid`.
+    pub fn service_with_header<T, F>(&self, service: &str, handler: F) -> Result<Service>
+    where
+        T: ServicePair,
+        F: Fn(T::Request, &HashMap<String, String>) -> ServiceResult<T::Response>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.advertise_service::<T, String, F>(service, DEFAULT_SERVICE_WORKERS, handler)
+    }
+
+    /// Advertises a service whose requests are handled by `workers` threads.
+    ///
+    /// Requests arriving while all workers are busy are queued.
+    pub fn service_with_workers<T, F>(
+        &self,
+        service: &str,
+        workers: usize,
+        handler: F,
+    ) -> Result<Service>
+    where
+        T: ServicePair,
+        F: Fn(T::Request) -> ServiceResult<T::Response> + Send + Sync + 'static,
+    {
+        self.advertise_service::<T, String, _>(service, workers, move |req, _| handler(req))
+    }
+
+    fn advertise_service<T, E, F>(
+        &self,
+        service: &str,
+        workers: usize,
+        handler: F,
+    ) -> Result<Service>
+    where
+        T: ServicePair,
+        E: fmt::Display,
+        F: Fn(T::Request, &HashMap<String, String>) -> std::result::Result<T::Response, E>
+            + Send
+            + Sync
+            + 'static,
     {
         let name = self.resolver.translate(service)?;
-        Service::new::<T, F>(
+        Service::new::<T, E, F>(
             Arc::clone(&self.master),
             Arc::clone(&self.slave),
             &self.hostname,
             &self.bind_address,
             &name,
+            workers,
             handler,
         )
     }
@@ -264,15 +624,135 @@ impl Ros {
         self.subscribe_with_ids(topic, queue_size, move |data, _| callback(data))
     }
 
+    #[inline]
     pub fn subscribe_with_ids<T, F>(
         &self,
         topic: &str,
-        mut queue_size: usize,
+        queue_size: usize,
         callback: F,
     ) -> Result<Subscriber>
     where
         T: Message,
         F: Fn(T, &str) + Send + 'static,
+    {
+        self.subscribe_with_info(topic, queue_size, move |data, event: &MessageEvent| {
+            callback(data, &event.caller_id)
+        })
+    }
+
+    pub fn subscribe_with_info<T, F>(
+        &self,
+        topic: &str,
+        queue_size: usize,
+        callback: F,
+    ) -> Result<Subscriber>
+    where
+        T: Message,
+        F: Fn(T, &MessageEvent) + Send + 'static,
+    {
+        self.subscribe_arc_with_info(topic, queue_size, move |data: Arc<T>, event| {
+            callback(
+                Arc::try_unwrap(data).unwrap_or_else(|data| T::clone(&data)),
+                event,
+            )
+        })
+    }
+
+    /// Subscribes with a callback that receives messages behind an `Arc`.
+    ///
+    /// Messages are decoded once per topic and shared by all of its callbacks,
+    /// which avoids cloning large messages for every callback. Subscribers of
+    /// the same topic share one queue, as large as the largest queue size
+    /// asked for, and their callbacks handle each message one after another.
+    #[inline]
+    pub fn subscribe_arc<T, F>(
+        &self,
+        topic: &str,
+        queue_size: usize,
+        callback: F,
+    ) -> Result<Subscriber>
+    where
+        T: Message,
+        F: Fn(Arc<T>) + Send + 'static,
+    {
+        self.subscribe_arc_with_info(topic, queue_size, move |data, _: &MessageEvent| {
+            callback(data)
+        })
+    }
+
+    /// Subscribes until the callback returns `CallbackControl::Unsubscribe`.
+    ///
+    /// This suits waiting for a single message, like a configuration on a
+    /// latched topic, without dropping the subscriber from outside.
+    pub fn subscribe_until<T, F>(
+        &self,
+        topic: &str,
+        mut queue_size: usize,
+        callback: F,
+    ) -> Result<Subscriber>
+    where
+        T: Message,
+        F: Fn(T) -> CallbackControl + Send + 'static,
+    {
+        if queue_size == 0 {
+            queue_size = usize::MAX;
+        }
+        let name = self.resolver.translate(topic)?;
+        Subscriber::new_until::<T, _>(
+            Arc::clone(&self.master),
+            Arc::clone(&self.slave),
+            Arc::clone(&self.clock),
+            &name,
+            queue_size,
+            move |data: Arc<T>, _: &MessageEvent| {
+                callback(Arc::try_unwrap(data).unwrap_or_else(|data| T::clone(&data)))
+            },
+        )
+    }
+
+    /// Subscribes to a topic of a type known only at runtime.
+    ///
+    /// Messages arrive undecoded, but publishers must match the given type,
+    /// which is also what gets registered with the master.
+    pub fn subscribe_raw<F>(
+        &self,
+        topic: &str,
+        mut queue_size: usize,
+        message_type: MessageType,
+        callback: F,
+    ) -> Result<Subscriber>
+    where
+        F: Fn(RawMessage, &MessageEvent) + Send + 'static,
+    {
+        if queue_size == 0 {
+            queue_size = usize::MAX;
+        }
+        let name = self.resolver.translate(topic)?;
+        Subscriber::with_type(
+            Arc::clone(&self.master),
+            Arc::clone(&self.slave),
+            Arc::clone(&self.clock),
+            &name,
+            queue_size,
+            message_type,
+            move |data: Arc<RawMessage>, event: &MessageEvent| {
+                callback(
+                    Arc::try_unwrap(data).unwrap_or_else(|data| RawMessage::clone(&data)),
+                    event,
+                )
+            },
+        )
+    }
+
+    fn subscribe_arc_with_info<T, F>(
+        &self,
+        topic: &str,
+        mut queue_size: usize,
+        callback: F,
+    ) -> Result<Subscriber>
+    where
+        T: Message,
+        F: Fn(Arc<T>, &MessageEvent) + Send + 'static,
     {
         if queue_size == 0 {
             queue_size = usize::max_value();
@@ -281,6 +761,7 @@ impl Ros {
         Subscriber::new::<T, F>(
             Arc::clone(&self.master),
             Arc::clone(&self.slave),
+            Arc::clone(&self.clock),
             &name,
             queue_size,
             callback,
@@ -305,26 +786,43 @@ impl Ros {
         )
     }
 
+    /// Advertises a topic of a type known only at runtime.
+    ///
+    /// The given type is registered with the master and offered to
+    /// subscribers, while the published bytes are sent as they are.
+    pub fn publish_raw(
+        &self,
+        topic: &str,
+        mut queue_size: usize,
+        message_type: MessageType,
+    ) -> Result<Publisher<RawMessage>> {
+        if queue_size == 0 {
+            queue_size = usize::MAX;
+        }
+        let name = self.resolver.translate(topic)?;
+        Publisher::with_type(
+            Arc::clone(&self.master),
+            Arc::clone(&self.slave),
+            Arc::clone(&self.clock),
+            &self.bind_address,
+            &name,
+            queue_size,
+            message_type,
+        )
+    }
+
     fn log_to_terminal(&self, level: i8, msg: &str, file: &str, line: u32) {
-        use colored::{Color, Colorize};
-
-        let format_string =
-            |prefix, color| format!("[{} @ {}:{}]: {}", prefix, file, line, msg).color(color);
-
-        match level {
-            Log::DEBUG => println!("{}", format_string("DEBUG", Color::White)),
-            Log::INFO => println!("{}", format_string("INFO", Color::White)),
-            Log::WARN => eprintln!("{}", format_string("WARN", Color::Yellow)),
-            Log::ERROR => eprintln!("{}", format_string("ERROR", Color::Red)),
-            Log::FATAL => eprintln!("{}", format_string("FATAL", Color::Red)),
-            _ => {}
+        match terminal_line(level, msg, file, line) {
+            Some((true, text)) => eprintln!("{}", text),
+            Some((false, text)) => println!("{}", text),
+            None => {}
         }
     }
 
     pub fn log(&self, level: i8, msg: String, file: &str, line: u32) {
         self.log_to_terminal(level, &msg, file, line);
-        let logger = &match self.logger {
-            Some(ref v) => v,
+        let logger = match *self.logger.lock().expect(FAILED_TO_LOCK) {
+            Some(ref logger) => logger.clone(),
             None => return,
         };
         let topics = self.slave.publications.get_topic_names();
@@ -342,6 +840,38 @@ impl Ros {
             error!("Logging error: {}", err);
         }
     }
+
+    pub fn log_record(&self, record: &log::Record) {
+        self.log(
+            crate::rosout::severity(record.level()),
+            record.args().to_string(),
+            record.file().unwrap_or_default(),
+            record.line().unwrap_or_default(),
+        );
+    }
+}
+
+/// Formats a log message for the terminal, telling whether it belongs on stderr.
+///
+/// This happens regardless of `/rosout`, so messages are never lost entirely.
+fn terminal_line(
+    level: i8,
+    msg: &str,
+    file: &str,
+    line: u32,
+) -> Option<(bool, colored::ColoredString)> {
+    use colored::{Color, Colorize};
+
+    let (prefix, color, to_stderr) = match level {
+        Log::DEBUG => ("DEBUG", Color::White, false),
+        Log::INFO => ("INFO", Color::White, false),
+        Log::WARN => ("WARN", Color::Yellow, true),
+        Log::ERROR => ("ERROR", Color::Red, true),
+        Log::FATAL => ("FATAL", Color::Red, true),
+        _ => return None,
+    };
+    let text = format!("[{} @ {}:{}]: {}", prefix, file, line, msg).color(color);
+    Some((to_stderr, text))
 }
 
 pub struct Parameter {
@@ -354,8 +884,12 @@ impl Parameter {
         &self.name
     }
 
+    /// Fetches the parameter and maps its value tree onto `T`.
+    ///
+    /// Dictionaries map onto structs, so a whole configuration namespace can
+    /// be read at once.
     pub fn get<'b, T: Deserialize<'b>>(&self) -> Response<T> {
-        self.master.get_param::<T>(&self.name)
+        decode_param(&self.name, self.get_raw()?)
     }
 
     pub fn get_raw(&self) -> Response<xml_rpc::Value> {
@@ -383,6 +917,72 @@ impl Parameter {
     }
 }
 
+pub(crate) fn decode_param<'b, T: Deserialize<'b>>(
+    name: &str,
+    value: xml_rpc::Value,
+) -> Response<T> {
+    T::deserialize(value).map_err(|err| {
+        ResponseError::Server(format!(
+            "Parameter '{}' has unexpected structure: {}",
+            name, err
+        ))
+    })
+}
+
+fn wait_for_service_on(
+    master: &Master,
+    clock: &dyn Clock,
+    name: &str,
+    timeout: Option<std::time::Duration>,
+) -> Result<()> {
+    let deadline = timeout.map(|timeout| clock.now() + timeout.into());
+    let mut delay = SERVICE_POLL_INITIAL_DELAY;
+    loop {
+        match master.lookup_service(name) {
+            Ok(_) => return Ok(()),
+            Err(ResponseError::Client(ref m)) if m == "no provider" => {}
+            Err(err) => return Err(err.into()),
+        }
+        let mut sleep_time = Duration::from(delay);
+        if let Some(deadline) = deadline {
+            let now = clock.now();
+            if now >= deadline {
+                bail!(ErrorKind::TimeoutError);
+            }
+            sleep_time = std::cmp::min(sleep_time, deadline - now);
+        }
+        clock.sleep(sleep_time);
+        delay = std::cmp::min(delay * 2, SERVICE_POLL_MAX_DELAY);
+    }
+}
+
+fn load_yaml_params(master: &Master, namespace: &str, yaml: &str) -> Result<()> {
+    let documents =
+        YamlLoader::load_from_str(yaml).chain_err(|| ErrorKind::BadYamlData(yaml.into()))?;
+    let mut params = vec![];
+    for document in documents {
+        flatten_yaml(namespace.into(), document, &mut params)?;
+    }
+    for (key, value) in params {
+        master.set_param_any(&key, value)?;
+    }
+    Ok(())
+}
+
+fn flatten_yaml(key: String, val: Yaml, params: &mut Vec<(String, xml_rpc::Value)>) -> Result<()> {
+    match val {
+        Yaml::Hash(items) => {
+            for (name, val) in items {
+                let name = yaml_to_string(name)?;
+                let child = format!("{}/{}", key.trim_end_matches('/'), name);
+                flatten_yaml(child, val, params)?;
+            }
+        }
+        val => params.push((key, yaml_to_xmlrpc(val)?)),
+    }
+    Ok(())
+}
+
 fn yaml_to_xmlrpc(val: Yaml) -> Result<xml_rpc::Value> {
     Ok(match val {
         Yaml::Real(v) => xml_rpc::Value::Double(
@@ -424,8 +1024,900 @@ pub struct Spinner {
 
 impl Drop for Spinner {
     fn drop(&mut self) {
-        while !self.shutdown_manager.awaiting_shutdown() {
-            sleep(std::time::Duration::from_millis(100));
+        self.shutdown_manager.wait_for_shutdown(None);
+    }
+}
+
+pub(crate) fn spin_once(shutdown_manager: &ShutdownManager) -> bool {
+    !shutdown_manager.wait_for_shutdown(Some(SPIN_PERIOD))
+}
+
+/// Shutdown requests end the wait right away. Simulated time is checked at
+/// least once per spin period, since it can run at any pace.
+pub(crate) fn spin_for(shutdown_manager: &ShutdownManager, clock: &dyn Clock, d: Duration) -> bool {
+    let deadline = clock.now() + d;
+    loop {
+        let remaining = (deadline - clock.now()).nanos();
+        if remaining <= 0 {
+            return !shutdown_manager.awaiting_shutdown();
+        }
+        let wait = SPIN_PERIOD.min(std::time::Duration::from_nanos(remaining as u64));
+        if shutdown_manager.wait_for_shutdown(Some(wait)) {
+            return false;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rosxmlrpc::mock::MockServer;
+    use serde_derive::Deserialize;
+    use std::sync::Mutex;
+    use std::thread;
+    use xml_rpc::Value;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Limits {
+        min: i32,
+        max: i32,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Config {
+        frame_id: String,
+        rate: f64,
+        enabled: bool,
+        limits: Limits,
+    }
+
+    fn structure(items: Vec<(&str, Value)>) -> Value {
+        Value::Struct(
+            items
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        )
+    }
+
+    fn limits() -> Value {
+        structure(vec![("min", Value::Int(-3)), ("max", Value::Int(8))])
+    }
+
+    #[test]
+    fn decodes_namespace_into_struct() {
+        let value = structure(vec![
+            ("frame_id", Value::String("base_link".into())),
+            ("rate", Value::Double(12.5)),
+            ("enabled", Value::Bool(true)),
+            ("limits", limits()),
+        ]);
+        let config: Config = decode_param("/config", value).unwrap();
+        assert_eq!(
+            Config {
+                frame_id: "base_link".into(),
+                rate: 12.5,
+                enabled: true,
+                limits: Limits { min: -3, max: 8 },
+            },
+            config
+        );
+    }
+
+    #[test]
+    fn reports_missing_field() {
+        let value = structure(vec![
+            ("frame_id", Value::String("base_link".into())),
+            ("rate", Value::Double(12.5)),
+            ("limits", limits()),
+        ]);
+        let message = decode_param::<Config>("/config", value)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("/config"), "{}", message);
+        assert!(message.contains("enabled"), "{}", message);
+    }
+
+    #[test]
+    fn reports_wrong_type() {
+        let value = structure(vec![
+            ("frame_id", Value::String("base_link".into())),
+            ("rate", Value::String("fast".into())),
+            ("enabled", Value::Bool(true)),
+            ("limits", limits()),
+        ]);
+        let message = decode_param::<Config>("/config", value)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("/config"), "{}", message);
+        assert!(message.contains("fast"), "{}", message);
+    }
+
+    fn mock_master() -> (Master, Arc<Mutex<Vec<(String, Value)>>>) {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let recorded = Arc::clone(&calls);
+        let mut server = MockServer::default();
+        server.on("setParam", move |args| {
+            let mut args = args.into_iter().skip(1);
+            match (args.next(), args.next()) {
+                (Some(Value::String(key)), Some(value)) => {
+                    recorded.lock().unwrap().push((key, value));
+                    Ok(Value::Int(0))
+                }
+                _ => Err(ResponseError::Client("Bad arguments".into())),
+            }
+        });
+        let uri = server.serve();
+        (Master::new(&uri, "/loader", "").unwrap(), calls)
+    }
+
+    #[test]
+    fn loads_yaml_as_flattened_params() {
+        let (master, calls) = mock_master();
+        let yaml = "
+rate: 10
+camera:
+  frame_id: optical
+  resolution: [640, 480]
+  exposure:
+    auto: true
+";
+        load_yaml_params(&master, "/ns/", yaml).unwrap();
+
+        let mut calls = calls.lock().unwrap().clone();
+        calls.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            vec![
+                ("/ns/camera/exposure/auto".to_owned(), Value::Bool(true)),
+                (
+                    "/ns/camera/frame_id".to_owned(),
+                    Value::String("optical".into())
+                ),
+                (
+                    "/ns/camera/resolution".to_owned(),
+                    Value::Array(vec![Value::Int(640), Value::Int(480)])
+                ),
+                ("/ns/rate".to_owned(), Value::Int(10)),
+            ],
+            calls
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_yaml() {
+        let (master, calls) = mock_master();
+        assert!(load_yaml_params(&master, "/ns", "key: [unclosed").is_err());
+        assert!(load_yaml_params(&master, "/ns", "key: ~").is_err());
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    fn mock_service_master(available_after: std::time::Duration) -> (Master, Arc<Mutex<usize>>) {
+        let lookups = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&lookups);
+        let start = std::time::Instant::now();
+        let mut server = MockServer::default();
+        server.on("lookupService", move |_args| {
+            *counter.lock().unwrap() += 1;
+            if start.elapsed() < available_after {
+                return Err(ResponseError::Client("no provider".into()));
+            }
+            Ok(Value::String("rosrpc://127.0.0.1:1".into()))
+        });
+        let uri = server.serve();
+        (Master::new(&uri, "/waiter", "").unwrap(), lookups)
+    }
+
+    #[test]
+    fn waits_for_service_registered_later() {
+        let (master, lookups) = mock_service_master(std::time::Duration::from_millis(300));
+        let start = std::time::Instant::now();
+        wait_for_service_on(&master, &RealClock::default(), "/add_two_ints", None).unwrap();
+        assert!(start.elapsed() >= std::time::Duration::from_millis(300));
+        // Backoff keeps the number of polls well below one per 10ms
+        assert!(*lookups.lock().unwrap() < 15);
+    }
+
+    #[test]
+    fn wait_for_service_times_out() {
+        let (master, _lookups) = mock_service_master(std::time::Duration::from_secs(60));
+        let timeout = std::time::Duration::from_millis(200);
+        let start = std::time::Instant::now();
+        let err =
+            wait_for_service_on(&master, &RealClock::default(), "/add", Some(timeout)).unwrap_err();
+        match err.kind() {
+            ErrorKind::TimeoutError => {}
+            kind => panic!("Unexpected error: {}", kind),
+        }
+        assert!(start.elapsed() >= timeout);
+        assert!(start.elapsed() < timeout * 3);
+    }
+
+    fn mock_rosout_master() -> String {
+        mock_rosout_master_failing(0)
+    }
+
+    /// Master that rejects the given number of publisher registrations first.
+    fn mock_rosout_master_failing(failures: usize) -> String {
+        let publisher = Arc::new(Mutex::new(None));
+        let registered = Arc::clone(&publisher);
+        let failures = Mutex::new(failures);
+        let mut server = MockServer::default();
+        server.on("registerPublisher", move |args| {
+            let mut failures = failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(ResponseError::Server("Not ready yet".into()));
+            }
+            if let Some(Value::String(uri)) = args.get(3) {
+                *registered.lock().unwrap() = Some(uri.clone());
+            }
+            Ok(Value::Array(vec![]))
+        });
+        server.on("registerSubscriber", move |_args| {
+            let publishers = publisher.lock().unwrap().clone();
+            Ok(Value::Array(
+                publishers.into_iter().map(Value::String).collect(),
+            ))
+        });
+        server.serve()
+    }
+
+    #[test]
+    fn injected_clock_drives_rates() {
+        let mut ros =
+            Ros::new_raw("http://127.0.0.1:1/", "127.0.0.1", "/", "node", ANY_PORT).unwrap();
+        let clock = Arc::new(SimulatedClock::default());
+        clock.trigger(Time { sec: 10, nsec: 0 });
+        ros.set_clock(Arc::clone(&clock) as Arc<dyn Clock>);
+        assert_eq!(Time { sec: 10, nsec: 0 }, ros.now());
+
+        let rate = ros.rate(10.0);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let rate_clock = Arc::clone(&clock);
+        thread::spawn(move || loop {
+            rate.sleep();
+            if tx.send(rate_clock.now()).is_err() {
+                break;
+            }
+        });
+
+        let short_wait = std::time::Duration::from_millis(50);
+        let timeout = std::time::Duration::from_secs(5);
+        for tick in 1..=3 {
+            clock.advance(Duration::from_nanos(50_000_000));
+            assert!(rx.recv_timeout(short_wait).is_err());
+            clock.advance(Duration::from_nanos(50_000_000));
+            assert_eq!(
+                Time { sec: 10, nsec: 0 } + Duration::from_nanos(tick * 100_000_000),
+                rx.recv_timeout(timeout).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn log_records_are_published_to_rosout() {
+        let uri = mock_rosout_master();
+        let mut ros = Ros::new_raw(&uri, "127.0.0.1", "/", "logger", ANY_PORT).unwrap();
+        ros.start_rosout();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tx = Mutex::new(tx);
+        let _subscriber = ros
+            .subscribe("/rosout", 100, move |message: Log| {
+                tx.lock().unwrap().send(message).unwrap();
+            })
+            .unwrap();
+
+        let received = (0..100)
+            .filter_map(|_| {
+                let mut record = log::Record::builder();
+                record
+                    .level(log::Level::Warn)
+                    .file(Some("src/power.rs"))
+                    .line(Some(42));
+                ros.log_record(&record.args(format_args!("battery at {}%", 15)).build());
+                rx.recv_timeout(std::time::Duration::from_millis(50)).ok()
+            })
+            .next()
+            .expect("no /rosout publication received");
+        assert_eq!(Log::WARN, received.level);
+        assert_eq!("battery at 15%", received.msg);
+        assert_eq!("src/power.rs", received.file);
+        assert_eq!(42, received.line);
+        assert_eq!("/logger", received.name);
+    }
+
+    #[test]
+    fn static_transforms_are_latched_together() {
+        use crate::msg::geometry_msgs::TransformStamped;
+        use crate::msg::tf2_msgs::TFMessage;
+        use crate::tf::StaticTransformBroadcaster;
+
+        let uri = mock_rosout_master();
+        let ros = Ros::new_raw(&uri, "127.0.0.1", "/", "broadcaster", ANY_PORT).unwrap();
+        let mut broadcaster =
+            StaticTransformBroadcaster::from_publisher(ros.publish("/tf_static", 1).unwrap());
+        let transform = |frame_id: &str, child_frame_id: &str, x: f64| {
+            let mut transform = TransformStamped::default();
+            transform.header.frame_id = frame_id.into();
+            transform.child_frame_id = child_frame_id.into();
+            transform.transform.translation.x = x;
+            transform.transform.rotation.w = 1.0;
+            transform
+        };
+        broadcaster
+            .send_transform(transform("map", "odom", 1.0))
+            .unwrap();
+        broadcaster
+            .send_transform(transform("odom", "base_link", 0.5))
+            .unwrap();
+        assert!(broadcaster
+            .send_transform(transform("", "laser", 0.2))
+            .is_err());
+
+        // Subscribing afterwards only receives the latched message
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tx = Mutex::new(tx);
+        let _subscriber = ros
+            .subscribe("/tf_static", 1, move |message: TFMessage| {
+                tx.lock().unwrap().send(message).unwrap();
+            })
+            .unwrap();
+        let message = rx.recv_timeout(std::time::Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            vec![
+                transform("map", "odom", 1.0),
+                transform("odom", "base_link", 0.5)
+            ],
+            message.transforms
+        );
+    }
+
+    #[test]
+    fn logging_survives_rosout_being_unavailable() {
+        let uri = mock_rosout_master_failing(1);
+        let ros = Ros::new_raw(&uri, "127.0.0.1", "/", "logger", ANY_PORT).unwrap();
+        ros.log(Log::WARN, "before any rosout".into(), "src/lib.rs", 1);
+        ros.start_rosout();
+        assert!(ros.logger.lock().unwrap().is_none());
+        ros.log(Log::WARN, "before rosout is ready".into(), "src/lib.rs", 2);
+
+        let start = std::time::Instant::now();
+        while ros.logger.lock().unwrap().is_none() {
+            assert!(start.elapsed() < ROSOUT_RETRY_INITIAL_DELAY * 5);
+            ros.log(Log::INFO, "while rosout is retried".into(), "src/lib.rs", 3);
+            thread::sleep(std::time::Duration::from_millis(50));
+        }
+        ros.log(Log::WARN, "after rosout is ready".into(), "src/lib.rs", 4);
+
+        let (to_stderr, text) = terminal_line(Log::WARN, "no rosout", "src/lib.rs", 2).unwrap();
+        assert!(to_stderr);
+        assert!(text.contains("[WARN @ src/lib.rs:2]: no rosout"));
+        assert!(!terminal_line(Log::INFO, "", "", 0).unwrap().0);
+    }
+
+    #[test]
+    fn rosout_retries_do_not_keep_node_alive() {
+        let uri = mock_rosout_master_failing(usize::MAX);
+        let ros = Ros::new_raw(&uri, "127.0.0.1", "/", "logger", ANY_PORT).unwrap();
+        ros.start_rosout();
+        let logger = Arc::downgrade(&ros.logger);
+        let master = Arc::downgrade(&ros.master);
+
+        drop(ros);
+
+        assert!(logger.upgrade().is_none());
+        assert!(master.upgrade().is_none());
+    }
+
+    #[test]
+    fn subscription_statistics_are_published() {
+        use crate::msg::rosgraph_msgs::TopicStatistics;
+
+        let uri = mock_rosout_master();
+        let mut ros = Ros::new_raw(&uri, "127.0.0.1", "/", "node", ANY_PORT).unwrap();
+        ros.start_statistics(std::time::Duration::from_millis(200))
+            .unwrap();
+        let publisher = ros.publish::<Log>("/chatter", 100).unwrap();
+        let _chatter = ros.subscribe("/chatter", 100, |_: Log| {}).unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tx = Mutex::new(tx);
+        let _statistics = ros
+            .subscribe("/statistics", 100, move |message: TopicStatistics| {
+                if message.topic == "/chatter" {
+                    tx.lock().unwrap().send(message).unwrap();
+                }
+            })
+            .unwrap();
+
+        let received = (0..250)
+            .filter_map(|_| {
+                publisher.send(Log::default()).unwrap();
+                rx.recv_timeout(std::time::Duration::from_millis(20)).ok()
+            })
+            .next()
+            .expect("no /statistics publication received");
+        assert_eq!("/node", received.node_pub);
+        assert_eq!("/node", received.node_sub);
+        assert!(received.delivered_msgs > 0);
+        assert_eq!(0, received.dropped_msgs);
+        assert!(received.traffic > 0);
+        assert!(received.window_start < received.window_stop);
+        assert!(received.period_mean.seconds() < 0.2);
+        assert!(received.period_max >= received.period_mean);
+    }
+
+    #[test]
+    fn resolves_names_with_namespace_and_remappings() {
+        let mut ros = Ros::new_raw(
+            "http://127.0.0.1:1/",
+            "127.0.0.1",
+            "/robot/",
+            "node",
+            ANY_PORT,
+        )
+        .unwrap();
+        ros.map("chatter", "remapped").unwrap();
+        ros.map("~image", "/camera/image_raw").unwrap();
+
+        assert_eq!("/robot/node", ros.name());
+        assert_eq!("/robot/remapped", ros.resolve_name("chatter").unwrap());
+        assert_eq!(
+            "/robot/remapped",
+            ros.resolve_name("/robot/chatter").unwrap()
+        );
+        assert_eq!("/camera/image_raw", ros.resolve_name("~image").unwrap());
+        assert_eq!("/robot/node/rate", ros.resolve_name("~rate").unwrap());
+        assert_eq!("/robot/other", ros.resolve_name("other").unwrap());
+        assert_eq!("/chatter", ros.resolve_name("/chatter").unwrap());
+        assert!(ros.resolve_name("").is_err());
+    }
+
+    fn mock_param_master(key: &'static str, value: Value) -> String {
+        let mut server = MockServer::default();
+        server.on("getParam", move |args| match args.get(1) {
+            Some(Value::String(requested)) if requested == key => Ok(value.clone()),
+            _ => Err(ResponseError::Client("Parameter is not set".into())),
+        });
+        server.serve()
+    }
+
+    #[test]
+    fn private_params_resolve_under_node_name() {
+        let uri = mock_param_master("/robot/node/x", Value::Int(5));
+        let mut ros = Ros::new_raw(&uri, "127.0.0.1", "/robot", "node", ANY_PORT).unwrap();
+
+        let param = ros.param("~x").unwrap();
+        assert_eq!("/robot/node/x", param.name());
+        assert_eq!(5, param.get::<i32>().unwrap());
+        assert!(ros.param("x").unwrap().get::<i32>().is_err());
+
+        ros.map("~limit", "~x").unwrap();
+        assert_eq!(5, ros.param("~limit").unwrap().get::<i32>().unwrap());
+    }
+
+    fn mock_param_server(params: &[(&str, Value)]) -> String {
+        let params = params
+            .iter()
+            .map(|(key, value)| (String::from(*key), value.clone()))
+            .collect::<HashMap<_, _>>();
+        let params = Arc::new(Mutex::new(params));
+        let key = |args: &[Value]| match args.get(1) {
+            Some(Value::String(key)) => Ok(key.clone()),
+            _ => Err(ResponseError::Client("Bad arguments".into())),
+        };
+        let mut server = MockServer::default();
+        let store = Arc::clone(&params);
+        server.on("getParam", move |args| {
+            let key = key(&args)?;
+            let value = store.lock().unwrap().get(&key).cloned();
+            value.ok_or_else(|| ResponseError::Client(format!("Parameter [{}] is not set", key)))
+        });
+        let store = Arc::clone(&params);
+        server.on("setParam", move |args| {
+            let key = key(&args)?;
+            let value = args.get(2).cloned().unwrap_or(Value::Int(0));
+            store.lock().unwrap().insert(key, value);
+            Ok(Value::Int(0))
+        });
+        let store = Arc::clone(&params);
+        server.on("deleteParam", move |args| {
+            let key = key(&args)?;
+            match store.lock().unwrap().remove(&key) {
+                Some(_) => Ok(Value::Int(0)),
+                None => Err(ResponseError::Client(format!(
+                    "Parameter [{}] is not set",
+                    key
+                ))),
+            }
+        });
+        let store = Arc::clone(&params);
+        server.on("hasParam", move |args| {
+            let key = key(&args)?;
+            Ok(Value::Bool(store.lock().unwrap().contains_key(&key)))
+        });
+        let store = Arc::clone(&params);
+        server.on("getParamNames", move |_args| {
+            let mut names = store.lock().unwrap().keys().cloned().collect::<Vec<_>>();
+            names.sort();
+            Ok(Value::Array(names.into_iter().map(Value::String).collect()))
+        });
+        server.serve()
+    }
+
+    #[test]
+    fn checks_param_existence_and_lists_names() {
+        let uri = mock_param_server(&[
+            ("/robot/node/rate", Value::Int(10)),
+            ("/robot/frame", Value::String("map".into())),
+        ]);
+        let ros = Ros::new_raw(&uri, "127.0.0.1", "/robot", "node", ANY_PORT).unwrap();
+
+        assert!(ros.param_exists("~rate").unwrap());
+        assert!(ros.param_exists("frame").unwrap());
+        assert!(!ros.param_exists("/frame").unwrap());
+        assert!(ros.param_exists("").is_err());
+        assert_eq!(
+            vec!["/robot/frame", "/robot/node/rate"],
+            ros.parameters().unwrap()
+        );
+    }
+
+    #[test]
+    fn deletes_params() {
+        let uri = mock_param_server(&[]);
+        let ros = Ros::new_raw(&uri, "127.0.0.1", "/robot", "node", ANY_PORT).unwrap();
+
+        ros.param("~lock").unwrap().set(&true).unwrap();
+        assert!(ros.param("~lock").unwrap().get::<bool>().unwrap());
+        ros.delete_param("~lock").unwrap();
+        assert!(ros.param("~lock").unwrap().get::<bool>().is_err());
+        assert!(!ros.param_exists("~lock").unwrap());
+        assert!(ros.delete_param("~lock").is_err());
+    }
+
+    fn mock_introspection_master() -> String {
+        let pair = |name: &str, value: &str| {
+            Value::Array(vec![
+                Value::String(name.into()),
+                Value::String(value.into()),
+            ])
+        };
+        let entries = |name: &str, nodes: &[&str]| {
+            let nodes = nodes.iter().map(|&node| Value::String(node.into()));
+            Value::Array(vec![
+                Value::String(name.into()),
+                Value::Array(nodes.collect()),
+            ])
+        };
+        let mut server = MockServer::default();
+        server.on("lookupNode", |args| match args.get(1) {
+            Some(Value::String(name)) if name == "/talker" => {
+                Ok(Value::String("http://robot:4000/".into()))
+            }
+            _ => Err(ResponseError::Client("Unknown node".into())),
+        });
+        server.on("getPublishedTopics", move |args| {
+            let subgraph = match args.get(1) {
+                Some(Value::String(subgraph)) => subgraph.clone(),
+                _ => return Err(ResponseError::Client("Missing argument 'subgraph'".into())),
+            };
+            let topics = [
+                ("/chatter", "std_msgs/String"),
+                ("/robot/odom", "nav_msgs/Odometry"),
+            ];
+            Ok(Value::Array(
+                topics
+                    .iter()
+                    .filter(|(name, _)| name.starts_with(&subgraph))
+                    .map(|&(name, datatype)| pair(name, datatype))
+                    .collect(),
+            ))
+        });
+        server.on("getSystemState", move |_args| {
+            Ok(Value::Array(vec![
+                Value::Array(vec![entries("/chatter", &["/talker"])]),
+                Value::Array(vec![entries("/chatter", &["/listener", "/rosbag"])]),
+                Value::Array(vec![entries("/talker/get_loggers", &["/talker"])]),
+            ]))
+        });
+        server.serve()
+    }
+
+    #[test]
+    fn introspects_master() {
+        let uri = mock_introspection_master();
+        let ros = Ros::new_raw(&uri, "127.0.0.1", "/", "monitor", ANY_PORT).unwrap();
+
+        assert_eq!("http://robot:4000/", ros.lookup_node("/talker").unwrap());
+        assert!(ros.lookup_node("/missing").is_err());
+
+        let topics = ros.published_topics_on_master("").unwrap();
+        let topics = topics
+            .iter()
+            .map(|topic| (topic.name.as_str(), topic.datatype.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("/chatter", "std_msgs/String"),
+                ("/robot/odom", "nav_msgs/Odometry"),
+            ],
+            topics
+        );
+        let topics = ros.published_topics_on_master("/robot").unwrap();
+        assert_eq!(1, topics.len());
+        assert_eq!("/robot/odom", topics[0].name);
+
+        let state = ros.state().unwrap();
+        assert_eq!(1, state.publishers.len());
+        assert_eq!("/chatter", state.publishers[0].name);
+        assert_eq!(vec!["/talker"], state.publishers[0].connections);
+        assert_eq!(
+            vec!["/listener", "/rosbag"],
+            state.subscribers[0].connections
+        );
+        assert_eq!("/talker/get_loggers", state.services[0].name);
+    }
+
+    fn mock_registration_master() -> (String, Arc<Mutex<Vec<String>>>) {
+        let callers = Arc::new(Mutex::new(vec![]));
+        let registered = Arc::clone(&callers);
+        let mut server = MockServer::default();
+        server.on("registerPublisher", move |args| {
+            if let Some(Value::String(caller_id)) = args.get(0) {
+                registered.lock().unwrap().push(caller_id.clone());
+            }
+            Ok(Value::Array(vec![]))
+        });
+        let uri = server.serve();
+        (uri, callers)
+    }
+
+    #[test]
+    #[allow(unused_variables)]
+    fn node_waits_for_unreachable_master() {
+        use crate::rosxmlrpc::mock::flaky_proxy;
+        use crate::rosxmlrpc::RetryPolicy;
+
+        let testcase = resolve::test_args::TESTCASE.lock().unwrap();
+        resolve::test_args::set_args(&vec![]);
+        std::env::remove_var("ROS_NAMESPACE");
+        let (master_uri, callers) = mock_registration_master();
+        let (uri, _connections) = flaky_proxy(&master_uri, 3);
+
+        assert!(Ros::new_with_master_and_hostname("node", &uri, "127.0.0.1").is_err());
+        assert!(callers.lock().unwrap().is_empty());
+
+        // Restores the global policy even if the node fails to start.
+        struct RestorePolicy(RetryPolicy);
+        impl Drop for RestorePolicy {
+            fn drop(&mut self) {
+                master::set_master_retry_policy(self.0.clone());
+            }
+        }
+        let _restore = RestorePolicy(master::master_retry_policy());
+        master::set_master_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: std::time::Duration::from_millis(10),
+            max_backoff: std::time::Duration::from_millis(50),
+            startup_timeout: std::time::Duration::from_secs(5),
+        });
+        let (uri, _connections) = flaky_proxy(&master_uri, 3);
+        let ros = Ros::new_with_master_and_hostname("node", &uri, "127.0.0.1");
+
+        assert!(ros.unwrap().is_ok());
+        assert_eq!(vec!["/node".to_owned()], *callers.lock().unwrap());
+    }
+
+    #[test]
+    #[allow(unused_variables)]
+    fn nodes_connect_to_injected_masters() {
+        let testcase = resolve::test_args::TESTCASE.lock().unwrap();
+        resolve::test_args::set_args(&vec![]);
+        std::env::remove_var("ROS_NAMESPACE");
+        let (first_uri, first_callers) = mock_registration_master();
+        let (second_uri, second_callers) = mock_registration_master();
+
+        let first = Ros::new_with_master_and_hostname("first", &first_uri, "127.0.0.1").unwrap();
+        let second = Ros::new_with_master_and_hostname("second", &second_uri, "127.0.0.1").unwrap();
+
+        assert_eq!(vec!["/first".to_owned()], *first_callers.lock().unwrap());
+        assert_eq!(vec!["/second".to_owned()], *second_callers.lock().unwrap());
+        assert_eq!("127.0.0.1", first.hostname());
+        assert!(second.uri().starts_with("http://127.0.0.1:"));
+    }
+
+    #[test]
+    #[allow(unused_variables)]
+    fn anonymous_nodes_register_distinct_names() {
+        let testcase = resolve::test_args::TESTCASE.lock().unwrap();
+        std::env::remove_var("ROS_NAMESPACE");
+        let (uri, callers) = mock_registration_master();
+        let master_arg = format!("__master:={}", uri);
+        resolve::test_args::set_args(&vec![&master_arg, "__hostname:=127.0.0.1"]);
+
+        let first = Ros::new_anonymous("talker").unwrap();
+        let second = Ros::new_anonymous("talker").unwrap();
+        resolve::test_args::set_args(&vec![]);
+
+        assert_ne!(first.name(), second.name());
+        assert!(first.name().starts_with("/talker_"));
+        assert_eq!(
+            format!("{}/rate", second.name()),
+            second.resolve_name("~rate").unwrap()
+        );
+        assert_eq!(
+            vec![first.name().to_owned(), second.name().to_owned()],
+            *callers.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn advertises_configured_hostname() {
+        let (uri, _callers) = mock_registration_master();
+        let ros = Ros::new_raw(&uri, "robot.local", "/", "node", ANY_PORT).unwrap();
+        assert_eq!("0.0.0.0", ros.bind_address());
+        assert!(ros.uri().starts_with("http://robot.local:"));
+        let _publisher = ros.publish::<Log>("/chatter", 1).unwrap();
+
+        let local_uri = ros.uri().replace("robot.local", "127.0.0.1");
+        let client = crate::rosxmlrpc::Client::new(&local_uri).unwrap();
+        let protocols = Value::Array(vec![Value::Array(vec![Value::String("TCPROS".into())])]);
+        let response = client
+            .request_tree_with_tree(
+                "requestTopic",
+                vec![
+                    Value::String("/listener".into()),
+                    Value::String("/chatter".into()),
+                    protocols,
+                ],
+            )
+            .unwrap();
+        match response {
+            Value::Array(ref protocol) => {
+                assert_eq!(Value::String("TCPROS".into()), protocol[0]);
+                assert_eq!(Value::String("robot.local".into()), protocol[1]);
+            }
+            value => panic!("Unexpected response: {:?}", value),
+        }
+
+        let local = Ros::new_raw(&uri, "localhost", "/", "local", ANY_PORT).unwrap();
+        assert_eq!("localhost", local.bind_address());
+        assert!(local.uri().starts_with("http://localhost:"));
+    }
+
+    #[test]
+    fn binds_servers_within_port_range() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let ros =
+            Ros::new_raw("http://127.0.0.1:1/", "127.0.0.1", "/", "node", port..=port).unwrap();
+        assert_eq!(format!("http://127.0.0.1:{}/", port), ros.uri());
+
+        let message = match ros.publish::<Log>("/chatter", 1) {
+            Ok(_) => panic!("Publisher bound outside of the port range"),
+            Err(err) => err.to_string(),
+        };
+        let expected = format!("No free port between {} and {}", port, port);
+        assert!(message.contains(&expected), "{}", message);
+    }
+
+    #[test]
+    fn shutdown_call_stops_node() {
+        let ros = Ros::new_raw("http://127.0.0.1:1/", "127.0.0.1", "/", "node", ANY_PORT).unwrap();
+        let client = crate::rosxmlrpc::Client::new(ros.uri()).unwrap();
+        assert!(ros.is_ok());
+
+        match client.request_tree_with_tree("shutdown", vec![Value::String("/rosnode".into())]) {
+            Err(ResponseError::Client(ref message)) => {
+                assert_eq!("Missing argument 'message'", message)
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+        assert!(ros.is_ok());
+
+        client
+            .request_tree_with_tree(
+                "shutdown",
+                vec![
+                    Value::String("/rosnode".into()),
+                    Value::String("killed by rosnode".into()),
+                ],
+            )
+            .unwrap();
+        let start = std::time::Instant::now();
+        while ros.is_ok() {
+            assert!(start.elapsed() < std::time::Duration::from_secs(5));
+            thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(Some("killed by rosnode".into()), ros.shutdown_reason());
+    }
+
+    #[test]
+    fn name_clash_shutdown_reports_reason() {
+        let ros = Ros::new_raw("http://127.0.0.1:1/", "127.0.0.1", "/", "node", ANY_PORT).unwrap();
+        let client = crate::rosxmlrpc::Client::new(ros.uri()).unwrap();
+        assert_eq!(None, ros.shutdown_reason());
+
+        client
+            .request_tree_with_tree(
+                "shutdown",
+                vec![
+                    Value::String("/master".into()),
+                    Value::String("[/node] Reason: new node registered with same name".into()),
+                ],
+            )
+            .unwrap();
+        let start = std::time::Instant::now();
+        while ros.is_ok() {
+            assert!(start.elapsed() < std::time::Duration::from_secs(5));
+            thread::sleep(std::time::Duration::from_millis(5));
+        }
+        let reason = ros.shutdown_reason().unwrap();
+        assert!(reason.starts_with("Another node registered with the name '/node'"));
+        assert!(reason.ends_with("new node registered with same name"));
+
+        // Later shutdowns keep the original reason
+        ros.shutdown_sender().shutdown_with_reason("other reason");
+        assert_eq!(Some(reason), ros.shutdown_reason());
+    }
+
+    #[test]
+    fn spin_returns_after_shutdown_request() {
+        use std::sync::mpsc::channel;
+
+        let ros = Ros::new_raw("http://127.0.0.1:1/", "127.0.0.1", "/", "node", ANY_PORT).unwrap();
+        assert!(ros.spin_once());
+
+        let spinner = ros.spin();
+        let (done_tx, done_rx) = channel();
+        thread::spawn(move || {
+            drop(spinner);
+            done_tx.send(()).unwrap();
+        });
+        assert!(done_rx
+            .recv_timeout(std::time::Duration::from_millis(300))
+            .is_err());
+
+        ros.shutdown_sender().shutdown();
+        done_rx
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        assert!(!ros.spin_once());
+    }
+
+    #[test]
+    fn spin_for_returns_once_clock_passes_deadline() {
+        use std::sync::mpsc::channel;
+
+        let mut ros =
+            Ros::new_raw("http://127.0.0.1:1/", "127.0.0.1", "/", "node", ANY_PORT).unwrap();
+        let clock = Arc::new(SimulatedClock::default());
+        clock.trigger(Time { sec: 10, nsec: 0 });
+        ros.set_clock(Arc::clone(&clock) as Arc<dyn Clock>);
+        let ros = Arc::new(ros);
+
+        let minute = Duration::from_seconds(60);
+        let spin_for_minute = |done_tx: std::sync::mpsc::Sender<bool>| {
+            let ros = Arc::clone(&ros);
+            thread::spawn(move || done_tx.send(ros.spin_for(minute)).unwrap());
+        };
+        let short_wait = std::time::Duration::from_millis(300);
+        let timeout = std::time::Duration::from_secs(5);
+
+        let (done_tx, done_rx) = channel();
+        spin_for_minute(done_tx.clone());
+        assert!(done_rx.recv_timeout(short_wait).is_err());
+        clock.advance(Duration::from_seconds(59));
+        assert!(done_rx.recv_timeout(short_wait).is_err());
+        clock.advance(Duration::from_seconds(1));
+        assert!(done_rx.recv_timeout(timeout).unwrap());
+
+        // Spinning can follow, and ends early on shutdown
+        spin_for_minute(done_tx);
+        assert!(done_rx.recv_timeout(short_wait).is_err());
+        ros.shutdown_sender().shutdown();
+        assert!(!done_rx.recv_timeout(timeout).unwrap());
+    }
+}
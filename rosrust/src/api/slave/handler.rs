@@ -1,12 +1,16 @@
+use super::parameters::ParamCache;
+use super::protocol::{ProtocolParams, TCPROS};
 use super::publications::PublicationsTracker;
 use super::subscriptions::SubscriptionsTracker;
 use crate::rosxmlrpc::{self, Response, ResponseError, Server};
-use crate::tcpros::Service;
-use crate::util::kill;
+use crate::tcpros::{ConnectionInfo, Service};
+use crate::util::ports::AddrInUse;
+use crate::util::{kill, FAILED_TO_LOCK};
 use log::{error, info};
 use nix::unistd::getpid;
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::convert::TryFrom;
+use std::net::{SocketAddr, TcpListener};
 use std::sync::{Arc, Mutex};
 use xml_rpc::{self, rouille, Params, Value};
 
@@ -14,9 +18,15 @@ pub struct SlaveHandler {
     pub subscriptions: SubscriptionsTracker,
     pub publications: PublicationsTracker,
     pub services: Arc<Mutex<HashMap<String, Service>>>,
+    pub parameters: ParamCache,
+    /// Reason given by the latest `shutdown` call.
+    pub shutdown_reason: Arc<Mutex<Option<String>>>,
     server: Server,
 }
 
+/// Message the master sends to a node when another one registers with its name.
+const NAME_CLASH_MESSAGE: &str = "new node registered with same name";
+
 fn unwrap_array_case(params: Params) -> Params {
     if let Some(&Value::Array(ref items)) = params.get(0) {
         return items.clone();
@@ -33,22 +43,16 @@ impl SlaveHandler {
     ) -> SlaveHandler {
         let mut server = Server::default();
 
-        server.register_value("getBusStats", "Bus stats", |_args| {
-            // TODO: implement actual stats displaying
-            Err(ResponseError::Server("Method not implemented".into()))
-        });
-
-        server.register_value("getBusInfo", "Bus info", |_args| {
-            // TODO: implement actual info displaying
-            Err(ResponseError::Server("Method not implemented".into()))
-        });
-
         let master_uri_string = String::from(master_uri);
 
         server.register_value("getMasterUri", "Master URI", move |_args| {
             Ok(Value::String(master_uri_string.clone()))
         });
 
+        let shutdown_reason = Arc::new(Mutex::new(None));
+        let reason = Arc::clone(&shutdown_reason);
+        let node_name = String::from(name);
+
         server.register_value("shutdown", "Shutdown", move |args| {
             let mut args = unwrap_array_case(args).into_iter();
             let _caller_id = args
@@ -58,7 +62,18 @@ impl SlaveHandler {
                 Some(Value::String(message)) => message,
                 _ => return Err(ResponseError::Client("Missing argument 'message'".into())),
             };
-            info!("Server is shutting down because: {}", message);
+            let message = if message.contains(NAME_CLASH_MESSAGE) {
+                let message = format!(
+                    "Another node registered with the name '{}': {}",
+                    node_name, message
+                );
+                error!("Server is shutting down because: {}", message);
+                message
+            } else {
+                info!("Server is shutting down because: {}", message);
+                message
+            };
+            *reason.lock().expect(FAILED_TO_LOCK) = Some(message);
             match shutdown_signal.send() {
                 Ok(()) => Ok(Value::Int(0)),
                 Err(err) => {
@@ -104,8 +119,45 @@ impl SlaveHandler {
             ))
         });
 
-        server.register_value("paramUpdate", "Parameter updated", |_args| {
-            // We don't do anything with parameter updates
+        let pubs = publications.clone();
+        let subs = subscriptions.clone();
+
+        server.register_value("getBusStats", "Bus stats", move |_args| {
+            Ok(BusStats::collect(&pubs, &subs).into())
+        });
+
+        let pubs = publications.clone();
+        let subs = subscriptions.clone();
+
+        server.register_value("getBusInfo", "Bus info", move |_args| {
+            Ok(Value::Array(
+                BusInfo::collect(&pubs, &subs)
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+            ))
+        });
+
+        let parameters = ParamCache::default();
+        let params = parameters.clone();
+
+        server.register_value("paramUpdate", "Parameter updated", move |args| {
+            let mut args = unwrap_array_case(args).into_iter();
+            let _caller_id = args
+                .next()
+                .ok_or_else(|| ResponseError::Client("Missing argument 'caller_id'".into()))?;
+            let key = match args.next() {
+                Some(Value::String(key)) => key,
+                _ => {
+                    return Err(ResponseError::Client(
+                        "Missing argument 'parameter_key'".into(),
+                    ));
+                }
+            };
+            let value = args.next().ok_or_else(|| {
+                ResponseError::Client("Missing argument 'parameter_value'".into())
+            })?;
+            params.update(&key, value);
             Ok(Value::Int(0))
         });
 
@@ -170,21 +222,19 @@ impl SlaveHandler {
             let port = pubs.get_port(&topic).ok_or_else(|| {
                 ResponseError::Client("Requested topic not published by node".into())
             })?;
-            let ip = hostname_string.clone();
             let mut has_tcpros = false;
             for protocol in protocols {
                 if let Value::Array(protocol) = protocol {
                     if let Some(&Value::String(ref name)) = protocol.get(0) {
-                        has_tcpros |= name == "TCPROS";
+                        has_tcpros |= name == TCPROS;
                     }
                 }
             }
             if has_tcpros {
-                Ok(Value::Array(vec![
-                    Value::String("TCPROS".into()),
-                    Value::String(ip),
-                    Value::Int(port),
-                ]))
+                let port = u16::try_from(port).map_err(|_| {
+                    ResponseError::Server(format!("Topic is published on invalid port {}", port))
+                })?;
+                Ok(ProtocolParams::tcpros(&hostname_string, port).into_value())
             } else {
                 Err(ResponseError::Server(
                     "No matching protocols available".into(),
@@ -196,6 +246,8 @@ impl SlaveHandler {
             subscriptions,
             publications,
             services: Arc::new(Mutex::new(HashMap::new())),
+            parameters,
+            shutdown_reason,
             server,
         }
     }
@@ -208,59 +260,473 @@ impl SlaveHandler {
             impl Fn(&rouille::Request) -> rouille::Response + Send + Sync + 'static,
         >,
     > {
-        self.server.bind(addr).map_err(Into::into)
+        self.server.bind(addr).map_err(|err| {
+            // The XML-RPC server does not tell why binding failed, so a taken
+            // port has to be detected separately
+            match TcpListener::bind(addr) {
+                Err(io_err) if io_err.is_addr_in_use() => io_err.into(),
+                _ => err.into(),
+            }
+        })
     }
 }
 
-#[allow(dead_code)]
 pub struct BusStats {
     pub publish: Vec<PublishStats>,
     pub subscribe: Vec<SubscribeStats>,
     pub service: ServiceStats,
 }
 
-#[allow(dead_code)]
+impl BusStats {
+    fn collect(publications: &PublicationsTracker, subscriptions: &SubscriptionsTracker) -> Self {
+        let publish = publications
+            .get_connections::<Vec<_>>()
+            .into_iter()
+            .map(|(name, connections)| PublishStats {
+                name,
+                data_sent: connections.iter().map(|v| v.bytes).sum(),
+                connection_data: connections
+                    .into_iter()
+                    .map(|v| PublishConnectionData {
+                        connection_id: v.id,
+                        bytes_sent: v.bytes,
+                        number_sent: v.messages,
+                        connected: true,
+                    })
+                    .collect(),
+            })
+            .collect();
+        let subscribe = subscriptions
+            .get_connections::<Vec<_>>()
+            .into_iter()
+            .map(|(name, connections)| SubscribeStats {
+                name,
+                connection_data: connections
+                    .into_iter()
+                    .map(|v| SubscribeConnectionData {
+                        connection_id: v.id,
+                        bytes_received: v.bytes,
+                        // Drops are only counted for the whole topic
+                        drop_estimate: -1,
+                        connected: true,
+                    })
+                    .collect(),
+            })
+            .collect();
+        BusStats {
+            publish,
+            subscribe,
+            service: ServiceStats::default(),
+        }
+    }
+}
+
+impl From<BusStats> for Value {
+    fn from(stats: BusStats) -> Value {
+        let publish = stats
+            .publish
+            .into_iter()
+            .map(|topic| {
+                let connections = topic
+                    .connection_data
+                    .into_iter()
+                    .map(|v| {
+                        Value::Array(vec![
+                            int_value(v.connection_id),
+                            int_value(v.bytes_sent),
+                            int_value(v.number_sent),
+                            Value::Bool(v.connected),
+                        ])
+                    })
+                    .collect();
+                Value::Array(vec![
+                    Value::String(topic.name),
+                    int_value(topic.data_sent),
+                    Value::Array(connections),
+                ])
+            })
+            .collect();
+        let subscribe = stats
+            .subscribe
+            .into_iter()
+            .map(|topic| {
+                let connections = topic
+                    .connection_data
+                    .into_iter()
+                    .map(|v| {
+                        Value::Array(vec![
+                            int_value(v.connection_id),
+                            int_value(v.bytes_received),
+                            Value::Int(v.drop_estimate),
+                            Value::Bool(v.connected),
+                        ])
+                    })
+                    .collect();
+                Value::Array(vec![Value::String(topic.name), Value::Array(connections)])
+            })
+            .collect();
+        let service = Value::Array(vec![
+            int_value(stats.service.number_of_requests),
+            int_value(stats.service.bytes_received),
+            int_value(stats.service.bytes_sent),
+        ]);
+        Value::Array(vec![
+            Value::Array(publish),
+            Value::Array(subscribe),
+            service,
+        ])
+    }
+}
+
+/// XML-RPC integers are 32 bit, so bigger counters saturate.
+fn int_value(value: usize) -> Value {
+    Value::Int(i32::try_from(value).unwrap_or(i32::MAX))
+}
+
 pub struct PublishStats {
     pub name: String,
-    pub data_sent: String,
-    pub connection_data: PublishConnectionData,
+    pub data_sent: usize,
+    pub connection_data: Vec<PublishConnectionData>,
 }
 
-#[allow(dead_code)]
 pub struct PublishConnectionData {
-    pub connection_id: String,
-    pub bytes_sent: i32,
-    pub number_sent: i32,
+    pub connection_id: usize,
+    pub bytes_sent: usize,
+    pub number_sent: usize,
     pub connected: bool,
 }
 
-#[allow(dead_code)]
 pub struct SubscribeStats {
     pub name: String,
-    pub connection_data: SubscribeConnectionData,
+    pub connection_data: Vec<SubscribeConnectionData>,
 }
 
-#[allow(dead_code)]
 pub struct SubscribeConnectionData {
-    pub connection_id: String,
-    pub bytes_received: i32,
+    pub connection_id: usize,
+    pub bytes_received: usize,
     pub drop_estimate: i32,
     pub connected: bool,
 }
 
-#[allow(dead_code)]
+#[derive(Default)]
 pub struct ServiceStats {
-    pub number_of_requests: i32,
-    pub bytes_received: i32,
-    pub bytes_sent: i32,
+    pub number_of_requests: usize,
+    pub bytes_received: usize,
+    pub bytes_sent: usize,
 }
 
-#[allow(dead_code)]
 pub struct BusInfo {
-    pub connection_id: String,
+    pub connection_id: usize,
     pub destination_id: String,
     pub direction: String,
     pub transport: String,
     pub topic: String,
     pub connected: bool,
 }
+
+impl BusInfo {
+    /// Lists outbound connections to subscribers, followed by inbound connections to publishers.
+    fn collect(
+        publications: &PublicationsTracker,
+        subscriptions: &SubscriptionsTracker,
+    ) -> Vec<Self> {
+        let outbound = publications
+            .get_connections::<Vec<_>>()
+            .into_iter()
+            .flat_map(|(topic, connections)| BusInfo::from_connections(topic, connections, "o"));
+        let inbound = subscriptions
+            .get_connections::<Vec<_>>()
+            .into_iter()
+            .flat_map(|(topic, connections)| BusInfo::from_connections(topic, connections, "i"));
+        outbound.chain(inbound).collect()
+    }
+
+    fn from_connections(
+        topic: String,
+        connections: Vec<ConnectionInfo>,
+        direction: &'static str,
+    ) -> impl Iterator<Item = Self> {
+        connections.into_iter().map(move |connection| BusInfo {
+            connection_id: connection.id,
+            destination_id: connection.peer,
+            direction: direction.into(),
+            transport: connection.transport.into(),
+            topic: topic.clone(),
+            connected: true,
+        })
+    }
+}
+
+impl From<BusInfo> for Value {
+    fn from(info: BusInfo) -> Value {
+        Value::Array(vec![
+            int_value(info.connection_id),
+            Value::String(info.destination_id),
+            Value::String(info.direction),
+            Value::String(info.transport),
+            Value::String(info.topic),
+            Value::Bool(info.connected),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::clock::RealClock;
+    use crate::msg::rosgraph_msgs::Clock;
+    use crate::rosmsg::RosMsg;
+    use crate::rosxmlrpc::mock::serve_bound;
+    use crate::tcpros::{header, Message, MessageType};
+    use crate::util::ports::ANY_PORT;
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use xml_rpc::Url;
+
+    fn new_handler() -> SlaveHandler {
+        let (shutdown_tx, _shutdown_rx) = kill::channel(kill::KillMode::Sync);
+        SlaveHandler::new("http://localhost:11311/", "localhost", "/node", shutdown_tx)
+    }
+
+    fn serve(handler: SlaveHandler) -> Url {
+        let bound = handler.bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        serve_bound(bound).parse().unwrap()
+    }
+
+    fn subscribe(port: u16, caller_id: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut fields = HashMap::<String, String>::new();
+        fields.insert(String::from("callerid"), String::from(caller_id));
+        fields.insert(String::from("topic"), String::from("/clock"));
+        fields.insert(String::from("md5sum"), Clock::md5sum());
+        fields.insert(String::from("type"), Clock::msg_type());
+        header::encode(&mut stream, &fields).unwrap();
+        header::decode(&mut stream).unwrap();
+        stream
+    }
+
+    fn wait_for<F: Fn() -> bool>(condition: F) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < Duration::from_secs(5), "Timed out");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn reports_taken_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let err = new_handler()
+            .bind(&listener.local_addr().unwrap())
+            .err()
+            .unwrap();
+        assert!(err.is_addr_in_use(), "{}", err);
+    }
+
+    #[test]
+    fn param_update_call_refreshes_cache() {
+        let handler = new_handler();
+        let parameters = handler.parameters.clone();
+        parameters.add("/rate");
+        parameters.update("/rate", Value::Int(10));
+
+        let uri = serve(handler);
+
+        let response = xml_rpc::call_value(
+            &uri,
+            "paramUpdate",
+            vec![
+                Value::String("/master".into()),
+                Value::String("/rate/".into()),
+                Value::Int(20),
+            ],
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            vec![
+                Value::Int(1),
+                Value::String("Parameter updated".into()),
+                Value::Int(0),
+            ],
+            remove_wrappers(response)
+        );
+        assert_eq!(Some(Value::Int(20)), parameters.get("/rate"));
+    }
+
+    #[test]
+    fn master_uri_call_returns_configured_master() {
+        let uri = serve(new_handler());
+
+        let response = xml_rpc::call_value(
+            &uri,
+            "getMasterUri",
+            vec![Value::String("/rostopic".into())],
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            vec![
+                Value::Int(1),
+                Value::String("Master URI".into()),
+                Value::String("http://localhost:11311/".into()),
+            ],
+            remove_wrappers(response)
+        );
+    }
+
+    fn remove_wrappers(mut params: Params) -> Params {
+        while let [Value::Array(..)] = params[..] {
+            params = unwrap_array_case(params);
+        }
+        params
+    }
+
+    #[test]
+    fn bus_stats_count_published_bytes() {
+        let handler = new_handler();
+        let publications = handler.publications.clone();
+        let stream = publications
+            .add::<Clock>(
+                "127.0.0.1",
+                &ANY_PORT,
+                "/clock",
+                1,
+                "/node",
+                MessageType::of::<Clock>(),
+                None,
+            )
+            .unwrap();
+        let port = publications.get_port("/clock").unwrap() as u16;
+        let uri = serve(handler);
+
+        let _subscriber = subscribe(port, "/listener");
+        wait_for(|| stream.subscriber_count() == 1);
+        let message = Clock::default();
+        let length = message.encode_vec().unwrap().len();
+        let connection = || {
+            publications
+                .get_connections::<Vec<_>>()
+                .remove(0)
+                .1
+                .remove(0)
+        };
+        for sent in 1..=3 {
+            stream.send(&message).unwrap();
+            // Waiting keeps the queue of one message from dropping any
+            wait_for(|| connection().bytes == sent * length);
+        }
+        let length = length as i32;
+
+        let response =
+            xml_rpc::call_value(&uri, "getBusStats", vec![Value::String("/master".into())])
+                .unwrap()
+                .unwrap();
+        let response = remove_wrappers(response);
+        assert_eq!(Value::Int(1), response[0]);
+        let (publish, subscribe, service) = match response[2] {
+            Value::Array(ref stats) => (stats[0].clone(), stats[1].clone(), stats[2].clone()),
+            ref value => panic!("Unexpected stats: {:?}", value),
+        };
+        let connection_id = Value::Int(connection().id as i32);
+        assert_eq!(
+            Value::Array(vec![Value::Array(vec![
+                Value::String("/clock".into()),
+                Value::Int(3 * length),
+                Value::Array(vec![Value::Array(vec![
+                    connection_id,
+                    Value::Int(3 * length),
+                    Value::Int(3),
+                    Value::Bool(true),
+                ])]),
+            ])]),
+            publish
+        );
+        assert_eq!(Value::Array(vec![]), subscribe);
+        assert_eq!(
+            Value::Array(vec![Value::Int(0), Value::Int(0), Value::Int(0)]),
+            service
+        );
+    }
+
+    #[test]
+    fn bus_info_lists_active_connections() {
+        let (shutdown_tx, _shutdown_rx) = kill::channel(kill::KillMode::Sync);
+        let talker = SlaveHandler::new(
+            "http://localhost:11311/",
+            "127.0.0.1",
+            "/talker",
+            shutdown_tx,
+        );
+        let publications = talker.publications.clone();
+        let _stream = publications
+            .add::<Clock>(
+                "127.0.0.1",
+                &ANY_PORT,
+                "/clock",
+                1,
+                "/talker",
+                MessageType::of::<Clock>(),
+                None,
+            )
+            .unwrap();
+        let talker_uri = serve(talker);
+
+        let listener = new_handler();
+        let subscriptions = listener.subscriptions.clone();
+        subscriptions
+            .add::<Clock, _>(
+                "/listener",
+                "/clock",
+                1,
+                Arc::new(RealClock::default()),
+                MessageType::of::<Clock>(),
+                None,
+                |_, _| {},
+            )
+            .unwrap();
+        let listener_uri = serve(listener);
+        subscriptions
+            .add_publishers(
+                "/clock",
+                "/listener",
+                vec![talker_uri.to_string()].into_iter(),
+            )
+            .unwrap();
+
+        let inbound = || subscriptions.get_connections::<Vec<_>>().remove(0).1;
+        let outbound = || publications.get_connections::<Vec<_>>().remove(0).1;
+        wait_for(|| !inbound().is_empty() && !outbound().is_empty());
+
+        let bus_info = |uri: &Url| {
+            let response =
+                xml_rpc::call_value(uri, "getBusInfo", vec![Value::String("/master".into())])
+                    .unwrap()
+                    .unwrap();
+            remove_wrappers(response)[2].clone()
+        };
+        assert_eq!(
+            Value::Array(vec![Value::Array(vec![
+                Value::Int(inbound()[0].id as i32),
+                Value::String(talker_uri.to_string()),
+                Value::String("i".into()),
+                Value::String("TCPROS".into()),
+                Value::String("/clock".into()),
+                Value::Bool(true),
+            ])]),
+            bus_info(&listener_uri)
+        );
+        assert_eq!(
+            Value::Array(vec![Value::Array(vec![
+                Value::Int(outbound()[0].id as i32),
+                Value::String("/listener".into()),
+                Value::String("o".into()),
+                Value::String("TCPROS".into()),
+                Value::String("/clock".into()),
+                Value::Bool(true),
+            ])]),
+            bus_info(&talker_uri)
+        );
+    }
+}
@@ -1,25 +1,37 @@
 mod handler;
+mod parameters;
+mod protocol;
 mod publications;
 mod subscriptions;
 
 use self::handler::SlaveHandler;
+pub use self::protocol::ProtocolParams;
 use super::error::{self, ErrorKind, Result};
 use crate::api::ShutdownManager;
-use crate::tcpros::{Message, PublisherStream, Service, ServicePair, ServiceResult};
+use crate::tcpros::{
+    Message, MessageEvent, MessageType, PublisherStream, Service, ServicePair, TcpKeepalive,
+};
+use crate::util::ports::bind_in_range;
 use crate::util::{kill, FAILED_TO_LOCK};
+use crate::Clock;
 use crossbeam::channel::TryRecvError;
 use log::error;
 use std::collections::HashMap;
+use std::fmt;
+use std::ops::RangeInclusive;
 use std::sync::{Arc, Mutex};
 use std::thread;
 
 pub struct Slave {
     name: String,
     uri: String,
+    ports: RangeInclusive<u16>,
     pub publications: publications::PublicationsTracker,
     pub subscriptions: subscriptions::SubscriptionsTracker,
     pub services: Arc<Mutex<HashMap<String, Service>>>,
+    pub parameters: parameters::ParamCache,
     pub shutdown_tx: kill::Sender,
+    tcp_keepalive: Mutex<Option<TcpKeepalive>>,
 }
 
 type SerdeResult<T> = Result<T>;
@@ -29,25 +41,40 @@ impl Slave {
         master_uri: &str,
         hostname: &str,
         bind_address: &str,
-        port: u16,
+        ports: RangeInclusive<u16>,
         name: &str,
         shutdown_manager: Arc<ShutdownManager>,
     ) -> Result<Slave> {
         use std::net::ToSocketAddrs;
 
         let (shutdown_tx, shutdown_rx) = kill::channel(kill::KillMode::Sync);
-        let handler = SlaveHandler::new(master_uri, hostname, name, shutdown_tx.clone());
-        let publications = handler.publications.clone();
-        let subscriptions = handler.subscriptions.clone();
-        let services = Arc::clone(&handler.services);
-        let socket_addr = match (bind_address, port).to_socket_addrs()?.next() {
+        let mut socket_addr = match (bind_address, 0).to_socket_addrs()?.next() {
             Some(socket_addr) => socket_addr,
             None => bail!(error::ErrorKind::from(error::rosxmlrpc::ErrorKind::BadUri(
-                format!("{}:{}", hostname, port)
+                String::from(bind_address)
             ))),
         };
 
-        let bound_handler = handler.bind(&socket_addr)?;
+        // Binding consumes the handler, so every attempt needs a new one
+        let (publications, subscriptions, services, parameters, shutdown_reason, bound_handler) =
+            bind_in_range(&ports, |port| -> Result<_> {
+                let handler = SlaveHandler::new(master_uri, hostname, name, shutdown_tx.clone());
+                let publications = handler.publications.clone();
+                let subscriptions = handler.subscriptions.clone();
+                let services = Arc::clone(&handler.services);
+                let parameters = handler.parameters.clone();
+                let shutdown_reason = Arc::clone(&handler.shutdown_reason);
+                socket_addr.set_port(port);
+                let bound_handler = handler.bind(&socket_addr)?;
+                Ok((
+                    publications,
+                    subscriptions,
+                    services,
+                    parameters,
+                    shutdown_reason,
+                    bound_handler,
+                ))
+            })?;
 
         let port = bound_handler.local_addr().port();
         let uri = format!("http://{}:{}/", hostname, port);
@@ -62,16 +89,22 @@ impl Slave {
                 // TODO: use a timed out poll once rouille provides it
                 std::thread::sleep(std::time::Duration::from_millis(5));
             }
-            shutdown_manager.shutdown();
+            match shutdown_reason.lock().expect(FAILED_TO_LOCK).take() {
+                Some(reason) => shutdown_manager.shutdown_with_reason(&reason),
+                None => shutdown_manager.shutdown(),
+            }
         });
 
         Ok(Slave {
             name: String::from(name),
             uri,
+            ports,
             publications,
             subscriptions,
             services,
+            parameters,
             shutdown_tx,
+            tcp_keepalive: Mutex::new(None),
         })
     }
 
@@ -80,6 +113,17 @@ impl Slave {
         &self.uri
     }
 
+    /// Keepalive of connections to topics and services added afterwards.
+    #[inline]
+    pub fn set_tcp_keepalive(&self, keepalive: Option<TcpKeepalive>) {
+        *self.tcp_keepalive.lock().expect(FAILED_TO_LOCK) = keepalive;
+    }
+
+    #[inline]
+    pub fn tcp_keepalive(&self) -> Option<TcpKeepalive> {
+        *self.tcp_keepalive.lock().expect(FAILED_TO_LOCK)
+    }
+
     pub fn add_publishers_to_subscription<T>(&self, topic: &str, publishers: T) -> SerdeResult<()>
     where
         T: Iterator<Item = String>,
@@ -88,16 +132,21 @@ impl Slave {
             .add_publishers(topic, &self.name, publishers)
     }
 
-    pub fn add_service<T, F>(
+    pub fn add_service<T, E, F>(
         &self,
         hostname: &str,
         bind_address: &str,
         service: &str,
+        workers: usize,
         handler: F,
     ) -> SerdeResult<String>
     where
         T: ServicePair,
-        F: Fn(T::Request) -> ServiceResult<T::Response> + Send + Sync + 'static,
+        E: fmt::Display,
+        F: Fn(T::Request, &HashMap<String, String>) -> std::result::Result<T::Response, E>
+            + Send
+            + Sync
+            + 'static,
     {
         use std::collections::hash_map::Entry;
         match self
@@ -111,8 +160,21 @@ impl Slave {
                 Err(ErrorKind::Duplicate("service".into()).into())
             }
             Entry::Vacant(entry) => {
-                let service =
-                    Service::new::<T, _>(hostname, bind_address, 0, service, &self.name, handler)?;
+                let handler = Arc::new(handler);
+                let keepalive = self.tcp_keepalive();
+                let service = bind_in_range(&self.ports, |port| {
+                    let handler = Arc::clone(&handler);
+                    Service::new::<T, E, _>(
+                        hostname,
+                        bind_address,
+                        port,
+                        service,
+                        &self.name,
+                        workers,
+                        keepalive,
+                        move |request, header: &HashMap<String, String>| handler(request, header),
+                    )
+                })?;
                 let api = service.api.clone();
                 entry.insert(service);
                 Ok(api)
@@ -131,12 +193,20 @@ impl Slave {
         hostname: &str,
         topic: &str,
         queue_size: usize,
+        message_type: MessageType,
     ) -> error::tcpros::Result<PublisherStream<T>>
     where
         T: Message,
     {
-        self.publications
-            .add(hostname, topic, queue_size, &self.name)
+        self.publications.add(
+            hostname,
+            &self.ports,
+            topic,
+            queue_size,
+            &self.name,
+            message_type,
+            self.tcp_keepalive(),
+        )
     }
 
     #[inline]
@@ -145,18 +215,32 @@ impl Slave {
     }
 
     #[inline]
-    pub fn add_subscription<T, F>(&self, topic: &str, queue_size: usize, callback: F) -> Result<()>
+    pub fn add_subscription<T, F>(
+        &self,
+        topic: &str,
+        queue_size: usize,
+        clock: Arc<dyn Clock>,
+        message_type: MessageType,
+        callback: F,
+    ) -> Result<usize>
     where
         T: Message,
-        F: Fn(T, &str) + Send + 'static,
+        F: Fn(Arc<T>, &MessageEvent) + Send + 'static,
     {
-        self.subscriptions
-            .add(&self.name, topic, queue_size, callback)
+        self.subscriptions.add(
+            &self.name,
+            topic,
+            queue_size,
+            clock,
+            message_type,
+            self.tcp_keepalive(),
+            callback,
+        )
     }
 
     #[inline]
-    pub fn remove_subscription(&self, topic: &str) {
-        self.subscriptions.remove(topic)
+    pub fn remove_subscription(&self, topic: &str, id: usize) -> bool {
+        self.subscriptions.remove(topic, id)
     }
 
     #[inline]
@@ -168,4 +252,39 @@ impl Slave {
     pub fn get_publisher_uris_of_subscription(&self, topic: &str) -> Vec<String> {
         self.subscriptions.publisher_uris(topic)
     }
+
+    #[inline]
+    pub fn add_cached_parameter(&self, key: &str) -> bool {
+        self.parameters.add(key)
+    }
+
+    #[inline]
+    pub fn remove_cached_parameter(&self, key: &str) -> bool {
+        self.parameters.remove(key)
+    }
+
+    #[inline]
+    pub fn set_cached_parameter(&self, key: &str, value: xml_rpc::Value) {
+        self.parameters.update(key, value)
+    }
+
+    #[inline]
+    pub fn get_cached_parameter(&self, key: &str) -> Option<xml_rpc::Value> {
+        self.parameters.get(key)
+    }
+
+    #[inline]
+    pub fn get_dropped_message_count_of_subscription(&self, topic: &str) -> usize {
+        self.subscriptions.dropped_message_count(topic)
+    }
+
+    #[inline]
+    pub fn set_transport_compression_of_subscription(&self, topic: &str, enabled: bool) {
+        self.subscriptions.set_transport_compression(topic, enabled)
+    }
+
+    #[inline]
+    pub fn set_udp_transport_of_subscription(&self, topic: &str, enabled: bool) {
+        self.subscriptions.set_udp_transport(topic, enabled)
+    }
 }
@@ -0,0 +1,227 @@
+use crate::util::FAILED_TO_LOCK;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use xml_rpc::Value;
+
+#[derive(Clone, Default)]
+pub struct ParamCache {
+    mapping: Arc<Mutex<HashMap<String, CachedValue>>>,
+}
+
+struct CachedValue {
+    handles: usize,
+    value: Option<Value>,
+}
+
+impl ParamCache {
+    /// Registers interest in a parameter, returning `true` if it was not cached before.
+    pub fn add(&self, key: &str) -> bool {
+        let mut mapping = self.mapping.lock().expect(FAILED_TO_LOCK);
+        let entry = mapping.entry(clean_key(key).into()).or_insert(CachedValue {
+            handles: 0,
+            value: None,
+        });
+        entry.handles += 1;
+        entry.handles == 1
+    }
+
+    /// Drops interest in a parameter, returning `true` if it is no longer cached.
+    pub fn remove(&self, key: &str) -> bool {
+        let key = clean_key(key);
+        let mut mapping = self.mapping.lock().expect(FAILED_TO_LOCK);
+        let last = match mapping.get_mut(key) {
+            Some(entry) => {
+                entry.handles -= 1;
+                entry.handles == 0
+            }
+            None => return false,
+        };
+        if last {
+            mapping.remove(key);
+        }
+        last
+    }
+
+    #[inline]
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.mapping
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .get(clean_key(key))
+            .and_then(|entry| entry.value.clone())
+    }
+
+    /// Applies a value pushed by the master for `key`.
+    ///
+    /// The master reports deleted parameters as empty structs, and can send
+    /// updates for a namespace that contains a cached parameter, or for a
+    /// parameter nested inside a cached namespace.
+    pub fn update(&self, key: &str, value: Value) {
+        let key = clean_key(key);
+        let value = match value {
+            Value::Struct(ref items) if items.is_empty() => None,
+            value => Some(value),
+        };
+        for (cached_key, entry) in self.mapping.lock().expect(FAILED_TO_LOCK).iter_mut() {
+            if cached_key == key {
+                entry.value = value.clone();
+            } else if let Some(path) = subpath(key, cached_key) {
+                entry.value = value.as_ref().and_then(|v| lookup(v, &path)).cloned();
+            } else if let Some(path) = subpath(cached_key, key) {
+                let root = entry
+                    .value
+                    .get_or_insert_with(|| Value::Struct(HashMap::new()));
+                replace(root, &path, value.clone());
+            }
+        }
+    }
+}
+
+fn clean_key(key: &str) -> &str {
+    match key.trim_end_matches('/') {
+        "" => "/",
+        key => key,
+    }
+}
+
+fn subpath<'a>(parent: &str, child: &'a str) -> Option<Vec<&'a str>> {
+    let rest = child.strip_prefix(parent)?;
+    if parent != "/" && !rest.starts_with('/') {
+        return None;
+    }
+    Some(rest.split('/').filter(|v| !v.is_empty()).collect())
+}
+
+fn lookup<'a>(value: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, item| match *value {
+        Value::Struct(ref items) => items.get(*item),
+        _ => None,
+    })
+}
+
+fn replace(value: &mut Value, path: &[&str], new_value: Option<Value>) {
+    let (last, path) = match path.split_last() {
+        Some(v) => v,
+        None => return,
+    };
+    let mut value = value;
+    for item in path {
+        value = as_struct(value)
+            .entry((*item).into())
+            .or_insert_with(|| Value::Struct(HashMap::new()));
+    }
+    let items = as_struct(value);
+    match new_value {
+        Some(new_value) => {
+            items.insert((*last).into(), new_value);
+        }
+        None => {
+            items.remove(*last);
+        }
+    }
+}
+
+fn as_struct(value: &mut Value) -> &mut HashMap<String, Value> {
+    if !matches!(*value, Value::Struct(..)) {
+        *value = Value::Struct(HashMap::new());
+    }
+    match *value {
+        Value::Struct(ref mut items) => items,
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn structure(items: Vec<(&str, Value)>) -> Value {
+        Value::Struct(
+            items
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn tracks_only_added_keys() {
+        let cache = ParamCache::default();
+        cache.update("/foo", Value::Int(3));
+        assert_eq!(None, cache.get("/foo"));
+
+        assert!(cache.add("/foo"));
+        cache.update("/foo/", Value::Int(4));
+        assert_eq!(Some(Value::Int(4)), cache.get("/foo"));
+    }
+
+    #[test]
+    fn keeps_value_until_last_handle_removed() {
+        let cache = ParamCache::default();
+        assert!(cache.add("/foo"));
+        assert!(!cache.add("/foo"));
+        cache.update("/foo", Value::Bool(true));
+
+        assert!(!cache.remove("/foo"));
+        assert_eq!(Some(Value::Bool(true)), cache.get("/foo"));
+        assert!(cache.remove("/foo"));
+        assert_eq!(None, cache.get("/foo"));
+        assert!(!cache.remove("/foo"));
+    }
+
+    #[test]
+    fn empty_struct_deletes_value() {
+        let cache = ParamCache::default();
+        cache.add("/foo");
+        cache.update("/foo", Value::Int(1));
+        cache.update("/foo", Value::Struct(HashMap::new()));
+        assert_eq!(None, cache.get("/foo"));
+    }
+
+    #[test]
+    fn namespace_update_reaches_nested_key() {
+        let cache = ParamCache::default();
+        cache.add("/ns/a/b");
+        cache.update(
+            "/ns/",
+            structure(vec![("a", structure(vec![("b", Value::Int(7))]))]),
+        );
+        assert_eq!(Some(Value::Int(7)), cache.get("/ns/a/b"));
+
+        cache.update("/ns", structure(vec![("c", Value::Int(1))]));
+        assert_eq!(None, cache.get("/ns/a/b"));
+    }
+
+    #[test]
+    fn nested_update_patches_namespace() {
+        let cache = ParamCache::default();
+        cache.add("/ns");
+        cache.update("/ns", structure(vec![("a", Value::Int(1))]));
+        cache.update("/ns/b/c", Value::Int(2));
+        assert_eq!(
+            Some(structure(vec![
+                ("a", Value::Int(1)),
+                ("b", structure(vec![("c", Value::Int(2))])),
+            ])),
+            cache.get("/ns")
+        );
+
+        cache.update("/ns/a", Value::Struct(HashMap::new()));
+        assert_eq!(
+            Some(structure(vec![(
+                "b",
+                structure(vec![("c", Value::Int(2))])
+            )])),
+            cache.get("/ns")
+        );
+    }
+
+    #[test]
+    fn sibling_prefix_is_not_nested() {
+        let cache = ParamCache::default();
+        cache.add("/foo");
+        cache.update("/foo", Value::Int(1));
+        cache.update("/foobar", Value::Int(2));
+        assert_eq!(Some(Value::Int(1)), cache.get("/foo"));
+    }
+}
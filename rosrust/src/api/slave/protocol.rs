@@ -0,0 +1,161 @@
+use crate::api::error::{ErrorKind, Result};
+use std::convert::TryFrom;
+use xml_rpc::{Params, Value};
+
+pub const TCPROS: &str = "TCPROS";
+
+/// Protocol chosen by a publisher in response to `requestTopic`.
+///
+/// Every protocol starts with the name, host and port. Further parameters
+/// depend on the protocol, like the connection ID and maximum datagram size
+/// of UDPROS, and are kept as they are.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProtocolParams {
+    pub name: String,
+    pub hostname: String,
+    pub port: u16,
+    pub extra: Vec<Value>,
+}
+
+impl ProtocolParams {
+    pub fn tcpros(hostname: &str, port: u16) -> Self {
+        Self {
+            name: TCPROS.into(),
+            hostname: hostname.into(),
+            port,
+            extra: vec![],
+        }
+    }
+
+    /// Parses the full `requestTopic` response, including its status code and message.
+    pub fn from_response(params: Params) -> Result<Self> {
+        let mut values = match <[Value; 1]>::try_from(params) {
+            Ok([Value::Array(values)]) => values.into_iter(),
+            _ => bail!(malformed("response is not a single array")),
+        };
+        match (values.next(), values.next(), values.next()) {
+            (Some(Value::Int(1)), _, Some(protocol)) => Self::from_value(protocol),
+            (Some(Value::Int(_)), Some(Value::String(message)), _) => {
+                bail!(ErrorKind::CommunicationIssue(format!(
+                    "Publisher refused topic connection: {}",
+                    message
+                )))
+            }
+            _ => bail!(malformed("missing status code or message")),
+        }
+    }
+
+    /// Parses the protocol parameters, given without the status code and message.
+    pub fn from_value(value: Value) -> Result<Self> {
+        let mut values = match value {
+            Value::Array(values) => values.into_iter(),
+            _ => bail!(malformed("protocol parameters are not an array")),
+        };
+        let (name, hostname, port) = match (values.next(), values.next(), values.next()) {
+            (Some(Value::String(name)), Some(Value::String(hostname)), Some(Value::Int(port))) => {
+                (name, hostname, port)
+            }
+            _ => bail!(malformed("expected protocol name, host and port")),
+        };
+        let port = u16::try_from(port)
+            .map_err(|_| malformed(&format!("port {} is out of range", port)))?;
+        Ok(Self {
+            name,
+            hostname,
+            port,
+            extra: values.collect(),
+        })
+    }
+
+    pub fn into_value(self) -> Value {
+        let mut values = vec![
+            Value::String(self.name),
+            Value::String(self.hostname),
+            Value::Int(i32::from(self.port)),
+        ];
+        values.extend(self.extra);
+        Value::Array(values)
+    }
+}
+
+fn malformed(details: &str) -> ErrorKind {
+    ErrorKind::CommunicationIssue(format!("Malformed requestTopic response: {}", details))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(code: i32, message: &str, protocol: Value) -> Params {
+        vec![Value::Array(vec![
+            Value::Int(code),
+            Value::String(message.into()),
+            protocol,
+        ])]
+    }
+
+    #[test]
+    fn parses_tcpros_response() {
+        let protocol = Value::Array(vec![
+            Value::String("TCPROS".into()),
+            Value::String("talker.local".into()),
+            Value::Int(41234),
+        ]);
+        let params = ProtocolParams::from_response(response(
+            1,
+            "ready on talker.local:41234",
+            protocol.clone(),
+        ))
+        .unwrap();
+        assert_eq!(ProtocolParams::tcpros("talker.local", 41234), params);
+        assert_eq!(protocol, params.into_value());
+    }
+
+    #[test]
+    fn keeps_protocol_specific_parameters() {
+        let extra = vec![
+            Value::Int(7),
+            Value::Int(1500),
+            Value::Base64(vec![1, 2, 3]),
+        ];
+        let mut values = vec![
+            Value::String("UDPROS".into()),
+            Value::String("10.0.0.5".into()),
+            Value::Int(52000),
+        ];
+        values.extend(extra.clone());
+        let params = ProtocolParams::from_response(response(1, "", Value::Array(values))).unwrap();
+        assert_eq!("UDPROS", params.name);
+        assert_eq!("10.0.0.5", params.hostname);
+        assert_eq!(52000, params.port);
+        assert_eq!(extra, params.extra);
+    }
+
+    #[test]
+    fn rejects_failures_and_malformed_responses() {
+        let err = ProtocolParams::from_response(response(
+            0,
+            "No matching protocols available",
+            Value::Int(0),
+        ))
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("No matching protocols available"),
+            "{}",
+            err
+        );
+
+        let bad_port = Value::Array(vec![
+            Value::String("TCPROS".into()),
+            Value::String("localhost".into()),
+            Value::Int(70000),
+        ]);
+        assert!(ProtocolParams::from_value(bad_port).is_err());
+        let missing_port = Value::Array(vec![
+            Value::String("TCPROS".into()),
+            Value::String("localhost".into()),
+        ]);
+        assert!(ProtocolParams::from_value(missing_port).is_err());
+        assert!(ProtocolParams::from_response(vec![]).is_err());
+    }
+}
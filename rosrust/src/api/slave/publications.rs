@@ -1,9 +1,11 @@
 use crate::api::error;
-use crate::tcpros::{Publisher, PublisherStream, Topic};
+use crate::tcpros::{ConnectionInfo, MessageType, Publisher, PublisherStream, TcpKeepalive, Topic};
+use crate::util::ports::bind_in_range;
 use crate::util::FAILED_TO_LOCK;
 use crate::Message;
 use std::collections::HashMap;
 use std::iter::FromIterator;
+use std::ops::RangeInclusive;
 use std::sync::{Arc, Mutex};
 
 #[derive(Clone, Default)]
@@ -33,6 +35,16 @@ impl PublicationsTracker {
             .collect()
     }
 
+    /// Subscriber connections of every published topic.
+    pub fn get_connections<T: FromIterator<(String, Vec<ConnectionInfo>)>>(&self) -> T {
+        self.mapping
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .iter()
+            .map(|(topic, publisher)| (topic.clone(), publisher.connections()))
+            .collect()
+    }
+
     #[inline]
     pub fn get_port(&self, topic: &str) -> Option<i32> {
         self.mapping
@@ -42,12 +54,16 @@ impl PublicationsTracker {
             .map(|publisher| i32::from(publisher.port))
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn add<T: Message>(
         &self,
         hostname: &str,
+        ports: &RangeInclusive<u16>,
         topic: &str,
         queue_size: usize,
         caller_id: &str,
+        message_type: MessageType,
+        keepalive: Option<TcpKeepalive>,
     ) -> error::tcpros::Result<PublisherStream<T>> {
         use std::collections::hash_map::Entry;
         match self
@@ -56,14 +72,27 @@ impl PublicationsTracker {
             .expect(FAILED_TO_LOCK)
             .entry(String::from(topic))
         {
-            Entry::Occupied(publisher_entry) => publisher_entry.get().stream(queue_size),
+            Entry::Occupied(publisher_entry) => {
+                let publisher = publisher_entry.get();
+                if publisher.message_type() != &message_type {
+                    bail!(error::tcpros::ErrorKind::MessageTypeMismatch(
+                        publisher.message_type().msg_type.clone(),
+                        message_type.msg_type,
+                    ));
+                }
+                publisher.stream(queue_size)
+            }
             Entry::Vacant(entry) => {
-                let publisher = Publisher::new::<T, _>(
-                    format!("{}:0", hostname).as_str(),
-                    topic,
-                    queue_size,
-                    caller_id,
-                )?;
+                let publisher = bind_in_range(ports, |port| {
+                    Publisher::new(
+                        (hostname, port),
+                        topic,
+                        queue_size,
+                        caller_id,
+                        message_type.clone(),
+                        keepalive,
+                    )
+                })?;
                 entry.insert(publisher).stream(queue_size)
             }
         }
@@ -74,3 +103,89 @@ impl PublicationsTracker {
         self.mapping.lock().expect(FAILED_TO_LOCK).remove(topic);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::rosgraph_msgs::{Clock, Log};
+    use crate::util::ports::ANY_PORT;
+    use crate::RawMessage;
+
+    #[test]
+    fn lists_current_topics() {
+        let tracker = PublicationsTracker::default();
+        assert!(tracker.get_topics::<Vec<_>>().is_empty());
+
+        let _stream = tracker
+            .add::<Clock>(
+                "127.0.0.1",
+                &ANY_PORT,
+                "/clock",
+                1,
+                "/node",
+                MessageType::of::<Clock>(),
+                None,
+            )
+            .unwrap();
+        let topics = tracker.get_topics::<Vec<_>>();
+        assert_eq!(1, topics.len());
+        assert_eq!("/clock", topics[0].name);
+        assert_eq!("rosgraph_msgs/Clock", topics[0].msg_type);
+
+        tracker.remove("/clock");
+        assert!(tracker.get_topics::<Vec<_>>().is_empty());
+    }
+
+    #[test]
+    fn raw_publications_keep_the_advertised_type() {
+        let tracker = PublicationsTracker::default();
+        let message_type = MessageType::of::<Clock>();
+
+        let _stream = tracker
+            .add::<RawMessage>(
+                "127.0.0.1",
+                &ANY_PORT,
+                "/clock",
+                1,
+                "/node",
+                message_type.clone(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            "rosgraph_msgs/Clock",
+            tracker.get_topics::<Vec<_>>()[0].msg_type
+        );
+
+        let _same = tracker
+            .add::<RawMessage>(
+                "127.0.0.1",
+                &ANY_PORT,
+                "/clock",
+                1,
+                "/node",
+                message_type,
+                None,
+            )
+            .unwrap();
+        let err = tracker
+            .add::<RawMessage>(
+                "127.0.0.1",
+                &ANY_PORT,
+                "/clock",
+                1,
+                "/node",
+                MessageType::of::<Log>(),
+                None,
+            )
+            .err()
+            .unwrap();
+        match err.kind() {
+            error::tcpros::ErrorKind::MessageTypeMismatch(expected, actual) => {
+                assert_eq!("rosgraph_msgs/Clock", expected);
+                assert_eq!("rosgraph_msgs/Log", actual);
+            }
+            kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+}
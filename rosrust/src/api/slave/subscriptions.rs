@@ -1,38 +1,91 @@
+use super::protocol::{ProtocolParams, TCPROS};
 use crate::api::error::{self, ErrorKind, Result};
-use crate::tcpros::{Subscriber, Topic};
+use crate::tcpros::{
+    ConnectionInfo, MessageEvent, MessageType, Subscriber, TcpKeepalive, Topic, WindowStats,
+};
+use crate::udpros::{Negotiation, UDPROS};
 use crate::util::FAILED_TO_LOCK;
+use crate::Clock;
 use crate::Message;
 use log::error;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::iter::FromIterator;
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use xml_rpc::{Url, Value};
+
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_millis(100);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(5);
+const RECONNECT_ATTEMPTS: usize = 10;
 
 #[derive(Clone, Default)]
 pub struct SubscriptionsTracker {
     mapping: Arc<Mutex<HashMap<String, Subscriber>>>,
+    reconnecting: Arc<Mutex<HashSet<(String, String)>>>,
 }
 
 impl SubscriptionsTracker {
+    /// Brings the topic's connections in line with the given publishers.
+    ///
+    /// Connections to unlisted publishers are closed, and publishers that
+    /// cannot be reached keep being retried in the background with an
+    /// exponential backoff for as long as they stay listed.
     pub fn add_publishers<T>(&self, topic: &str, name: &str, publishers: T) -> Result<()>
     where
         T: Iterator<Item = String>,
     {
+        let mut result = Ok(());
         if let Some(mut subscription) = self.mapping.lock().expect(FAILED_TO_LOCK).get_mut(topic) {
             let publisher_set: BTreeSet<String> = publishers.collect();
             subscription.limit_publishers_to(&publisher_set);
             for publisher in publisher_set {
                 if let Err(err) = connect_to_publisher(&mut subscription, name, &publisher, topic) {
-                    let info = err
-                        .iter()
-                        .map(|v| format!("{}", v))
-                        .collect::<Vec<_>>()
-                        .join("\nCaused by:");
-                    error!("Failed to connect to publisher '{}': {}", publisher, info);
-                    return Err(err);
+                    log_connection_error(&publisher, &err);
+                    self.reconnect_with_backoff(topic, name, &publisher);
+                    result = Err(err);
                 }
             }
         }
-        Ok(())
+        result
+    }
+
+    fn reconnect_with_backoff(&self, topic: &str, name: &str, publisher: &str) {
+        let key = (String::from(topic), String::from(publisher));
+        if !self
+            .reconnecting
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .insert(key.clone())
+        {
+            return;
+        }
+        let tracker = self.clone();
+        let name = String::from(name);
+        thread::spawn(move || {
+            let (topic, publisher) = &key;
+            let mut delay = RECONNECT_INITIAL_DELAY;
+            for _ in 0..RECONNECT_ATTEMPTS {
+                thread::sleep(delay);
+                let mut mapping = tracker.mapping.lock().expect(FAILED_TO_LOCK);
+                let subscription = match mapping.get_mut(topic) {
+                    Some(subscription) if subscription.is_publisher_known(publisher) => {
+                        subscription
+                    }
+                    _ => break,
+                };
+                match connect_to_publisher(subscription, &name, publisher, topic) {
+                    Ok(()) => break,
+                    Err(err) => log_connection_error(publisher, &err),
+                }
+                delay = std::cmp::min(delay * 2, RECONNECT_MAX_DELAY);
+            }
+            tracker
+                .reconnecting
+                .lock()
+                .expect(FAILED_TO_LOCK)
+                .remove(&key);
+        });
     }
 
     #[inline]
@@ -46,33 +99,85 @@ impl SubscriptionsTracker {
             .collect()
     }
 
-    pub fn add<T, F>(&self, name: &str, topic: &str, queue_size: usize, callback: F) -> Result<()>
+    /// Established publisher connections of every subscribed topic.
+    pub fn get_connections<T: FromIterator<(String, Vec<ConnectionInfo>)>>(&self) -> T {
+        self.mapping
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .iter()
+            .map(|(topic, subscriber)| (topic.clone(), subscriber.connections()))
+            .collect()
+    }
+
+    /// Statistics windows of every publisher connection, by topic and publisher caller ID.
+    ///
+    /// A new window gets started for each connection.
+    pub fn take_statistics(&self) -> Vec<(String, String, WindowStats)> {
+        self.mapping
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .iter()
+            .flat_map(|(topic, subscriber)| {
+                subscriber
+                    .take_statistics()
+                    .into_iter()
+                    .map(move |(caller_id, stats)| (topic.clone(), caller_id, stats))
+            })
+            .collect()
+    }
+
+    /// Adds a callback to the topic, returning an ID for removing it.
+    ///
+    /// Callbacks on the same topic share one subscription, whose queue grows
+    /// to the largest queue size asked for. The callbacks handle each message
+    /// one after another, so a slow callback delays the others.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add<T, F>(
+        &self,
+        name: &str,
+        topic: &str,
+        queue_size: usize,
+        clock: Arc<dyn Clock>,
+        message_type: MessageType,
+        keepalive: Option<TcpKeepalive>,
+        callback: F,
+    ) -> Result<usize>
     where
         T: Message,
-        F: Fn(T, &str) + Send + 'static,
+        F: Fn(Arc<T>, &MessageEvent) + Send + 'static,
     {
-        use std::collections::hash_map::Entry;
-        match self
-            .mapping
-            .lock()
-            .expect(FAILED_TO_LOCK)
-            .entry(String::from(topic))
-        {
-            Entry::Occupied(..) => {
-                error!("Duplicate subscription to topic '{}' attempted", topic);
-                Err(ErrorKind::Duplicate("subscription".into()).into())
-            }
-            Entry::Vacant(entry) => {
-                let subscriber = Subscriber::new::<T, F>(name, topic, queue_size, callback);
-                entry.insert(subscriber);
-                Ok(())
-            }
+        let mut mapping = self.mapping.lock().expect(FAILED_TO_LOCK);
+        let subscriber = mapping.entry(String::from(topic)).or_insert_with(|| {
+            let message_type = message_type.clone();
+            Subscriber::new::<T>(name, topic, queue_size, clock, message_type, keepalive)
+        });
+        if subscriber.message_type() != &message_type {
+            let err = error::tcpros::ErrorKind::SubscriberTypeMismatch(
+                subscriber.message_type().msg_type.clone(),
+                message_type.msg_type,
+            );
+            error!("Failed to add callback to topic '{}': {}", topic, err);
+            bail!(ErrorKind::from(err));
         }
+        subscriber.set_queue_size_max(queue_size);
+        subscriber.add_callback(callback).map_err(|err| {
+            error!("Failed to add callback to topic '{}': {}", topic, err);
+            err.into()
+        })
     }
 
-    #[inline]
-    pub fn remove(&self, topic: &str) {
-        self.mapping.lock().expect(FAILED_TO_LOCK).remove(topic);
+    /// Removes a callback, and the subscription along with the last one.
+    ///
+    /// Returns whether the subscription got removed.
+    pub fn remove(&self, topic: &str, id: usize) -> bool {
+        let mut mapping = self.mapping.lock().expect(FAILED_TO_LOCK);
+        match mapping.get(topic) {
+            Some(subscriber) if subscriber.remove_callback(id) == 0 => {
+                mapping.remove(topic);
+                true
+            }
+            _ => false,
+        }
     }
 
     #[inline]
@@ -92,6 +197,38 @@ impl SubscriptionsTracker {
             .get(topic)
             .map_or_else(Vec::new, Subscriber::publisher_uris)
     }
+
+    #[inline]
+    pub fn dropped_message_count(&self, topic: &str) -> usize {
+        self.mapping
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .get(topic)
+            .map_or(0, Subscriber::dropped_message_count)
+    }
+
+    #[inline]
+    pub fn set_transport_compression(&self, topic: &str, enabled: bool) {
+        if let Some(subscriber) = self.mapping.lock().expect(FAILED_TO_LOCK).get(topic) {
+            subscriber.set_transport_compression(enabled);
+        }
+    }
+
+    #[inline]
+    pub fn set_udp_transport(&self, topic: &str, enabled: bool) {
+        if let Some(subscriber) = self.mapping.lock().expect(FAILED_TO_LOCK).get(topic) {
+            subscriber.set_udp_transport(enabled);
+        }
+    }
+}
+
+fn log_connection_error(publisher: &str, err: &error::Error) {
+    let info = err
+        .iter()
+        .map(|v| format!("{}", v))
+        .collect::<Vec<_>>()
+        .join("\nCaused by:");
+    error!("Failed to connect to publisher '{}': {}", publisher, info);
 }
 
 fn connect_to_publisher(
@@ -103,34 +240,264 @@ fn connect_to_publisher(
     if subscriber.is_connected_to(publisher) {
         return Ok(());
     }
-    let (protocol, hostname, port) = request_topic(publisher, caller_id, topic)?;
-    if protocol != "TCPROS" {
-        bail!(ErrorKind::CommunicationIssue(format!(
-            "Publisher responded with a non-TCPROS protocol: {}",
-            protocol
-        )))
+    let uri = parse_uri(publisher)?;
+    let tcpros = Value::Array(vec![Value::String(TCPROS.into())]);
+    // UDPROS is offered first, with TCPROS as a fallback
+    let (socket, protocols) = if subscriber.udp_transport() {
+        let host = uri.host_str().unwrap_or_default();
+        let (socket, offer) = subscriber.offer_udp(caller_id, host)?;
+        (Some(socket), vec![offer, tcpros])
+    } else {
+        (None, vec![tcpros])
+    };
+    let protocol = request_topic(&uri, caller_id, topic, protocols)?;
+    match (protocol.name.as_str(), socket) {
+        (TCPROS, _) => subscriber
+            .connect_to(publisher, (protocol.hostname.as_str(), protocol.port))
+            .map_err(|err| ErrorKind::Io(err).into()),
+        (UDPROS, Some(socket)) => {
+            let negotiation = Negotiation::from_params(&protocol.extra).map_err(ErrorKind::Io)?;
+            subscriber
+                .connect_udp(publisher, socket, negotiation)
+                .map_err(Into::into)
+        }
+        _ => bail!(ErrorKind::CommunicationIssue(format!(
+            "Publisher responded with an unoffered protocol: {}",
+            protocol.name
+        ))),
     }
-    subscriber
-        .connect_to(publisher, (hostname.as_str(), port as u16))
-        .map_err(|err| ErrorKind::Io(err).into())
+}
+
+fn parse_uri(publisher_uri: &str) -> Result<Url> {
+    use crate::rosxmlrpc::error::ResultExt;
+    Ok(publisher_uri
+        .parse()
+        .chain_err(|| error::rosxmlrpc::ErrorKind::BadUri(publisher_uri.into()))?)
 }
 
 fn request_topic(
-    publisher_uri: &str,
+    uri: &Url,
     caller_id: &str,
     topic: &str,
-) -> error::rosxmlrpc::Result<(String, String, i32)> {
+    protocols: Vec<Value>,
+) -> Result<ProtocolParams> {
     use crate::rosxmlrpc::error::ResultExt;
-    let (_code, _message, protocols): (i32, String, (String, String, i32)) = xml_rpc::Client::new()
-        .map_err(error::rosxmlrpc::ErrorKind::ForeignXmlRpc)?
-        .call(
-            &publisher_uri
-                .parse()
-                .chain_err(|| error::rosxmlrpc::ErrorKind::BadUri(publisher_uri.into()))?,
-            "requestTopic",
-            &(caller_id, topic, [["TCPROS"]]),
-        )
+    let params = vec![
+        Value::String(caller_id.into()),
+        Value::String(topic.into()),
+        Value::Array(protocols),
+    ];
+    let response = xml_rpc::call_value(uri, "requestTopic", params)
         .chain_err(|| error::rosxmlrpc::ErrorKind::TopicConnectionError(topic.to_owned()))?
-        .map_err(|_| "error")?;
-    Ok(protocols)
+        .map_err(|fault| ErrorKind::CommunicationIssue(fault.message))?;
+    ProtocolParams::from_response(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::clock::RealClock;
+    use crate::msg::rosgraph_msgs::Clock as ClockMsg;
+    use crate::rosmsg::RosMsg;
+    use crate::rosxmlrpc::mock::MockServer;
+    use crate::rosxmlrpc::ResponseError;
+    use crate::tcpros::header;
+    use crate::time::Time;
+    use crate::udpros;
+    use std::io::Read;
+    use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    static FAILED_TO_ACCEPT: &str = "Failed to accept subscriber connection";
+
+    /// Publisher slave API that fails `requestTopic` the given number of times.
+    fn mock_publisher(failures: usize) -> (String, TcpListener, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = i32::from(listener.local_addr().unwrap().port());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        let mut server = MockServer::default();
+        server.on("requestTopic", move |_args| {
+            if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                return Err(ResponseError::Server("Not ready yet".into()));
+            }
+            Ok(Value::Array(vec![
+                Value::String("TCPROS".into()),
+                Value::String("127.0.0.1".into()),
+                Value::Int(port),
+            ]))
+        });
+        let uri = server.serve();
+        (uri, listener, requests)
+    }
+
+    fn subscribed_tracker() -> SubscriptionsTracker {
+        let tracker = SubscriptionsTracker::default();
+        tracker
+            .add::<ClockMsg, _>(
+                "/listener",
+                "/clock",
+                1,
+                Arc::new(RealClock::default()),
+                MessageType::of::<ClockMsg>(),
+                None,
+                |_, _| {},
+            )
+            .unwrap();
+        tracker
+    }
+
+    fn accept_with_header(listener: &TcpListener) -> TcpStream {
+        let (mut stream, _) = listener.accept().expect(FAILED_TO_ACCEPT);
+        header::decode(&mut stream).unwrap();
+        let mut fields = HashMap::<String, String>::new();
+        fields.insert("callerid".into(), "/talker".into());
+        fields.insert("md5sum".into(), ClockMsg::md5sum());
+        fields.insert("type".into(), ClockMsg::msg_type());
+        header::encode(&mut stream, &fields).unwrap();
+        stream
+    }
+
+    #[test]
+    fn callbacks_on_one_topic_keep_largest_queue_size() {
+        let tracker = subscribed_tracker();
+        for &queue_size in &[5, 2] {
+            tracker
+                .add::<ClockMsg, _>(
+                    "/listener",
+                    "/clock",
+                    queue_size,
+                    Arc::new(RealClock::default()),
+                    MessageType::of::<ClockMsg>(),
+                    None,
+                    |_, _| {},
+                )
+                .unwrap();
+        }
+        let mapping = tracker.mapping.lock().unwrap();
+        assert_eq!(5, mapping["/clock"].queue_size());
+    }
+
+    #[test]
+    fn publisher_update_connects_to_new_publisher() {
+        let tracker = subscribed_tracker();
+        let (uri, listener, requests) = mock_publisher(0);
+
+        tracker
+            .add_publishers("/clock", "/listener", vec![uri.clone()].into_iter())
+            .unwrap();
+
+        let _stream = accept_with_header(&listener);
+        assert_eq!(1, requests.load(Ordering::SeqCst));
+        assert_eq!(vec![uri], tracker.publisher_uris("/clock"));
+    }
+
+    #[test]
+    fn unreachable_publisher_is_retried_with_backoff() {
+        let tracker = subscribed_tracker();
+        let (uri, listener, requests) = mock_publisher(2);
+
+        let start = Instant::now();
+        tracker
+            .add_publishers("/clock", "/listener", vec![uri].into_iter())
+            .unwrap_err();
+
+        accept_with_header(&listener);
+        assert_eq!(3, requests.load(Ordering::SeqCst));
+        assert!(start.elapsed() >= RECONNECT_INITIAL_DELAY * 3);
+    }
+
+    #[test]
+    fn unlisted_publisher_is_disconnected() {
+        let tracker = subscribed_tracker();
+        let (uri, listener, _requests) = mock_publisher(0);
+
+        tracker
+            .add_publishers("/clock", "/listener", vec![uri].into_iter())
+            .unwrap();
+        let mut stream = accept_with_header(&listener);
+        tracker
+            .add_publishers("/clock", "/listener", vec![].into_iter())
+            .unwrap();
+        assert_eq!(0, tracker.publisher_count("/clock"));
+
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(0, stream.read(&mut [0; 16]).unwrap());
+    }
+
+    #[test]
+    fn udp_transport_receives_messages_over_udpros() {
+        let (tx, rx) = mpsc::channel();
+        let tracker = SubscriptionsTracker::default();
+        tracker
+            .add::<ClockMsg, _>(
+                "/listener",
+                "/clock",
+                1,
+                Arc::new(RealClock::default()),
+                MessageType::of::<ClockMsg>(),
+                None,
+                move |message: Arc<ClockMsg>, _| tx.send(message.clock).unwrap(),
+            )
+            .unwrap();
+        tracker.set_udp_transport("/clock", true);
+
+        let (offer_tx, offer_rx) = mpsc::channel();
+        let offer_tx = Mutex::new(offer_tx);
+        let mut server = MockServer::default();
+        server.on("requestTopic", move |args| {
+            let offer = match args.get(2) {
+                Some(Value::Array(protocols)) => protocols[0].clone(),
+                _ => return Err(ResponseError::Client("No protocols".into())),
+            };
+            offer_tx.lock().unwrap().send(offer).unwrap();
+            let mut fields = HashMap::<String, String>::new();
+            fields.insert("callerid".into(), "/talker".into());
+            fields.insert("md5sum".into(), ClockMsg::md5sum());
+            fields.insert("type".into(), ClockMsg::msg_type());
+            let mut header = vec![];
+            fields.encode(&mut header).unwrap();
+            header.drain(..4);
+            Ok(Value::Array(vec![
+                Value::String(udpros::UDPROS.into()),
+                Value::String("127.0.0.1".into()),
+                Value::Int(0),
+                Value::Int(5),
+                Value::Int(udpros::MAX_DATAGRAM_SIZE as i32),
+                Value::Base64(header),
+            ]))
+        });
+        let uri = server.serve();
+        tracker
+            .add_publishers("/clock", "/listener", vec![uri].into_iter())
+            .unwrap();
+
+        let offer = match offer_rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+            Value::Array(offer) => offer,
+            _ => panic!("Offer is not an array"),
+        };
+        assert_eq!(Value::String(udpros::UDPROS.into()), offer[0]);
+        let address: SocketAddr = match (&offer[2], &offer[3]) {
+            (Value::String(host), Value::Int(port)) => format!("{}:{}", host, port).parse(),
+            _ => panic!("Offer lacks an address"),
+        }
+        .unwrap();
+        let message = ClockMsg {
+            clock: Time::from_nanos(42),
+        };
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let data = message.encode_vec().unwrap();
+        for datagram in udpros::fragment(5, 0, &data, 16).unwrap() {
+            sender.send_to(&datagram, address).unwrap();
+        }
+
+        let received = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(message.clock, received);
+        let connections = tracker.get_connections::<Vec<_>>();
+        assert_eq!(udpros::UDPROS, connections[0].1[0].transport);
+    }
 }
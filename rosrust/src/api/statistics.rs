@@ -0,0 +1,70 @@
+use super::clock::Clock;
+use super::raii::Publisher;
+use super::slave::Slave;
+use crate::msg::rosgraph_msgs::TopicStatistics;
+use crate::time::Duration;
+use crossbeam::channel::{bounded, RecvTimeoutError, Sender};
+use log::error;
+use std::sync::Arc;
+use std::thread;
+
+/// Default length of a statistics window, matching `roscpp`.
+pub const DEFAULT_WINDOW: std::time::Duration = std::time::Duration::from_secs(4);
+
+/// Publishes statistics about the node's subscriptions to `/statistics`.
+///
+/// Each window produces a message for every publisher connection that
+/// delivered messages during it. Publishing stops once this gets dropped.
+pub struct StatisticsPublisher {
+    _stop: Sender<()>,
+}
+
+impl StatisticsPublisher {
+    pub fn new(
+        publisher: Publisher<TopicStatistics>,
+        slave: Arc<Slave>,
+        clock: Arc<dyn Clock>,
+        node_name: &str,
+        window: std::time::Duration,
+    ) -> Self {
+        let (stop_tx, stop_rx) = bounded(0);
+        let node_name = String::from(node_name);
+        thread::spawn(move || {
+            let mut window_start = clock.now();
+            while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(window) {
+                let window_stop = clock.now();
+                for (topic, node_pub, stats) in slave.subscriptions.take_statistics() {
+                    if stats.delivered == 0 {
+                        continue;
+                    }
+                    let message = TopicStatistics {
+                        topic,
+                        node_pub,
+                        node_sub: node_name.clone(),
+                        window_start,
+                        window_stop,
+                        delivered_msgs: stats.delivered as i32,
+                        dropped_msgs: stats.dropped as i32,
+                        traffic: stats.traffic as i32,
+                        period_mean: seconds_to_duration(stats.period.mean()),
+                        period_stddev: seconds_to_duration(stats.period.stddev()),
+                        period_max: seconds_to_duration(stats.period.max()),
+                        stamp_age_mean: seconds_to_duration(stats.age.mean()),
+                        stamp_age_stddev: seconds_to_duration(stats.age.stddev()),
+                        stamp_age_max: seconds_to_duration(stats.age.max()),
+                    };
+                    if let Err(err) = publisher.send(message) {
+                        error!("Failed to publish topic statistics: {}", err);
+                    }
+                }
+                window_start = window_stop;
+            }
+        });
+        Self { _stop: stop_tx }
+    }
+}
+
+#[inline]
+fn seconds_to_duration(seconds: f64) -> Duration {
+    Duration::from_nanos((seconds * 1e9) as i64)
+}
@@ -3,13 +3,22 @@
 #[macro_use]
 extern crate error_chain;
 
-pub use crate::api::raii::{Publisher, Service, Subscriber};
-pub use crate::api::{error, Clock, Parameter};
+pub use crate::api::raii::{CachedParameter, CallbackControl, Publisher, Service, Subscriber};
+pub use crate::api::{
+    error, set_master_retry_policy, Clock, Parameter, RealClock, RetryPolicy, SimulatedClock,
+    Timer, TimerEvent,
+};
 #[doc(hidden)]
 pub use crate::rosmsg::RosMsg;
+pub use crate::rosmsg::{max_message_size, set_max_message_size, DEFAULT_MAX_MESSAGE_SIZE};
+pub use crate::rosout::RosoutLogger;
 pub use crate::singleton::*;
-pub use crate::tcpros::{Client, ClientResponse, Message, ServicePair};
-pub use crate::time::{Duration, Time};
+pub use crate::tcpros::{
+    set_callback_threads, set_transport_compression, Client, ClientCall, ClientResponse,
+    ClientStream, Connector, LenientMessage, Message, MessageEvent, MessageType, RawMessage,
+    ServicePair, ServiceResultExt, TcpKeepalive, DEFAULT_CALL_TIMEOUT,
+};
+pub use crate::time::{Duration, SteadyTime, Time};
 #[doc(hidden)]
 pub use rosrust_codegen::*;
 
@@ -17,10 +26,15 @@ pub mod api;
 mod log_macros;
 #[doc(hidden)]
 pub mod msg;
+pub mod pointcloud;
+pub mod rosbag;
 #[doc(hidden)]
 pub mod rosmsg;
+pub mod rosout;
 mod rosxmlrpc;
 pub mod singleton;
 mod tcpros;
+pub mod tf;
 mod time;
+pub mod udpros;
 mod util;
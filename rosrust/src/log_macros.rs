@@ -40,3 +40,48 @@ macro_rules! ros_fatal {
         $crate::ros_log!($crate::msg::rosgraph_msgs::Log::FATAL, $($arg)*);
     }
 }
+
+#[macro_export]
+macro_rules! ros_log_throttle {
+    ($period:expr, $level:expr, $($arg:tt)+) => {{
+        static THROTTLE: $crate::rosout::LogThrottle = $crate::rosout::LogThrottle::new();
+        if THROTTLE.is_allowed($crate::now(), $period) {
+            $crate::ros_log!($level, $($arg)+);
+        }
+    }}
+}
+
+#[macro_export]
+macro_rules! ros_debug_throttle {
+    ($period:expr, $($arg:tt)*) => {
+        $crate::ros_log_throttle!($period, $crate::msg::rosgraph_msgs::Log::DEBUG, $($arg)*);
+    }
+}
+
+#[macro_export]
+macro_rules! ros_info_throttle {
+    ($period:expr, $($arg:tt)*) => {
+        $crate::ros_log_throttle!($period, $crate::msg::rosgraph_msgs::Log::INFO, $($arg)*);
+    }
+}
+
+#[macro_export]
+macro_rules! ros_warn_throttle {
+    ($period:expr, $($arg:tt)*) => {
+        $crate::ros_log_throttle!($period, $crate::msg::rosgraph_msgs::Log::WARN, $($arg)*);
+    }
+}
+
+#[macro_export]
+macro_rules! ros_err_throttle {
+    ($period:expr, $($arg:tt)*) => {
+        $crate::ros_log_throttle!($period, $crate::msg::rosgraph_msgs::Log::ERROR, $($arg)*);
+    }
+}
+
+#[macro_export]
+macro_rules! ros_fatal_throttle {
+    ($period:expr, $($arg:tt)*) => {
+        $crate::ros_log_throttle!($period, $crate::msg::rosgraph_msgs::Log::FATAL, $($arg)*);
+    }
+}
@@ -1 +1,88 @@
-crate::rosmsg_include!(rosgraph_msgs / Clock, rosgraph_msgs / Log, INTERNAL);
+crate::rosmsg_include!(
+    geometry_msgs / TransformStamped,
+    rosgraph_msgs / Clock,
+    rosgraph_msgs / Log,
+    rosgraph_msgs / TopicStatistics,
+    tf2_msgs / TFMessage,
+    INTERNAL
+);
+
+/// Real generated messages for tests, instead of hand-written copies.
+#[cfg(test)]
+pub(crate) mod test_msgs {
+    crate::rosmsg_include!(
+        geometry_msgs / Vector3,
+        roscpp / SetLoggerLevel,
+        sensor_msgs / PointCloud2,
+        std_srvs / Empty,
+        INTERNAL
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rosgraph_msgs::{Clock, Log, TopicStatistics};
+    use crate::rosmsg::RosMsg;
+    use crate::{Duration, Time};
+
+    #[test]
+    fn generated_messages_debug_clone_and_compare() {
+        let mut log = Log::default();
+        log.level = Log::WARN;
+        log.msg = "disk almost full".into();
+        log.topics = vec!["/rosout".into()];
+
+        let copy = log.clone();
+        assert_eq!(log, copy);
+        let text = format!("{:?}", copy);
+        assert!(text.starts_with("Log {"), "{}", text);
+        assert!(text.contains("level: 4"), "{}", text);
+        assert!(text.contains("msg: \"disk almost full\""), "{}", text);
+        assert!(text.contains("topics: [\"/rosout\"]"), "{}", text);
+
+        let mut other = copy.clone();
+        other.header.seq = 1;
+        assert_ne!(log, other);
+        assert_eq!(Clock::default(), Clock::default());
+    }
+
+    mod with_builders {
+        crate::rosmsg_include!(rosgraph_msgs / Log, INTERNAL, BUILDERS);
+    }
+
+    #[test]
+    fn generated_messages_support_builder_and_default_update() {
+        use with_builders::rosgraph_msgs::Log;
+
+        let built = Log::builder()
+            .level(Log::ERROR)
+            .name("/talker")
+            .topics(vec![String::from("/chatter")])
+            .build();
+        let literal = Log {
+            level: Log::ERROR,
+            name: "/talker".into(),
+            topics: vec!["/chatter".into()],
+            ..Default::default()
+        };
+        assert_eq!(literal, built);
+        assert_eq!(Log::default(), Log::builder().build());
+        assert_eq!(0, built.line);
+    }
+
+    #[test]
+    fn generated_time_and_duration_fields_round_trip() {
+        let message = TopicStatistics {
+            window_start: Time { sec: 5, nsec: 6 },
+            period_mean: Duration { sec: -1, nsec: 7 },
+            ..Default::default()
+        };
+        let mut bytes = vec![];
+        message.encode(&mut bytes).unwrap();
+        // Three empty strings precede the first time field
+        assert_eq!(&[5, 0, 0, 0, 6, 0, 0, 0], &bytes[12..20]);
+        assert_eq!(&[255, 255, 255, 255, 7, 0, 0, 0], &bytes[40..48]);
+        assert_eq!(12 + 2 * 8 + 3 * 4 + 6 * 8, bytes.len());
+        assert_eq!(message, TopicStatistics::decode(&bytes[..]).unwrap());
+    }
+}
@@ -0,0 +1,17 @@
+#![allow(deprecated)]
+error_chain! {
+    errors {
+        MissingField(name: String) {
+            description("Point cloud has no field of that name")
+            display("Point cloud has no field named '{}'", name)
+        }
+        InvalidField(name: String, details: String) {
+            description("Point cloud field cannot be read")
+            display("Point cloud field '{}' cannot be read: {}", name, details)
+        }
+        InvalidLayout(details: String) {
+            description("Point cloud layout does not match its data")
+            display("Point cloud layout does not match its data: {}", details)
+        }
+    }
+}
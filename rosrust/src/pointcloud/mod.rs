@@ -0,0 +1,343 @@
+//! Typed access to the points of `sensor_msgs/PointCloud2` messages.
+//!
+//! Point clouds store their points in a byte blob, row by row. Every point
+//! takes `point_step` bytes, and every row `row_step` bytes, which can include
+//! padding. The field descriptors give the name, offset and data type of each
+//! value within a point.
+//!
+//! `PointCloud` reads values straight from the blob, without copying it. The
+//! `point_cloud!` macro creates one from any generated `PointCloud2` type.
+
+use self::error::{ErrorKind, Result};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+pub mod error;
+
+/// Data types of point fields, as numbered in `sensor_msgs/PointField`.
+pub const INT8: u8 = 1;
+pub const UINT8: u8 = 2;
+pub const INT16: u8 = 3;
+pub const UINT16: u8 = 4;
+pub const INT32: u8 = 5;
+pub const UINT32: u8 = 6;
+pub const FLOAT32: u8 = 7;
+pub const FLOAT64: u8 = 8;
+
+/// Size in bytes of a value of the given data type.
+pub fn datatype_size(datatype: u8) -> Option<usize> {
+    match datatype {
+        INT8 | UINT8 => Some(1),
+        INT16 | UINT16 => Some(2),
+        INT32 | UINT32 | FLOAT32 => Some(4),
+        FLOAT64 => Some(8),
+        _ => None,
+    }
+}
+
+/// Descriptor of a value within every point, like `sensor_msgs/PointField`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PointField {
+    pub name: String,
+    pub offset: u32,
+    pub datatype: u8,
+    pub count: u32,
+}
+
+/// Point cloud data along with the layout needed for reading it.
+#[derive(Clone, Debug)]
+pub struct PointCloud<'a> {
+    fields: Vec<PointField>,
+    width: usize,
+    height: usize,
+    point_step: usize,
+    row_step: usize,
+    is_bigendian: bool,
+    data: &'a [u8],
+}
+
+impl<'a> PointCloud<'a> {
+    /// Fails if the data is too short for the given dimensions.
+    pub fn new(
+        fields: Vec<PointField>,
+        width: u32,
+        height: u32,
+        point_step: u32,
+        row_step: u32,
+        is_bigendian: bool,
+        data: &'a [u8],
+    ) -> Result<Self> {
+        let cloud = Self {
+            fields,
+            width: width as usize,
+            height: height as usize,
+            point_step: point_step as usize,
+            row_step: row_step as usize,
+            is_bigendian,
+            data,
+        };
+        if cloud.height > 0 && cloud.width * cloud.point_step > cloud.row_step {
+            bail!(ErrorKind::InvalidLayout(format!(
+                "row step of {} bytes is shorter than {} points of {} bytes",
+                cloud.row_step, cloud.width, cloud.point_step
+            )));
+        }
+        let required = match cloud.height {
+            0 => 0,
+            height => (height - 1) * cloud.row_step + cloud.width * cloud.point_step,
+        };
+        if cloud.data.len() < required {
+            bail!(ErrorKind::InvalidLayout(format!(
+                "{} bytes of data instead of at least {}",
+                cloud.data.len(),
+                required
+            )));
+        }
+        Ok(cloud)
+    }
+
+    /// Number of points, including invalid ones in organized clouds.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.width * self.height
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn fields(&self) -> &[PointField] {
+        &self.fields
+    }
+
+    #[inline]
+    pub fn field(&self, name: &str) -> Option<&PointField> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    /// Values of the field for every point, converted to `f64`.
+    ///
+    /// Fields holding several values per point only yield their first one.
+    pub fn iter_field(&self, name: &str) -> Result<impl Iterator<Item = f64> + 'a> {
+        let read = self.reader(name)?;
+        let data = self.data;
+        Ok(self
+            .point_offsets()
+            .map(move |offset| read(&data[offset..])))
+    }
+
+    /// Coordinates of every point, taken from the `x`, `y` and `z` fields.
+    pub fn iter_xyz(&self) -> Result<impl Iterator<Item = [f32; 3]> + 'a> {
+        let x = self.reader("x")?;
+        let y = self.reader("y")?;
+        let z = self.reader("z")?;
+        let data = self.data;
+        Ok(self.point_offsets().map(move |offset| {
+            let point = &data[offset..];
+            [x(point) as f32, y(point) as f32, z(point) as f32]
+        }))
+    }
+
+    /// Byte offsets at which the points start.
+    fn point_offsets(&self) -> impl Iterator<Item = usize> {
+        let (width, point_step, row_step) = (self.width, self.point_step, self.row_step);
+        (0..self.len()).map(move |index| (index / width) * row_step + (index % width) * point_step)
+    }
+
+    /// Returns a function reading the field from the bytes of a point.
+    fn reader(&self, name: &str) -> Result<impl Fn(&[u8]) -> f64> {
+        let field = self
+            .field(name)
+            .ok_or_else(|| ErrorKind::MissingField(name.into()))?;
+        let invalid = |details: String| ErrorKind::InvalidField(name.into(), details);
+        let size = datatype_size(field.datatype)
+            .ok_or_else(|| invalid(format!("unknown data type {}", field.datatype)))?;
+        let offset = field.offset as usize;
+        if offset + size > self.point_step {
+            bail!(invalid(format!(
+                "value at offset {} exceeds point step of {} bytes",
+                offset, self.point_step
+            )));
+        }
+        let (datatype, is_bigendian) = (field.datatype, self.is_bigendian);
+        Ok(move |point: &[u8]| {
+            let bytes = &point[offset..offset + size];
+            if is_bigendian {
+                read_value::<BigEndian>(bytes, datatype)
+            } else {
+                read_value::<LittleEndian>(bytes, datatype)
+            }
+        })
+    }
+}
+
+fn read_value<B: ByteOrder>(bytes: &[u8], datatype: u8) -> f64 {
+    match datatype {
+        INT8 => f64::from(bytes[0] as i8),
+        UINT8 => f64::from(bytes[0]),
+        INT16 => f64::from(B::read_i16(bytes)),
+        UINT16 => f64::from(B::read_u16(bytes)),
+        INT32 => f64::from(B::read_i32(bytes)),
+        UINT32 => f64::from(B::read_u32(bytes)),
+        FLOAT32 => f64::from(B::read_f32(bytes)),
+        _ => B::read_f64(bytes),
+    }
+}
+
+/// Creates a `PointCloud` from a generated `sensor_msgs/PointCloud2` message.
+///
+/// The message is borrowed, so the data does not get copied.
+#[macro_export]
+macro_rules! point_cloud {
+    ($cloud:expr) => {{
+        let cloud = &$cloud;
+        $crate::pointcloud::PointCloud::new(
+            cloud
+                .fields
+                .iter()
+                .map(|field| $crate::pointcloud::PointField {
+                    name: field.name.clone(),
+                    offset: field.offset,
+                    datatype: field.datatype,
+                    count: field.count,
+                })
+                .collect(),
+            cloud.width,
+            cloud.height,
+            cloud.point_step,
+            cloud.row_step,
+            cloud.is_bigendian,
+            &cloud.data,
+        )
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::test_msgs::sensor_msgs::{PointCloud2, PointField as GeneratedField};
+    use byteorder::WriteBytesExt;
+
+    fn field(name: &str, offset: u32, datatype: u8) -> GeneratedField {
+        GeneratedField {
+            name: name.into(),
+            offset,
+            datatype,
+            count: 1,
+        }
+    }
+
+    // Two rows of two points, with x, y, z and intensity, and four bytes of row padding
+    fn small_cloud() -> PointCloud2 {
+        let mut data = vec![];
+        for row in 0..2 {
+            for column in 0..2 {
+                let index = (row * 2 + column) as f32;
+                data.write_f32::<LittleEndian>(index).unwrap();
+                data.write_f32::<LittleEndian>(index * 10.0).unwrap();
+                data.write_f32::<LittleEndian>(-index).unwrap();
+                data.write_u16::<LittleEndian>(100 + row * 2 + column)
+                    .unwrap();
+                data.extend_from_slice(&[0, 0]);
+            }
+            data.extend_from_slice(&[0xff; 4]);
+        }
+        PointCloud2 {
+            height: 2,
+            width: 2,
+            fields: vec![
+                field("x", 0, FLOAT32),
+                field("y", 4, FLOAT32),
+                field("z", 8, FLOAT32),
+                field("intensity", 12, UINT16),
+            ],
+            is_bigendian: false,
+            point_step: 16,
+            row_step: 36,
+            data,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn iterates_over_points_of_cloud() {
+        let message = small_cloud();
+        let cloud = point_cloud!(message).unwrap();
+        assert_eq!(4, cloud.len());
+        assert_eq!(
+            vec![
+                [0.0, 0.0, 0.0],
+                [1.0, 10.0, -1.0],
+                [2.0, 20.0, -2.0],
+                [3.0, 30.0, -3.0],
+            ],
+            cloud.iter_xyz().unwrap().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![100.0, 101.0, 102.0, 103.0],
+            cloud.iter_field("intensity").unwrap().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn datatypes_match_generated_constants() {
+        assert_eq!(
+            [INT8, UINT8, INT16, UINT16, INT32, UINT32, FLOAT32, FLOAT64],
+            [
+                GeneratedField::INT8,
+                GeneratedField::UINT8,
+                GeneratedField::INT16,
+                GeneratedField::UINT16,
+                GeneratedField::INT32,
+                GeneratedField::UINT32,
+                GeneratedField::FLOAT32,
+                GeneratedField::FLOAT64,
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_big_endian_values() {
+        let mut data = vec![];
+        data.write_f64::<BigEndian>(1.5).unwrap();
+        data.write_i16::<BigEndian>(-300).unwrap();
+        let fields = vec![
+            PointField {
+                name: "range".into(),
+                offset: 0,
+                datatype: FLOAT64,
+                count: 1,
+            },
+            PointField {
+                name: "ring".into(),
+                offset: 8,
+                datatype: INT16,
+                count: 1,
+            },
+        ];
+        let cloud = PointCloud::new(fields, 1, 1, 10, 10, true, &data).unwrap();
+        assert_eq!(
+            vec![1.5],
+            cloud.iter_field("range").unwrap().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![-300.0],
+            cloud.iter_field("ring").unwrap().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_layouts_and_fields() {
+        let mut message = small_cloud();
+        message.fields[3].offset = 15;
+        let cloud = point_cloud!(message).unwrap();
+        assert!(cloud.iter_field("intensity").is_err());
+        assert!(cloud.iter_field("rgb").is_err());
+
+        message.data.truncate(60);
+        assert!(point_cloud!(message).is_err());
+        message.row_step = 20;
+        assert!(point_cloud!(message).is_err());
+    }
+}
@@ -0,0 +1,28 @@
+#![allow(deprecated)]
+error_chain! {
+    foreign_links {
+        Io(::std::io::Error);
+    }
+    errors {
+        BadMagic(line: String) {
+            description("File is not a version 2.0 bag")
+            display("File is not a version 2.0 bag, it starts with: {}", line)
+        }
+        InvalidRecord(details: String) {
+            description("Bag contains an invalid record")
+            display("Bag contains an invalid record: {}", details)
+        }
+        MissingField(field: String) {
+            description("Record header field missing")
+            display("Record header field '{}' missing", field)
+        }
+        UnknownConnection(id: u32) {
+            description("Message refers to an unknown connection")
+            display("Message refers to unknown connection {}", id)
+        }
+        UnsupportedCompression(compression: String) {
+            description("Chunk compression is not supported")
+            display("Chunk compression '{}' is not supported", compression)
+        }
+    }
+}
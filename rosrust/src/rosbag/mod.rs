@@ -0,0 +1,17 @@
+//! Reading and writing of recorded bag files, in the version 2.0 format.
+//!
+//! A bag starts with a bag header record, followed by chunk records. Each
+//! chunk holds the connection records and message data records written
+//! while it was open. Index data and chunk info records follow for random
+//! access, and are skipped when reading sequentially.
+//!
+//! Messages are returned serialized, together with the md5sum and type of
+//! their connection, and can be decoded with the matching message type.
+
+pub use self::reader::{BagMessage, Connection, Reader};
+pub use self::writer::{Writer, DEFAULT_CHUNK_THRESHOLD};
+
+pub mod error;
+mod reader;
+mod record;
+mod writer;
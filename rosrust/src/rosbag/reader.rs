@@ -0,0 +1,295 @@
+use super::error::{ErrorKind, Result, ResultExt};
+use super::record::{
+    read_record, Record, RecordHeader, MAGIC, OP_BAG_HEADER, OP_CHUNK, OP_CHUNK_INFO,
+    OP_CONNECTION, OP_INDEX_DATA, OP_MESSAGE_DATA,
+};
+use crate::time::Time;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read};
+use std::path::Path;
+
+/// Connection record, describing the topic and type of the messages on it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Connection {
+    pub id: u32,
+    pub topic: String,
+    pub msg_type: String,
+    pub md5sum: String,
+    pub message_definition: String,
+    pub caller_id: Option<String>,
+    pub latching: bool,
+}
+
+impl Connection {
+    fn from_record(record: &Record) -> Result<Connection> {
+        let id = record.header.u32("conn")?;
+        let fields = RecordHeader::decode(&record.data)
+            .chain_err(|| format!("Failed to decode header of connection {}", id))?;
+        Ok(Connection {
+            id,
+            topic: record.header.string("topic")?,
+            msg_type: fields.string("type")?,
+            md5sum: fields.string("md5sum")?,
+            message_definition: fields.string("message_definition")?,
+            caller_id: if fields.has("callerid") {
+                Some(fields.string("callerid")?)
+            } else {
+                None
+            },
+            latching: fields.has("latching") && fields.string("latching")? == "1",
+        })
+    }
+}
+
+/// Serialized message stored in a bag, which can be decoded with `RosMsg::decode`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BagMessage {
+    pub topic: String,
+    pub time: Time,
+    pub data: Vec<u8>,
+    pub md5sum: String,
+    pub msg_type: String,
+}
+
+/// Sequential reader of version 2.0 bag files.
+///
+/// Messages are yielded in the order they are stored in the file, which is
+/// the order they were recorded in. Only uncompressed chunks are supported,
+/// and compressed ones produce an error before the reader moves on.
+pub struct Reader<R> {
+    source: R,
+    chunk: Option<Cursor<Vec<u8>>>,
+    connections: HashMap<u32, Connection>,
+    finished: bool,
+}
+
+impl Reader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Reader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut source: R) -> Result<Self> {
+        let mut magic = vec![0; MAGIC.len()];
+        source.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            bail!(ErrorKind::BadMagic(
+                String::from_utf8_lossy(&magic).trim_end().into()
+            ));
+        }
+        let header = read_record(&mut source)?
+            .ok_or_else(|| ErrorKind::InvalidRecord("bag header missing".into()))?;
+        if header.header.op()? != OP_BAG_HEADER {
+            bail!(ErrorKind::InvalidRecord(
+                "bag does not start with a bag header".into()
+            ));
+        }
+        Ok(Reader {
+            source,
+            chunk: None,
+            connections: HashMap::new(),
+            finished: false,
+        })
+    }
+
+    /// Connections encountered so far while reading.
+    pub fn connections(&self) -> impl Iterator<Item = &Connection> {
+        self.connections.values()
+    }
+
+    fn read_from_chunk(&mut self) -> Option<Result<BagMessage>> {
+        loop {
+            let record = match read_record(self.chunk.as_mut()?) {
+                Ok(Some(record)) => record,
+                Ok(None) => {
+                    self.chunk = None;
+                    return None;
+                }
+                Err(err) => {
+                    self.chunk = None;
+                    return Some(Err(err));
+                }
+            };
+            if let Some(message) = self.handle_record(record).transpose() {
+                return Some(message);
+            }
+        }
+    }
+
+    fn handle_record(&mut self, record: Record) -> Result<Option<BagMessage>> {
+        match record.header.op()? {
+            OP_CHUNK => {
+                let compression = record.header.string("compression")?;
+                if compression != "none" {
+                    bail!(ErrorKind::UnsupportedCompression(compression));
+                }
+                self.chunk = Some(Cursor::new(record.data));
+            }
+            OP_CONNECTION => {
+                let connection = Connection::from_record(&record)?;
+                self.connections.insert(connection.id, connection);
+            }
+            OP_MESSAGE_DATA => {
+                let id = record.header.u32("conn")?;
+                let connection = self
+                    .connections
+                    .get(&id)
+                    .ok_or(ErrorKind::UnknownConnection(id))?;
+                return Ok(Some(BagMessage {
+                    topic: connection.topic.clone(),
+                    time: record.header.time("time")?,
+                    data: record.data,
+                    md5sum: connection.md5sum.clone(),
+                    msg_type: connection.msg_type.clone(),
+                }));
+            }
+            // Index data and chunk info only speed up random access
+            OP_BAG_HEADER | OP_INDEX_DATA | OP_CHUNK_INFO => {}
+            op => bail!(ErrorKind::InvalidRecord(format!("unknown op code {}", op))),
+        }
+        Ok(None)
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = Result<BagMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(message) = self.read_from_chunk() {
+                return Some(message);
+            }
+            if self.finished {
+                return None;
+            }
+            let record = match read_record(&mut self.source) {
+                Ok(Some(record)) => record,
+                Ok(None) => {
+                    self.finished = true;
+                    return None;
+                }
+                Err(err) => {
+                    self.finished = true;
+                    return Some(Err(err));
+                }
+            };
+            if let Some(message) = self.handle_record(record).transpose() {
+                return Some(message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::record::write_record;
+    use super::*;
+    use crate::rosmsg::RosMsg;
+
+    static FAILED_TO_READ: &'static str = "Failed to read";
+
+    static SMALL_BAG: &[u8] = include_bytes!("fixtures/small.bag");
+
+    fn record(fields: &[(&str, &[u8])], data: &[u8]) -> Vec<u8> {
+        let mut output = vec![];
+        write_record(&mut output, fields, data).expect(FAILED_TO_READ);
+        output
+    }
+
+    fn bag_with_chunk(compression: &str) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend(record(&[("op", &[OP_BAG_HEADER])], b""));
+        data.extend(record(
+            &[("op", &[OP_CHUNK]), ("compression", compression.as_bytes())],
+            b"\x00",
+        ));
+        data
+    }
+
+    #[test]
+    fn reads_messages_from_fixture() {
+        let reader = Reader::new(SMALL_BAG).expect(FAILED_TO_READ);
+        let messages = reader.collect::<Result<Vec<_>>>().expect(FAILED_TO_READ);
+
+        let summary = messages
+            .iter()
+            .map(|m| (m.topic.as_str(), m.time.sec, m.time.nsec, m.md5sum.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("/chatter", 100, 5, "992ce8a1687cec8c8bd883ec73ca41d1"),
+                (
+                    "/count",
+                    100,
+                    250_000_000,
+                    "da5909fbe378aeaf85e547e830cc1bb7"
+                ),
+                ("/chatter", 101, 0, "992ce8a1687cec8c8bd883ec73ca41d1"),
+            ],
+            summary
+        );
+        assert_eq!("std_msgs/Int32", messages[1].msg_type);
+
+        let text = String::decode(messages[0].data.as_slice()).expect(FAILED_TO_READ);
+        assert_eq!("hello", text);
+        let count = i32::decode(messages[1].data.as_slice()).expect(FAILED_TO_READ);
+        assert_eq!(42, count);
+    }
+
+    #[test]
+    fn collects_connections() {
+        let mut reader = Reader::new(SMALL_BAG).expect(FAILED_TO_READ);
+        reader.next().expect(FAILED_TO_READ).expect(FAILED_TO_READ);
+        let mut connections = reader.connections().cloned().collect::<Vec<_>>();
+        connections.sort_by_key(|c| c.id);
+        assert_eq!(2, connections.len());
+        assert_eq!("/count", connections[1].topic);
+        assert_eq!("int32 data\n", connections[1].message_definition);
+        assert_eq!(Some("/talker".into()), connections[0].caller_id);
+        assert!(!connections[0].latching);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let err = Reader::new(&b"#ROSBAG V1.2\nrest of the file"[..])
+            .err()
+            .expect(FAILED_TO_READ);
+        match *err.kind() {
+            ErrorKind::BadMagic(ref line) => assert_eq!("#ROSBAG V1.2", line),
+            ref kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn reports_compressed_chunks() {
+        let data = bag_with_chunk("bz2");
+        let mut reader = Reader::new(data.as_slice()).expect(FAILED_TO_READ);
+        let err = reader
+            .next()
+            .expect(FAILED_TO_READ)
+            .err()
+            .expect(FAILED_TO_READ);
+        match *err.kind() {
+            ErrorKind::UnsupportedCompression(ref compression) => assert_eq!("bz2", compression),
+            ref kind => panic!("Unexpected error: {}", kind),
+        }
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn reports_messages_on_unknown_connections() {
+        let mut data = bag_with_chunk("none");
+        data.extend(record(
+            &[
+                ("op", &[OP_MESSAGE_DATA]),
+                ("conn", &9u32.to_le_bytes()),
+                ("time", &[0; 8]),
+            ],
+            b"",
+        ));
+        let mut reader = Reader::new(data.as_slice()).expect(FAILED_TO_READ);
+        assert!(reader.next().expect(FAILED_TO_READ).is_err());
+    }
+}
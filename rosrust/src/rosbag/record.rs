@@ -0,0 +1,201 @@
+use super::error::{ErrorKind, Result};
+use crate::time::Time;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+pub const MAGIC: &[u8] = b"#ROSBAG V2.0\n";
+
+pub const OP_MESSAGE_DATA: u8 = 0x02;
+pub const OP_BAG_HEADER: u8 = 0x03;
+pub const OP_INDEX_DATA: u8 = 0x04;
+pub const OP_CHUNK: u8 = 0x05;
+pub const OP_CHUNK_INFO: u8 = 0x06;
+pub const OP_CONNECTION: u8 = 0x07;
+
+pub struct Record {
+    pub header: RecordHeader,
+    pub data: Vec<u8>,
+}
+
+/// Record header fields, whose values can hold arbitrary bytes.
+pub struct RecordHeader {
+    fields: HashMap<String, Vec<u8>>,
+}
+
+impl RecordHeader {
+    pub fn decode(mut data: &[u8]) -> Result<RecordHeader> {
+        let mut fields = HashMap::new();
+        while !data.is_empty() {
+            let length = data.read_u32::<LittleEndian>()? as usize;
+            if length > data.len() {
+                bail!(ErrorKind::InvalidRecord(format!(
+                    "header field of {} bytes exceeds the remaining {} bytes",
+                    length,
+                    data.len()
+                )));
+            }
+            let (field, rest) = data.split_at(length);
+            data = rest;
+            let separator = field
+                .iter()
+                .position(|&v| v == b'=')
+                .ok_or_else(|| ErrorKind::InvalidRecord("header field is missing '='".into()))?;
+            let name = String::from_utf8(field[..separator].to_vec()).map_err(|_| {
+                ErrorKind::InvalidRecord("header field name is not valid UTF-8".into())
+            })?;
+            fields.insert(name, field[separator + 1..].to_vec());
+        }
+        Ok(RecordHeader { fields })
+    }
+
+    pub fn bytes(&self, name: &str) -> Result<&[u8]> {
+        self.fields
+            .get(name)
+            .map(Vec::as_slice)
+            .ok_or_else(|| ErrorKind::MissingField(name.into()).into())
+    }
+
+    pub fn op(&self) -> Result<u8> {
+        self.bytes("op")?.read_u8().map_err(Into::into)
+    }
+
+    pub fn u32(&self, name: &str) -> Result<u32> {
+        self.bytes(name)?
+            .read_u32::<LittleEndian>()
+            .map_err(Into::into)
+    }
+
+    pub fn time(&self, name: &str) -> Result<Time> {
+        let mut data = self.bytes(name)?;
+        Ok(Time {
+            sec: data.read_u32::<LittleEndian>()?,
+            nsec: data.read_u32::<LittleEndian>()?,
+        })
+    }
+
+    pub fn string(&self, name: &str) -> Result<String> {
+        String::from_utf8(self.bytes(name)?.to_vec()).map_err(|_| {
+            ErrorKind::InvalidRecord(format!("header field '{}' is not valid UTF-8", name)).into()
+        })
+    }
+
+    #[inline]
+    pub fn has(&self, name: &str) -> bool {
+        self.fields.contains_key(name)
+    }
+}
+
+/// Reads the next record, or `None` if the source ended cleanly between records.
+pub fn read_record<R: Read>(source: &mut R) -> Result<Option<Record>> {
+    let header_length = match source.read_u32::<LittleEndian>() {
+        Ok(v) => v,
+        Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let header = RecordHeader::decode(&read_block(source, header_length)?)?;
+    let data_length = source.read_u32::<LittleEndian>()?;
+    let data = read_block(source, data_length)?;
+    Ok(Some(Record { header, data }))
+}
+
+/// Encodes header fields, without the leading length of the whole header.
+pub fn encode_fields(fields: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for &(name, value) in fields {
+        let length = (name.len() + 1 + value.len()) as u32;
+        data.extend_from_slice(&length.to_le_bytes());
+        data.extend_from_slice(name.as_bytes());
+        data.push(b'=');
+        data.extend_from_slice(value);
+    }
+    data
+}
+
+pub fn write_record<W: Write>(sink: &mut W, fields: &[(&str, &[u8])], data: &[u8]) -> Result<()> {
+    let header = encode_fields(fields);
+    sink.write_u32::<LittleEndian>(header.len() as u32)?;
+    sink.write_all(&header)?;
+    sink.write_u32::<LittleEndian>(data.len() as u32)?;
+    sink.write_all(data)?;
+    Ok(())
+}
+
+pub fn time_bytes(time: Time) -> [u8; 8] {
+    let mut data = [0; 8];
+    data[..4].copy_from_slice(&time.sec.to_le_bytes());
+    data[4..].copy_from_slice(&time.nsec.to_le_bytes());
+    data
+}
+
+fn read_block<R: Read>(source: &mut R, length: u32) -> Result<Vec<u8>> {
+    // Reading through `take` avoids allocating huge buffers for corrupted lengths
+    let mut data = Vec::new();
+    source.take(u64::from(length)).read_to_end(&mut data)?;
+    if data.len() != length as usize {
+        bail!(ErrorKind::InvalidRecord(format!(
+            "expected {} bytes, but the bag ended after {}",
+            length,
+            data.len()
+        )));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FAILED_TO_DECODE: &'static str = "Failed to decode";
+
+    fn field(name: &str, value: &[u8]) -> Vec<u8> {
+        encode_fields(&[(name, value)])
+    }
+
+    #[test]
+    fn decodes_binary_header_fields() {
+        let mut data = field("op", &[OP_MESSAGE_DATA]);
+        data.extend(field("conn", &7u32.to_le_bytes()));
+        data.extend(field("time", &[1, 0, 0, 0, 2, 0, 0, 0]));
+        data.extend(field("topic", b"/a=b"));
+        let header = RecordHeader::decode(&data).expect(FAILED_TO_DECODE);
+
+        assert_eq!(OP_MESSAGE_DATA, header.op().expect(FAILED_TO_DECODE));
+        assert_eq!(7, header.u32("conn").expect(FAILED_TO_DECODE));
+        let time = header.time("time").expect(FAILED_TO_DECODE);
+        assert_eq!((1, 2), (time.sec, time.nsec));
+        assert_eq!("/a=b", header.string("topic").expect(FAILED_TO_DECODE));
+        assert!(header.u32("missing").is_err());
+        assert!(header.time("conn").is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let mut data = field("topic", b"/chatter");
+        data.truncate(data.len() - 1);
+        assert!(RecordHeader::decode(&data).is_err());
+        assert!(RecordHeader::decode(&field("topic", b"")[..4]).is_err());
+    }
+
+    #[test]
+    fn encodes_fields() {
+        assert_eq!(
+            vec![4, 0, 0, 0, b'o', b'p', b'=', 5, 3, 0, 0, 0, b'a', b'=', b'b'],
+            encode_fields(&[("op", &[OP_CHUNK]), ("a", b"b")])
+        );
+    }
+
+    #[test]
+    fn reads_records_until_end() {
+        let mut data = vec![];
+        write_record(&mut data, &[("op", &[OP_CHUNK])], b"abc").expect(FAILED_TO_DECODE);
+        let mut source = io::Cursor::new(data);
+
+        let record = read_record(&mut source)
+            .expect(FAILED_TO_DECODE)
+            .expect(FAILED_TO_DECODE);
+        assert_eq!(OP_CHUNK, record.header.op().expect(FAILED_TO_DECODE));
+        assert_eq!(b"abc".to_vec(), record.data);
+        assert!(read_record(&mut source).expect(FAILED_TO_DECODE).is_none());
+    }
+}
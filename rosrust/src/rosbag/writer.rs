@@ -0,0 +1,419 @@
+use super::error::Result;
+use super::record::{
+    encode_fields, time_bytes, write_record, MAGIC, OP_BAG_HEADER, OP_CHUNK, OP_CHUNK_INFO,
+    OP_CONNECTION, OP_INDEX_DATA, OP_MESSAGE_DATA,
+};
+use crate::tcpros::Message;
+use crate::time::Time;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Size reserved for the bag header record, so it can be rewritten in place on close.
+const BAG_HEADER_LENGTH: usize = 4096;
+
+pub const DEFAULT_CHUNK_THRESHOLD: usize = 768 * 1024;
+
+struct WrittenConnection {
+    id: u32,
+    topic: String,
+    fields: Vec<u8>,
+}
+
+struct ChunkInfo {
+    position: u64,
+    start_time: Time,
+    end_time: Time,
+    counts: BTreeMap<u32, u32>,
+}
+
+/// Writer of version 2.0 bag files.
+///
+/// Messages are grouped into uncompressed chunks of roughly the chunk
+/// threshold in size. Each chunk is followed by index data for its
+/// connections, and the connection and chunk info records are written on
+/// close, after which the bag header is updated to point at them. Dropping
+/// the writer closes it, but ignores any errors.
+pub struct Writer<W: Write + Seek> {
+    sink: W,
+    connections: HashMap<(String, String), WrittenConnection>,
+    chunk: Vec<u8>,
+    chunk_index: BTreeMap<u32, Vec<(Time, u32)>>,
+    chunk_times: Option<(Time, Time)>,
+    chunk_infos: Vec<ChunkInfo>,
+    chunk_threshold: usize,
+    closed: bool,
+}
+
+impl Writer<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Writer::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek> Writer<W> {
+    pub fn new(mut sink: W) -> Result<Self> {
+        sink.write_all(MAGIC)?;
+        write_bag_header(&mut sink, 0, 0, 0)?;
+        Ok(Writer {
+            sink,
+            connections: HashMap::new(),
+            chunk: Vec::new(),
+            chunk_index: BTreeMap::new(),
+            chunk_times: None,
+            chunk_infos: Vec::new(),
+            chunk_threshold: DEFAULT_CHUNK_THRESHOLD,
+            closed: false,
+        })
+    }
+
+    /// Sets the uncompressed size after which a chunk gets written out.
+    pub fn set_chunk_threshold(&mut self, bytes: usize) {
+        self.chunk_threshold = bytes;
+    }
+
+    pub fn write<T: Message>(&mut self, topic: &str, time: &Time, message: &T) -> Result<()> {
+        let mut data = Vec::new();
+        message.encode(&mut data)?;
+        self.write_raw::<T>(topic, time, &data)
+    }
+
+    /// Writes a message that is already serialized as `T`.
+    pub fn write_raw<T: Message>(&mut self, topic: &str, time: &Time, data: &[u8]) -> Result<()> {
+        let id = self.connection_id::<T>(topic)?;
+        let offset = self.chunk.len() as u32;
+        write_record(
+            &mut self.chunk,
+            &[
+                ("op", &[OP_MESSAGE_DATA]),
+                ("conn", &id.to_le_bytes()),
+                ("time", &time_bytes(*time)),
+            ],
+            data,
+        )?;
+        self.chunk_index
+            .entry(id)
+            .or_default()
+            .push((*time, offset));
+        self.chunk_times = Some(match self.chunk_times {
+            Some((start, end)) => (start.min(*time), end.max(*time)),
+            None => (*time, *time),
+        });
+        if self.chunk.len() >= self.chunk_threshold {
+            self.write_chunk()?;
+        }
+        Ok(())
+    }
+
+    /// Writes out the remaining messages and all indexes.
+    pub fn close(mut self) -> Result<()> {
+        self.finish()
+    }
+
+    fn connection_id<T: Message>(&mut self, topic: &str) -> Result<u32> {
+        let key = (topic.to_owned(), T::md5sum());
+        if let Some(connection) = self.connections.get(&key) {
+            return Ok(connection.id);
+        }
+        let connection = WrittenConnection {
+            id: self.connections.len() as u32,
+            topic: topic.into(),
+            fields: encode_fields(&[
+                ("topic", topic.as_bytes()),
+                ("type", T::msg_type().as_bytes()),
+                ("md5sum", T::md5sum().as_bytes()),
+                ("message_definition", T::msg_definition().as_bytes()),
+            ]),
+        };
+        // Readers need the connection record before the first message on it
+        write_connection(&mut self.chunk, &connection)?;
+        let id = connection.id;
+        self.connections.insert(key, connection);
+        Ok(id)
+    }
+
+    fn write_chunk(&mut self) -> Result<()> {
+        let (start_time, end_time) = match self.chunk_times.take() {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let position = self.sink.stream_position()?;
+        write_record(
+            &mut self.sink,
+            &[
+                ("op", &[OP_CHUNK]),
+                ("compression", b"none"),
+                ("size", &(self.chunk.len() as u32).to_le_bytes()),
+            ],
+            &self.chunk,
+        )?;
+        self.chunk.clear();
+
+        let mut counts = BTreeMap::new();
+        for (id, entries) in std::mem::take(&mut self.chunk_index) {
+            let mut data = Vec::with_capacity(entries.len() * 12);
+            for &(time, offset) in &entries {
+                data.extend_from_slice(&time_bytes(time));
+                data.extend_from_slice(&offset.to_le_bytes());
+            }
+            write_record(
+                &mut self.sink,
+                &[
+                    ("op", &[OP_INDEX_DATA]),
+                    ("ver", &1u32.to_le_bytes()),
+                    ("conn", &id.to_le_bytes()),
+                    ("count", &(entries.len() as u32).to_le_bytes()),
+                ],
+                &data,
+            )?;
+            counts.insert(id, entries.len() as u32);
+        }
+        self.chunk_infos.push(ChunkInfo {
+            position,
+            start_time,
+            end_time,
+            counts,
+        });
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        self.write_chunk()?;
+
+        let index_position = self.sink.stream_position()?;
+        let mut connections = self.connections.values().collect::<Vec<_>>();
+        connections.sort_by_key(|connection| connection.id);
+        for connection in connections {
+            write_connection(&mut self.sink, connection)?;
+        }
+        for info in &self.chunk_infos {
+            let mut data = Vec::with_capacity(info.counts.len() * 8);
+            for (id, count) in &info.counts {
+                data.extend_from_slice(&id.to_le_bytes());
+                data.extend_from_slice(&count.to_le_bytes());
+            }
+            write_record(
+                &mut self.sink,
+                &[
+                    ("op", &[OP_CHUNK_INFO]),
+                    ("ver", &1u32.to_le_bytes()),
+                    ("chunk_pos", &info.position.to_le_bytes()),
+                    ("start_time", &time_bytes(info.start_time)),
+                    ("end_time", &time_bytes(info.end_time)),
+                    ("count", &(info.counts.len() as u32).to_le_bytes()),
+                ],
+                &data,
+            )?;
+        }
+
+        self.sink.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+        write_bag_header(
+            &mut self.sink,
+            index_position,
+            self.connections.len() as u32,
+            self.chunk_infos.len() as u32,
+        )?;
+        self.sink.seek(SeekFrom::End(0))?;
+        self.sink.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Drop for Writer<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+fn write_connection<W: Write>(sink: &mut W, connection: &WrittenConnection) -> Result<()> {
+    write_record(
+        sink,
+        &[
+            ("op", &[OP_CONNECTION]),
+            ("conn", &connection.id.to_le_bytes()),
+            ("topic", connection.topic.as_bytes()),
+        ],
+        &connection.fields,
+    )
+}
+
+fn write_bag_header<W: Write>(
+    sink: &mut W,
+    index_position: u64,
+    connection_count: u32,
+    chunk_count: u32,
+) -> Result<()> {
+    let index_position = index_position.to_le_bytes();
+    let connection_count = connection_count.to_le_bytes();
+    let chunk_count = chunk_count.to_le_bytes();
+    let fields: [(&str, &[u8]); 4] = [
+        ("op", &[OP_BAG_HEADER]),
+        ("index_pos", &index_position),
+        ("conn_count", &connection_count),
+        ("chunk_count", &chunk_count),
+    ];
+    // The header length and fields take up part of the reserved space, padding fills the rest
+    let padding = BAG_HEADER_LENGTH - 4 - encode_fields(&fields).len();
+    write_record(sink, &fields, &vec![b' '; padding])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::record::{read_record, RecordHeader};
+    use super::super::Reader;
+    use super::*;
+    use crate::msg::rosgraph_msgs::{Clock, Log};
+    use crate::rosmsg::RosMsg;
+    use std::io::Cursor;
+
+    static FAILED_TO_WRITE: &'static str = "Failed to write";
+    static FAILED_TO_READ: &'static str = "Failed to read";
+
+    fn clock(sec: u32) -> Clock {
+        Clock {
+            clock: Time { sec, nsec: 0 },
+        }
+    }
+
+    fn log(text: &str) -> Log {
+        Log {
+            level: Log::INFO,
+            msg: text.into(),
+            ..Default::default()
+        }
+    }
+
+    fn write_bag(chunk_threshold: usize) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        let mut writer = Writer::new(&mut data).expect(FAILED_TO_WRITE);
+        writer.set_chunk_threshold(chunk_threshold);
+        for sec in 1..=3 {
+            let time = Time { sec, nsec: 500 };
+            writer
+                .write("/clock", &time, &clock(sec))
+                .expect(FAILED_TO_WRITE);
+            writer
+                .write("/rosout", &time, &log(&format!("tick {}", sec)))
+                .expect(FAILED_TO_WRITE);
+        }
+        writer.close().expect(FAILED_TO_WRITE);
+        data.into_inner()
+    }
+
+    fn records(data: &[u8]) -> Vec<(u8, RecordHeader)> {
+        let mut source = &data[MAGIC.len()..];
+        let mut output = vec![];
+        while let Some(record) = read_record(&mut source).expect(FAILED_TO_READ) {
+            output.push((record.header.op().expect(FAILED_TO_READ), record.header));
+        }
+        output
+    }
+
+    #[test]
+    fn written_messages_read_back() {
+        for &threshold in &[DEFAULT_CHUNK_THRESHOLD, 1] {
+            let data = write_bag(threshold);
+            let messages = Reader::new(data.as_slice())
+                .expect(FAILED_TO_READ)
+                .collect::<Result<Vec<_>>>()
+                .expect(FAILED_TO_READ);
+            assert_eq!(6, messages.len());
+            for (sec, pair) in (1..=3).zip(messages.chunks(2)) {
+                assert_eq!("/clock", pair[0].topic);
+                assert_eq!(Clock::md5sum(), pair[0].md5sum);
+                assert_eq!((sec, 500), (pair[0].time.sec, pair[0].time.nsec));
+                let decoded = Clock::decode(pair[0].data.as_slice()).expect(FAILED_TO_READ);
+                assert_eq!(clock(sec), decoded);
+
+                assert_eq!("/rosout", pair[1].topic);
+                assert_eq!("rosgraph_msgs/Log", pair[1].msg_type);
+                let decoded = Log::decode(pair[1].data.as_slice()).expect(FAILED_TO_READ);
+                assert_eq!(log(&format!("tick {}", sec)), decoded);
+            }
+        }
+    }
+
+    #[test]
+    fn bag_header_points_at_index() {
+        let data = write_bag(1);
+        let records = records(&data);
+        let header = &records[0].1;
+        assert_eq!(OP_BAG_HEADER, records[0].0);
+        assert_eq!(2, header.u32("conn_count").expect(FAILED_TO_READ));
+        assert_eq!(6, header.u32("chunk_count").expect(FAILED_TO_READ));
+
+        let mut index_position = [0; 8];
+        index_position.copy_from_slice(header.bytes("index_pos").expect(FAILED_TO_READ));
+        let index_position = u64::from_le_bytes(index_position) as usize;
+        let index = records_from(&data, index_position);
+        let ops = index.iter().map(|v| v.0).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                OP_CONNECTION,
+                OP_CONNECTION,
+                OP_CHUNK_INFO,
+                OP_CHUNK_INFO,
+                OP_CHUNK_INFO,
+                OP_CHUNK_INFO,
+                OP_CHUNK_INFO,
+                OP_CHUNK_INFO,
+            ],
+            ops
+        );
+        let positions = index[2..]
+            .iter()
+            .map(|(_, info)| {
+                let mut position = [0; 8];
+                position.copy_from_slice(info.bytes("chunk_pos").expect(FAILED_TO_READ));
+                u64::from_le_bytes(position) as usize
+            })
+            .collect::<Vec<_>>();
+        // The first chunk directly follows the space reserved for the bag header
+        assert_eq!(MAGIC.len() + BAG_HEADER_LENGTH + 4, positions[0]);
+        for position in positions {
+            let chunk = records_from(&data, position);
+            assert_eq!(OP_CHUNK, chunk[0].0);
+            assert_eq!(OP_INDEX_DATA, chunk[1].0);
+        }
+    }
+
+    fn records_from(data: &[u8], position: usize) -> Vec<(u8, RecordHeader)> {
+        let mut prefixed = MAGIC.to_vec();
+        prefixed.extend_from_slice(&data[position..]);
+        records(&prefixed)
+    }
+
+    #[test]
+    fn index_offsets_point_at_messages() {
+        let data = write_bag(DEFAULT_CHUNK_THRESHOLD);
+        let mut source = &data[MAGIC.len()..];
+        read_record(&mut source).expect(FAILED_TO_READ);
+        let chunk = read_record(&mut source)
+            .expect(FAILED_TO_READ)
+            .expect(FAILED_TO_READ);
+        let index = read_record(&mut source)
+            .expect(FAILED_TO_READ)
+            .expect(FAILED_TO_READ);
+        assert_eq!(OP_INDEX_DATA, index.header.op().expect(FAILED_TO_READ));
+        assert_eq!(3, index.header.u32("count").expect(FAILED_TO_READ));
+
+        for entry in index.data.chunks(12) {
+            let mut offset = [0; 4];
+            offset.copy_from_slice(&entry[8..]);
+            let mut message = &chunk.data[u32::from_le_bytes(offset) as usize..];
+            let message = read_record(&mut message)
+                .expect(FAILED_TO_READ)
+                .expect(FAILED_TO_READ);
+            assert_eq!(OP_MESSAGE_DATA, message.header.op().expect(FAILED_TO_READ));
+            assert_eq!(
+                &entry[..8],
+                message.header.bytes("time").expect(FAILED_TO_READ)
+            );
+        }
+    }
+}
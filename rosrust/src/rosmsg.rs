@@ -2,25 +2,103 @@ use crate::time::{Duration, Time};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Default for the largest size accepted from a length prefix, 100 MB.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 100 * 1024 * 1024;
+
+static MAX_MESSAGE_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_MESSAGE_SIZE);
+
+/// Sets the largest size in bytes that length prefixes of received data may announce.
+///
+/// Messages, strings and arrays are checked against it before memory is allocated
+/// for them, so a corrupt or hostile peer cannot exhaust memory with a single
+/// huge length prefix. The limit applies to the whole process.
+#[inline]
+pub fn set_max_message_size(size: usize) {
+    MAX_MESSAGE_SIZE.store(size, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn max_message_size() -> usize {
+    MAX_MESSAGE_SIZE.load(Ordering::Relaxed)
+}
+
+/// Fails for sizes above the limit set with `set_max_message_size`.
+pub fn check_message_size(size: usize) -> io::Result<()> {
+    let max_size = max_message_size();
+    if size > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Length of {} bytes exceeds the limit of {} bytes",
+                size, max_size
+            ),
+        ));
+    }
+    Ok(())
+}
 
 pub trait RosMsg: std::marker::Sized {
+    /// Fewest bytes that any encoded value takes up.
+    ///
+    /// Length prefixes of arrays are checked against the message size limit
+    /// with it, since a valid array cannot be shorter than its element count
+    /// times this length.
+    const MIN_ENCODED_LEN: usize = 0;
+
     fn encode<W: io::Write>(&self, w: W) -> io::Result<()>;
     fn decode<R: io::Read>(r: R) -> io::Result<Self>;
 
     #[inline]
     fn encode_vec(&self) -> io::Result<Vec<u8>> {
-        let mut writer = io::Cursor::new(Vec::with_capacity(128));
+        self.encode_vec_with_capacity(128)
+    }
+
+    /// Like `encode_vec`, but preallocates the buffer, which avoids repeated
+    /// reallocation when encoding large messages of roughly known size.
+    fn encode_vec_with_capacity(&self, capacity: usize) -> io::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(capacity + 4);
+        self.encode_into_vec(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Like `encode_vec`, but replaces the contents of an existing buffer,
+    /// keeping its allocation so it can be reused across many messages.
+    fn encode_into_vec(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        buffer.clear();
+        let mut writer = io::Cursor::new(buffer);
         // skip the first 4 bytes that will contain the message length
         writer.set_position(4);
 
         self.encode(&mut writer)?;
 
         // write the message length to the start of the header
-        let message_length = (writer.position() - 4) as u32;
+        let message_length = writer.position() as usize - 4;
         writer.set_position(0);
-        message_length.encode(&mut writer)?;
-        Ok(writer.into_inner())
+        write_data_size(message_length, &mut writer)
+    }
+
+    /// Number of bytes produced by `encode`, excluding the length prefix.
+    fn encoded_len(&self) -> io::Result<usize> {
+        let mut counter = ByteCounter(0);
+        self.encode(&mut counter)?;
+        Ok(counter.0)
+    }
+
+    /// Writes the length prefixed message directly into the writer, without
+    /// buffering the whole message in memory first.
+    ///
+    /// Nested strings and vectors already know their size before being written,
+    /// so only the outer length prefix is unknown upfront. It is obtained with a
+    /// dry run of the encoding that only counts bytes, trading a second pass over
+    /// the message for not holding a full copy of it.
+    fn encode_stream<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        let message_length = self.encoded_len()?;
+        write_data_size(message_length, w.by_ref())?;
+        self.encode(w)
     }
 
     #[inline]
@@ -32,7 +110,24 @@ pub trait RosMsg: std::marker::Sized {
     }
 }
 
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 impl RosMsg for bool {
+    const MIN_ENCODED_LEN: usize = 1;
+
     #[inline]
     fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
         w.write_u8(*self as u8)
@@ -45,6 +140,8 @@ impl RosMsg for bool {
 }
 
 impl RosMsg for u8 {
+    const MIN_ENCODED_LEN: usize = 1;
+
     #[inline]
     fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
         w.write_u8(*self)
@@ -57,6 +154,8 @@ impl RosMsg for u8 {
 }
 
 impl RosMsg for i8 {
+    const MIN_ENCODED_LEN: usize = 1;
+
     #[inline]
     fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
         w.write_i8(*self)
@@ -69,6 +168,8 @@ impl RosMsg for i8 {
 }
 
 impl RosMsg for u16 {
+    const MIN_ENCODED_LEN: usize = 2;
+
     #[inline]
     fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
         w.write_u16::<LittleEndian>(*self)
@@ -81,6 +182,8 @@ impl RosMsg for u16 {
 }
 
 impl RosMsg for i16 {
+    const MIN_ENCODED_LEN: usize = 2;
+
     #[inline]
     fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
         w.write_i16::<LittleEndian>(*self)
@@ -93,6 +196,8 @@ impl RosMsg for i16 {
 }
 
 impl RosMsg for u32 {
+    const MIN_ENCODED_LEN: usize = 4;
+
     #[inline]
     fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
         w.write_u32::<LittleEndian>(*self)
@@ -105,6 +210,8 @@ impl RosMsg for u32 {
 }
 
 impl RosMsg for i32 {
+    const MIN_ENCODED_LEN: usize = 4;
+
     #[inline]
     fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
         w.write_i32::<LittleEndian>(*self)
@@ -117,6 +224,8 @@ impl RosMsg for i32 {
 }
 
 impl RosMsg for u64 {
+    const MIN_ENCODED_LEN: usize = 8;
+
     #[inline]
     fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
         w.write_u64::<LittleEndian>(*self)
@@ -129,6 +238,8 @@ impl RosMsg for u64 {
 }
 
 impl RosMsg for i64 {
+    const MIN_ENCODED_LEN: usize = 8;
+
     #[inline]
     fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
         w.write_i64::<LittleEndian>(*self)
@@ -141,6 +252,8 @@ impl RosMsg for i64 {
 }
 
 impl RosMsg for f32 {
+    const MIN_ENCODED_LEN: usize = 4;
+
     #[inline]
     fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
         w.write_f32::<LittleEndian>(*self)
@@ -153,6 +266,8 @@ impl RosMsg for f32 {
 }
 
 impl RosMsg for f64 {
+    const MIN_ENCODED_LEN: usize = 8;
+
     #[inline]
     fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
         w.write_f64::<LittleEndian>(*self)
@@ -164,6 +279,79 @@ impl RosMsg for f64 {
     }
 }
 
+/// Characters are sent as a single byte, matching the ROS `char` type,
+/// so only characters in the range `'\0'..='\u{ff}'` can be encoded.
+impl RosMsg for char {
+    const MIN_ENCODED_LEN: usize = 1;
+
+    #[inline]
+    fn encode<W: io::Write>(&self, w: W) -> io::Result<()> {
+        let value = *self as u32;
+        if value > 0xff {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Character '{}' does not fit within a single byte", self),
+            ));
+        }
+        (value as u8).encode(w)
+    }
+
+    #[inline]
+    fn decode<R: io::Read>(r: R) -> io::Result<Self> {
+        u8::decode(r).map(char::from)
+    }
+}
+
+/// Messages that always encode to the same number of bytes.
+///
+/// Arrays of them get encoded and decoded as a single block of memory when
+/// their memory layout matches the wire format on a little-endian target.
+/// `rosrust_codegen` implements it for messages without strings and
+/// variable length arrays.
+///
+/// # Safety
+///
+/// `ENCODED_LEN` must be the length of every encoded value. `MATCHES_MEMORY_LAYOUT`
+/// may only be true if values are stored in memory exactly as they are encoded
+/// on little-endian targets, without any padding, and if any encoded data
+/// forms a valid value.
+pub unsafe trait FixedSize: RosMsg {
+    const ENCODED_LEN: usize;
+    const MATCHES_MEMORY_LAYOUT: bool;
+}
+
+macro_rules! impl_fixed_size_primitive {
+    ($($t:ty),*) => {$(
+        unsafe impl FixedSize for $t {
+            const ENCODED_LEN: usize = std::mem::size_of::<$t>();
+            const MATCHES_MEMORY_LAYOUT: bool = true;
+        }
+    )*};
+}
+
+impl_fixed_size_primitive!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64);
+
+// Only 0 and 1 are valid in memory, while the wire format permits any byte
+unsafe impl FixedSize for bool {
+    const ENCODED_LEN: usize = 1;
+    const MATCHES_MEMORY_LAYOUT: bool = false;
+}
+
+unsafe impl FixedSize for Time {
+    const ENCODED_LEN: usize = 8;
+    const MATCHES_MEMORY_LAYOUT: bool = std::mem::size_of::<Time>() == 8;
+}
+
+unsafe impl FixedSize for Duration {
+    const ENCODED_LEN: usize = 8;
+    const MATCHES_MEMORY_LAYOUT: bool = std::mem::size_of::<Duration>() == 8;
+}
+
+#[inline]
+fn copies_memory<T: FixedSize>() -> bool {
+    cfg!(target_endian = "little") && T::MATCHES_MEMORY_LAYOUT
+}
+
 #[inline]
 pub fn encode_fixed_slice<W: io::Write, T: RosMsg>(data: &[T], mut w: W) -> io::Result<()> {
     data.iter().try_for_each(|v| v.encode(w.by_ref()))
@@ -176,13 +364,56 @@ pub fn decode_fixed_vec<R: io::Read, T: RosMsg>(len: u32, mut r: R) -> io::Resul
 
 #[inline]
 pub fn encode_variable_slice<W: io::Write, T: RosMsg>(data: &[T], mut w: W) -> io::Result<()> {
-    (data.len() as u32).encode(w.by_ref())?;
+    write_data_size(data.len(), w.by_ref())?;
     encode_fixed_slice(data, w)
 }
 
 #[inline]
 pub fn decode_variable_vec<R: io::Read, T: RosMsg>(mut r: R) -> io::Result<Vec<T>> {
-    decode_fixed_vec(u32::decode(r.by_ref())?, r)
+    let num_elements = u32::decode(r.by_ref())?;
+    // Elements that may encode to nothing still count as a byte each, so the
+    // element count stays bounded by the limit too
+    let min_num_bytes = (num_elements as usize).saturating_mul(T::MIN_ENCODED_LEN.max(1));
+    check_message_size(min_num_bytes)?;
+    decode_fixed_vec(num_elements, r)
+}
+
+/// Fast vector encoding for fixed size messages, writing the whole
+/// array at once if their memory layout matches the wire format.
+#[inline]
+pub fn encode_variable_fixed_size_slice<W: io::Write, T: FixedSize>(
+    data: &[T],
+    mut w: W,
+) -> io::Result<()> {
+    if !copies_memory::<T>() {
+        return encode_variable_slice(data, w);
+    }
+    write_data_size(data.len(), w.by_ref())?;
+    let ptr = data.as_ptr() as *const u8;
+    // Values hold no padding, so all of their bytes are initialized
+    w.write_all(unsafe { std::slice::from_raw_parts(ptr, data.len() * T::ENCODED_LEN) })
+}
+
+/// Fast vector decoding for fixed size messages, reading the whole
+/// array at once if their memory layout matches the wire format.
+#[inline]
+pub fn decode_variable_fixed_size_vec<R: io::Read, T: FixedSize>(mut r: R) -> io::Result<Vec<T>> {
+    if !copies_memory::<T>() {
+        return decode_variable_vec(r);
+    }
+    let num_elements = u32::decode(r.by_ref())? as usize;
+    let num_bytes = num_elements.saturating_mul(T::ENCODED_LEN);
+    check_message_size(num_bytes)?;
+
+    let mut buf = Vec::<T>::with_capacity(num_elements);
+    // Any data forms a valid value, so zeroed memory does too
+    unsafe {
+        std::ptr::write_bytes(buf.as_mut_ptr(), 0, num_elements);
+        buf.set_len(num_elements);
+    }
+    let ptr = buf.as_mut_ptr() as *mut u8;
+    r.read_exact(unsafe { std::slice::from_raw_parts_mut(ptr, num_bytes) })?;
+    Ok(buf)
 }
 
 /// Fast vector encoding when platform endiannes matches wire
@@ -193,13 +424,12 @@ pub fn encode_variable_primitive_slice<W: io::Write, T: RosMsg>(
     data: &[T],
     mut w: W,
 ) -> io::Result<()> {
-    (data.len() as u32).encode(w.by_ref())?;
+    write_data_size(data.len(), w.by_ref())?;
     let ptr = data.as_ptr() as *const u8;
 
     // Because both wire and system are little endian, we simply copy
     // the in-memory slice to the buffer directly.
-    w.write(unsafe { std::slice::from_raw_parts(ptr, data.len() * std::mem::size_of::<T>()) })
-        .map(|_| ())
+    w.write_all(unsafe { std::slice::from_raw_parts(ptr, data.len() * std::mem::size_of::<T>()) })
 }
 
 #[inline]
@@ -217,7 +447,8 @@ pub fn encode_variable_primitive_slice<W: io::Write, T: RosMsg>(
 #[cfg(target_endian = "little")]
 pub fn decode_variable_primitive_vec<R: io::Read, T: RosMsg>(mut r: R) -> io::Result<Vec<T>> {
     let num_elements = u32::decode(r.by_ref())? as usize;
-    let num_bytes = num_elements * std::mem::size_of::<T>();
+    let num_bytes = num_elements.saturating_mul(std::mem::size_of::<T>());
+    check_message_size(num_bytes)?;
 
     // Allocate the memory w/o initializing because we will later fill
     // all the memory.
@@ -242,12 +473,31 @@ pub fn decode_variable_primitive_vec<R: io::Read, T: RosMsg>(mut r: R) -> io::Re
     decode_variable_vec(r)
 }
 
+/// Byte array encoding with a single write, independent of platform endianness.
+#[inline]
+pub fn encode_byte_slice<W: io::Write>(data: &[u8], mut w: W) -> io::Result<()> {
+    write_data_size(data.len(), w.by_ref())?;
+    w.write_all(data)
+}
+
+/// Byte array decoding with a single read, independent of platform endianness.
+#[inline]
+pub fn decode_byte_vec<R: io::Read>(mut r: R) -> io::Result<Vec<u8>> {
+    let num_bytes = u32::decode(r.by_ref())? as usize;
+    check_message_size(num_bytes)?;
+    let mut buf = vec![0; num_bytes];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
 #[inline]
 pub fn encode_str<W: io::Write>(value: &str, w: W) -> io::Result<()> {
-    encode_variable_slice(value.as_bytes(), w)
+    encode_byte_slice(value.as_bytes(), w)
 }
 
 impl RosMsg for String {
+    const MIN_ENCODED_LEN: usize = 4;
+
     #[inline]
     fn encode<W: io::Write>(&self, w: W) -> io::Result<()> {
         encode_str(self, w)
@@ -255,17 +505,21 @@ impl RosMsg for String {
 
     #[inline]
     fn decode<R: io::Read>(r: R) -> io::Result<Self> {
-        decode_variable_vec::<R, u8>(r).and_then(|v| {
+        decode_byte_vec(r).and_then(|v| {
             String::from_utf8(v).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
         })
     }
 }
 
+/// Maps use the format of connection headers: the total size of the rows,
+/// followed by a length-prefixed `key=value` string for each entry.
 impl<Hasher> RosMsg for HashMap<String, String, Hasher>
 where
     Hasher: std::hash::BuildHasher,
     HashMap<String, String, Hasher>: Default,
 {
+    const MIN_ENCODED_LEN: usize = 4;
+
     #[inline]
     fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
         let rows = self
@@ -273,7 +527,7 @@ where
             .map(|(key, value)| format!("{}={}", key, value))
             .collect::<Vec<String>>();
         let data_size: usize = rows.iter().map(|item| item.len() + 4).sum();
-        write_data_size(data_size as u32, w.by_ref())?;
+        write_data_size(data_size, w.by_ref())?;
         rows.into_iter()
             .try_for_each(|item| item.encode(w.by_ref()))
     }
@@ -283,8 +537,8 @@ where
         let data_size = u64::from(read_data_size(r.by_ref())?);
         let mut limited_r = r.take(data_size);
         let mut output = HashMap::<String, String, Hasher>::default();
-        // TODO: ensure we break only on EOF
-        while let Ok(item) = String::decode(&mut limited_r) {
+        while limited_r.limit() > 0 {
+            let item = String::decode(&mut limited_r)?;
             let parts = item.splitn(2, '=').collect::<Vec<&str>>();
             match *parts.as_slice() {
                 [key, value] => output.insert(key.into(), value.into()),
@@ -300,7 +554,32 @@ where
     }
 }
 
+impl<T: RosMsg> RosMsg for Option<T> {
+    /// Optional values are written as the bare inner value, or nothing at all
+    /// when absent, since ROS messages have no native notion of optionals.
+    #[inline]
+    fn encode<W: io::Write>(&self, w: W) -> io::Result<()> {
+        match *self {
+            Some(ref value) => value.encode(w),
+            None => Ok(()),
+        }
+    }
+
+    /// Because absent values occupy no space on the wire, this is only
+    /// unambiguous for trailing fields. Running out of data is treated as `None`.
+    #[inline]
+    fn decode<R: io::Read>(r: R) -> io::Result<Self> {
+        match T::decode(r) {
+            Ok(value) => Ok(Some(value)),
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
 impl RosMsg for Time {
+    const MIN_ENCODED_LEN: usize = 8;
+
     #[inline]
     fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
         self.sec.encode(w.by_ref())?;
@@ -318,6 +597,8 @@ impl RosMsg for Time {
 }
 
 impl RosMsg for Duration {
+    const MIN_ENCODED_LEN: usize = 8;
+
     #[inline]
     fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
         self.sec.encode(w.by_ref())?;
@@ -339,7 +620,381 @@ fn read_data_size<R: io::Read>(r: R) -> io::Result<u32> {
     u32::decode(r)
 }
 
+/// Sizes are sent as 32 bit integers, so larger values are rejected
+/// instead of being silently truncated into a corrupt message.
 #[inline]
-fn write_data_size<W: io::Write>(value: u32, w: W) -> io::Result<()> {
+fn write_data_size<W: io::Write>(value: usize, w: W) -> io::Result<()> {
+    let value = u32::try_from(value).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Message too long, size {} does not fit in 32 bits", value),
+        )
+    })?;
     value.encode(w)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FAILED_TO_ENCODE: &'static str = "Failed to encode";
+    static FAILED_TO_DECODE: &'static str = "Failed to decode";
+
+    fn encode_to_vec<T: RosMsg>(value: &T) -> Vec<u8> {
+        let mut cursor = io::Cursor::new(Vec::new());
+        value.encode(&mut cursor).expect(FAILED_TO_ENCODE);
+        cursor.into_inner()
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct FloatArray(Vec<f32>);
+
+    impl RosMsg for FloatArray {
+        fn encode<W: io::Write>(&self, w: W) -> io::Result<()> {
+            encode_variable_primitive_slice(&self.0, w)
+        }
+
+        fn decode<R: io::Read>(r: R) -> io::Result<Self> {
+            decode_variable_primitive_vec(r).map(FloatArray)
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    #[repr(C)]
+    struct Point {
+        x: f64,
+        y: f64,
+        z: f64,
+    }
+
+    impl RosMsg for Point {
+        fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+            self.x.encode(w.by_ref())?;
+            self.y.encode(w.by_ref())?;
+            self.z.encode(w)
+        }
+
+        fn decode<R: io::Read>(mut r: R) -> io::Result<Self> {
+            Ok(Self {
+                x: RosMsg::decode(r.by_ref())?,
+                y: RosMsg::decode(r.by_ref())?,
+                z: RosMsg::decode(r)?,
+            })
+        }
+    }
+
+    unsafe impl FixedSize for Point {
+        const ENCODED_LEN: usize = 24;
+        const MATCHES_MEMORY_LAYOUT: bool = std::mem::size_of::<Point>() == 24;
+    }
+
+    // Padding after the flag keeps the memory layout from matching
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    #[repr(C)]
+    struct Flagged {
+        flag: u8,
+        value: u32,
+    }
+
+    impl RosMsg for Flagged {
+        fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+            self.flag.encode(w.by_ref())?;
+            self.value.encode(w)
+        }
+
+        fn decode<R: io::Read>(mut r: R) -> io::Result<Self> {
+            Ok(Self {
+                flag: RosMsg::decode(r.by_ref())?,
+                value: RosMsg::decode(r)?,
+            })
+        }
+    }
+
+    unsafe impl FixedSize for Flagged {
+        const ENCODED_LEN: usize = 5;
+        const MATCHES_MEMORY_LAYOUT: bool = std::mem::size_of::<Flagged>() == 5;
+    }
+
+    fn assert_fixed_size_arrays_match_elementwise<T>(data: &[T])
+    where
+        T: FixedSize + std::fmt::Debug + PartialEq,
+    {
+        let mut elementwise = Vec::new();
+        encode_variable_slice(data, &mut elementwise).expect(FAILED_TO_ENCODE);
+        let mut bulk = Vec::new();
+        encode_variable_fixed_size_slice(data, &mut bulk).expect(FAILED_TO_ENCODE);
+        assert_eq!(elementwise, bulk);
+        assert_eq!(4 + data.len() * T::ENCODED_LEN, bulk.len());
+
+        let decoded: Vec<T> =
+            decode_variable_fixed_size_vec(bulk.as_slice()).expect(FAILED_TO_DECODE);
+        assert_eq!(data, decoded.as_slice());
+        let decoded: Vec<T> = decode_variable_vec(bulk.as_slice()).expect(FAILED_TO_DECODE);
+        assert_eq!(data, decoded.as_slice());
+    }
+
+    #[test]
+    fn bulk_paths_produce_little_endian_wire_format() {
+        // Copying memory is only correct on targets that store values like the wire format
+        assert_eq!(cfg!(target_endian = "little"), copies_memory::<u32>());
+        assert_eq!(cfg!(target_endian = "little"), copies_memory::<Time>());
+        assert!(!copies_memory::<bool>());
+
+        let data = [1u32, 0x0102_0304];
+        let expected = vec![2, 0, 0, 0, 1, 0, 0, 0, 4, 3, 2, 1];
+        let mut bulk = Vec::new();
+        encode_variable_primitive_slice(&data, &mut bulk).expect(FAILED_TO_ENCODE);
+        assert_eq!(expected, bulk);
+        let mut bulk = Vec::new();
+        encode_variable_fixed_size_slice(&data, &mut bulk).expect(FAILED_TO_ENCODE);
+        assert_eq!(expected, bulk);
+
+        let decoded: Vec<u32> =
+            decode_variable_primitive_vec(expected.as_slice()).expect(FAILED_TO_DECODE);
+        assert_eq!(&data, decoded.as_slice());
+        let decoded: Vec<u32> =
+            decode_variable_fixed_size_vec(expected.as_slice()).expect(FAILED_TO_DECODE);
+        assert_eq!(&data, decoded.as_slice());
+
+        let times = [Time::from_nanos(1_000_000_258)];
+        let mut bulk = Vec::new();
+        encode_variable_fixed_size_slice(&times, &mut bulk).expect(FAILED_TO_ENCODE);
+        assert_eq!(vec![1, 0, 0, 0, 1, 0, 0, 0, 2, 1, 0, 0], bulk);
+    }
+
+    #[test]
+    fn fixed_size_arrays_match_elementwise_encoding() {
+        use crate::msg::rosgraph_msgs::Clock;
+
+        assert!(Point::MATCHES_MEMORY_LAYOUT);
+        let points = (0..100)
+            .map(|v| Point {
+                x: f64::from(v),
+                y: -f64::from(v) / 3.0,
+                z: f64::from(v) * 1e10,
+            })
+            .collect::<Vec<_>>();
+        assert_fixed_size_arrays_match_elementwise(&points);
+        assert_fixed_size_arrays_match_elementwise::<Point>(&[]);
+
+        let times = (0..100)
+            .map(|v| Time {
+                sec: v,
+                nsec: v * 7,
+            })
+            .collect::<Vec<_>>();
+        assert_fixed_size_arrays_match_elementwise(&times);
+
+        assert!(Clock::MATCHES_MEMORY_LAYOUT);
+        let clocks = times
+            .into_iter()
+            .map(|clock| Clock { clock })
+            .collect::<Vec<_>>();
+        assert_fixed_size_arrays_match_elementwise(&clocks);
+    }
+
+    #[test]
+    fn fixed_size_arrays_fall_back_to_elementwise_encoding() {
+        assert!(!Flagged::MATCHES_MEMORY_LAYOUT);
+        let values = (0..100)
+            .map(|v| Flagged {
+                flag: v as u8,
+                value: v * 1000,
+            })
+            .collect::<Vec<_>>();
+        assert_fixed_size_arrays_match_elementwise(&values);
+        assert_fixed_size_arrays_match_elementwise(&[true, false, true]);
+    }
+
+    #[test]
+    fn fails_on_fixed_size_array_shorter_than_declared() {
+        let mut data = Vec::new();
+        encode_variable_fixed_size_slice(&[Point::default(); 3], &mut data)
+            .expect(FAILED_TO_ENCODE);
+        data.truncate(data.len() - 1);
+        let err = decode_variable_fixed_size_vec::<_, Point>(data.as_slice()).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+
+    #[test]
+    fn byte_arrays_match_elementwise_encoding() {
+        let data = (0..=255).cycle().take(1000).collect::<Vec<u8>>();
+        let mut expected = Vec::new();
+        encode_variable_slice(&data, &mut expected).expect(FAILED_TO_ENCODE);
+        let mut actual = Vec::new();
+        encode_byte_slice(&data, &mut actual).expect(FAILED_TO_ENCODE);
+        assert_eq!(expected, actual);
+        assert_eq!(
+            data,
+            decode_byte_vec(actual.as_slice()).expect(FAILED_TO_DECODE)
+        );
+        let elementwise: Vec<u8> = decode_variable_vec(actual.as_slice()).expect(FAILED_TO_DECODE);
+        assert_eq!(data, elementwise);
+    }
+
+    #[test]
+    fn fails_on_byte_array_shorter_than_declared() {
+        let input = [5, 0, 0, 0, 1, 2, 3];
+        let err = decode_byte_vec(&input[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+
+    #[test]
+    fn maps_survive_round_trip() {
+        let mut data = HashMap::<String, String>::new();
+        data.insert("callerid".into(), "/node".into());
+        data.insert("expression".into(), "a=b".into());
+        data.insert("empty".into(), String::new());
+        let encoded = encode_to_vec(&data);
+        assert_eq!(
+            encoded.len(),
+            4 + u32::decode(&encoded[..]).unwrap() as usize
+        );
+        let decoded =
+            HashMap::<String, String>::decode(encoded.as_slice()).expect(FAILED_TO_DECODE);
+        assert_eq!(data, decoded);
+    }
+
+    #[test]
+    fn rejects_truncated_and_malformed_maps() {
+        let mut data = HashMap::<String, String>::new();
+        data.insert("topic".into(), "/chatter".into());
+        let encoded = encode_to_vec(&data);
+        let err = HashMap::<String, String>::decode(&encoded[..encoded.len() - 1]).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+
+        let input = [7, 0, 0, 0, 3, 0, 0, 0, b'a', b'b', b'c'];
+        let err = HashMap::<String, String>::decode(&input[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn rejects_oversized_length_prefixes_before_allocating() {
+        let input = [0xff, 0xff, 0xff, 0xff, 1, 2, 3, 4];
+        let err = FloatArray::decode(&input[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        let err = decode_byte_vec(&input[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        let err = String::decode(&input[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        let err = decode_variable_vec::<_, u64>(&input[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        let err = decode_variable_fixed_size_vec::<_, Point>(&input[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn checks_array_lengths_against_encoded_element_size() {
+        // 10M strings take at least 40 MB on the wire, but 240 MB in memory
+        let input = 10_000_000u32.to_le_bytes();
+        let err = decode_variable_vec::<_, String>(&input[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+
+        let input = 30_000_000u32.to_le_bytes();
+        let err = decode_variable_vec::<_, u32>(&input[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn accepts_lengths_within_limit() {
+        check_message_size(DEFAULT_MAX_MESSAGE_SIZE).expect(FAILED_TO_DECODE);
+        check_message_size(DEFAULT_MAX_MESSAGE_SIZE + 1).unwrap_err();
+    }
+
+    #[test]
+    fn writes_message_without_fields_as_zero_length() {
+        use crate::msg::test_msgs::std_srvs::EmptyReq;
+
+        let data = EmptyReq {}.encode_vec().expect(FAILED_TO_ENCODE);
+        assert_eq!(vec![0, 0, 0, 0], data);
+        assert_eq!(
+            EmptyReq {},
+            EmptyReq::decode_slice(&data).expect(FAILED_TO_DECODE)
+        );
+    }
+
+    #[test]
+    fn writes_same_data_with_preallocated_capacity() {
+        let data = FloatArray(vec![1.5f32; 10_000]);
+        let expected = data.encode_vec().expect(FAILED_TO_ENCODE);
+        let actual = data
+            .encode_vec_with_capacity(data.0.len() * 4 + 4)
+            .expect(FAILED_TO_ENCODE);
+        assert_eq!(expected, actual);
+        assert_eq!(4 + 4 + data.0.len() * 4, actual.len());
+        assert_eq!(
+            data,
+            FloatArray::decode_slice(&actual).expect(FAILED_TO_DECODE)
+        );
+    }
+
+    #[test]
+    fn writes_same_data_into_reused_buffer() {
+        let mut buffer = Vec::new();
+        let large = FloatArray(vec![1.5f32; 100]);
+        large.encode_into_vec(&mut buffer).expect(FAILED_TO_ENCODE);
+        assert_eq!(large.encode_vec().expect(FAILED_TO_ENCODE), buffer);
+        let capacity = buffer.capacity();
+
+        let small = FloatArray(vec![2.5f32; 3]);
+        small.encode_into_vec(&mut buffer).expect(FAILED_TO_ENCODE);
+        assert_eq!(small.encode_vec().expect(FAILED_TO_ENCODE), buffer);
+        assert_eq!(capacity, buffer.capacity());
+    }
+
+    #[test]
+    fn writes_same_data_when_streaming() {
+        let data = FloatArray(vec![1.5f32, 2.0, -3.25]);
+        let mut cursor = io::Cursor::new(Vec::new());
+        data.encode_stream(&mut cursor).expect(FAILED_TO_ENCODE);
+        assert_eq!(
+            data.encode_vec().expect(FAILED_TO_ENCODE),
+            cursor.into_inner()
+        );
+        assert_eq!(16, data.encoded_len().expect(FAILED_TO_ENCODE));
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn fails_to_write_size_beyond_u32() {
+        let mut cursor = io::Cursor::new(Vec::new());
+        let err = write_data_size(0x1_0000_0000, &mut cursor).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        assert!(cursor.into_inner().is_empty());
+        write_data_size(0xffff_ffff, io::Cursor::new(Vec::new())).expect(FAILED_TO_ENCODE);
+    }
+
+    #[test]
+    fn writes_single_byte_char() {
+        assert_eq!(vec![b'a'], encode_to_vec(&'a'));
+        let value = char::decode(io::Cursor::new(vec![b'a'])).expect(FAILED_TO_DECODE);
+        assert_eq!('a', value);
+    }
+
+    #[test]
+    fn fails_to_write_multibyte_char() {
+        let err = '\u{263a}'.encode(io::Cursor::new(Vec::new())).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+
+    #[test]
+    fn writes_option_some_as_inner_value() {
+        assert_eq!(encode_to_vec(&5u32), encode_to_vec(&Some(5u32)));
+        assert_eq!(vec![5, 0, 0, 0], encode_to_vec(&Some(5u32)));
+    }
+
+    #[test]
+    fn writes_option_none_as_nothing() {
+        assert_eq!(Vec::<u8>::new(), encode_to_vec(&None::<u32>));
+    }
+
+    #[test]
+    fn reads_option_round_trip() {
+        let data = encode_to_vec(&Some(5u32));
+        let value = Option::<u32>::decode(io::Cursor::new(data)).expect(FAILED_TO_DECODE);
+        assert_eq!(Some(5u32), value);
+        let data = encode_to_vec(&None::<u32>);
+        let value = Option::<u32>::decode(io::Cursor::new(data)).expect(FAILED_TO_DECODE);
+        assert_eq!(None, value);
+    }
+}
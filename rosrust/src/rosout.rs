@@ -0,0 +1,147 @@
+use crate::msg::rosgraph_msgs::Log;
+use crate::time::Time;
+use log::{Level, LevelFilter, Metadata, Record};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+thread_local! {
+    static FORWARDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Logger for the `log` crate that forwards records to `/rosout`.
+///
+/// Records are dropped until the node is initialized. Install it with
+/// `rosrust::init_log()`.
+pub struct RosoutLogger {
+    level: LevelFilter,
+}
+
+impl RosoutLogger {
+    pub fn new(level: LevelFilter) -> Self {
+        Self { level }
+    }
+
+    #[inline]
+    pub fn level(&self) -> LevelFilter {
+        self.level
+    }
+}
+
+impl Default for RosoutLogger {
+    fn default() -> Self {
+        Self::new(LevelFilter::Info)
+    }
+}
+
+impl log::Log for RosoutLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        // Publishing can log errors itself, which must not feed back into the logger
+        FORWARDING.with(|forwarding| {
+            if forwarding.replace(true) {
+                return;
+            }
+            crate::singleton::log_record(record);
+            forwarding.set(false);
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Maps a `log` level to the matching `rosgraph_msgs/Log` severity.
+pub fn severity(level: Level) -> i8 {
+    match level {
+        Level::Error => Log::ERROR,
+        Level::Warn => Log::WARN,
+        Level::Info => Log::INFO,
+        Level::Debug | Level::Trace => Log::DEBUG,
+    }
+}
+
+/// Per call site state of the throttled logging macros.
+#[doc(hidden)]
+pub struct LogThrottle {
+    last: AtomicI64,
+}
+
+const NEVER_LOGGED: i64 = i64::MIN;
+
+impl LogThrottle {
+    pub const fn new() -> Self {
+        Self {
+            last: AtomicI64::new(NEVER_LOGGED),
+        }
+    }
+
+    /// Returns `true` if nothing was logged within the last `period` seconds.
+    ///
+    /// Time moving backwards, as when simulated time restarts, resets the throttle.
+    pub fn is_allowed(&self, now: Time, period: f64) -> bool {
+        let now = now.nanos();
+        let last = self.last.load(Ordering::Relaxed);
+        if last != NEVER_LOGGED && now >= last && ((now - last) as f64) < period * 1e9 {
+            return false;
+        }
+        self.last
+            .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+impl Default for LogThrottle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Log as _;
+
+    #[test]
+    fn maps_levels_to_ros_severities() {
+        assert_eq!(Log::ERROR, severity(Level::Error));
+        assert_eq!(Log::WARN, severity(Level::Warn));
+        assert_eq!(Log::INFO, severity(Level::Info));
+        assert_eq!(Log::DEBUG, severity(Level::Debug));
+        assert_eq!(Log::DEBUG, severity(Level::Trace));
+    }
+
+    #[test]
+    fn filters_by_level() {
+        let logger = RosoutLogger::default();
+        let metadata = |level| Metadata::builder().level(level).build();
+        assert!(logger.enabled(&metadata(Level::Warn)));
+        assert!(logger.enabled(&metadata(Level::Info)));
+        assert!(!logger.enabled(&metadata(Level::Debug)));
+    }
+
+    #[test]
+    fn throttle_logs_once_per_period() {
+        let throttle = LogThrottle::new();
+        let step = 10_000_000;
+        let logged = (0..1000)
+            .filter(|i| throttle.is_allowed(Time::from_nanos(5_000_000_000 + i * step), 1.5))
+            .count();
+        // 10 seconds of calls with a 1.5 second period, the first call always logging
+        assert_eq!(7, logged);
+    }
+
+    #[test]
+    fn throttle_resets_when_time_goes_back() {
+        let throttle = LogThrottle::new();
+        assert!(throttle.is_allowed(Time::from_nanos(10_000_000_000), 1.0));
+        assert!(!throttle.is_allowed(Time::from_nanos(10_500_000_000), 1.0));
+        assert!(throttle.is_allowed(Time::from_nanos(1_000_000_000), 1.0));
+        assert!(!throttle.is_allowed(Time::from_nanos(1_999_999_999), 1.0));
+        assert!(throttle.is_allowed(Time::from_nanos(2_000_000_000), 1.0));
+    }
+}
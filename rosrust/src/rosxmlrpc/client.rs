@@ -1,10 +1,50 @@
 use super::error::{ErrorKind, Result, ResultExt};
 use super::{Response, ResponseError, ResponseInfo};
 use serde::{Deserialize, Serialize};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 use xml_rpc::{self, Params, Url, Value};
 
+/// How calls get retried while the server cannot be reached.
+///
+/// Only calls that fail to reach the server are retried. Errors reported by
+/// the server are returned right away. The default makes a single attempt.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts made for every call, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every further one.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between retries.
+    pub max_backoff: Duration,
+    /// How long node initialization waits for the master to come up.
+    pub startup_timeout: Duration,
+}
+
+impl RetryPolicy {
+    /// Delay before the given retry, counting from zero.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32.checked_shl(retry).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |delay| delay.min(self.max_backoff))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            startup_timeout: Duration::from_secs(0),
+        }
+    }
+}
+
 pub struct Client {
     master_uri: Url,
+    retry_policy: RetryPolicy,
 }
 
 impl Client {
@@ -12,11 +52,52 @@ impl Client {
         let master_uri = master_uri
             .parse()
             .chain_err(|| ErrorKind::BadUri(master_uri.into()))?;
-        Ok(Client { master_uri })
+        Ok(Client {
+            master_uri,
+            retry_policy: RetryPolicy::default(),
+        })
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Whether the server answers calls, even if only with an error.
+    pub fn is_reachable(&self) -> bool {
+        let params = vec![Value::String("/rosrust".into())];
+        xml_rpc::call_value(&self.master_uri, "getPid", params).is_ok()
+    }
+
+    /// Waits for the server to answer calls, for the policy's startup timeout.
+    pub fn wait_until_reachable(&self) -> Response<()> {
+        let deadline = Instant::now() + self.retry_policy.startup_timeout;
+        let mut retry = 0;
+        while !self.is_reachable() {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(ResponseError::Client(format!(
+                    "Server at {} is unreachable",
+                    self.master_uri
+                )));
+            }
+            sleep(self.retry_policy.backoff(retry).min(deadline - now));
+            retry += 1;
+        }
+        Ok(())
     }
 
     pub fn request_tree_with_tree(&self, name: &str, params: Params) -> Response<Value> {
-        let call_result = xml_rpc::call_value(&self.master_uri, name, params);
+        let mut retry = 0;
+        let call_result = loop {
+            match xml_rpc::call_value(&self.master_uri, name, params.clone()) {
+                Err(_) if retry + 1 < self.retry_policy.max_attempts => {
+                    sleep(self.retry_policy.backoff(retry));
+                    retry += 1;
+                }
+                call_result => break call_result,
+            }
+        };
 
         let server_response = call_result.map_err(|err| {
             ResponseError::Client(format!("Failed to perform call to server: {}", err))
@@ -66,3 +147,85 @@ fn bad_request_structure<T: ::std::fmt::Display>(err: T) -> ResponseError {
 fn bad_response_structure<T: ::std::fmt::Display>(err: T) -> ResponseError {
     ResponseError::Server(format!("Response data has unexpected structure: {}", err))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rosxmlrpc::mock::{flaky_proxy, MockServer};
+    use std::net::TcpListener;
+    use std::sync::atomic::Ordering;
+
+    fn retrying(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(40),
+            startup_timeout: Duration::from_secs(5),
+        }
+    }
+
+    fn echo_server() -> String {
+        let mut server = MockServer::default();
+        server.on("echo", |args| Ok(args[1].clone()));
+        server.serve()
+    }
+
+    fn echo(client: &Client) -> Response<String> {
+        client.request("echo", &("/node", "hello"))
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_limit() {
+        let policy = retrying(5);
+        assert_eq!(Duration::from_millis(10), policy.backoff(0));
+        assert_eq!(Duration::from_millis(20), policy.backoff(1));
+        assert_eq!(Duration::from_millis(40), policy.backoff(2));
+        assert_eq!(Duration::from_millis(40), policy.backoff(3));
+        assert_eq!(Duration::from_millis(40), policy.backoff(100));
+    }
+
+    #[test]
+    fn retries_calls_that_fail_to_reach_server() {
+        let server = echo_server();
+
+        let (uri, connections) = flaky_proxy(&server, 2);
+        let client = Client::new(&uri).unwrap().with_retry_policy(retrying(3));
+        assert_eq!("hello", echo(&client).unwrap());
+        assert_eq!(3, connections.load(Ordering::SeqCst));
+
+        let (uri, connections) = flaky_proxy(&server, 2);
+        let client = Client::new(&uri).unwrap().with_retry_policy(retrying(2));
+        assert!(echo(&client).is_err());
+        assert_eq!(2, connections.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn does_not_retry_errors_reported_by_server() {
+        let (uri, connections) = flaky_proxy(&echo_server(), 0);
+        let client = Client::new(&uri).unwrap().with_retry_policy(retrying(3));
+        let response: Response<String> = client.request("missing", &("/node",));
+        assert!(response.is_err());
+        assert_eq!(1, connections.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn waits_for_server_until_startup_timeout() {
+        let (uri, connections) = flaky_proxy(&echo_server(), 3);
+        let client = Client::new(&uri).unwrap().with_retry_policy(retrying(1));
+        client.wait_until_reachable().unwrap();
+        assert_eq!(4, connections.load(Ordering::SeqCst));
+
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let mut policy = retrying(1);
+        policy.startup_timeout = Duration::from_millis(100);
+        let uri = format!("http://127.0.0.1:{}/", port);
+        let client = Client::new(&uri).unwrap().with_retry_policy(policy);
+        let start = Instant::now();
+        assert!(client.wait_until_reachable().is_err());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -1,4 +1,6 @@
 #![allow(deprecated)]
+use crate::util::ports::AddrInUse;
+
 error_chain! {
     foreign_links {
         Io(::std::io::Error);
@@ -17,3 +19,19 @@ error_chain! {
         }
     }
 }
+
+impl AddrInUse for ErrorKind {
+    fn is_addr_in_use(&self) -> bool {
+        match *self {
+            ErrorKind::Io(ref err) => err.is_addr_in_use(),
+            _ => false,
+        }
+    }
+}
+
+impl AddrInUse for Error {
+    #[inline]
+    fn is_addr_in_use(&self) -> bool {
+        self.kind().is_addr_in_use()
+    }
+}
@@ -0,0 +1,78 @@
+//! XML-RPC servers standing in for the master and other nodes in tests.
+
+use super::{Response, Server};
+use std::io;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use xml_rpc::server::BoundServer;
+use xml_rpc::{rouille, Params, Value};
+
+/// Server that answers the methods it is given, from a background thread.
+///
+/// Calls of other methods fail, like they do on a real server.
+#[derive(Default)]
+pub struct MockServer {
+    server: Server,
+}
+
+impl MockServer {
+    /// Answers calls of the method with the handler's response.
+    pub fn on<F>(&mut self, method: &str, handler: F)
+    where
+        F: Fn(Params) -> Response<Value> + Send + Sync + 'static,
+    {
+        self.server.register_value(method, "Mock response", handler);
+    }
+
+    /// Starts serving on a free loopback port, and returns the server's URI.
+    pub fn serve(self) -> String {
+        serve_bound(self.server.bind(&"127.0.0.1:0".parse().unwrap()).unwrap())
+    }
+}
+
+/// Polls an already bound server from a background thread, and returns its URI.
+pub fn serve_bound<F>(bound: BoundServer<F>) -> String
+where
+    F: Fn(&rouille::Request) -> rouille::Response + Send + Sync + 'static,
+{
+    let uri = format!("http://{}/", bound.local_addr());
+    thread::spawn(move || loop {
+        bound.poll();
+        thread::sleep(Duration::from_millis(5));
+    });
+    uri
+}
+
+fn forward(mut from: TcpStream, mut to: TcpStream) {
+    thread::spawn(move || {
+        let _ = io::copy(&mut from, &mut to);
+        let _ = to.shutdown(Shutdown::Write);
+    });
+}
+
+/// Forwards connections to the server, after closing the first `failures` of them.
+pub fn flaky_proxy(server_uri: &str, failures: usize) -> (String, Arc<AtomicUsize>) {
+    let server_address = server_uri
+        .trim_start_matches("http://")
+        .trim_end_matches('/')
+        .to_owned();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let uri = format!("http://{}/", listener.local_addr().unwrap());
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&connections);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                continue;
+            }
+            let server = TcpStream::connect(&server_address).unwrap();
+            forward(stream.try_clone().unwrap(), server.try_clone().unwrap());
+            forward(server, stream);
+        }
+    });
+    (uri, connections)
+}
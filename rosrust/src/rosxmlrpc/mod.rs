@@ -1,10 +1,12 @@
-pub use self::client::Client;
+pub use self::client::{Client, RetryPolicy};
 use self::response_info::ResponseInfo;
 pub use self::server::Server;
 use std;
 
 pub mod client;
 pub mod error;
+#[cfg(test)]
+pub(crate) mod mock;
 mod response_info;
 pub mod server;
 
@@ -1,14 +1,21 @@
-use crate::api::raii::{Publisher, Service, Subscriber};
+use crate::api::raii::{CachedParameter, CallbackControl, Publisher, Service, Subscriber};
 use crate::api::resolve::get_unused_args;
-use crate::api::{Delay, Parameter, Rate, Ros, SystemState, Topic};
+use crate::api::{Delay, Parameter, Rate, Ros, SystemState, Timer, TimerEvent, Topic};
 use crate::error::{ErrorKind, Result};
+use crate::rosout::RosoutLogger;
 use crate::rosxmlrpc::Response;
-use crate::tcpros::{Client, Message, ServicePair, ServiceResult};
-use crate::time::{Duration, Time};
+use crate::tcpros::{
+    Client, Message, MessageEvent, MessageType, RawMessage, ServicePair, ServiceResult,
+    TcpKeepalive,
+};
+use crate::time::{Duration, SteadyTime, Time};
 use crate::util::FAILED_TO_LOCK;
 use crossbeam::sync::ShardedLock;
 use ctrlc;
 use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
 use std::time;
 
 lazy_static! {
@@ -20,17 +27,59 @@ pub fn init(name: &str) {
     try_init(name).expect("ROS init failed!");
 }
 
+/// Initializes the node, panicking if that fails.
+///
+/// This is the behavior of `init`, for simple binaries that cannot go on
+/// without a node. Use `try_init` to handle failures instead.
+#[inline]
+pub fn init_or_exit(name: &str) {
+    init(name)
+}
+
+/// Initializes the node, returning an error if that fails.
+///
+/// Failures include an invalid or unreachable master, and failing to set up
+/// the node's servers. Unlike `init`, this lets applications retry or report them.
 #[inline]
 pub fn try_init(name: &str) -> Result<()> {
     try_init_with_options(name, true)
 }
 
 pub fn try_init_with_options(name: &str, capture_sigint: bool) -> Result<()> {
+    init_with(|| Ros::new(name), capture_sigint)
+}
+
+/// Initializes a node with a unique name, so several instances can run at once.
+///
+/// The process ID and a timestamp get appended to the name, unless one is
+/// set with the `__name` argument.
+#[inline]
+pub fn init_anonymous(name: &str) {
+    try_init_anonymous(name).expect("ROS init failed!");
+}
+
+#[inline]
+pub fn try_init_anonymous(name: &str) -> Result<()> {
+    init_with(|| Ros::new_anonymous(name), true)
+}
+
+/// Initializes a node whose servers only bind to ports from the given range.
+///
+/// See `Ros::new_with_port_range` for details.
+#[inline]
+pub fn try_init_with_port_range(name: &str, ports: RangeInclusive<u16>) -> Result<()> {
+    init_with(|| Ros::new_with_port_range(name, ports), true)
+}
+
+fn init_with<F>(create: F, capture_sigint: bool) -> Result<()>
+where
+    F: FnOnce() -> crate::api::error::Result<Ros>,
+{
     let mut ros = ROS.write().expect(FAILED_TO_LOCK);
     if ros.is_some() {
         bail!(ErrorKind::MultipleInitialization);
     }
-    let client = Ros::new(name)?;
+    let client = create()?;
     if capture_sigint {
         let shutdown_sender = client.shutdown_sender();
         ctrlc::set_handler(move || {
@@ -74,11 +123,22 @@ pub fn hostname() -> String {
     ros!().hostname().into()
 }
 
+#[inline]
+pub fn resolve_name(name: &str) -> Result<String> {
+    ros!().resolve_name(name)
+}
+
 #[inline]
 pub fn now() -> Time {
     ros!().now()
 }
 
+/// Time of a monotonic clock, which works before `init` too.
+#[inline]
+pub fn steady_now() -> SteadyTime {
+    SteadyTime::now()
+}
+
 #[inline]
 pub fn delay(d: Duration) -> Delay {
     ros!().delay(d)
@@ -94,6 +154,14 @@ pub fn rate(rate: f64) -> Rate {
     ros!().rate(rate)
 }
 
+#[inline]
+pub fn create_timer<F>(period: Duration, callback: F) -> Timer
+where
+    F: FnMut(TimerEvent) + Send + 'static,
+{
+    ros!().create_timer(period, callback)
+}
+
 #[inline]
 pub fn is_ok() -> bool {
     ros!().is_ok()
@@ -105,16 +173,60 @@ pub fn spin() {
     let _spinner = { ros!().spin() };
 }
 
+#[inline]
+pub fn spin_once() -> bool {
+    // The lock is released before waiting, same as in spin
+    let shutdown_manager = { ros!().shutdown_sender() };
+    crate::api::spin_once(&shutdown_manager)
+}
+
+/// Spins until the duration passes on the node's clock, or shutdown gets requested.
+///
+/// Returns whether the node should keep running, so later spins can follow.
+#[inline]
+pub fn spin_for(d: Duration) -> bool {
+    // The lock is released before waiting, same as in spin
+    let shutdown_manager = { ros!().shutdown_sender() };
+    let clock = { ros!().clock() };
+    clock.await_init();
+    crate::api::spin_for(&shutdown_manager, clock.as_ref(), d)
+}
+
 #[inline]
 pub fn shutdown() {
     ros!().shutdown_sender().shutdown()
 }
 
+#[inline]
+pub fn shutdown_reason() -> Option<String> {
+    ros!().shutdown_reason()
+}
+
 #[inline]
 pub fn param(name: &str) -> Option<Parameter> {
     ros!().param(name)
 }
 
+#[inline]
+pub fn param_cached(name: &str) -> Result<CachedParameter> {
+    ros!().param_cached(name)
+}
+
+#[inline]
+pub fn param_exists(name: &str) -> Result<bool> {
+    ros!().param_exists(name)
+}
+
+#[inline]
+pub fn delete_param(name: &str) -> Result<()> {
+    ros!().delete_param(name)
+}
+
+#[inline]
+pub fn load_params_from_yaml(namespace: &str, yaml: &str) -> Result<()> {
+    ros!().load_params_from_yaml(namespace, yaml)
+}
+
 #[inline]
 pub fn parameters() -> Response<Vec<String>> {
     ros!().parameters()
@@ -130,11 +242,41 @@ pub fn topics() -> Response<Vec<Topic>> {
     ros!().topics()
 }
 
+#[inline]
+pub fn published_topics_on_master(subgraph: &str) -> Response<Vec<Topic>> {
+    ros!().published_topics_on_master(subgraph)
+}
+
+#[inline]
+pub fn lookup_node(name: &str) -> Response<String> {
+    ros!().lookup_node(name)
+}
+
+#[inline]
+pub fn published_topics() -> Vec<Topic> {
+    ros!().published_topics()
+}
+
+#[inline]
+pub fn subscribed_topics() -> Vec<Topic> {
+    ros!().subscribed_topics()
+}
+
+#[inline]
+pub fn set_tcp_keepalive(keepalive: Option<TcpKeepalive>) {
+    ros!().set_tcp_keepalive(keepalive)
+}
+
 #[inline]
 pub fn client<T: ServicePair>(service: &str) -> Result<Client<T>> {
     ros!().client::<T>(service)
 }
 
+#[inline]
+pub fn persistent_client<T: ServicePair>(service: &str) -> Result<Client<T>> {
+    ros!().persistent_client::<T>(service)
+}
+
 #[inline]
 pub fn wait_for_service(service: &str, timeout: Option<time::Duration>) -> Result<()> {
     ros!().wait_for_service(service, timeout)
@@ -149,6 +291,37 @@ where
     ros!().service::<T, F>(service, handler)
 }
 
+#[inline]
+pub fn service_with_error<T, E, F>(service: &str, handler: F) -> Result<Service>
+where
+    T: ServicePair,
+    E: std::fmt::Display,
+    F: Fn(T::Request) -> std::result::Result<T::Response, E> + Send + Sync + 'static,
+{
+    ros!().service_with_error::<T, E, F>(service, handler)
+}
+
+#[inline]
+pub fn service_with_header<T, F>(service: &str, handler: F) -> Result<Service>
+where
+    T: ServicePair,
+    F: Fn(T::Request, &HashMap<String, String>) -> ServiceResult<T::Response>
+        + Send
+        + Sync
+        + 'static,
+{
+    ros!().service_with_header::<T, F>(service, handler)
+}
+
+#[inline]
+pub fn service_with_workers<T, F>(service: &str, workers: usize, handler: F) -> Result<Service>
+where
+    T: ServicePair,
+    F: Fn(T::Request) -> ServiceResult<T::Response> + Send + Sync + 'static,
+{
+    ros!().service_with_workers::<T, F>(service, workers, handler)
+}
+
 #[inline]
 pub fn subscribe<T, F>(topic: &str, queue_size: usize, callback: F) -> Result<Subscriber>
 where
@@ -167,6 +340,46 @@ where
     ros!().subscribe_with_ids::<T, F>(topic, queue_size, callback)
 }
 
+#[inline]
+pub fn subscribe_with_info<T, F>(topic: &str, queue_size: usize, callback: F) -> Result<Subscriber>
+where
+    T: Message,
+    F: Fn(T, &MessageEvent) + Send + 'static,
+{
+    ros!().subscribe_with_info::<T, F>(topic, queue_size, callback)
+}
+
+#[inline]
+pub fn subscribe_until<T, F>(topic: &str, queue_size: usize, callback: F) -> Result<Subscriber>
+where
+    T: Message,
+    F: Fn(T) -> CallbackControl + Send + 'static,
+{
+    ros!().subscribe_until::<T, F>(topic, queue_size, callback)
+}
+
+#[inline]
+pub fn subscribe_arc<T, F>(topic: &str, queue_size: usize, callback: F) -> Result<Subscriber>
+where
+    T: Message,
+    F: Fn(Arc<T>) + Send + 'static,
+{
+    ros!().subscribe_arc::<T, F>(topic, queue_size, callback)
+}
+
+#[inline]
+pub fn subscribe_raw<F>(
+    topic: &str,
+    queue_size: usize,
+    message_type: MessageType,
+    callback: F,
+) -> Result<Subscriber>
+where
+    F: Fn(RawMessage, &MessageEvent) + Send + 'static,
+{
+    ros!().subscribe_raw::<F>(topic, queue_size, message_type, callback)
+}
+
 #[inline]
 pub fn publish<T>(topic: &str, queue_size: usize) -> Result<Publisher<T>>
 where
@@ -175,9 +388,76 @@ where
     ros!().publish::<T>(topic, queue_size)
 }
 
+#[inline]
+pub fn publish_raw(
+    topic: &str,
+    queue_size: usize,
+    message_type: MessageType,
+) -> Result<Publisher<RawMessage>> {
+    ros!().publish_raw(topic, queue_size, message_type)
+}
+
 #[inline]
 pub fn log(level: i8, msg: String, file: &str, line: u32) {
     ros!().log(level, msg, file, line)
 }
 
+/// Installs a `RosoutLogger`, forwarding `log` crate records of `level` and above to `/rosout`.
+pub fn init_log_with_level(level: log::LevelFilter) -> Result<()> {
+    log::set_boxed_logger(Box::new(RosoutLogger::new(level)))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[inline]
+pub fn init_log() -> Result<()> {
+    init_log_with_level(RosoutLogger::default().level())
+}
+
+pub(crate) fn log_record(record: &log::Record) {
+    // The lock is held for writing while the node initializes, which can itself log
+    if let Ok(ros) = ROS.try_read() {
+        if let Some(ref ros) = *ros {
+            ros.log_record(record);
+        }
+    }
+}
+
 static UNINITIALIZED: &str = "ROS uninitialized. Please run ros::init(name) first!";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::error::rosxmlrpc;
+    use crate::api::resolve;
+    use crate::rosxmlrpc::ResponseError;
+
+    #[test]
+    #[allow(unused_variables)]
+    fn try_init_reports_failures() {
+        let testcase = resolve::test_args::TESTCASE.lock().expect(FAILED_TO_LOCK);
+
+        resolve::test_args::set_args(&vec!["__master:=http://127.0.0.1:1/"]);
+        let err = try_init("node").unwrap_err();
+        assert!(
+            matches!(err.kind(), ErrorKind::Response(ResponseError::Client(_))),
+            "{}",
+            err
+        );
+        assert!(!is_initialized());
+
+        resolve::test_args::set_args(&vec!["__master:=not a uri"]);
+        let err = try_init("node").unwrap_err();
+        assert!(
+            matches!(
+                err.kind(),
+                ErrorKind::XmlRpc(rosxmlrpc::ErrorKind::BadUri(_))
+            ),
+            "{}",
+            err
+        );
+        assert!(!is_initialized());
+
+        resolve::test_args::set_args(&vec![]);
+    }
+}
@@ -1,17 +1,33 @@
-use super::error::{ErrorKind, Result, ResultExt};
-use super::header::{decode, encode};
+use super::error::{decode_error, ErrorKind, Result, ResultExt};
+use super::header::{check_rejection, decode, encode};
+use super::util::keepalive::{self, TcpKeepalive};
 use super::{ServicePair, ServiceResult};
-use crate::rosmsg::RosMsg;
+use crate::rosmsg::{check_message_size, RosMsg};
+use crate::util::FAILED_TO_LOCK;
 use byteorder::{LittleEndian, ReadBytesExt};
 use log::error;
 use net2::TcpStreamExt;
 use std;
 use std::collections::HashMap;
+use std::future::Future;
 use std::io;
 use std::io::{Read, Write};
-use std::net::TcpStream;
-use std::sync::Arc;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
 use std::thread;
+use std::time::{Duration, Instant};
+
+/// Largest part of a request written at once, between checks for cancellation.
+const WRITE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How often a write stalled by the service checks for cancellation.
+const CANCELLATION_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Timeout of `Client::call` for clients that were not given one with `Client::with_timeout`.
+pub const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub struct ClientResponse<T> {
     handle: thread::JoinHandle<Result<ServiceResult<T>>>,
@@ -34,36 +50,221 @@ impl<T: Send + 'static> ClientResponse<T> {
     }
 }
 
+/// Future resolving to the response of a service call, created by `Client::call`.
+///
+/// It works with any executor, as the call itself does not depend on one.
+/// Dropping it cancels sending a request that is still being written.
+pub struct ClientCall<T> {
+    state: Arc<Mutex<CallState<T>>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+struct CallState<T> {
+    result: Option<Result<ServiceResult<T>>>,
+    waker: Option<Waker>,
+}
+
+impl<T> Future for ClientCall<T> {
+    type Output = Result<ServiceResult<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.state.lock().expect(FAILED_TO_LOCK);
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for ClientCall<T> {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Connection to a service provider that requests get exchanged over.
+pub trait ClientStream: Read + Write + Send {
+    /// Limits how long a single write may block, like `TcpStream::set_write_timeout`.
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Limits how long a single read may block, like `TcpStream::set_read_timeout`.
+    ///
+    /// Connectors apply the client's timeout to new streams already, so this
+    /// is only needed to change it on an open connection.
+    #[inline]
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Enables TCP keepalive probes, which streams of other transports ignore.
+    #[inline]
+    fn set_tcp_keepalive(&self, _keepalive: &TcpKeepalive) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ClientStream for TcpStream {
+    #[inline]
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    #[inline]
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    #[inline]
+    fn set_tcp_keepalive(&self, keepalive: &TcpKeepalive) -> io::Result<()> {
+        keepalive::apply(self, Some(keepalive))
+    }
+}
+
+/// Opens connections to service providers.
+///
+/// Clients connect over TCP by default. Other transports, like in-memory
+/// streams in tests, can be used through `Client::with_connector`.
+pub trait Connector: Send + Sync {
+    /// Connects to the provider at `address`, given as `host:port`.
+    fn connect(
+        &self,
+        address: &str,
+        timeout: Option<Duration>,
+        nodelay: bool,
+    ) -> io::Result<Box<dyn ClientStream>>;
+}
+
+struct TcpConnector;
+
+impl Connector for TcpConnector {
+    fn connect(
+        &self,
+        address: &str,
+        timeout: Option<Duration>,
+        nodelay: bool,
+    ) -> io::Result<Box<dyn ClientStream>> {
+        let stream = connect_to_tcp_with_multiple_attempts(address, 15, timeout, nodelay)?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// Looks up the current URI of a service provider.
+type Lookup = Arc<dyn Fn() -> Option<String> + Send + Sync>;
+
 struct ClientInfo {
     caller_id: String,
-    uri: String,
+    uri: Mutex<String>,
     service: String,
+    timeout: Option<Duration>,
+    tcp_nodelay: bool,
+    tcp_keepalive: Option<TcpKeepalive>,
+    persistent: bool,
+    lookup: Option<Lookup>,
+    connector: Arc<dyn Connector>,
+    connection: Mutex<Option<Box<dyn ClientStream>>>,
+    closed: AtomicBool,
 }
 
+impl ClientInfo {
+    fn new(caller_id: &str, uri: &str, service: &str, persistent: bool) -> ClientInfo {
+        ClientInfo {
+            caller_id: String::from(caller_id),
+            uri: Mutex::new(String::from(uri)),
+            service: String::from(service),
+            timeout: None,
+            tcp_nodelay: true,
+            tcp_keepalive: None,
+            persistent,
+            lookup: None,
+            connector: Arc::new(TcpConnector),
+            connection: Mutex::new(None),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    fn uri(&self) -> String {
+        self.uri.lock().expect(FAILED_TO_LOCK).clone()
+    }
+}
+
+/// Marks the client as closed once all of its handles are gone.
+struct ClientHandle(Arc<ClientInfo>);
+
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Client of a single service.
+///
+/// Requests still being written get cancelled once every handle of the
+/// client has been dropped, so large requests to a stalled service do not
+/// keep their threads blocked.
 #[derive(Clone)]
 pub struct Client<T: ServicePair> {
     info: std::sync::Arc<ClientInfo>,
+    _handle: Arc<ClientHandle>,
     phantom: std::marker::PhantomData<T>,
 }
 
-fn connect_to_tcp_with_multiple_attempts(uri: &str, attempts: usize) -> io::Result<TcpStream> {
+fn connect_to_tcp(uri: &str, timeout: Option<Duration>, nodelay: bool) -> io::Result<TcpStream> {
+    let stream = match timeout {
+        Some(timeout) => {
+            let mut err = io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                "Could not resolve any address",
+            );
+            let mut connected = None;
+            for address in uri.to_socket_addrs()? {
+                match TcpStream::connect_timeout(&address, timeout) {
+                    Ok(stream) => {
+                        connected = Some(stream);
+                        break;
+                    }
+                    Err(error) => err = error,
+                }
+            }
+            connected.ok_or(err)?
+        }
+        None => TcpStream::connect(uri)?,
+    };
+    stream.set_linger(None)?;
+    stream.set_nodelay(nodelay)?;
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+    Ok(stream)
+}
+
+fn connect_to_tcp_with_multiple_attempts(
+    uri: &str,
+    attempts: usize,
+    timeout: Option<Duration>,
+    nodelay: bool,
+) -> io::Result<TcpStream> {
     let mut err = io::Error::new(
         io::ErrorKind::Other,
         "Tried to connect via TCP with 0 connection attempts",
     );
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
     let mut repeat_delay_ms = 1;
     for _ in 0..attempts {
-        let stream_result = TcpStream::connect(uri).and_then(|stream| {
-            stream.set_linger(None)?;
-            Ok(stream)
-        });
-        match stream_result {
+        match connect_to_tcp(uri, timeout, nodelay) {
             Ok(stream) => {
                 return Ok(stream);
             }
             Err(error) => err = error,
         }
-        std::thread::sleep(std::time::Duration::from_millis(repeat_delay_ms));
+        let delay = Duration::from_millis(repeat_delay_ms);
+        if let Some(deadline) = deadline {
+            if Instant::now() + delay >= deadline {
+                break;
+            }
+        }
+        std::thread::sleep(delay);
         repeat_delay_ms *= 2;
     }
     Err(err)
@@ -71,90 +272,316 @@ fn connect_to_tcp_with_multiple_attempts(uri: &str, attempts: usize) -> io::Resu
 
 impl<T: ServicePair> Client<T> {
     pub fn new(caller_id: &str, uri: &str, service: &str) -> Client<T> {
+        Self::from_info(ClientInfo::new(caller_id, uri, service, false))
+    }
+
+    /// Creates a client that keeps its connection to the service open between requests.
+    ///
+    /// If the connection breaks, it is reestablished on the next request.
+    /// A request is only sent again on the new connection if writing it to
+    /// the broken one failed. Once written, a lost response is returned as an
+    /// error, since the service might have handled the request already.
+    pub fn new_persistent(caller_id: &str, uri: &str, service: &str) -> Client<T> {
+        Self::from_info(ClientInfo::new(caller_id, uri, service, true))
+    }
+
+    fn from_info(info: ClientInfo) -> Client<T> {
+        let info = Arc::new(info);
         Client {
-            info: std::sync::Arc::new(ClientInfo {
-                caller_id: String::from(caller_id),
-                uri: String::from(uri),
-                service: String::from(service),
-            }),
+            _handle: Arc::new(ClientHandle(Arc::clone(&info))),
+            info,
             phantom: std::marker::PhantomData,
         }
     }
 
-    pub fn req(&self, args: &T::Request) -> Result<ServiceResult<T::Response>> {
-        Self::request_body(
-            args,
-            &self.info.uri,
+    /// Limits how long connecting to the service and waiting on its replies may take.
+    ///
+    /// Without a timeout, an unresponsive service can block a request indefinitely.
+    pub fn with_timeout(self, timeout: Duration) -> Client<T> {
+        self.reconfigure(|info| info.timeout = Some(timeout))
+    }
+
+    /// Controls whether Nagle's algorithm is disabled on connections, which is the default.
+    ///
+    /// Disabling it lowers latency, while enabling it can save bandwidth on constrained links.
+    pub fn with_tcp_nodelay(self, tcp_nodelay: bool) -> Client<T> {
+        self.reconfigure(|info| info.tcp_nodelay = tcp_nodelay)
+    }
+
+    /// Enables TCP keepalive probes on connections to the service.
+    ///
+    /// Clients made by a node default to the node's keepalive settings.
+    pub fn with_tcp_keepalive(self, tcp_keepalive: Option<TcpKeepalive>) -> Client<T> {
+        self.reconfigure(|info| info.tcp_keepalive = tcp_keepalive)
+    }
+
+    /// Fails over to another provider when connecting to the current one fails.
+    ///
+    /// The lookup is called to find the service's provider again, and if it
+    /// returns a different URI, the connection is retried there. The new
+    /// provider is kept for later requests.
+    pub fn with_failover<F>(self, lookup: F) -> Client<T>
+    where
+        F: Fn() -> Option<String> + Send + Sync + 'static,
+    {
+        self.reconfigure(|info| info.lookup = Some(Arc::new(lookup)))
+    }
+
+    /// Opens connections through the given connector instead of over TCP.
+    pub fn with_connector<C: Connector + 'static>(self, connector: C) -> Client<T> {
+        self.reconfigure(|info| info.connector = Arc::new(connector))
+    }
+
+    fn reconfigure<F: FnOnce(&mut ClientInfo)>(self, update: F) -> Client<T> {
+        let mut info = ClientInfo::new(
             &self.info.caller_id,
+            &self.info.uri(),
             &self.info.service,
-        )
+            self.info.persistent,
+        );
+        info.timeout = self.info.timeout;
+        info.tcp_nodelay = self.info.tcp_nodelay;
+        info.tcp_keepalive = self.info.tcp_keepalive;
+        info.lookup = self.info.lookup.clone();
+        info.connector = Arc::clone(&self.info.connector);
+        update(&mut info);
+        Self::from_info(info)
+    }
+
+    pub fn req(&self, args: &T::Request) -> Result<ServiceResult<T::Response>> {
+        Self::request_body(args, &self.info, self.info.timeout, &AtomicBool::new(false))
     }
 
     pub fn req_async(&self, args: T::Request) -> ClientResponse<T::Response> {
         let info = Arc::clone(&self.info);
         ClientResponse {
             handle: thread::spawn(move || {
-                Self::request_body(&args, &info.uri, &info.caller_id, &info.service)
+                Self::request_body(&args, &info, info.timeout, &AtomicBool::new(false))
             }),
         }
     }
 
+    /// Calls the service, returning a future of the response.
+    ///
+    /// The network exchange still uses blocking IO, but happens on a thread
+    /// of its own, which wakes the awaiting task once the response arrives.
+    /// So no executor thread gets blocked, and a hung service only holds up
+    /// the calls made to it. Unless the client has a timeout, calls give up
+    /// after `DEFAULT_CALL_TIMEOUT`, which also ends their threads.
+    pub fn call(&self, args: T::Request) -> ClientCall<T::Response> {
+        let state = Arc::new(Mutex::new(CallState {
+            result: None,
+            waker: None,
+        }));
+        let call_state = Arc::clone(&state);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let call_cancelled = Arc::clone(&cancelled);
+        let info = Arc::clone(&self.info);
+        let timeout = info.timeout.unwrap_or(DEFAULT_CALL_TIMEOUT);
+        thread::spawn(move || {
+            let result = Self::request_body(&args, &info, Some(timeout), &call_cancelled);
+            let mut state = call_state.lock().expect(FAILED_TO_LOCK);
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+        ClientCall { state, cancelled }
+    }
+
     fn request_body(
         args: &T::Request,
-        uri: &str,
-        caller_id: &str,
-        service: &str,
+        info: &ClientInfo,
+        timeout: Option<Duration>,
+        cancelled: &AtomicBool,
     ) -> Result<ServiceResult<T::Response>> {
-        let trimmed_uri = uri.trim_start_matches("rosrpc://");
-        let mut stream = connect_to_tcp_with_multiple_attempts(trimmed_uri, 15)
-            .chain_err(|| ErrorKind::ServiceConnectionFail(service.into(), uri.into()))?;
+        let is_cancelled =
+            || cancelled.load(Ordering::SeqCst) || info.closed.load(Ordering::SeqCst);
 
-        // Service request starts by exchanging connection headers
-        exchange_headers::<T, _>(&mut stream, caller_id, service)?;
+        if !info.persistent {
+            let mut stream = Self::connect(info, timeout)?;
+            let response = Self::exchange_request(args, &mut stream, timeout, &is_cancelled)?;
 
-        let mut writer = io::Cursor::new(Vec::with_capacity(128));
-        // skip the first 4 bytes that will contain the message length
-        writer.set_position(4);
+            let mut dump = vec![];
+            if let Err(err) = stream.read_to_end(&mut dump) {
+                error!("Failed to read from TCP stream: {:?}", err)
+            }
 
-        args.encode(&mut writer)?;
+            return Ok(response);
+        }
 
-        // write the message length to the start of the header
-        let message_length = (writer.position() - 4) as u32;
-        writer.set_position(0);
-        message_length.encode(&mut writer)?;
+        // Holding the lock for the whole exchange keeps concurrent requests
+        // from interleaving on the shared stream
+        let mut connection = info.connection.lock().expect(FAILED_TO_LOCK);
+        if let Some(mut stream) = connection.take() {
+            stream.set_read_timeout(timeout)?;
+            // The service might have dropped the connection since the last request.
+            // A request that could not be written was not handled, so it is sent
+            // again on a fresh connection. Once written, the service might have
+            // handled it already, so any later failure is returned instead.
+            match Self::send_request(args, &mut stream, timeout, &is_cancelled) {
+                Ok(()) => {
+                    let response = Self::receive_response(&mut stream)?;
+                    *connection = Some(stream);
+                    return Ok(response);
+                }
+                Err(err) => {
+                    if let ErrorKind::ServiceRequestCancelled = *err.kind() {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+        let mut stream = Self::connect(info, timeout)?;
+        let response = Self::exchange_request(args, &mut stream, timeout, &is_cancelled)?;
+        *connection = Some(stream);
+        Ok(response)
+    }
 
-        // Send request to service
-        stream.write_all(&writer.into_inner())?;
+    fn connect(info: &ClientInfo, timeout: Option<Duration>) -> Result<Box<dyn ClientStream>> {
+        let uri = info.uri();
+        let err = match Self::connect_to(info, &uri, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => err,
+        };
+        match info.lookup.as_ref().and_then(|lookup| lookup()) {
+            Some(new_uri) if new_uri != uri => {
+                error!(
+                    "Failed to reach service '{}' at {}, failing over to {}",
+                    info.service, uri, new_uri
+                );
+                let stream = Self::connect_to(info, &new_uri, timeout)?;
+                *info.uri.lock().expect(FAILED_TO_LOCK) = new_uri;
+                Ok(stream)
+            }
+            _ => Err(err),
+        }
+    }
 
-        // Service responds with a boolean byte, signalling success
-        let success = read_verification_byte(&mut stream)
-            .chain_err(|| ErrorKind::ServiceResponseInterruption)?;
-        Ok(if success {
-            // Decode response as response type upon success
+    fn connect_to(
+        info: &ClientInfo,
+        uri: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Box<dyn ClientStream>> {
+        let trimmed_uri = uri.trim_start_matches("rosrpc://");
+        let mut stream = info
+            .connector
+            .connect(trimmed_uri, timeout, info.tcp_nodelay)
+            .chain_err(|| ErrorKind::ServiceConnectionFail(info.service.clone(), uri.into()))?;
+        if let Some(keepalive) = &info.tcp_keepalive {
+            stream.set_tcp_keepalive(keepalive)?;
+        }
 
-            // TODO: validate response length
-            let _length = stream.read_u32::<LittleEndian>();
+        // Service request starts by exchanging connection headers
+        exchange_headers::<T, _>(&mut stream, &info.caller_id, &info.service, info.persistent)?;
 
-            let data = RosMsg::decode(&mut stream)?;
+        Ok(stream)
+    }
 
-            let mut dump = vec![];
-            if let Err(err) = stream.read_to_end(&mut dump) {
-                error!("Failed to read from TCP stream: {:?}", err)
-            }
+    fn exchange_request(
+        args: &T::Request,
+        stream: &mut Box<dyn ClientStream>,
+        timeout: Option<Duration>,
+        is_cancelled: &dyn Fn() -> bool,
+    ) -> Result<ServiceResult<T::Response>> {
+        Self::send_request(args, stream, timeout, is_cancelled)?;
+        Self::receive_response(stream)
+    }
 
-            Ok(data)
+    fn send_request(
+        args: &T::Request,
+        stream: &mut Box<dyn ClientStream>,
+        timeout: Option<Duration>,
+        is_cancelled: &dyn Fn() -> bool,
+    ) -> Result<()> {
+        write_cancellable(stream.as_mut(), &args.encode_vec()?, timeout, is_cancelled)
+    }
+
+    fn receive_response(stream: &mut Box<dyn ClientStream>) -> Result<ServiceResult<T::Response>> {
+        // Service responds with a boolean byte, signalling success
+        let success =
+            read_verification_byte(stream).chain_err(|| ErrorKind::ServiceResponseInterruption)?;
+        Ok(if success {
+            // Decode response as response type upon success
+            Ok(read_response_body(stream)?)
         } else {
             // Decode response as string upon failure
-            let data = RosMsg::decode(&mut stream)?;
+            Err(RosMsg::decode(stream).map_err(decode_error)?)
+        })
+    }
+}
 
-            let mut dump = vec![];
-            if let Err(err) = stream.read_to_end(&mut dump) {
-                error!("Failed to read from TCP stream: {:?}", err)
+/// Writes data in chunks, stopping early once the request gets cancelled.
+///
+/// A write stalled by the service is retried until the timeout passes
+/// without any progress, checking for cancellation in between.
+fn write_cancellable<S: ClientStream + ?Sized>(
+    stream: &mut S,
+    data: &[u8],
+    timeout: Option<Duration>,
+    is_cancelled: &dyn Fn() -> bool,
+) -> Result<()> {
+    let check_interval = timeout.map_or(CANCELLATION_CHECK_INTERVAL, |timeout| {
+        timeout.min(CANCELLATION_CHECK_INTERVAL)
+    });
+    stream.set_write_timeout(Some(check_interval))?;
+    let result = write_chunks(&mut *stream, data, timeout, is_cancelled);
+    stream.set_write_timeout(timeout)?;
+    result
+}
+
+fn write_chunks<S: Write + ?Sized>(
+    stream: &mut S,
+    mut data: &[u8],
+    timeout: Option<Duration>,
+    is_cancelled: &dyn Fn() -> bool,
+) -> Result<()> {
+    let mut last_progress = Instant::now();
+    while !data.is_empty() {
+        if is_cancelled() {
+            bail!(ErrorKind::ServiceRequestCancelled);
+        }
+        let chunk = &data[..data.len().min(WRITE_CHUNK_SIZE)];
+        match stream.write(chunk) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+            Ok(written) => {
+                data = &data[written..];
+                last_progress = Instant::now();
             }
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) if is_timeout(&err) => {
+                if timeout.is_some_and(|timeout| last_progress.elapsed() >= timeout) {
+                    return Err(err.into());
+                }
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
 
-            Err(data)
-        })
+#[inline]
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+fn read_response_body<R: std::io::Read, M: RosMsg>(reader: &mut R) -> Result<M> {
+    let length = reader
+        .read_u32::<LittleEndian>()
+        .chain_err(|| ErrorKind::ServiceResponseInterruption)?;
+    check_message_size(length as usize)?;
+    let mut buffer = Vec::new();
+    reader
+        .take(u64::from(length))
+        .read_to_end(&mut buffer)
+        .chain_err(|| ErrorKind::ServiceResponseInterruption)?;
+    if buffer.len() != length as usize {
+        bail!(ErrorKind::ServiceResponseInterruption);
     }
+    RosMsg::decode(io::Cursor::new(buffer)).map_err(decode_error)
 }
 
 #[inline]
@@ -162,7 +589,12 @@ fn read_verification_byte<R: std::io::Read>(reader: &mut R) -> std::io::Result<b
     reader.read_u8().map(|v| v != 0)
 }
 
-fn write_request<T, U>(mut stream: &mut U, caller_id: &str, service: &str) -> Result<()>
+fn write_request<T, U>(
+    mut stream: &mut U,
+    caller_id: &str,
+    service: &str,
+    persistent: bool,
+) -> Result<()>
 where
     T: ServicePair,
     U: std::io::Write,
@@ -172,6 +604,10 @@ where
     fields.insert(String::from("service"), String::from(service));
     fields.insert(String::from("md5sum"), T::md5sum());
     fields.insert(String::from("type"), T::msg_type());
+    fields.insert(String::from("message_definition"), T::msg_definition());
+    if persistent {
+        fields.insert(String::from("persistent"), String::from("1"));
+    }
     encode(&mut stream, &fields)?;
     Ok(())
 }
@@ -182,17 +618,612 @@ where
     U: std::io::Read,
 {
     let fields = decode(&mut stream)?;
+    check_rejection(&fields)?;
     if fields.get("callerid").is_none() {
         bail!(ErrorKind::HeaderMissingField("callerid".into()));
     }
+    match fields.get("md5sum") {
+        Some(md5sum) => match_service_field("md5sum", &T::md5sum(), md5sum)?,
+        None => bail!(ErrorKind::HeaderMissingField("md5sum".into())),
+    }
+    if let Some(msg_type) = fields.get("type") {
+        match_service_field("type", &T::msg_type(), msg_type)?;
+    }
+    Ok(())
+}
+
+fn match_service_field(field: &str, expected: &str, actual: &str) -> Result<()> {
+    if expected != actual && expected != "*" && actual != "*" {
+        bail!(ErrorKind::ServiceTypeMismatch(
+            field.into(),
+            expected.into(),
+            actual.into(),
+        ));
+    }
     Ok(())
 }
 
-fn exchange_headers<T, U>(stream: &mut U, caller_id: &str, service: &str) -> Result<()>
+fn exchange_headers<T, U>(
+    stream: &mut U,
+    caller_id: &str,
+    service: &str,
+    persistent: bool,
+) -> Result<()>
 where
     T: ServicePair,
     U: std::io::Write + std::io::Read,
 {
-    write_request::<T, U>(stream, caller_id, service)?;
+    write_request::<T, U>(stream, caller_id, service, persistent)?;
     read_response::<T, U>(stream)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::header;
+    use super::super::Message;
+    use super::*;
+    use crate::msg::test_msgs::roscpp::{SetLoggerLevel, SetLoggerLevelReq};
+    use byteorder::WriteBytesExt;
+    use std::net::TcpListener;
+
+    #[derive(Clone, Debug, Default, PartialEq)]
+    struct Increment;
+
+    impl RosMsg for Increment {
+        fn encode<W: io::Write>(&self, _w: W) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn decode<R: io::Read>(_r: R) -> io::Result<Self> {
+            Ok(Increment)
+        }
+    }
+
+    impl Message for Increment {
+        fn msg_definition() -> String {
+            "uint32 value\n---\nuint32 value\n".into()
+        }
+
+        fn md5sum() -> String {
+            "0123456789abcdef0123456789abcdef".into()
+        }
+
+        fn msg_type() -> String {
+            "test_msgs/Increment".into()
+        }
+    }
+
+    impl ServicePair for Increment {
+        type Request = u32;
+        type Response = u32;
+    }
+
+    fn response_header(md5sum: &str) -> HashMap<String, String> {
+        let mut fields = HashMap::new();
+        fields.insert(String::from("callerid"), String::from("/server"));
+        fields.insert(String::from("md5sum"), String::from(md5sum));
+        fields.insert(String::from("type"), Increment::msg_type());
+        fields
+    }
+
+    fn encode_header(fields: &HashMap<String, String>) -> io::Cursor<Vec<u8>> {
+        let mut cursor = io::Cursor::new(Vec::new());
+        header::encode(&mut cursor, fields).unwrap();
+        cursor.set_position(0);
+        cursor
+    }
+
+    fn serve_header<S: Read + Write>(stream: &mut S) -> HashMap<String, String> {
+        let request = header::decode(stream).unwrap();
+        header::encode(stream, &response_header(&Increment::md5sum())).unwrap();
+        request
+    }
+
+    fn serve_increment<S: Read + Write>(stream: &mut S) {
+        let _length = stream.read_u32::<LittleEndian>().unwrap();
+        let value = stream.read_u32::<LittleEndian>().unwrap();
+        stream.write_u8(1).unwrap();
+        stream.write_u32::<LittleEndian>(4).unwrap();
+        stream.write_u32::<LittleEndian>(value + 1).unwrap();
+    }
+
+    /// One end of an in-memory connection.
+    struct Pipe {
+        incoming: std::sync::mpsc::Receiver<Vec<u8>>,
+        outgoing: std::sync::mpsc::Sender<Vec<u8>>,
+        buffer: io::Cursor<Vec<u8>>,
+    }
+
+    fn pipe() -> (Pipe, Pipe) {
+        let (first_tx, first_rx) = std::sync::mpsc::channel();
+        let (second_tx, second_rx) = std::sync::mpsc::channel();
+        let end = |incoming, outgoing| Pipe {
+            incoming,
+            outgoing,
+            buffer: io::Cursor::new(vec![]),
+        };
+        (end(first_rx, second_tx), end(second_rx, first_tx))
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            while self.buffer.position() == self.buffer.get_ref().len() as u64 {
+                match self.incoming.recv() {
+                    Ok(data) => self.buffer = io::Cursor::new(data),
+                    // The other end got dropped
+                    Err(_) => return Ok(0),
+                }
+            }
+            self.buffer.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.outgoing
+                .send(buf.to_vec())
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ClientStream for Pipe {
+        fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Hands out a single in-memory connection to `service.local:1234`.
+    struct PipeConnector(Mutex<Option<Pipe>>);
+
+    impl Connector for PipeConnector {
+        fn connect(
+            &self,
+            address: &str,
+            _timeout: Option<Duration>,
+            _nodelay: bool,
+        ) -> io::Result<Box<dyn ClientStream>> {
+            let stream = match address {
+                "service.local:1234" => self.0.lock().expect(FAILED_TO_LOCK).take(),
+                _ => None,
+            };
+            match stream {
+                Some(stream) => Ok(Box::new(stream)),
+                None => Err(io::Error::from(io::ErrorKind::ConnectionRefused)),
+            }
+        }
+    }
+
+    #[test]
+    fn exchanges_request_through_custom_connector() {
+        let (client_end, mut server_end) = pipe();
+        let server = thread::spawn(move || {
+            let request = serve_header(&mut server_end);
+            serve_increment(&mut server_end);
+            request
+        });
+
+        let client =
+            Client::<Increment>::new("/client", "rosrpc://service.local:1234", "/increment")
+                .with_connector(PipeConnector(Mutex::new(Some(client_end))));
+        assert_eq!(Ok(6), client.req(&5).unwrap());
+
+        let request = server.join().unwrap();
+        assert_eq!(Some(&String::from("/client")), request.get("callerid"));
+        assert_eq!(Some(&String::from("/increment")), request.get("service"));
+        assert!(client.req(&5).is_err());
+    }
+
+    #[test]
+    fn reads_response_body_of_declared_length() {
+        let data = vec![4, 0, 0, 0, 7, 0, 0, 0, 1, 2, 3];
+        let value: u32 = read_response_body(&mut io::Cursor::new(data)).unwrap();
+        assert_eq!(7, value);
+    }
+
+    #[test]
+    fn fails_on_response_body_shorter_than_declared() {
+        let data = vec![10, 0, 0, 0, 7, 0, 0, 0];
+        let result = read_response_body::<_, u32>(&mut io::Cursor::new(data));
+        match result.unwrap_err().kind() {
+            ErrorKind::ServiceResponseInterruption => {}
+            kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_response_body_length() {
+        let data = vec![0xff, 0xff, 0xff, 0xff, 7, 0, 0, 0];
+        let result = read_response_body::<_, u32>(&mut io::Cursor::new(data));
+        match result.unwrap_err().kind() {
+            ErrorKind::Io(err) => assert_eq!(io::ErrorKind::InvalidData, err.kind()),
+            kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn accepts_matching_response_header() {
+        let mut data = encode_header(&response_header(&Increment::md5sum()));
+        read_response::<Increment, _>(&mut data).unwrap();
+        let mut data = encode_header(&response_header("*"));
+        read_response::<Increment, _>(&mut data).unwrap();
+    }
+
+    #[test]
+    fn rejects_mismatched_response_md5sum() {
+        let mut data = encode_header(&response_header("ffffffffffffffffffffffffffffffff"));
+        match read_response::<Increment, _>(&mut data).unwrap_err().kind() {
+            ErrorKind::ServiceTypeMismatch(field, ..) => assert_eq!("md5sum", field),
+            kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn rejects_mismatched_response_type() {
+        let mut fields = response_header(&Increment::md5sum());
+        fields.insert(String::from("type"), String::from("test_msgs/Other"));
+        let mut data = encode_header(&fields);
+        match read_response::<Increment, _>(&mut data).unwrap_err().kind() {
+            ErrorKind::ServiceTypeMismatch(field, ..) => assert_eq!("type", field),
+            kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn persistent_client_reuses_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("rosrpc://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = serve_header(&mut stream);
+            serve_increment(&mut stream);
+            serve_increment(&mut stream);
+            request
+        });
+
+        // A second connection would never get its headers answered, so the
+        // timeout makes the test fail instead of hanging in that case
+        let client = Client::<Increment>::new_persistent("/client", &uri, "/increment")
+            .with_timeout(Duration::from_secs(5));
+        assert_eq!(Ok(6), client.req(&5).unwrap());
+        assert_eq!(Ok(11), client.req(&10).unwrap());
+
+        let request = server.join().unwrap();
+        assert_eq!(Some(&String::from("1")), request.get("persistent"));
+        assert_eq!(
+            Some(&Increment::msg_definition()),
+            request.get("message_definition")
+        );
+    }
+
+    #[test]
+    fn persistent_client_does_not_resend_written_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("rosrpc://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            serve_header(&mut stream);
+            serve_increment(&mut stream);
+            // Reads the second request, but drops the connection without responding
+            let _length = stream.read_u32::<LittleEndian>().unwrap();
+            let _value = stream.read_u32::<LittleEndian>().unwrap();
+            drop(stream);
+            listener
+        });
+
+        let client = Client::<Increment>::new_persistent("/client", &uri, "/increment")
+            .with_timeout(Duration::from_secs(5));
+        assert_eq!(Ok(6), client.req(&5).unwrap());
+        assert!(client.req(&10).is_err());
+
+        let listener = server.join().unwrap();
+        listener.set_nonblocking(true).unwrap();
+        assert_eq!(
+            io::ErrorKind::WouldBlock,
+            listener.accept().unwrap_err().kind()
+        );
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        use std::task::Wake;
+
+        struct ThreadWaker(thread::Thread);
+
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn awaits_response_of_call() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("rosrpc://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            serve_header(&mut stream);
+            // Delaying the response makes the future wait for it
+            thread::sleep(Duration::from_millis(50));
+            serve_increment(&mut stream);
+        });
+
+        let client = Client::<Increment>::new("/client", &uri, "/increment")
+            .with_timeout(Duration::from_secs(5));
+        assert_eq!(Ok(8), block_on(client.call(7)).unwrap());
+    }
+
+    #[test]
+    fn hung_service_does_not_stall_calls_of_other_clients() {
+        let hung = TcpListener::bind("127.0.0.1:0").unwrap();
+        let hung_uri = format!("rosrpc://{}", hung.local_addr().unwrap());
+        thread::spawn(move || {
+            // Accepts connections, but never answers them
+            let _streams = hung.incoming().collect::<Vec<_>>();
+        });
+        let hung_client = Client::<Increment>::new("/client", &hung_uri, "/hung");
+        let _hung_calls = (0..16)
+            .map(|value| hung_client.call(value))
+            .collect::<Vec<_>>();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("rosrpc://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            serve_header(&mut stream);
+            serve_increment(&mut stream);
+        });
+        let client = Client::<Increment>::new("/client", &uri, "/increment")
+            .with_timeout(Duration::from_secs(5));
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        thread::spawn(move || done_tx.send(block_on(client.call(7)).unwrap()));
+        let response = done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(Ok(8), response);
+    }
+
+    const UPLOAD_SIZE: usize = 64 << 20;
+
+    // Returns how much of a large request reached a service that stops reading
+    fn bytes_received_after<F>(cancel: F) -> usize
+    where
+        F: FnOnce(Client<SetLoggerLevel>, SetLoggerLevelReq),
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("rosrpc://{}", listener.local_addr().unwrap());
+        let (cancelled_tx, cancelled_rx) = std::sync::mpsc::channel();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            header::decode(&mut stream).unwrap();
+            let mut fields = response_header(&SetLoggerLevel::md5sum());
+            fields.insert(String::from("type"), SetLoggerLevel::msg_type());
+            header::encode(&mut stream, &fields).unwrap();
+            cancelled_rx.recv().unwrap();
+            let mut received = vec![];
+            let _ = stream.read_to_end(&mut received);
+            received.len()
+        });
+
+        let client = Client::<SetLoggerLevel>::new("/client", &uri, "/set_logger_level");
+        let request = SetLoggerLevelReq {
+            logger: "x".repeat(UPLOAD_SIZE),
+            level: String::from("debug"),
+        };
+        cancel(client, request);
+        // Gives the writing thread time to notice the cancellation and close the stream
+        thread::sleep(CANCELLATION_CHECK_INTERVAL * 3);
+        cancelled_tx.send(()).unwrap();
+        server.join().unwrap()
+    }
+
+    #[test]
+    fn dropping_call_cancels_request_being_written() {
+        let received = bytes_received_after(|client, request| {
+            let call = client.call(request);
+            thread::sleep(Duration::from_millis(200));
+            drop(call);
+        });
+        assert!(0 < received && received < UPLOAD_SIZE, "{}", received);
+    }
+
+    #[test]
+    fn dropping_client_cancels_request_being_written() {
+        let received = bytes_received_after(|client, request| {
+            let _response = client.req_async(request);
+            thread::sleep(Duration::from_millis(200));
+            drop(client);
+        });
+        assert!(0 < received && received < UPLOAD_SIZE, "{}", received);
+    }
+
+    #[test]
+    fn cancelled_write_unwinds() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let _peer = listener.accept().unwrap();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let writer_cancelled = Arc::clone(&cancelled);
+        let (result_tx, result_rx) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let is_cancelled = || writer_cancelled.load(Ordering::SeqCst);
+            let result = write_cancellable(&mut stream, &vec![0; UPLOAD_SIZE], None, &is_cancelled);
+            result_tx.send(result).unwrap();
+        });
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(result_rx.try_recv().is_err());
+        cancelled.store(true, Ordering::SeqCst);
+        let result = result_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        match result.unwrap_err().kind() {
+            ErrorKind::ServiceRequestCancelled => {}
+            kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn writing_respects_timeout_without_progress() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let _peer = listener.accept().unwrap();
+        let start = Instant::now();
+        let result = write_cancellable(
+            &mut stream,
+            &vec![0; UPLOAD_SIZE],
+            Some(Duration::from_millis(200)),
+            &|| false,
+        );
+        match result.unwrap_err().kind() {
+            ErrorKind::Io(err) => assert!(is_timeout(err)),
+            kind => panic!("Unexpected error: {}", kind),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            Some(Duration::from_millis(200)),
+            stream.write_timeout().unwrap()
+        );
+    }
+
+    #[test]
+    fn fails_over_to_provider_from_lookup() {
+        // Nothing listens on the first provider's port once the listener is dropped
+        let dead_uri = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .map(|address| format!("rosrpc://{}", address))
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let live_uri = format!("rosrpc://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                serve_header(&mut stream);
+                serve_increment(&mut stream);
+            }
+        });
+
+        let lookups = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&lookups);
+        let client = Client::<Increment>::new("/client", &dead_uri, "/increment")
+            .with_timeout(Duration::from_millis(200))
+            .with_failover(move || {
+                *counter.lock().unwrap() += 1;
+                Some(live_uri.clone())
+            });
+        assert_eq!(Ok(6), client.req(&5).unwrap());
+        assert_eq!(Ok(11), client.req(&10).unwrap());
+        server.join().unwrap();
+        assert_eq!(1, *lookups.lock().unwrap());
+    }
+
+    #[test]
+    fn reports_failure_when_lookup_finds_same_provider() {
+        let dead_uri = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .map(|address| format!("rosrpc://{}", address))
+            .unwrap();
+        let lookup_uri = dead_uri.clone();
+        let client = Client::<Increment>::new("/client", &dead_uri, "/increment")
+            .with_timeout(Duration::from_millis(200))
+            .with_failover(move || Some(lookup_uri.clone()));
+        match client.req(&5).unwrap_err().kind() {
+            ErrorKind::ServiceConnectionFail(service, uri) => {
+                assert_eq!("/increment", service);
+                assert_eq!(&dead_uri, uri);
+            }
+            kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn connecting_respects_timeout() {
+        // Nothing listens on the port once the listener is dropped
+        let address = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap();
+        let start = Instant::now();
+        let result = connect_to_tcp_with_multiple_attempts(
+            &address.to_string(),
+            15,
+            Some(Duration::from_millis(200)),
+            true,
+        );
+        assert!(result.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn reading_respects_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut stream = connect_to_tcp_with_multiple_attempts(
+            &address,
+            1,
+            Some(Duration::from_millis(50)),
+            true,
+        )
+        .unwrap();
+        let _peer = listener.accept().unwrap();
+        assert!(read_verification_byte(&mut stream).is_err());
+    }
+
+    #[test]
+    fn connecting_enables_configured_keepalive() {
+        struct InspectedTcp(Mutex<Option<TcpStream>>);
+
+        impl Connector for Arc<InspectedTcp> {
+            fn connect(
+                &self,
+                address: &str,
+                timeout: Option<Duration>,
+                nodelay: bool,
+            ) -> io::Result<Box<dyn ClientStream>> {
+                let stream = connect_to_tcp(address, timeout, nodelay)?;
+                *self.0.lock().unwrap() = Some(stream.try_clone()?);
+                Ok(Box::new(stream))
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("rosrpc://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            serve_header(&mut stream);
+            serve_increment(&mut stream);
+        });
+
+        let keepalive = TcpKeepalive::new(Duration::from_secs(30))
+            .with_interval(Duration::from_secs(5))
+            .with_count(3);
+        let connector = Arc::new(InspectedTcp(Mutex::new(None)));
+        let client = Client::<Increment>::new("/client", &uri, "/increment")
+            .with_timeout(Duration::from_secs(5))
+            .with_connector(Arc::clone(&connector))
+            .with_tcp_keepalive(Some(keepalive));
+        assert_eq!(Ok(3), client.req(&2).unwrap());
+
+        let stream = connector.0.lock().unwrap().take().unwrap();
+        assert_eq!(Some(keepalive), keepalive::configured(&stream).unwrap());
+    }
+
+    #[test]
+    fn connecting_configures_nodelay() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let stream = connect_to_tcp_with_multiple_attempts(&address, 1, None, true).unwrap();
+        assert!(stream.nodelay().unwrap());
+        let stream = connect_to_tcp_with_multiple_attempts(&address, 1, None, false).unwrap();
+        assert!(!stream.nodelay().unwrap());
+    }
+}
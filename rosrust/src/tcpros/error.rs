@@ -1,4 +1,6 @@
 #![allow(deprecated)]
+use crate::util::ports::AddrInUse;
+
 error_chain! {
     foreign_links {
         Io(::std::io::Error);
@@ -21,18 +23,47 @@ error_chain! {
             description("Data field within header missing")
             display("Data field '{}' within header missing", field)
         }
+        HandshakeRejected(message: String) {
+            description("Peer rejected the connection header")
+            display("Peer rejected the connection header: {}", message)
+        }
         MessageTypeMismatch(expected: String, actual: String) {
             description("Cannot publish with multiple message types")
             display("Cannot publish '{}' data on '{}' publisher", actual, expected)
         }
+        SubscriberTypeMismatch(expected: String, actual: String) {
+            description("Cannot subscribe with multiple message types")
+            display("Cannot receive '{}' data from '{}' subscriber", actual, expected)
+        }
+        UnexpectedEof {
+            description("Data stream ended before the data was complete")
+            display("Data stream ended before the data was complete")
+        }
+        MalformedData(details: String) {
+            description("Received data is malformed")
+            display("Received data is malformed: {}", details)
+        }
         ServiceResponseInterruption {
             description("Data stream interrupted while reading service response")
             display("Data stream interrupted while reading service response")
         }
+        ServiceRequestCancelled {
+            description("Service request was cancelled while being sent")
+            display("Service request was cancelled while being sent")
+        }
         ServiceResponseUnknown {
             description("Unknown error caused service response to panic")
             display("Unknown error caused service response to panic")
         }
+        ServiceTypeMismatch(field: String, expected: String, actual: String) {
+            description("Service does not serve the expected type")
+            display("Service does not serve the expected type. Field '{}' expected: '{}' actual: '{}'",
+                    field, expected, actual)
+        }
+        ServiceCallFailed(message: String) {
+            description("Service reported a failure while handling the request")
+            display("Service reported a failure while handling the request: {}", message)
+        }
     }
 }
 
@@ -49,7 +80,66 @@ impl Error {
     pub fn is_closed_connection(&self) -> bool {
         match *self.kind() {
             ErrorKind::Io(ref io_err) => is_closed_connection(io_err),
+            ErrorKind::UnexpectedEof => true,
             _ => false,
         }
     }
 }
+
+impl AddrInUse for ErrorKind {
+    fn is_addr_in_use(&self) -> bool {
+        match *self {
+            ErrorKind::Io(ref io_err) => io_err.is_addr_in_use(),
+            _ => false,
+        }
+    }
+}
+
+impl AddrInUse for Error {
+    #[inline]
+    fn is_addr_in_use(&self) -> bool {
+        self.kind().is_addr_in_use()
+    }
+}
+
+/// Classifies a failure to decode received data.
+///
+/// Streams that end early are told apart from invalid data, since the former
+/// usually means the peer disconnected, while the latter is a protocol violation.
+pub fn decode_error(err: ::std::io::Error) -> Error {
+    use std::io::ErrorKind as IoErrorKind;
+    match err.kind() {
+        IoErrorKind::UnexpectedEof => ErrorKind::UnexpectedEof.into(),
+        IoErrorKind::InvalidData => ErrorKind::MalformedData(err.to_string()).into(),
+        _ => err.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rosmsg::RosMsg;
+
+    #[test]
+    fn distinguishes_truncated_from_malformed_data() {
+        let truncated = [5, 0, 0, 0, b'a', b'b'];
+        let err = String::decode(&truncated[..])
+            .map_err(decode_error)
+            .unwrap_err();
+        match *err.kind() {
+            ErrorKind::UnexpectedEof => {}
+            ref kind => panic!("Unexpected error: {}", kind),
+        }
+        assert!(err.is_closed_connection());
+
+        let garbage = [2, 0, 0, 0, 0xff, 0xfe];
+        let err = String::decode(&garbage[..])
+            .map_err(decode_error)
+            .unwrap_err();
+        match *err.kind() {
+            ErrorKind::MalformedData(..) => {}
+            ref kind => panic!("Unexpected error: {}", kind),
+        }
+        assert!(!err.is_closed_connection());
+    }
+}
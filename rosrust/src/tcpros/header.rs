@@ -1,25 +1,42 @@
+use super::error::{decode_error, Error as TcprosError, ErrorKind, Result};
 use crate::rosmsg::RosMsg;
 use std;
 use std::collections::HashMap;
 use std::io::Error;
 
-pub fn decode<R: std::io::Read>(data: &mut R) -> Result<HashMap<String, String>, Error> {
-    RosMsg::decode(data)
+pub fn decode<R: std::io::Read>(data: &mut R) -> Result<HashMap<String, String>> {
+    RosMsg::decode(data).map_err(decode_error)
 }
 
 pub fn encode<W: std::io::Write>(
     writer: &mut W,
     data: &HashMap<String, String>,
-) -> Result<(), Error> {
+) -> std::result::Result<(), Error> {
     data.encode(writer)
 }
 
-pub fn match_field(
-    fields: &HashMap<String, String>,
-    field: &str,
-    expected: &str,
-) -> Result<(), super::error::Error> {
-    use super::error::ErrorKind;
+/// Tells the peer why its connection header got rejected, with a header holding an `error` field.
+///
+/// Nothing is sent if the connection is closed already.
+pub fn reject<W: std::io::Write>(writer: &mut W, reason: &TcprosError) {
+    if reason.is_closed_connection() {
+        return;
+    }
+    let mut fields = HashMap::<String, String>::new();
+    fields.insert(String::from("error"), reason.to_string());
+    // The peer might have hung up already, in which case it needs no explanation
+    let _ = encode(writer, &fields);
+}
+
+/// Fails with the peer's explanation if its header rejects the connection.
+pub fn check_rejection(fields: &HashMap<String, String>) -> Result<()> {
+    match fields.get("error") {
+        Some(message) => bail!(ErrorKind::HandshakeRejected(message.clone())),
+        None => Ok(()),
+    }
+}
+
+pub fn match_field(fields: &HashMap<String, String>, field: &str, expected: &str) -> Result<()> {
     let actual = match fields.get(field) {
         Some(actual) => actual,
         None => bail!(ErrorKind::HeaderMissingField(field.into())),
@@ -99,6 +116,17 @@ mod tests {
         assert_eq!(Some(&String::from("123")), data.get("abc"));
     }
 
+    #[test]
+    fn reports_truncated_header_as_unexpected_eof() {
+        use super::super::error::ErrorKind;
+        let input = vec![11, 0, 0, 0, 7, 0, 0, 0, 97, 98, 99];
+        let err = decode(&mut std::io::Cursor::new(input)).unwrap_err();
+        match *err.kind() {
+            ErrorKind::UnexpectedEof => {}
+            ref kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+
     #[test]
     fn reads_typical_header() {
         let input = vec![
@@ -134,4 +162,23 @@ mod tests {
         assert_eq!(Some(&String::from("/chatter")), data.get("topic"));
         assert_eq!(Some(&String::from("std_msgs/String")), data.get("type"));
     }
+
+    #[test]
+    fn rejection_carries_reason_to_peer() {
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let reason = TcprosError::from(ErrorKind::HeaderMissingField("callerid".into()));
+        reject(&mut cursor, &reason);
+        cursor.set_position(0);
+        let fields = decode(&mut cursor).expect(FAILED_TO_DECODE);
+        assert_eq!(1, fields.len());
+        match *check_rejection(&fields).unwrap_err().kind() {
+            ErrorKind::HandshakeRejected(ref message) => assert_eq!(&reason.to_string(), message),
+            ref kind => panic!("Unexpected error: {}", kind),
+        }
+
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        reject(&mut cursor, &ErrorKind::UnexpectedEof.into());
+        assert!(cursor.into_inner().is_empty());
+        assert!(check_rejection(&HashMap::new()).is_ok());
+    }
 }
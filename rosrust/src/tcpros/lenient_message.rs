@@ -0,0 +1,115 @@
+use super::Message;
+use crate::rosmsg::RosMsg;
+use crate::Clock;
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+/// Message that gets decoded as `T`, ignoring any data following its fields.
+///
+/// Like `RawMessage`, it uses the `*` wildcard for its md5sum and type, so
+/// subscribers accept publishers of any type. This allows receiving messages
+/// whose definition appends fields to the one of `T`, while only the known
+/// fields get decoded.
+///
+/// The wildcard skips the md5sum check that normally rejects publishers with
+/// a different definition, so publishers of unrelated types are not detected,
+/// and their messages get decoded as garbage or fail to decode. This is only
+/// meant for deployments where the message definitions are known to match.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LenientMessage<T>(pub T);
+
+impl<T: Message> Message for LenientMessage<T> {
+    #[inline]
+    fn msg_definition() -> String {
+        T::msg_definition()
+    }
+
+    #[inline]
+    fn md5sum() -> String {
+        "*".into()
+    }
+
+    #[inline]
+    fn msg_type() -> String {
+        "*".into()
+    }
+
+    #[inline]
+    fn set_header(&mut self, clock: &Arc<dyn Clock>, seq: &Arc<AtomicUsize>) {
+        self.0.set_header(clock, seq)
+    }
+}
+
+impl<T: RosMsg> RosMsg for LenientMessage<T> {
+    #[inline]
+    fn encode<W: io::Write>(&self, w: W) -> io::Result<()> {
+        self.0.encode(w)
+    }
+
+    /// Decodes the fields known to `T`, leaving any following data unread.
+    #[inline]
+    fn decode<R: io::Read>(r: R) -> io::Result<Self> {
+        T::decode(r).map(LenientMessage)
+    }
+
+    /// Decodes a message within its declared length, ignoring trailing fields.
+    fn decode_slice(bytes: &[u8]) -> io::Result<Self> {
+        let mut body = bytes;
+        let length = body.read_u32::<LittleEndian>()? as usize;
+        let body = body.get(..length).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Message is shorter than its declared length",
+            )
+        })?;
+        Self::decode(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::rosgraph_msgs::Clock as ClockMsg;
+    use crate::time::Time;
+    use byteorder::WriteBytesExt;
+
+    #[test]
+    fn ignores_trailing_bytes() {
+        let message = ClockMsg {
+            clock: Time { sec: 1, nsec: 2 },
+        };
+        let mut body = message.encode_vec().unwrap().split_off(4);
+        body.extend_from_slice(&[7, 7, 7, 7, 7, 7, 7, 7]);
+        let mut bytes = vec![];
+        bytes.write_u32::<LittleEndian>(body.len() as u32).unwrap();
+        bytes.extend_from_slice(&body);
+
+        let decoded = LenientMessage::<ClockMsg>::decode_slice(&bytes).unwrap();
+        assert_eq!(message, decoded.0);
+        assert_eq!("*", LenientMessage::<ClockMsg>::md5sum());
+    }
+
+    #[test]
+    fn leaves_trailing_bytes_in_reader() {
+        let message = ClockMsg {
+            clock: Time { sec: 1, nsec: 2 },
+        };
+        let mut body = message.encode_vec().unwrap().split_off(4);
+        body.extend_from_slice(&[7, 7]);
+
+        let mut reader = io::Cursor::new(&body);
+        let decoded = LenientMessage::<ClockMsg>::decode(&mut reader).unwrap();
+        assert_eq!(message, decoded.0);
+        assert_eq!(8, reader.position());
+    }
+
+    #[test]
+    fn rejects_messages_shorter_than_declared() {
+        let mut bytes = ClockMsg::default().encode_vec().unwrap();
+        bytes[0] += 1;
+        let err = LenientMessage::<ClockMsg>::decode_slice(&bytes).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+}
@@ -1,8 +1,15 @@
-pub use self::client::{Client, ClientResponse};
+pub use self::client::{
+    Client, ClientCall, ClientResponse, ClientStream, Connector, DEFAULT_CALL_TIMEOUT,
+};
 pub use self::error::Error;
+pub use self::lenient_message::LenientMessage;
 pub use self::publisher::{Publisher, PublisherStream};
-pub use self::service::Service;
-pub use self::subscriber::Subscriber;
+pub use self::raw_message::RawMessage;
+pub use self::service::{Service, DEFAULT_SERVICE_WORKERS};
+pub use self::subscriber::{set_callback_threads, MessageEvent, Subscriber};
+pub use self::util::compression::set_transport_compression;
+pub use self::util::keepalive::TcpKeepalive;
+pub use self::util::stats::{ConnectionInfo, WindowStats};
 
 use crate::rosmsg::RosMsg;
 use crate::Clock;
@@ -12,14 +19,43 @@ use std::sync::Arc;
 
 mod client;
 pub mod error;
-mod header;
+pub(crate) mod header;
+mod lenient_message;
 mod publisher;
+mod raw_message;
 mod service;
 mod subscriber;
 mod util;
 
 pub type ServiceResult<T> = Result<T, String>;
 
+/// Conversions for the nested results returned by service calls.
+pub trait ServiceResultExt<T> {
+    /// Merges a failure reported by the service into the outer error,
+    /// as `ErrorKind::ServiceCallFailed`.
+    fn into_result(self) -> error::Result<T>;
+
+    /// Transforms a successful response, keeping both kinds of failure.
+    fn map_response<U, F: FnOnce(T) -> U>(self, f: F) -> error::Result<ServiceResult<U>>;
+
+    /// Returns the response, or `default` if either the call or the service failed.
+    fn response_or(self, default: T) -> T;
+}
+
+impl<T> ServiceResultExt<T> for error::Result<ServiceResult<T>> {
+    fn into_result(self) -> error::Result<T> {
+        self?.map_err(|message| error::ErrorKind::ServiceCallFailed(message).into())
+    }
+
+    fn map_response<U, F: FnOnce(T) -> U>(self, f: F) -> error::Result<ServiceResult<U>> {
+        self.map(|result| result.map(f))
+    }
+
+    fn response_or(self, default: T) -> T {
+        self.into_result().unwrap_or(default)
+    }
+}
+
 pub trait Message: Clone + Debug + Default + PartialEq + RosMsg + Send + Sync + 'static {
     fn msg_definition() -> String;
     fn md5sum() -> String;
@@ -32,8 +68,87 @@ pub trait ServicePair: Clone + Debug + Default + PartialEq + Message {
     type Response: RosMsg + Send + 'static;
 }
 
+/// Type of the messages on a topic, as told to the master and in connection headers.
+///
+/// Typed publishers and subscribers take it from their message type. Raw ones
+/// get it at runtime, for example from the connection header of another topic.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageType {
+    pub msg_type: String,
+    pub md5sum: String,
+    pub msg_definition: String,
+}
+
+impl MessageType {
+    pub fn of<T: Message>() -> Self {
+        Self {
+            msg_type: T::msg_type(),
+            md5sum: T::md5sum(),
+            msg_definition: T::msg_definition(),
+        }
+    }
+
+    /// Whether this is the `*` wildcard, which matches any type.
+    #[inline]
+    pub fn is_wildcard(&self) -> bool {
+        self.md5sum == "*"
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Topic {
     pub name: String,
     pub msg_type: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::error::{Error, ErrorKind};
+    use super::*;
+
+    #[test]
+    fn service_result_merges_service_failure() {
+        let result: error::Result<ServiceResult<u32>> = Ok(Err("bad request".into()));
+        match result.into_result().unwrap_err().kind() {
+            ErrorKind::ServiceCallFailed(message) => assert_eq!("bad request", message),
+            kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn service_result_keeps_success_and_connection_errors() {
+        let result: error::Result<ServiceResult<u32>> = Ok(Ok(5));
+        assert_eq!(5, result.into_result().unwrap());
+        let result: error::Result<ServiceResult<u32>> =
+            Err(Error::from(ErrorKind::ServiceResponseInterruption));
+        match result.into_result().unwrap_err().kind() {
+            ErrorKind::ServiceResponseInterruption => {}
+            kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn service_result_maps_only_successful_responses() {
+        let result: error::Result<ServiceResult<u32>> = Ok(Ok(5));
+        assert_eq!(Ok(10), result.map_response(|v| v * 2).unwrap());
+        let result: error::Result<ServiceResult<u32>> = Ok(Err("bad request".into()));
+        assert_eq!(
+            Err(String::from("bad request")),
+            result.map_response(|v| v * 2).unwrap()
+        );
+        let result: error::Result<ServiceResult<u32>> =
+            Err(Error::from(ErrorKind::ServiceResponseInterruption));
+        assert!(result.map_response(|v| v * 2).is_err());
+    }
+
+    #[test]
+    fn service_result_falls_back_on_any_failure() {
+        let result: error::Result<ServiceResult<u32>> = Ok(Ok(5));
+        assert_eq!(5, result.response_or(1));
+        let result: error::Result<ServiceResult<u32>> = Ok(Err("bad request".into()));
+        assert_eq!(1, result.response_or(1));
+        let result: error::Result<ServiceResult<u32>> =
+            Err(Error::from(ErrorKind::ServiceResponseInterruption));
+        assert_eq!(1, result.response_or(1));
+    }
+}
@@ -1,22 +1,32 @@
 use super::error::{ErrorKind, Result, ResultExt};
 use super::header;
+use super::util::compression::{self, Compressor};
+use super::util::keepalive::TcpKeepalive;
+use super::util::stats::{ConnectionInfo, ConnectionStats};
 use super::util::streamfork::{fork, DataStream, TargetList};
 use super::util::tcpconnection;
-use super::{Message, Topic};
+use super::{Message, MessageType, Topic};
 use crate::util::FAILED_TO_LOCK;
 use log::error;
 use std;
 use std::collections::HashMap;
+use std::io::{self, Write};
 use std::net::{TcpListener, TcpStream, ToSocketAddrs};
 use std::sync::{atomic, Arc, Mutex};
 
+/// Connection to a subscriber, which compresses messages if that was negotiated.
+type SubscriberStream = Box<dyn Write + Send>;
+
 pub struct Publisher {
     subscriptions: DataStream,
     pub port: u16,
     pub topic: Topic,
+    message_type: MessageType,
     last_message: Arc<Mutex<Arc<Vec<u8>>>>,
+    latching: Arc<atomic::AtomicBool>,
     queue_size: usize,
     exists: Arc<atomic::AtomicBool>,
+    compression: Arc<atomic::AtomicBool>,
 }
 
 impl Drop for Publisher {
@@ -25,9 +35,13 @@ impl Drop for Publisher {
     }
 }
 
-fn match_concrete_headers<T: Message>(fields: &HashMap<String, String>, topic: &str) -> Result<()> {
-    header::match_field(fields, "md5sum", &T::md5sum())?;
-    header::match_field(fields, "type", &T::msg_type())?;
+fn match_concrete_headers(
+    fields: &HashMap<String, String>,
+    message_type: &MessageType,
+    topic: &str,
+) -> Result<()> {
+    header::match_field(fields, "md5sum", &message_type.md5sum)?;
+    header::match_field(fields, "type", &message_type.msg_type)?;
     header::match_field(fields, "topic", topic)?;
     Ok(())
 }
@@ -39,54 +53,116 @@ fn match_wildcard_headers(fields: &HashMap<String, String>, topic: &str) -> Resu
     Ok(())
 }
 
-fn read_request<T: Message, U: std::io::Read>(mut stream: &mut U, topic: &str) -> Result<String> {
+fn read_request<U: std::io::Read>(
+    mut stream: &mut U,
+    message_type: &MessageType,
+    topic: &str,
+) -> Result<HashMap<String, String>> {
     let fields = header::decode(&mut stream)?;
-    if let Err(err) = match_concrete_headers::<T>(&fields, topic) {
+    if let Err(err) = match_concrete_headers(&fields, message_type, topic) {
         match_wildcard_headers(&fields, topic).map_err(|_| err)?;
     }
-    let caller_id = fields
-        .get("callerid")
-        .ok_or_else(|| ErrorKind::HeaderMissingField("callerid".into()))?;
-    Ok(caller_id.clone())
+    if !fields.contains_key("callerid") {
+        bail!(ErrorKind::HeaderMissingField("callerid".into()));
+    }
+    Ok(fields)
+}
+
+/// Applies the subscriber's `tcp_nodelay` preference to its connection.
+///
+/// Nagle's algorithm stays disabled for subscribers that don't state a preference.
+fn apply_tcp_nodelay(stream: &TcpStream, fields: &HashMap<String, String>) -> std::io::Result<()> {
+    let nodelay = match fields.get("tcp_nodelay") {
+        Some(value) => value == "1",
+        None => true,
+    };
+    stream.set_nodelay(nodelay)
 }
 
-fn write_response<T: Message, U: std::io::Write>(
+fn write_response<U: std::io::Write>(
     mut stream: &mut U,
+    message_type: &MessageType,
     caller_id: &str,
+    latching: bool,
+    compressed: bool,
 ) -> Result<()> {
     let mut fields = HashMap::<String, String>::new();
-    fields.insert(String::from("md5sum"), T::md5sum());
-    fields.insert(String::from("type"), T::msg_type());
+    fields.insert(String::from("md5sum"), message_type.md5sum.clone());
+    fields.insert(String::from("type"), message_type.msg_type.clone());
+    // Recorders like rosbag store the definition alongside the messages
+    fields.insert(
+        String::from("message_definition"),
+        message_type.msg_definition.clone(),
+    );
     fields.insert(String::from("callerid"), caller_id.into());
+    fields.insert(
+        String::from("latching"),
+        String::from(if latching { "1" } else { "0" }),
+    );
+    if compressed {
+        fields.insert(
+            String::from(compression::HEADER_FIELD),
+            String::from(compression::LZ4),
+        );
+    }
     header::encode(&mut stream, &fields)?;
     Ok(())
 }
 
-fn exchange_headers<T, U>(mut stream: &mut U, topic: &str, pub_caller_id: &str) -> Result<String>
+fn exchange_headers<U>(
+    mut stream: &mut U,
+    message_type: &MessageType,
+    topic: &str,
+    pub_caller_id: &str,
+    latching: bool,
+    compression: bool,
+) -> Result<(HashMap<String, String>, bool)>
 where
-    T: Message,
     U: std::io::Write + std::io::Read,
 {
-    let caller_id = read_request::<T, U>(&mut stream, topic)?;
-    write_response::<T, U>(&mut stream, pub_caller_id)?;
-    Ok(caller_id)
+    let fields = match read_request(&mut stream, message_type, topic) {
+        Ok(fields) => fields,
+        Err(err) => {
+            header::reject(&mut stream, &err);
+            return Err(err);
+        }
+    };
+    let compressed = compression && compression::is_offered(&fields);
+    write_response(
+        &mut stream,
+        message_type,
+        pub_caller_id,
+        latching,
+        compressed,
+    )?;
+    Ok((fields, compressed))
 }
 
-fn process_subscriber<T, U>(
+#[allow(clippy::too_many_arguments)]
+fn process_subscriber(
     topic: &str,
-    mut stream: U,
-    targets: &TargetList<U>,
+    message_type: &MessageType,
+    mut stream: TcpStream,
+    targets: &TargetList<SubscriberStream>,
     last_message: &Mutex<Arc<Vec<u8>>>,
     pub_caller_id: &str,
-) -> tcpconnection::Feedback
-where
-    T: Message,
-    U: std::io::Read + std::io::Write + Send,
-{
-    let result = exchange_headers::<T, _>(&mut stream, topic, pub_caller_id)
-        .chain_err(|| ErrorKind::TopicConnectionFail(topic.into()));
-    let caller_id = match result {
-        Ok(caller_id) => caller_id,
+    latching: bool,
+    compression: bool,
+) -> tcpconnection::Feedback {
+    // Only latching publishers store a message to send to new subscribers
+    let latched_message = Arc::clone(&last_message.lock().expect(FAILED_TO_LOCK));
+
+    let result = exchange_headers(
+        &mut stream,
+        message_type,
+        topic,
+        pub_caller_id,
+        latching,
+        compression,
+    )
+    .chain_err(|| ErrorKind::TopicConnectionFail(topic.into()));
+    let (mut fields, compressed) = match result {
+        Ok(result) => result,
         Err(err) => {
             let info = err
                 .iter()
@@ -98,12 +174,27 @@ where
         }
     };
 
-    if let Err(err) = stream.write_all(&last_message.lock().expect(FAILED_TO_LOCK)) {
-        error!("{}", err);
-        return tcpconnection::Feedback::AcceptNextStream;
+    if let Err(err) = apply_tcp_nodelay(&stream, &fields) {
+        error!("Failed to configure Nagle's algorithm: {}", err);
     }
+    // Presence of the field was validated while reading the request
+    let caller_id = fields.remove("callerid").unwrap_or_default();
 
-    if targets.add(caller_id, stream).is_err() {
+    let mut stream: SubscriberStream = if compressed {
+        Box::new(Compressor::new(stream))
+    } else {
+        Box::new(stream)
+    };
+    let stats = ConnectionStats::new();
+    if !latched_message.is_empty() {
+        if let Err(err) = stream.write_all(&latched_message) {
+            error!("{}", err);
+            return tcpconnection::Feedback::AcceptNextStream;
+        }
+        stats.record(latched_message.len());
+    }
+
+    if targets.add(caller_id, stream, stats).is_err() {
         // The TCP listener gets shut down when streamfork's thread deallocates.
         // This happens only when all the corresponding publisher streams get deallocated,
         // causing streamfork's data channel to shut down
@@ -114,16 +205,17 @@ where
 }
 
 impl Publisher {
-    pub fn new<T, U>(
+    /// Creates a publisher that offers the given message type to subscribers.
+    ///
+    /// Raw publishers use it to advertise a type known only at runtime.
+    pub fn new<U: ToSocketAddrs>(
         address: U,
         topic: &str,
         queue_size: usize,
         caller_id: &str,
-    ) -> Result<Publisher>
-    where
-        T: Message,
-        U: ToSocketAddrs,
-    {
+        message_type: MessageType,
+        keepalive: Option<TcpKeepalive>,
+    ) -> Result<Publisher> {
         let listener = TcpListener::bind(address)?;
         let socket_address = listener.local_addr()?;
 
@@ -132,35 +224,57 @@ impl Publisher {
         let port = socket_address.port();
         let (targets, data) = fork(queue_size);
         let last_message = Arc::new(Mutex::new(Arc::new(Vec::new())));
+        let latching = Arc::new(atomic::AtomicBool::new(false));
+        let compression = Arc::new(atomic::AtomicBool::new(compression::transport_compression()));
 
         let iterate_handler = {
             let publisher_exists = publisher_exists.clone();
             let topic = String::from(topic);
             let last_message = Arc::clone(&last_message);
             let caller_id = String::from(caller_id);
+            let latching = Arc::clone(&latching);
+            let compression = Arc::clone(&compression);
+            let message_type = message_type.clone();
 
             move |stream: TcpStream| {
                 if !publisher_exists.load(atomic::Ordering::SeqCst) {
                     return tcpconnection::Feedback::StopAccepting;
                 }
-                process_subscriber::<T, _>(&topic, stream, &targets, &last_message, &caller_id)
+                process_subscriber(
+                    &topic,
+                    &message_type,
+                    stream,
+                    &targets,
+                    &last_message,
+                    &caller_id,
+                    latching.load(atomic::Ordering::SeqCst),
+                    compression.load(atomic::Ordering::Relaxed),
+                )
             }
         };
 
-        tcpconnection::iterate(listener, format!("topic '{}'", topic), iterate_handler);
+        tcpconnection::iterate(
+            listener,
+            format!("topic '{}'", topic),
+            keepalive,
+            iterate_handler,
+        );
 
         let topic = Topic {
             name: String::from(topic),
-            msg_type: T::msg_type(),
+            msg_type: message_type.msg_type.clone(),
         };
 
         Ok(Publisher {
             subscriptions: data,
             port,
             topic,
+            message_type,
             last_message,
+            latching,
             queue_size,
             exists: publisher_exists,
+            compression,
         })
     }
 
@@ -173,6 +287,17 @@ impl Publisher {
     pub fn get_topic(&self) -> &Topic {
         &self.topic
     }
+
+    #[inline]
+    pub fn message_type(&self) -> &MessageType {
+        &self.message_type
+    }
+
+    /// Connections of the currently subscribed nodes, by subscriber caller ID.
+    #[inline]
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        self.subscriptions.target_connections()
+    }
 }
 
 // TODO: publisher should only be removed from master API once the publisher and all
@@ -185,12 +310,15 @@ pub struct PublisherStream<T: Message> {
     last_message: Arc<Mutex<Arc<Vec<u8>>>>,
     datatype: std::marker::PhantomData<T>,
     latching: bool,
+    publisher_latching: Arc<atomic::AtomicBool>,
+    compression: Arc<atomic::AtomicBool>,
 }
 
 impl<T: Message> PublisherStream<T> {
+    /// Streams of wildcard messages, like raw ones, can publish on any publisher.
     fn new(publisher: &Publisher) -> Result<PublisherStream<T>> {
         let msg_type = T::msg_type();
-        if publisher.topic.msg_type != msg_type {
+        if T::md5sum() != "*" && publisher.topic.msg_type != msg_type {
             bail!(ErrorKind::MessageTypeMismatch(
                 publisher.topic.msg_type.clone(),
                 msg_type,
@@ -201,6 +329,8 @@ impl<T: Message> PublisherStream<T> {
             datatype: std::marker::PhantomData,
             last_message: Arc::clone(&publisher.last_message),
             latching: false,
+            publisher_latching: Arc::clone(&publisher.latching),
+            compression: Arc::clone(&publisher.compression),
         };
         stream.set_queue_size_max(publisher.queue_size);
         Ok(stream)
@@ -216,9 +346,61 @@ impl<T: Message> PublisherStream<T> {
         self.stream.target_names()
     }
 
+    /// Sets whether compression is offered to subscribers that connect afterwards.
+    ///
+    /// The default comes from `set_transport_compression`.
+    #[inline]
+    pub fn set_transport_compression(&self, enabled: bool) {
+        self.compression.store(enabled, atomic::Ordering::Relaxed);
+    }
+
+    /// Latching publishers send their last message to every new subscriber.
+    ///
+    /// Enabling latching takes effect from the next sent message, while
+    /// subscribers connecting afterwards are told right away that the topic
+    /// is latched. Disabling it forgets the currently latched message.
+    /// Streams of the same topic share the flag told to subscribers, so the
+    /// last call on any of them decides it.
     #[inline]
     pub fn set_latching(&mut self, latching: bool) {
         self.latching = latching;
+        self.publisher_latching
+            .store(latching, atomic::Ordering::SeqCst);
+        if !latching {
+            *self.last_message.lock().expect(FAILED_TO_LOCK) = Arc::new(Vec::new());
+        }
+    }
+
+    /// Registers a callback invoked with the caller ID of every newly connected subscriber.
+    ///
+    /// It runs on the thread accepting connections, after the subscriber was added,
+    /// so messages sent from within the callback reach the new subscriber too.
+    #[inline]
+    pub fn on_connect<F: Fn(&str) + Send + Sync + 'static>(&self, callback: F) {
+        self.stream.add_connect_callback(Arc::new(callback));
+    }
+
+    /// Registers a callback invoked with the caller ID of every disconnected subscriber.
+    ///
+    /// Disconnects are only noticed once sending a message to the subscriber fails.
+    #[inline]
+    pub fn on_disconnect<F: Fn(&str) + Send + Sync + 'static>(&self, callback: F) {
+        self.stream.add_disconnect_callback(Arc::new(callback));
+    }
+
+    /// Registers a callback invoked with the caller ID of a subscriber and the error
+    /// that made writing to it fail.
+    ///
+    /// The subscriber gets dropped, and the callback runs right before `on_disconnect`
+    /// callbacks, on the thread writing messages. Closed connections are reported too,
+    /// since they are otherwise not logged. Publishing does not wait for the callback,
+    /// but a slow callback delays delivery of later messages.
+    #[inline]
+    pub fn on_publish_error<F>(&self, callback: F)
+    where
+        F: Fn(&str, &io::Error) + Send + Sync + 'static,
+    {
+        self.stream.add_publish_error_callback(Arc::new(callback));
     }
 
     #[inline]
@@ -231,7 +413,13 @@ impl<T: Message> PublisherStream<T> {
         self.stream.set_queue_size_max(queue_size);
     }
 
-    pub fn send(&self, message: &T) -> Result<()> {
+    /// Queues the message for all connected subscribers, returning their count.
+    ///
+    /// Messages are written in the background, so a failing subscriber does not
+    /// fail the call. Subscribers whose connection broke get removed once writing
+    /// to them fails, get reported through `on_disconnect`, and are no longer
+    /// counted afterwards.
+    pub fn send(&self, message: &T) -> Result<usize> {
         let bytes = Arc::new(message.encode_vec()?);
 
         if self.latching {
@@ -240,7 +428,423 @@ impl<T: Message> PublisherStream<T> {
 
         // Subscriptions can only be closed from the Publisher side
         // There is no way for the streamfork thread to fail by itself
+        let subscriber_count = self.stream.target_count();
         self.stream.send(bytes).expect("Connected thread died");
-        Ok(())
+        Ok(subscriber_count)
+    }
+
+    /// Publishes the messages in order, queueing them together.
+    ///
+    /// Every subscriber gets all of them written before its stream is
+    /// flushed. Each message takes a place in the queue, so a batch larger
+    /// than the queue only keeps its newest messages.
+    pub fn send_batch<'a, I>(&self, messages: I) -> Result<usize>
+    where
+        I: IntoIterator<Item = &'a T>,
+        T: 'a,
+    {
+        let batch = messages
+            .into_iter()
+            .map(|message| message.encode_vec().map(Arc::new))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let last = match batch.last() {
+            Some(last) => last,
+            None => return Ok(self.stream.target_count()),
+        };
+
+        if self.latching {
+            *self.last_message.lock().expect(FAILED_TO_LOCK) = Arc::clone(last);
+        }
+
+        let subscriber_count = self.stream.target_count();
+        self.stream
+            .send_batch(batch)
+            .expect("Connected thread died");
+        Ok(subscriber_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::rosgraph_msgs::Clock;
+    use crate::rosmsg::RosMsg;
+
+    static FAILED_TO_PUBLISH: &'static str = "Failed to publish";
+
+    fn latched_message(publisher: &Publisher) -> Arc<Vec<u8>> {
+        Arc::clone(&publisher.last_message.lock().expect(FAILED_TO_LOCK))
+    }
+
+    #[test]
+    fn latches_only_messages_sent_while_latching() {
+        let publisher = Publisher::new(
+            "127.0.0.1:0",
+            "/clock",
+            1,
+            "/node",
+            MessageType::of::<Clock>(),
+            None,
+        )
+        .expect(FAILED_TO_PUBLISH);
+        let mut stream = publisher.stream::<Clock>(1).expect(FAILED_TO_PUBLISH);
+        assert!(latched_message(&publisher).is_empty());
+
+        stream.send(&Clock::default()).expect(FAILED_TO_PUBLISH);
+        assert!(latched_message(&publisher).is_empty());
+
+        stream.set_latching(true);
+        assert!(latched_message(&publisher).is_empty());
+        let message = Clock::default();
+        stream.send(&message).expect(FAILED_TO_PUBLISH);
+        assert_eq!(message.encode_vec().unwrap(), *latched_message(&publisher));
+
+        stream.set_latching(false);
+        assert!(latched_message(&publisher).is_empty());
+    }
+
+    fn connect_subscriber(publisher: &Publisher, caller_id: &str) -> TcpStream {
+        connect_with_header(publisher, caller_id).0
+    }
+
+    // Returns the connection along with the header the publisher responded with
+    fn connect_with_header(
+        publisher: &Publisher,
+        caller_id: &str,
+    ) -> (TcpStream, HashMap<String, String>) {
+        let mut subscriber = TcpStream::connect(("127.0.0.1", publisher.port)).unwrap();
+        let mut fields = HashMap::<String, String>::new();
+        fields.insert(String::from("callerid"), String::from(caller_id));
+        fields.insert(String::from("topic"), String::from("/clock"));
+        fields.insert(String::from("md5sum"), Clock::md5sum());
+        fields.insert(String::from("type"), Clock::msg_type());
+        header::encode(&mut subscriber, &fields).unwrap();
+        let response = header::decode(&mut subscriber).unwrap();
+        (subscriber, response)
+    }
+
+    #[test]
+    fn tells_subscribers_about_latching_before_first_message() {
+        let publisher = Publisher::new(
+            "127.0.0.1:0",
+            "/clock",
+            1,
+            "/node",
+            MessageType::of::<Clock>(),
+            None,
+        )
+        .expect(FAILED_TO_PUBLISH);
+        let mut stream = publisher.stream::<Clock>(1).expect(FAILED_TO_PUBLISH);
+        let (_, fields) = connect_with_header(&publisher, "/listener");
+        assert_eq!(Some(&String::from("0")), fields.get("latching"));
+
+        stream.set_latching(true);
+        assert!(latched_message(&publisher).is_empty());
+        let (_, fields) = connect_with_header(&publisher, "/listener");
+        assert_eq!(Some(&String::from("1")), fields.get("latching"));
+
+        stream.set_latching(false);
+        let (_, fields) = connect_with_header(&publisher, "/listener");
+        assert_eq!(Some(&String::from("0")), fields.get("latching"));
+    }
+
+    #[test]
+    fn raw_stream_publishes_with_type_given_at_runtime() {
+        use crate::tcpros::RawMessage;
+        use std::time::{Duration, Instant};
+
+        let publisher = Publisher::new(
+            "127.0.0.1:0",
+            "/clock",
+            1,
+            "/relay",
+            MessageType::of::<Clock>(),
+            None,
+        )
+        .expect(FAILED_TO_PUBLISH);
+        assert_eq!("rosgraph_msgs/Clock", publisher.get_topic().msg_type);
+        let stream = publisher.stream::<RawMessage>(1).expect(FAILED_TO_PUBLISH);
+
+        // Typed subscribers get the real type and md5sum in the handshake
+        let mut subscriber = TcpStream::connect(("127.0.0.1", publisher.port)).unwrap();
+        let mut fields = HashMap::<String, String>::new();
+        fields.insert(String::from("callerid"), String::from("/listener"));
+        fields.insert(String::from("topic"), String::from("/clock"));
+        fields.insert(String::from("md5sum"), Clock::md5sum());
+        fields.insert(String::from("type"), Clock::msg_type());
+        header::encode(&mut subscriber, &fields).unwrap();
+        let response = header::decode(&mut subscriber).unwrap();
+        assert_eq!(Some(&Clock::md5sum()), response.get("md5sum"));
+        assert_eq!(Some(&Clock::msg_type()), response.get("type"));
+        assert_eq!(
+            Some(&Clock::msg_definition()),
+            response.get("message_definition")
+        );
+
+        let start = Instant::now();
+        while stream.subscriber_count() < 1 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let message = Clock {
+            clock: crate::Time { sec: 3, nsec: 4 },
+        };
+        let encoded = message.encode_vec().unwrap();
+        stream
+            .send(&RawMessage(encoded[4..].to_vec()))
+            .expect(FAILED_TO_PUBLISH);
+        assert_eq!(8, u32::decode(&mut subscriber).unwrap());
+        assert_eq!(message, Clock::decode(&mut subscriber).unwrap());
+    }
+
+    #[test]
+    fn notifies_about_connecting_and_disconnecting_subscribers() {
+        use std::sync::mpsc::channel;
+        use std::time::Duration;
+
+        let publisher = Publisher::new(
+            "127.0.0.1:0",
+            "/clock",
+            1,
+            "/node",
+            MessageType::of::<Clock>(),
+            None,
+        )
+        .expect(FAILED_TO_PUBLISH);
+        let stream = publisher.stream::<Clock>(1).expect(FAILED_TO_PUBLISH);
+        let (connect_tx, connect_rx) = channel();
+        let (disconnect_tx, disconnect_rx) = channel();
+        let connect_tx = Mutex::new(connect_tx);
+        let disconnect_tx = Mutex::new(disconnect_tx);
+        let responder = stream.clone();
+        stream.on_connect(move |caller_id| {
+            // Publishing from within the callback must not deadlock
+            responder.send(&Clock::default()).expect(FAILED_TO_PUBLISH);
+            connect_tx
+                .lock()
+                .unwrap()
+                .send(caller_id.to_owned())
+                .unwrap();
+        });
+        stream.on_disconnect(move |caller_id| {
+            disconnect_tx
+                .lock()
+                .unwrap()
+                .send(caller_id.to_owned())
+                .unwrap();
+        });
+
+        let mut subscriber = connect_subscriber(&publisher, "/listener");
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!("/listener", connect_rx.recv_timeout(timeout).unwrap());
+        // The message sent from the callback arrives, starting with its length
+        assert_eq!(8, u32::decode(&mut subscriber).unwrap());
+        assert_eq!(1, stream.subscriber_count());
+
+        drop(subscriber);
+        // Closed sockets are only detected after a few writes
+        for _ in 0..100 {
+            stream.send(&Clock::default()).expect(FAILED_TO_PUBLISH);
+            if let Ok(caller_id) = disconnect_rx.recv_timeout(Duration::from_millis(50)) {
+                assert_eq!("/listener", caller_id);
+                assert_eq!(0, stream.subscriber_count());
+                return;
+            }
+        }
+        panic!("Disconnect was never reported");
+    }
+
+    #[test]
+    fn reports_write_errors_with_peer() {
+        use crate::tcpros::util::streamfork::is_closed_connection;
+        use std::sync::mpsc::channel;
+        use std::time::{Duration, Instant};
+
+        let publisher = Publisher::new(
+            "127.0.0.1:0",
+            "/clock",
+            1,
+            "/node",
+            MessageType::of::<Clock>(),
+            None,
+        )
+        .expect(FAILED_TO_PUBLISH);
+        let stream = publisher.stream::<Clock>(1).expect(FAILED_TO_PUBLISH);
+        let (error_tx, error_rx) = channel();
+        let error_tx = Mutex::new(error_tx);
+        stream.on_publish_error(move |caller_id, err| {
+            error_tx
+                .lock()
+                .unwrap()
+                .send((caller_id.to_owned(), is_closed_connection(err), err.kind()))
+                .unwrap();
+        });
+
+        let subscriber = connect_subscriber(&publisher, "/closed");
+        let _listener = connect_subscriber(&publisher, "/listener");
+        let start = Instant::now();
+        while stream.subscriber_count() < 2 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        drop(subscriber);
+        for _ in 0..100 {
+            stream.send(&Clock::default()).expect(FAILED_TO_PUBLISH);
+            if let Ok((caller_id, closed, kind)) = error_rx.recv_timeout(Duration::from_millis(50))
+            {
+                assert_eq!("/closed", caller_id);
+                assert!(closed, "{:?}", kind);
+                assert_eq!(vec![String::from("/listener")], stream.subscriber_names());
+                return;
+            }
+        }
+        panic!("Write error was never reported");
+    }
+
+    #[test]
+    fn send_reports_number_of_reached_subscribers() {
+        use std::time::{Duration, Instant};
+
+        let publisher = Publisher::new(
+            "127.0.0.1:0",
+            "/clock",
+            1,
+            "/node",
+            MessageType::of::<Clock>(),
+            None,
+        )
+        .expect(FAILED_TO_PUBLISH);
+        let stream = publisher.stream::<Clock>(1).expect(FAILED_TO_PUBLISH);
+        assert_eq!(0, stream.send(&Clock::default()).expect(FAILED_TO_PUBLISH));
+
+        let mut connected = connect_subscriber(&publisher, "/connected");
+        let disconnected = connect_subscriber(&publisher, "/disconnected");
+        let start = Instant::now();
+        while stream.subscriber_count() < 2 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(2, stream.send(&Clock::default()).expect(FAILED_TO_PUBLISH));
+        assert_eq!(8, u32::decode(&mut connected).unwrap());
+        assert_eq!(Clock::default(), Clock::decode(&mut connected).unwrap());
+
+        drop(disconnected);
+        // Closed sockets are only detected after a few writes, without failing any send
+        let start = Instant::now();
+        while stream.send(&Clock::default()).expect(FAILED_TO_PUBLISH) == 2 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(vec![String::from("/connected")], stream.subscriber_names());
+
+        // The remaining subscriber keeps receiving messages
+        let last = Clock {
+            clock: crate::Time { sec: 7, nsec: 0 },
+        };
+        assert_eq!(1, stream.send(&last).expect(FAILED_TO_PUBLISH));
+        connected
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let start = Instant::now();
+        loop {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            let length = u32::decode(&mut connected).unwrap();
+            let mut body = vec![0; length as usize];
+            std::io::Read::read_exact(&mut connected, &mut body).unwrap();
+            if Clock::decode(&mut std::io::Cursor::new(body)).unwrap() == last {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn delivers_batch_in_order() {
+        use std::time::{Duration, Instant};
+
+        let publisher = Publisher::new(
+            "127.0.0.1:0",
+            "/clock",
+            1,
+            "/node",
+            MessageType::of::<Clock>(),
+            None,
+        )
+        .expect(FAILED_TO_PUBLISH);
+        let stream = publisher.stream::<Clock>(5).expect(FAILED_TO_PUBLISH);
+        let mut subscriber = connect_subscriber(&publisher, "/listener");
+        let start = Instant::now();
+        while stream.subscriber_count() < 1 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let messages = (1..=5)
+            .map(|sec| Clock {
+                clock: crate::Time { sec, nsec: 0 },
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(1, stream.send_batch(&messages).expect(FAILED_TO_PUBLISH));
+        subscriber
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        for message in &messages {
+            assert_eq!(8, u32::decode(&mut subscriber).unwrap());
+            assert_eq!(*message, Clock::decode(&mut subscriber).unwrap());
+        }
+    }
+
+    #[test]
+    fn honors_subscriber_tcp_nodelay_preference() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut fields = HashMap::<String, String>::new();
+
+        apply_tcp_nodelay(&stream, &fields).unwrap();
+        assert!(stream.nodelay().unwrap());
+
+        fields.insert(String::from("tcp_nodelay"), String::from("0"));
+        apply_tcp_nodelay(&stream, &fields).unwrap();
+        assert!(!stream.nodelay().unwrap());
+
+        fields.insert(String::from("tcp_nodelay"), String::from("1"));
+        apply_tcp_nodelay(&stream, &fields).unwrap();
+        assert!(stream.nodelay().unwrap());
+    }
+
+    #[test]
+    fn writes_latching_flag_in_response_header() {
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        write_response(
+            &mut cursor,
+            &MessageType::of::<Clock>(),
+            "/node",
+            true,
+            false,
+        )
+        .unwrap();
+        cursor.set_position(0);
+        let fields = header::decode(&mut cursor).unwrap();
+        assert_eq!(Some(&String::from("1")), fields.get("latching"));
+    }
+
+    #[test]
+    fn writes_message_definition_in_response_header() {
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        write_response(
+            &mut cursor,
+            &MessageType::of::<Clock>(),
+            "/node",
+            false,
+            false,
+        )
+        .unwrap();
+        cursor.set_position(0);
+        let fields = header::decode(&mut cursor).unwrap();
+        let definition = fields.get("message_definition").unwrap();
+        assert!(!definition.is_empty());
+        assert_eq!(&Clock::msg_definition(), definition);
     }
 }
@@ -0,0 +1,63 @@
+use super::Message;
+use crate::rosmsg::RosMsg;
+use std::io;
+
+/// Serialized message of any type, for nodes that relay or record arbitrary topics.
+///
+/// It uses the `*` wildcard for its md5sum and type, so subscribers accept
+/// publishers of any type, and receive their messages without decoding them.
+/// When the actual type is known at runtime, `publish_raw` and `subscribe_raw`
+/// advertise and request it instead of the wildcard.
+/// The contained bytes exclude the length prefix used on the wire.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RawMessage(pub Vec<u8>);
+
+impl Message for RawMessage {
+    #[inline]
+    fn msg_definition() -> String {
+        "*".into()
+    }
+
+    #[inline]
+    fn md5sum() -> String {
+        "*".into()
+    }
+
+    #[inline]
+    fn msg_type() -> String {
+        "*".into()
+    }
+}
+
+impl RosMsg for RawMessage {
+    #[inline]
+    fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(&self.0)
+    }
+
+    #[inline]
+    fn decode<R: io::Read>(mut r: R) -> io::Result<Self> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+        Ok(RawMessage(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::rosgraph_msgs::Clock;
+    use crate::time::Time;
+
+    #[test]
+    fn keeps_encoded_bytes_unchanged() {
+        let message = Clock {
+            clock: Time { sec: 1, nsec: 2 },
+        };
+        let bytes = message.encode_vec().unwrap();
+        let raw = RawMessage::decode_slice(&bytes).unwrap();
+        assert_eq!(bytes[4..].to_vec(), raw.0);
+        assert_eq!(bytes, raw.encode_vec().unwrap());
+        assert_eq!(message, Clock::decode(raw.0.as_slice()).unwrap());
+    }
+}
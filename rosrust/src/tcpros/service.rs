@@ -1,17 +1,29 @@
-use super::error::{ErrorKind, Result};
+use super::error::{decode_error, Error, ErrorKind, Result};
 use super::header;
+use super::util::keepalive::TcpKeepalive;
 use super::util::tcpconnection;
-use super::{ServicePair, ServiceResult};
-use crate::rosmsg::{encode_str, RosMsg};
+use super::util::workerpool::WorkerPool;
+use super::ServicePair;
+use crate::rosmsg::{check_message_size, encode_str, RosMsg};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use log::error;
 use std;
 use std::collections::HashMap;
-use std::io;
+use std::fmt;
+use std::io::Read;
 use std::net::{TcpListener, TcpStream};
-use std::sync::{atomic, Arc};
+use std::sync::{atomic, mpsc, Arc};
 use std::thread;
 
+/// Number of requests a service handles concurrently unless configured otherwise.
+pub const DEFAULT_SERVICE_WORKERS: usize = 8;
+
+/// Number of persistent connections a service keeps open at once.
+///
+/// Each one occupies a thread that waits for the client's next request.
+/// Clients beyond the limit get a single request handled, like non-persistent ones.
+const MAX_PERSISTENT_CONNECTIONS: usize = 64;
+
 pub struct Service {
     pub api: String,
     pub msg_type: String,
@@ -26,17 +38,24 @@ impl Drop for Service {
 }
 
 impl Service {
-    pub fn new<T, F>(
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<T, E, F>(
         hostname: &str,
         bind_address: &str,
         port: u16,
         service: &str,
         node_name: &str,
+        workers: usize,
+        keepalive: Option<TcpKeepalive>,
         handler: F,
     ) -> Result<Service>
     where
         T: ServicePair,
-        F: Fn(T::Request) -> ServiceResult<T::Response> + Send + Sync + 'static,
+        E: fmt::Display,
+        F: Fn(T::Request, &HashMap<String, String>) -> std::result::Result<T::Response, E>
+            + Send
+            + Sync
+            + 'static,
     {
         let listener = TcpListener::bind((bind_address, port))?;
         let socket_address = listener.local_addr()?;
@@ -49,16 +68,33 @@ impl Service {
             let service = String::from(service);
             let node_name = String::from(node_name);
             let handler = Arc::new(handler);
+            let pool = Arc::new(WorkerPool::new(&format!("service '{}'", service), workers));
+            let persistent = Arc::new(atomic::AtomicUsize::new(0));
             move |stream: TcpStream| {
                 if !service_exists.load(atomic::Ordering::SeqCst) {
                     return tcpconnection::Feedback::StopAccepting;
                 }
-                consume_client::<T, _, _>(&service, &node_name, Arc::clone(&handler), stream);
+                if let Err(err) = stream.set_nodelay(true) {
+                    error!("Failed to disable Nagle's algorithm: {}", err);
+                }
+                consume_client::<T, _, _, _>(
+                    &service,
+                    &node_name,
+                    Arc::clone(&handler),
+                    &pool,
+                    &persistent,
+                    stream,
+                );
                 tcpconnection::Feedback::AcceptNextStream
             }
         };
 
-        tcpconnection::iterate(listener, format!("service '{}'", service), iterate_handler);
+        tcpconnection::iterate(
+            listener,
+            format!("service '{}'", service),
+            keepalive,
+            iterate_handler,
+        );
 
         Ok(Service {
             api,
@@ -71,14 +107,24 @@ impl Service {
 
 enum RequestType {
     Probe,
-    Action,
+    Action(HashMap<String, String>),
 }
 
-fn consume_client<T, U, F>(service: &str, node_name: &str, handler: Arc<F>, mut stream: U)
-where
+fn consume_client<T, U, E, F>(
+    service: &str,
+    node_name: &str,
+    handler: Arc<F>,
+    pool: &Arc<WorkerPool>,
+    persistent: &Arc<atomic::AtomicUsize>,
+    mut stream: U,
+) where
     T: ServicePair,
     U: std::io::Read + std::io::Write + Send + 'static,
-    F: Fn(T::Request) -> ServiceResult<T::Response> + Send + Sync + 'static,
+    E: fmt::Display,
+    F: Fn(T::Request, &HashMap<String, String>) -> std::result::Result<T::Response, E>
+        + Send
+        + Sync
+        + 'static,
 {
     // Service request starts by exchanging connection headers
     match exchange_headers::<T, _>(&mut stream, service, node_name) {
@@ -93,8 +139,26 @@ where
             return;
         }
 
-        // Spawn a thread for handling requests
-        Ok(RequestType::Action) => spawn_request_handler::<T, U, F>(stream, Arc::clone(&handler)),
+        // Waiting for further requests happens outside of the pool, so idle
+        // persistent connections do not hold on to workers
+        Ok(RequestType::Action(fields)) => {
+            if header::match_field(&fields, "persistent", "1").is_ok() {
+                if let Some(slot) = PersistentSlot::acquire(persistent) {
+                    let pool = Arc::clone(pool);
+                    thread::spawn(move || {
+                        serve_persistent::<T, U, E, F>(stream, fields, handler, &pool);
+                        drop(slot);
+                    });
+                    return;
+                }
+                error!(
+                    "Service '{}' has {} persistent connections already, closing new one after its first request",
+                    service, MAX_PERSISTENT_CONNECTIONS
+                );
+            }
+            // Queue the request for the next free worker
+            pool.execute(move || handle_request::<T, U, E, F>(stream, &fields, &handler))
+        }
         Ok(RequestType::Probe) => (),
     }
 }
@@ -104,7 +168,13 @@ where
     T: ServicePair,
     U: std::io::Write + std::io::Read,
 {
-    let req_type = read_request::<T, U>(stream, service)?;
+    let req_type = match read_request::<T, U>(stream, service) {
+        Ok(req_type) => req_type,
+        Err(err) => {
+            header::reject(stream, &err);
+            return Err(err);
+        }
+    };
     write_response::<T, U>(stream, node_name)?;
     Ok(req_type)
 }
@@ -122,7 +192,7 @@ fn read_request<T: ServicePair, U: std::io::Read>(
         return Ok(RequestType::Probe);
     }
     header::match_field(&fields, "md5sum", &T::md5sum())?;
-    Ok(RequestType::Action)
+    Ok(RequestType::Action(fields))
 }
 
 fn write_response<T, U>(stream: &mut U, node_name: &str) -> Result<()>
@@ -134,71 +204,525 @@ where
     fields.insert(String::from("callerid"), String::from(node_name));
     fields.insert(String::from("md5sum"), T::md5sum());
     fields.insert(String::from("type"), T::msg_type());
+    fields.insert(String::from("message_definition"), T::msg_definition());
     header::encode(stream, &fields)?;
     Ok(())
 }
 
-fn spawn_request_handler<T, U, F>(stream: U, handler: Arc<F>)
+fn handle_request<T, U, E, F>(mut stream: U, fields: &HashMap<String, String>, handler: &F)
 where
+    T: ServicePair,
+    U: std::io::Read + std::io::Write,
+    E: fmt::Display,
+    F: Fn(T::Request, &HashMap<String, String>) -> std::result::Result<T::Response, E>,
+{
+    let result = read_request_body(&mut stream)
+        .and_then(|body| respond::<T, _, E, F>(&mut stream, &body, fields, handler));
+    if let Err(err) = result {
+        log_failure(&err);
+    }
+}
+
+/// Reserves one of the persistent connections of a service until dropped.
+struct PersistentSlot(Arc<atomic::AtomicUsize>);
+
+impl PersistentSlot {
+    fn acquire(count: &Arc<atomic::AtomicUsize>) -> Option<Self> {
+        count
+            .fetch_update(atomic::Ordering::SeqCst, atomic::Ordering::SeqCst, |open| {
+                Some(open + 1).filter(|&open| open <= MAX_PERSISTENT_CONNECTIONS)
+            })
+            .ok()?;
+        Some(PersistentSlot(Arc::clone(count)))
+    }
+}
+
+impl Drop for PersistentSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, atomic::Ordering::SeqCst);
+    }
+}
+
+/// Handles requests of a persistent client until it closes the connection.
+///
+/// Each request is handled by the pool in turn, with the connection passed
+/// along to the worker and back.
+fn serve_persistent<T, U, E, F>(
+    mut stream: U,
+    fields: HashMap<String, String>,
+    handler: Arc<F>,
+    pool: &WorkerPool,
+) where
     T: ServicePair,
     U: std::io::Read + std::io::Write + Send + 'static,
-    F: Fn(T::Request) -> ServiceResult<T::Response> + Send + Sync + 'static,
+    E: fmt::Display,
+    F: Fn(T::Request, &HashMap<String, String>) -> std::result::Result<T::Response, E>
+        + Send
+        + Sync
+        + 'static,
 {
-    thread::spawn(move || {
-        if let Err(err) = handle_request_loop::<T, U, F>(stream, &handler) {
-            if !err.is_closed_connection() {
-                let info = err
-                    .iter()
-                    .map(|v| format!("{}", v))
-                    .collect::<Vec<_>>()
-                    .join("\nCaused by:");
-                error!("{}", info);
-            }
-        }
-    });
+    let fields = Arc::new(fields);
+    loop {
+        let body = match read_request_body(&mut stream) {
+            Ok(body) => body,
+            Err(err) => return log_failure(&err),
+        };
+        let (done_tx, done_rx) = mpsc::channel();
+        let fields = Arc::clone(&fields);
+        let handler = Arc::clone(&handler);
+        pool.execute(move || {
+            let result = respond::<T, _, E, F>(&mut stream, &body, &fields, &handler);
+            // The connection thread only stops waiting when the handler panics
+            let _ = done_tx.send((stream, result));
+        });
+        stream = match done_rx.recv() {
+            Ok((stream, Ok(()))) => stream,
+            Ok((_, Err(err))) => return log_failure(&err),
+            Err(_) => return,
+        };
+    }
 }
 
-fn handle_request_loop<T, U, F>(mut stream: U, handler: &F) -> Result<()>
+fn log_failure(err: &Error) {
+    if !err.is_closed_connection() {
+        let info = err
+            .iter()
+            .map(|v| format!("{}", v))
+            .collect::<Vec<_>>()
+            .join("\nCaused by:");
+        error!("{}", info);
+    }
+}
+
+/// Reads the request with its length prefix, refusing lengths that are too large to handle.
+///
+/// Reading the whole body before decoding it keeps persistent connections in
+/// sync, even when a request turns out to be malformed.
+fn read_request_body<U: std::io::Read + std::io::Write>(stream: &mut U) -> Result<Vec<u8>> {
+    let length = stream.read_u32::<LittleEndian>().map_err(decode_error)? as usize;
+    if check_message_size(length).is_err() {
+        // The rest of the stream cannot be made sense of without reading the request
+        stream.write_u8(0)?;
+        encode_str("Request exceeds the message size limit", &mut *stream)?;
+        bail!(ErrorKind::MalformedData(format!(
+            "request of {} bytes exceeds the message size limit",
+            length
+        )));
+    }
+    let mut body = Vec::with_capacity(length);
+    stream.take(length as u64).read_to_end(&mut body)?;
+    if body.len() != length {
+        bail!(ErrorKind::UnexpectedEof);
+    }
+    Ok(body)
+}
+
+fn respond<T, U, E, F>(
+    stream: &mut U,
+    body: &[u8],
+    fields: &HashMap<String, String>,
+    handler: &F,
+) -> Result<()>
 where
     T: ServicePair,
-    U: std::io::Read + std::io::Write,
-    F: Fn(T::Request) -> ServiceResult<T::Response>,
+    U: std::io::Write,
+    E: fmt::Display,
+    F: Fn(T::Request, &HashMap<String, String>) -> std::result::Result<T::Response, E>,
 {
-    // Receive request from client
-    // TODO: validate message length
-    let _length = stream.read_u32::<LittleEndian>();
-    // Break out of loop in case of failure to read request
-    // TODO: handle retained connections
-    if let Ok(req) = RosMsg::decode(&mut stream) {
+    match RosMsg::decode(body) {
         // Call function that handles request and returns response
-        match handler(req) {
+        Ok(req) => match handler(req, fields) {
             Ok(res) => {
                 // Send True flag and response in case of success
                 stream.write_u8(1)?;
-                let mut writer = io::Cursor::new(Vec::with_capacity(128));
-                // skip the first 4 bytes that will contain the message length
-                writer.set_position(4);
-
-                res.encode(&mut writer)?;
-
-                // write the message length to the start of the header
-                let message_length = (writer.position() - 4) as u32;
-                writer.set_position(0);
-                message_length.encode(&mut writer)?;
-
-                stream.write_all(&writer.into_inner())?;
+                stream.write_all(&res.encode_vec()?)?;
             }
-            Err(message) => {
+            Err(err) => {
                 // Send False flag and error message string in case of failure
                 stream.write_u8(0)?;
-                RosMsg::encode(&message, &mut stream)?;
+                encode_str(&err.to_string(), &mut *stream)?;
+            }
+        },
+        // Upon failure to parse request, send client failure message
+        Err(_) => {
+            stream.write_u8(0)?;
+            encode_str("Failed to parse passed arguments", &mut *stream)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Client, Message};
+    use super::*;
+    use crate::msg::test_msgs::std_srvs::{Empty, EmptyReq, EmptyRes};
+    use std::io::{self, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[derive(Clone, Debug, Default, PartialEq)]
+    struct Double;
+
+    impl RosMsg for Double {
+        fn encode<W: io::Write>(&self, _w: W) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn decode<R: io::Read>(_r: R) -> io::Result<Self> {
+            Ok(Double)
+        }
+    }
+
+    impl Message for Double {
+        fn msg_definition() -> String {
+            String::new()
+        }
+
+        fn md5sum() -> String {
+            "fedcba9876543210fedcba9876543210".into()
+        }
+
+        fn msg_type() -> String {
+            "test_msgs/Double".into()
+        }
+    }
+
+    impl ServicePair for Double {
+        type Request = u32;
+        type Response = u32;
+    }
+
+    #[derive(Clone, Debug, Default, PartialEq)]
+    struct Halve;
+
+    impl RosMsg for Halve {
+        fn encode<W: io::Write>(&self, _w: W) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn decode<R: io::Read>(_r: R) -> io::Result<Self> {
+            Ok(Halve)
+        }
+    }
+
+    impl Message for Halve {
+        fn msg_definition() -> String {
+            String::new()
+        }
+
+        fn md5sum() -> String {
+            "00112233445566778899aabbccddeeff".into()
+        }
+
+        fn msg_type() -> String {
+            "test_msgs/Halve".into()
+        }
+    }
+
+    impl ServicePair for Halve {
+        type Request = u32;
+        type Response = u32;
+    }
+
+    fn client(service: &Service) -> Client<Double> {
+        Client::new("/client", &service.api, &service.service)
+    }
+
+    #[test]
+    fn queues_requests_beyond_worker_count() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let handler = {
+            let running = Arc::clone(&running);
+            let peak = Arc::clone(&peak);
+            move |req: u32, _: &HashMap<String, String>| {
+                let current = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(current, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(50));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(req * 2)
             }
         };
+        let service = Service::new::<Double, String, _>(
+            "127.0.0.1",
+            "127.0.0.1",
+            0,
+            "/double",
+            "/server",
+            2,
+            None,
+            handler,
+        )
+        .unwrap();
+
+        let calls = (0..8u32)
+            .map(|value| {
+                let client = client(&service);
+                thread::spawn(move || client.req(&value).unwrap().unwrap())
+            })
+            .collect::<Vec<_>>();
+        let results = calls
+            .into_iter()
+            .map(|call| call.join().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!((0..8).map(|v| v * 2).collect::<Vec<_>>(), results);
+        assert_eq!(2, peak.load(Ordering::SeqCst));
     }
 
-    // Upon failure to read request, send client failure message
-    // This can be caused by actual issues or by the client stopping the connection
-    stream.write_u8(0)?;
-    encode_str("Failed to parse passed arguments", &mut stream)?;
-    Ok(())
+    #[derive(Debug)]
+    enum DoubleError {
+        Overflow(u32),
+    }
+
+    impl fmt::Display for DoubleError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {
+                DoubleError::Overflow(value) => write!(f, "doubling {} overflows", value),
+            }
+        }
+    }
+
+    fn checked_double(value: u32) -> std::result::Result<u32, DoubleError> {
+        value.checked_mul(2).ok_or(DoubleError::Overflow(value))
+    }
+
+    #[test]
+    fn custom_handler_error_reaches_client() {
+        let service = Service::new::<Double, DoubleError, _>(
+            "127.0.0.1",
+            "127.0.0.1",
+            0,
+            "/double",
+            "/server",
+            1,
+            None,
+            |req: u32, _: &HashMap<String, String>| {
+                let doubled = checked_double(req)?;
+                Ok(doubled)
+            },
+        )
+        .unwrap();
+
+        assert_eq!(Ok(8), client(&service).req(&4).unwrap());
+        assert_eq!(
+            Err(String::from("doubling 4294967295 overflows")),
+            client(&service).req(&u32::MAX).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejection_reason_reaches_client() {
+        let service = Service::new::<Double, String, _>(
+            "127.0.0.1",
+            "127.0.0.1",
+            0,
+            "/double",
+            "/server",
+            1,
+            None,
+            |req: u32, _: &HashMap<String, String>| Ok(req * 2),
+        )
+        .unwrap();
+
+        let client = Client::<Halve>::new("/client", &service.api, &service.service);
+        match *client.req(&4).unwrap_err().kind() {
+            ErrorKind::HandshakeRejected(ref message) => {
+                assert!(message.contains(&Double::md5sum()));
+                assert!(message.contains(&Halve::md5sum()));
+            }
+            ref kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn handler_sees_connection_header() {
+        let caller_ids = Arc::new(std::sync::Mutex::new(vec![]));
+        let seen = Arc::clone(&caller_ids);
+        let service = Service::new::<Double, String, _>(
+            "127.0.0.1",
+            "127.0.0.1",
+            0,
+            "/double",
+            "/server",
+            1,
+            None,
+            move |req: u32, fields: &HashMap<String, String>| {
+                seen.lock().unwrap().push(fields["callerid"].clone());
+                Ok(req)
+            },
+        )
+        .unwrap();
+
+        assert_eq!(Ok(3), client(&service).req(&3).unwrap());
+        assert_eq!(vec![String::from("/client")], *caller_ids.lock().unwrap());
+    }
+
+    #[test]
+    fn empty_service_round_trips_over_loopback() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let service = Service::new::<Empty, String, _>(
+            "127.0.0.1",
+            "127.0.0.1",
+            0,
+            "/reset",
+            "/server",
+            1,
+            None,
+            move |_: EmptyReq, _: &HashMap<String, String>| {
+                // Every second call fails, with the reason as the only response data
+                match counter.fetch_add(1, Ordering::SeqCst) % 2 {
+                    0 => Ok(EmptyRes {}),
+                    _ => Err(String::from("busy")),
+                }
+            },
+        )
+        .unwrap();
+
+        let client = Client::<Empty>::new("/client", &service.api, &service.service);
+        assert_eq!(Ok(EmptyRes {}), client.req(&EmptyReq {}).unwrap());
+        assert_eq!(Err(String::from("busy")), client.req(&EmptyReq {}).unwrap());
+
+        // Checked on the raw connection, since clients would hide a broken
+        // connection by reconnecting
+        let mut stream = connect_raw::<Empty>(&service, true);
+        // The failure flag, then the reason with its length prefix
+        let busy = [0, 4, 0, 0, 0, b'b', b'u', b's', b'y'];
+        for _ in 0..2 {
+            stream
+                .write_all(&EmptyReq {}.encode_vec().unwrap())
+                .unwrap();
+            let mut response = [0xff; 5];
+            stream.read_exact(&mut response).unwrap();
+            assert_eq!([1, 0, 0, 0, 0], response);
+
+            stream
+                .write_all(&EmptyReq {}.encode_vec().unwrap())
+                .unwrap();
+            let mut response = [0xff; 9];
+            stream.read_exact(&mut response).unwrap();
+            assert_eq!(busy, response);
+        }
+        assert_eq!(6, calls.load(Ordering::SeqCst));
+
+        // Nothing follows the last response
+        stream
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let mut rest = [0];
+        let err = stream.read(&mut rest).unwrap_err();
+        assert!(
+            matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            "{}",
+            err
+        );
+    }
+
+    // Speaks the protocol directly, since clients reconnect when a connection gets closed
+    fn connect_raw<T: Message>(service: &Service, persistent: bool) -> TcpStream {
+        let address = service.api.trim_start_matches("rosrpc://");
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut fields = HashMap::new();
+        fields.insert(String::from("callerid"), String::from("/client"));
+        fields.insert(String::from("service"), service.service.clone());
+        fields.insert(String::from("md5sum"), T::md5sum());
+        if persistent {
+            fields.insert(String::from("persistent"), String::from("1"));
+        }
+        header::encode(&mut stream, &fields).unwrap();
+        header::decode(&mut stream).unwrap();
+        stream
+    }
+
+    fn call_raw(stream: &mut TcpStream, value: u32) -> u32 {
+        stream.write_all(&value.encode_vec().unwrap()).unwrap();
+        assert_eq!(1, stream.read_u8().unwrap());
+        assert_eq!(4, stream.read_u32::<LittleEndian>().unwrap());
+        stream.read_u32::<LittleEndian>().unwrap()
+    }
+
+    fn counting_double_service(handled: &Arc<AtomicUsize>) -> Service {
+        let handled = Arc::clone(handled);
+        Service::new::<Double, String, _>(
+            "127.0.0.1",
+            "127.0.0.1",
+            0,
+            "/double",
+            "/server",
+            1,
+            None,
+            move |req: u32, _: &HashMap<String, String>| {
+                handled.fetch_add(1, Ordering::SeqCst);
+                Ok(req * 2)
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn persistent_connection_serves_several_requests() {
+        let handled = Arc::new(AtomicUsize::new(0));
+        let service = counting_double_service(&handled);
+
+        let mut stream = connect_raw::<Double>(&service, true);
+        for value in 1..=3 {
+            assert_eq!(value * 2, call_raw(&mut stream, value));
+        }
+        assert_eq!(3, handled.load(Ordering::SeqCst));
+
+        // A malformed request gets refused without losing track of the next one
+        stream.write_all(&[2, 0, 0, 0, 7, 7]).unwrap();
+        assert_eq!(0, stream.read_u8().unwrap());
+        assert_eq!(
+            "Failed to parse passed arguments",
+            String::decode(&mut stream).unwrap()
+        );
+        assert_eq!(8, call_raw(&mut stream, 4));
+        assert_eq!(4, handled.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn idle_persistent_connection_leaves_workers_free() {
+        let handled = Arc::new(AtomicUsize::new(0));
+        let service = counting_double_service(&handled);
+
+        let mut idle = connect_raw::<Double>(&service, true);
+        assert_eq!(2, call_raw(&mut idle, 1));
+        assert_eq!(Ok(6), client(&service).req(&3).unwrap());
+        assert_eq!(10, call_raw(&mut idle, 5));
+    }
+
+    #[test]
+    fn limits_open_persistent_connections() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let mut slots = (0..MAX_PERSISTENT_CONNECTIONS)
+            .map(|_| PersistentSlot::acquire(&count).unwrap())
+            .collect::<Vec<_>>();
+        assert!(PersistentSlot::acquire(&count).is_none());
+        slots.pop();
+        slots.push(PersistentSlot::acquire(&count).unwrap());
+        drop(slots);
+        assert_eq!(0, count.load(atomic::Ordering::SeqCst));
+    }
+
+    #[test]
+    fn closes_connection_after_single_request() {
+        let handled = Arc::new(AtomicUsize::new(0));
+        let service = counting_double_service(&handled);
+
+        let mut stream = connect_raw::<Double>(&service, false);
+        assert_eq!(10, call_raw(&mut stream, 5));
+        let mut rest = vec![];
+        stream.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
 }
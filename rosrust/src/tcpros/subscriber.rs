@@ -1,97 +1,499 @@
 use super::error::{ErrorKind, Result, ResultExt};
-use super::header::{decode, encode, match_field};
-use super::{Message, Topic};
-use crate::rosmsg::RosMsg;
+use super::header::{check_rejection, decode, encode, match_field};
+use super::util::compression;
+use super::util::keepalive::{self, TcpKeepalive};
+use super::util::stats::{ConnectionInfo, ConnectionStats, WindowStats};
+use super::util::workerpool::WorkerPool;
+use super::{Message, MessageType, Topic};
+use crate::rosmsg::{check_message_size, RosMsg};
+use crate::time::Time;
+use crate::udpros::{self, Negotiation};
 use crate::util::lossy_channel::{lossy_channel, LossyReceiver, LossySender};
+use crate::util::FAILED_TO_LOCK;
+use crate::Clock;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use crossbeam::channel::{bounded, Receiver, Sender, TrySendError};
+use crossbeam::channel::{bounded, Receiver, RecvTimeoutError, Sender, TrySendError};
+use lazy_static::lazy_static;
 use log::error;
 use std;
-use std::collections::{BTreeSet, HashMap};
-use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
-use std::sync::Arc;
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+use xml_rpc::Value;
+
+lazy_static! {
+    static ref CALLBACK_POOL: Mutex<Option<Arc<WorkerPool>>> = Mutex::new(None);
+}
+
+/// Sets whether subscribers created afterwards share a pool of threads for callbacks.
+///
+/// By default every subscriber runs its callbacks on a thread of its own. With
+/// a pool, callbacks of all subscribers run on at most `threads` threads, while
+/// each subscriber still handles its messages one at a time and in order.
+/// Subscribers created before keep their way of running callbacks.
+pub fn set_callback_threads(threads: Option<usize>) {
+    let pool = threads.map(|threads| Arc::new(WorkerPool::new("subscriber callbacks", threads)));
+    *CALLBACK_POOL.lock().expect(FAILED_TO_LOCK) = pool;
+}
+
+/// Details about the delivery of a received message.
+#[derive(Clone, Debug)]
+pub struct MessageEvent {
+    /// Time at which the message was read from the connection, based on the node's clock.
+    pub receipt_time: Time,
+    /// Caller ID of the publisher, as sent in its connection header.
+    pub caller_id: String,
+}
+
+// Backoff between attempts to connect to a publisher whose addresses all failed
+const CONNECT_RETRY_INITIAL_DELAY: Duration = Duration::from_millis(100);
+const CONNECT_RETRY_MAX_DELAY: Duration = Duration::from_secs(5);
+const CONNECT_ATTEMPTS: u32 = 10;
+
+/// Connections by publisher URI, holding the stream once it is established.
+type Connections = Arc<Mutex<BTreeMap<String, Option<PublisherConnection>>>>;
+
+struct PublisherConnection {
+    link: Link,
+    caller_id: String,
+    stats: Arc<ConnectionStats>,
+}
+
+/// Socket that messages of a publisher connection arrive on.
+enum Link {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+impl Link {
+    fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        match self {
+            Link::Tcp(stream) => stream.local_addr(),
+            Link::Udp(socket) => socket.local_addr(),
+        }
+    }
+
+    fn transport(&self) -> &'static str {
+        match self {
+            Link::Tcp(_) => "TCPROS",
+            Link::Udp(_) => udpros::UDPROS,
+        }
+    }
+
+    /// Closes TCP streams. UDP receive loops stop on their own once their
+    /// connection is forgotten.
+    fn shutdown(&self) -> std::io::Result<()> {
+        match self {
+            Link::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            Link::Udp(_) => Ok(()),
+        }
+    }
+}
+
+type Callback<T> = Arc<Mutex<Box<dyn Fn(Arc<T>, &MessageEvent) + Send>>>;
+
+/// Callbacks registered on a subscriber, which all share each decoded message.
+struct Callbacks<T> {
+    mapping: Mutex<BTreeMap<usize, Callback<T>>>,
+    next_id: AtomicUsize,
+}
+
+impl<T: Message> Callbacks<T> {
+    fn new() -> Self {
+        Self {
+            mapping: Mutex::new(BTreeMap::new()),
+            next_id: AtomicUsize::new(0),
+        }
+    }
+
+    fn add<F>(&self, callback: F) -> usize
+    where
+        F: Fn(Arc<T>, &MessageEvent) + Send + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.mapping
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .insert(id, Arc::new(Mutex::new(Box::new(callback))));
+        id
+    }
+
+    fn snapshot(&self) -> Vec<Callback<T>> {
+        self.mapping
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Type-erased access to the callbacks, used where the message type is unknown.
+trait CallbackList: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+
+    /// Removes the callback, returning how many are left.
+    fn remove(&self, id: usize) -> usize;
+}
+
+impl<T: Message> CallbackList for Callbacks<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn remove(&self, id: usize) -> usize {
+        let mut mapping = self.mapping.lock().expect(FAILED_TO_LOCK);
+        mapping.remove(&id);
+        mapping.len()
+    }
+}
 
 pub struct Subscriber {
     data_stream: LossySender<MessageInfo>,
-    publishers_stream: Sender<SocketAddr>,
+    sink: MessageSink,
+    clock: Arc<dyn Clock>,
+    publishers_stream: Sender<(String, Vec<SocketAddr>)>,
     pub topic: Topic,
-    pub connected_publishers: BTreeSet<String>,
+    message_type: MessageType,
+    connections: Connections,
+    known_publishers: BTreeSet<String>,
+    callbacks: Arc<dyn CallbackList>,
+    compression: Arc<AtomicBool>,
+    udp_transport: AtomicBool,
 }
 
 impl Subscriber {
-    pub fn new<T, F>(caller_id: &str, topic: &str, queue_size: usize, callback: F) -> Subscriber
+    /// Creates a subscriber that asks publishers for the given message type.
+    ///
+    /// Raw subscribers use it to request a type known only at runtime,
+    /// instead of accepting any type through the wildcard.
+    pub fn new<T>(
+        caller_id: &str,
+        topic: &str,
+        queue_size: usize,
+        clock: Arc<dyn Clock>,
+        message_type: MessageType,
+        keepalive: Option<TcpKeepalive>,
+    ) -> Subscriber
     where
         T: Message,
-        F: Fn(T, &str) + Send + 'static,
     {
         let (data_tx, data_rx) = lossy_channel(queue_size);
         let publisher_connection_queue_size = 8;
         let (pub_tx, pub_rx) = bounded(publisher_connection_queue_size);
         let caller_id = String::from(caller_id);
         let topic_name = String::from(topic);
-        let data_stream = data_tx.clone();
-        thread::spawn(move || join_connections::<T>(&data_tx, pub_rx, &caller_id, &topic_name));
-        thread::spawn(move || handle_data::<T, F>(data_rx, callback));
+        let callbacks = Arc::new(Callbacks::<T>::new());
+        let pool = CALLBACK_POOL.lock().expect(FAILED_TO_LOCK).clone();
+        let sink = MessageSink {
+            wake: start_dispatch(data_rx, Arc::clone(&callbacks), pool),
+            queue: data_tx.clone(),
+        };
+        let connections = Connections::default();
+        let thread_connections = Arc::clone(&connections);
+        let compression = Arc::new(AtomicBool::new(compression::transport_compression()));
+        let thread_compression = Arc::clone(&compression);
+        let thread_message_type = message_type.clone();
+        let thread_sink = sink.clone();
+        let thread_clock = Arc::clone(&clock);
+        thread::spawn(move || {
+            join_connections(
+                &thread_sink,
+                pub_rx,
+                &thread_connections,
+                &caller_id,
+                &topic_name,
+                &thread_message_type,
+                &thread_clock,
+                &thread_compression,
+                keepalive.as_ref(),
+            )
+        });
         let topic = Topic {
             name: String::from(topic),
-            msg_type: T::msg_type(),
+            msg_type: message_type.msg_type.clone(),
         };
         Subscriber {
-            data_stream,
+            data_stream: data_tx,
+            sink,
+            clock,
             publishers_stream: pub_tx,
             topic,
-            connected_publishers: BTreeSet::new(),
+            message_type,
+            connections,
+            known_publishers: BTreeSet::new(),
+            callbacks,
+            compression,
+            udp_transport: AtomicBool::new(false),
         }
     }
 
+    /// Sets whether publishers connected to afterwards get asked for UDPROS.
+    ///
+    /// TCPROS is offered as well, for publishers without UDPROS support.
+    pub fn set_udp_transport(&self, enabled: bool) {
+        self.udp_transport.store(enabled, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn udp_transport(&self) -> bool {
+        self.udp_transport.load(Ordering::Relaxed)
+    }
+
+    /// Binds a socket for receiving from a publisher on `publisher_host`,
+    /// and describes it for `requestTopic`.
+    pub fn offer_udp(
+        &self,
+        caller_id: &str,
+        publisher_host: &str,
+    ) -> std::io::Result<(UdpSocket, Value)> {
+        let fields = request_fields(&self.message_type, caller_id, &self.topic.name);
+        udpros::offer(publisher_host, &fields)
+    }
+
+    /// Receives messages of the publisher on a socket it accepted through `requestTopic`.
+    ///
+    /// Like TCPROS connections, the connection is forgotten once the publisher
+    /// is no longer listed, or once it reports an error.
+    pub fn connect_udp(
+        &self,
+        publisher_uri: &str,
+        socket: UdpSocket,
+        negotiation: Negotiation,
+    ) -> Result<()> {
+        let Negotiation {
+            connection_id,
+            fields,
+        } = negotiation;
+        check_rejection(&fields)?;
+        check_response_fields(&fields, &self.message_type)?;
+        let pub_caller_id = fields.get("callerid").cloned().unwrap_or_default();
+        let local_addr = socket.local_addr()?;
+        let stats = Arc::new(ConnectionStats::new());
+        {
+            let mut connections = self.connections.lock().expect(FAILED_TO_LOCK);
+            if connections.contains_key(publisher_uri) {
+                return Ok(());
+            }
+            connections.insert(
+                publisher_uri.to_owned(),
+                Some(PublisherConnection {
+                    link: Link::Udp(socket.try_clone()?),
+                    caller_id: pub_caller_id.clone(),
+                    stats: Arc::clone(&stats),
+                }),
+            );
+        }
+        let target = self.sink.clone();
+        let clock = Arc::clone(&self.clock);
+        let connections = Arc::clone(&self.connections);
+        let publisher_uri = String::from(publisher_uri);
+        let with_header = starts_with_header(&self.message_type.msg_definition);
+        thread::spawn(move || {
+            let pub_caller_id = Arc::new(pub_caller_id);
+            let keep_going =
+                || target.is_open() && is_current(&connections, &publisher_uri, Some(local_addr));
+            let result = udpros::receive(&socket, connection_id, keep_going, |buffer| {
+                let receipt_time = clock.now();
+                let wire_length = buffer.len();
+                let info = (Arc::clone(&pub_caller_id), receipt_time, buffer);
+                deliver(&target, &stats, info, wire_length, with_header)
+            });
+            if let Err(err) = result {
+                error!(
+                    "Failed to receive from publisher '{}': {}",
+                    publisher_uri, err
+                );
+            }
+            forget_connection(&connections, &publisher_uri, Some(local_addr));
+        });
+        Ok(())
+    }
+
+    /// Sets whether compression is offered to publishers connected to afterwards.
+    ///
+    /// The default comes from `set_transport_compression`.
+    pub fn set_transport_compression(&self, enabled: bool) {
+        self.compression.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Adds a callback, returning an ID for removing it.
+    ///
+    /// Every message is decoded once, and shared between all callbacks.
+    /// The message type must be the one the subscriber was created with.
+    pub fn add_callback<T, F>(&self, callback: F) -> Result<usize>
+    where
+        T: Message,
+        F: Fn(Arc<T>, &MessageEvent) + Send + 'static,
+    {
+        let callbacks = self
+            .callbacks
+            .as_any()
+            .downcast_ref::<Callbacks<T>>()
+            .ok_or_else(|| {
+                ErrorKind::SubscriberTypeMismatch(self.topic.msg_type.clone(), T::msg_type())
+            })?;
+        Ok(callbacks.add(callback))
+    }
+
+    /// Grows the queue of received messages to at least the given size.
+    ///
+    /// Callbacks share the queue, so it is as large as the largest size any
+    /// of them asked for.
+    #[inline]
+    pub fn set_queue_size_max(&self, queue_size: usize) {
+        self.data_stream.set_queue_size_max(queue_size);
+    }
+
+    #[cfg(test)]
+    pub fn queue_size(&self) -> usize {
+        *self.data_stream.queue_size.lock().expect(FAILED_TO_LOCK)
+    }
+
+    /// Removes a callback, returning how many are left.
+    #[inline]
+    pub fn remove_callback(&self, id: usize) -> usize {
+        self.callbacks.remove(id)
+    }
+
+    /// Number of publishers with an established connection.
+    ///
+    /// Connections still exchanging headers are not counted, and closed ones
+    /// stop being counted as soon as they are noticed.
     #[inline]
     pub fn publisher_count(&self) -> usize {
-        self.connected_publishers.len()
+        self.connections
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .values()
+            .filter(|connection| connection.is_some())
+            .count()
     }
 
     #[inline]
     pub fn publisher_uris(&self) -> Vec<String> {
-        self.connected_publishers.iter().cloned().collect()
+        self.connections
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .keys()
+            .cloned()
+            .collect()
     }
 
+    /// Established connections, by publisher URI.
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        self.connections
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .iter()
+            .filter_map(|(uri, connection)| {
+                connection.as_ref().map(|connection| ConnectionInfo {
+                    transport: connection.link.transport(),
+                    ..connection.stats.info(uri)
+                })
+            })
+            .collect()
+    }
+
+    /// Statistics window of every established connection, by publisher caller ID.
+    ///
+    /// A new window gets started for each connection.
+    pub fn take_statistics(&self) -> Vec<(String, WindowStats)> {
+        self.connections
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .values()
+            .filter_map(|connection| {
+                connection.as_ref().map(|connection| {
+                    (connection.caller_id.clone(), connection.stats.take_window())
+                })
+            })
+            .collect()
+    }
+
+    #[inline]
+    pub fn dropped_message_count(&self) -> usize {
+        self.data_stream.dropped_count()
+    }
+
+    /// Starts connecting to the publisher, unless a connection already exists or is pending.
+    ///
+    /// The addresses are tried in turn. Once all of them fail, the attempt is
+    /// repeated with an exponential backoff, for as long as the publisher is
+    /// still listed.
     #[allow(clippy::identity_conversion)]
     pub fn connect_to<U: ToSocketAddrs>(
         &mut self,
         publisher: &str,
         addresses: U,
     ) -> std::io::Result<()> {
-        for address in addresses.to_socket_addrs()? {
-            // This should never fail, so it's safe to unwrap
-            // Failure could only be caused by the join_connections
-            // thread not running, which only happens after
-            // Subscriber has been deconstructed
-            self.publishers_stream
-                .send(address)
-                .expect("Connected thread died");
-        }
-        self.connected_publishers.insert(publisher.to_owned());
+        let addresses = addresses.to_socket_addrs()?.collect::<Vec<_>>();
+        {
+            let mut connections = self.connections.lock().expect(FAILED_TO_LOCK);
+            if connections.contains_key(publisher) {
+                return Ok(());
+            }
+            connections.insert(publisher.to_owned(), None);
+        }
+        // This should never fail, so it's safe to unwrap
+        // Failure could only be caused by the join_connections
+        // thread not running, which only happens after
+        // Subscriber has been deconstructed
+        self.publishers_stream
+            .send((publisher.to_owned(), addresses))
+            .expect("Connected thread died");
         Ok(())
     }
 
+    /// Whether a connection to the publisher is established or being established.
+    ///
+    /// Connections that get closed, or that keep failing, are forgotten, so the
+    /// publisher gets connected to again on the next publisher update.
     pub fn is_connected_to(&self, publisher: &str) -> bool {
-        self.connected_publishers.contains(publisher)
+        self.connections
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .contains_key(publisher)
     }
 
+    /// Whether the publisher was part of the latest publisher update.
+    pub fn is_publisher_known(&self, publisher: &str) -> bool {
+        self.known_publishers.contains(publisher)
+    }
+
+    /// Closes connections to publishers that are no longer listed.
     pub fn limit_publishers_to(&mut self, publishers: &BTreeSet<String>) {
-        let difference: Vec<String> = self
-            .connected_publishers
-            .difference(publishers)
+        let mut connections = self.connections.lock().expect(FAILED_TO_LOCK);
+        let difference: Vec<String> = connections
+            .keys()
+            .filter(|publisher| !publishers.contains(*publisher))
             .cloned()
             .collect();
         for item in difference {
-            self.connected_publishers.remove(&item);
+            if let Some(Some(connection)) = connections.remove(&item) {
+                if let Err(err) = connection.link.shutdown() {
+                    error!(
+                        "Failed to close connection to publisher '{}': {}",
+                        item, err
+                    );
+                }
+            }
         }
+        self.known_publishers = publishers.clone();
     }
 
     pub fn get_topic(&self) -> &Topic {
         &self.topic
     }
+
+    #[inline]
+    pub fn message_type(&self) -> &MessageType {
+        &self.message_type
+    }
 }
 
 impl Drop for Subscriber {
@@ -105,103 +507,482 @@ impl Drop for Subscriber {
     }
 }
 
-fn handle_data<T, F>(data: LossyReceiver<MessageInfo>, callback: F)
-where
-    T: Message,
-    F: Fn(T, &str),
-{
+/// Wakes up the dispatch of messages on a shared pool.
+type Wake = Arc<dyn Fn() + Send + Sync>;
+
+/// Queue of received messages, along with what makes callbacks handle them.
+#[derive(Clone)]
+struct MessageSink {
+    queue: LossySender<MessageInfo>,
+    wake: Option<Wake>,
+}
+
+impl MessageSink {
+    fn try_send(&self, info: MessageInfo) -> std::result::Result<(), TrySendError<MessageInfo>> {
+        self.queue.try_send(info)?;
+        if let Some(ref wake) = self.wake {
+            wake();
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn is_open(&self) -> bool {
+        self.queue.is_open()
+    }
+}
+
+/// Starts handling received messages, on a dedicated thread or on the pool.
+///
+/// Dispatch on the pool needs waking up for every message.
+fn start_dispatch<T: Message>(
+    data: LossyReceiver<MessageInfo>,
+    callbacks: Arc<Callbacks<T>>,
+    pool: Option<Arc<WorkerPool>>,
+) -> Option<Wake> {
+    match pool {
+        Some(pool) => {
+            let dispatch = Arc::new(PoolDispatch {
+                pool,
+                data,
+                callbacks,
+                scheduled: AtomicBool::new(false),
+            });
+            Some(Arc::new(move || dispatch.wake()))
+        }
+        None => {
+            thread::spawn(move || handle_data(data, &callbacks));
+            None
+        }
+    }
+}
+
+/// Handles messages of a single subscriber on a shared pool.
+///
+/// At most one job per subscriber is queued or running at any time, which
+/// keeps messages in order. Every job handles a single message, so
+/// subscribers sharing the pool take turns.
+struct PoolDispatch<T> {
+    pool: Arc<WorkerPool>,
+    data: LossyReceiver<MessageInfo>,
+    callbacks: Arc<Callbacks<T>>,
+    scheduled: AtomicBool,
+}
+
+impl<T: Message> PoolDispatch<T> {
+    fn wake(self: &Arc<Self>) {
+        if !self.scheduled.swap(true, Ordering::SeqCst) {
+            let dispatch = Arc::clone(self);
+            self.pool.execute(move || dispatch.handle_next());
+        }
+    }
+
+    fn handle_next(self: Arc<Self>) {
+        // Messages left behind by a destroyed subscriber are not handled
+        if !self.data.kill_rx.kill_rx.is_empty() {
+            return;
+        }
+        if let Ok(buffer) = self.data.data_rx.try_recv() {
+            handle_message(buffer, &self.callbacks);
+        }
+        self.scheduled.store(false, Ordering::SeqCst);
+        // Messages could have arrived before clearing the flag, without waking us up
+        if !self.data.data_rx.is_empty() {
+            self.wake();
+        }
+    }
+}
+
+fn handle_data<T: Message>(data: LossyReceiver<MessageInfo>, callbacks: &Callbacks<T>) {
     for buffer in data {
-        match RosMsg::decode_slice(&buffer.data) {
-            Ok(value) => callback(value, &buffer.caller_id),
-            Err(err) => error!("Failed to decode message: {}", err),
+        handle_message(buffer, callbacks);
+    }
+}
+
+fn handle_message<T: Message>(buffer: MessageInfo, callbacks: &Callbacks<T>) {
+    let value = match RosMsg::decode_slice(&buffer.data) {
+        Ok(value) => Arc::new(value),
+        Err(err) => {
+            error!("Failed to decode message: {}", err);
+            return;
+        }
+    };
+    let event = MessageEvent {
+        receipt_time: buffer.receipt_time,
+        caller_id: String::clone(&buffer.caller_id),
+    };
+    // The last callback gets the only remaining reference, so a single
+    // callback can take the message out without cloning it
+    let callbacks = callbacks.snapshot();
+    let mut callbacks = callbacks.iter().peekable();
+    while let Some(callback) = callbacks.next() {
+        let callback = callback.lock().expect(FAILED_TO_LOCK);
+        if callbacks.peek().is_none() {
+            (*callback)(value, &event);
+            break;
         }
+        (*callback)(Arc::clone(&value), &event);
     }
 }
 
-fn join_connections<T>(
-    data_stream: &LossySender<MessageInfo>,
-    publishers: Receiver<SocketAddr>,
+/// Attempt to connect to a publisher, through any of its addresses.
+struct PendingConnection {
+    publisher_uri: String,
+    addresses: Vec<SocketAddr>,
+    failures: u32,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn join_connections(
+    data_stream: &MessageSink,
+    publishers: Receiver<(String, Vec<SocketAddr>)>,
+    connections: &Connections,
     caller_id: &str,
     topic: &str,
-) where
-    T: Message,
-{
-    // Ends when publisher sender is destroyed, which happens at Subscriber destruction
-    for publisher in publishers {
-        let result = join_connection::<T>(data_stream, &publisher, caller_id, topic)
-            .chain_err(|| ErrorKind::TopicConnectionFail(topic.into()));
-        if let Err(err) = result {
-            let info = err
-                .iter()
-                .map(|v| format!("{}", v))
-                .collect::<Vec<_>>()
-                .join("\nCaused by:");
-            error!("{}", info);
+    message_type: &MessageType,
+    clock: &Arc<dyn Clock>,
+    compression: &AtomicBool,
+    keepalive: Option<&TcpKeepalive>,
+) {
+    let with_header = starts_with_header(&message_type.msg_definition);
+    // Attempts whose addresses all failed, along with the time of their retry
+    let mut retries: Vec<(Instant, PendingConnection)> = vec![];
+    loop {
+        let now = Instant::now();
+        let mut pending = match retries.iter().position(|(due, _)| *due <= now) {
+            Some(index) => retries.swap_remove(index).1,
+            None => {
+                let received = match retries.iter().map(|(due, _)| *due).min() {
+                    Some(due) => publishers.recv_timeout(due - now),
+                    None => publishers
+                        .recv()
+                        .map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match received {
+                    Ok((publisher_uri, addresses)) => PendingConnection {
+                        publisher_uri,
+                        addresses,
+                        failures: 0,
+                    },
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    // Publisher sender is destroyed at Subscriber destruction
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        };
+        // Retries end once the publisher gets removed
+        if pending.failures > 0 && !is_pending(connections, &pending.publisher_uri) {
+            continue;
+        }
+        let connected = pending.addresses.iter().any(|publisher| {
+            let result = connect(publisher, keepalive)
+                .map_err(Into::into)
+                .and_then(|stream| {
+                    join_connection(
+                        data_stream,
+                        &pending.publisher_uri,
+                        stream,
+                        connections,
+                        caller_id,
+                        topic,
+                        message_type,
+                        clock,
+                        with_header,
+                        compression.load(Ordering::Relaxed),
+                    )
+                })
+                .chain_err(|| ErrorKind::TopicConnectionFail(topic.into()));
+            if let Err(err) = &result {
+                let info = err
+                    .iter()
+                    .map(|v| format!("{}", v))
+                    .collect::<Vec<_>>()
+                    .join("\nCaused by:");
+                error!("{}", info);
+            }
+            result.is_ok()
+        });
+        if connected {
+            continue;
+        }
+        pending.failures += 1;
+        if pending.failures < CONNECT_ATTEMPTS {
+            let delay = CONNECT_RETRY_INITIAL_DELAY * 2u32.pow(pending.failures - 1);
+            let delay = delay.min(CONNECT_RETRY_MAX_DELAY);
+            retries.push((Instant::now() + delay, pending));
+        } else {
+            forget_connection(connections, &pending.publisher_uri, None);
         }
     }
 }
 
-fn join_connection<T>(
-    data_stream: &LossySender<MessageInfo>,
-    publisher: &SocketAddr,
+fn connect(publisher: &SocketAddr, keepalive: Option<&TcpKeepalive>) -> std::io::Result<TcpStream> {
+    let stream = TcpStream::connect(publisher)?;
+    stream.set_nodelay(true)?;
+    keepalive::apply(&stream, keepalive)?;
+    Ok(stream)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn join_connection(
+    data_stream: &MessageSink,
+    publisher_uri: &str,
+    mut stream: TcpStream,
+    connections: &Connections,
     caller_id: &str,
     topic: &str,
-) -> Result<()>
-where
-    T: Message,
-{
-    let mut stream = TcpStream::connect(publisher)?;
-    let pub_caller_id = exchange_headers::<T, _>(&mut stream, caller_id, topic)?;
+    message_type: &MessageType,
+    clock: &Arc<dyn Clock>,
+    with_header: bool,
+    compression: bool,
+) -> Result<()> {
+    let (pub_caller_id, compressed) =
+        exchange_headers(&mut stream, message_type, caller_id, topic, compression)?;
+    let local_addr = stream.local_addr()?;
+    let pub_caller_id = pub_caller_id.unwrap_or_default();
+    let stats = Arc::new(ConnectionStats::new());
+    match connections
+        .lock()
+        .expect(FAILED_TO_LOCK)
+        .get_mut(publisher_uri)
+    {
+        Some(entry) if entry.is_none() => {
+            *entry = Some(PublisherConnection {
+                link: Link::Tcp(stream.try_clone()?),
+                caller_id: pub_caller_id.clone(),
+                stats: Arc::clone(&stats),
+            })
+        }
+        // Publisher got removed while we were connecting, or is connected already
+        _ => return Ok(()),
+    }
     let target = data_stream.clone();
+    let clock = Arc::clone(clock);
+    let connections = Arc::clone(connections);
+    let publisher_uri = String::from(publisher_uri);
     thread::spawn(move || {
-        let pub_caller_id = Arc::new(pub_caller_id.unwrap_or_default());
-        while let Ok(buffer) = package_to_vector(&mut stream) {
-            if let Err(TrySendError::Disconnected(_)) =
-                target.try_send(MessageInfo::new(Arc::clone(&pub_caller_id), buffer))
-            {
-                // Data receiver has been destroyed after
-                // Subscriber destructor's kill signal
+        let pub_caller_id = Arc::new(pub_caller_id);
+        // Stops reading large messages early once the subscriber is gone
+        let is_open = |_, _| target.is_open();
+        while let Ok(buffer) = package_to_vector_chunked(&mut stream, READ_CHUNK_SIZE, is_open) {
+            let receipt_time = clock.now();
+            // Traffic is counted as it was on the wire
+            let wire_length = buffer.len();
+            let buffer = if compressed {
+                match compression::decompress(&buffer) {
+                    Ok(buffer) => buffer,
+                    Err(err) => {
+                        error!(
+                            "Failed to decompress message from '{}': {}",
+                            publisher_uri, err
+                        );
+                        break;
+                    }
+                }
+            } else {
+                buffer
+            };
+            let info = (Arc::clone(&pub_caller_id), receipt_time, buffer);
+            if !deliver(&target, &stats, info, wire_length, with_header) {
                 break;
             }
         }
+        forget_connection(&connections, &publisher_uri, Some(local_addr));
     });
     Ok(())
 }
 
-fn write_request<T: Message, U: std::io::Write>(
+/// Queues a received message for the callbacks, returning whether the subscriber still exists.
+///
+/// The message comes with the caller ID of its publisher and its receipt time.
+/// Traffic is counted with the given length, as the message was on the wire.
+fn deliver(
+    target: &MessageSink,
+    stats: &ConnectionStats,
+    (caller_id, receipt_time, buffer): (Arc<String>, Time, Vec<u8>),
+    wire_length: usize,
+    with_header: bool,
+) -> bool {
+    let header = if with_header {
+        read_header_fields(&buffer).ok()
+    } else {
+        None
+    };
+    stats.record(wire_length);
+    stats.record_receipt(wire_length, receipt_time, header);
+    let info = MessageInfo::new(caller_id, receipt_time, buffer);
+    // Data receiver has been destroyed after Subscriber destructor's kill signal
+    !matches!(target.try_send(info), Err(TrySendError::Disconnected(_)))
+}
+
+/// Whether the publisher is listed, with its connection still being established.
+fn is_pending(connections: &Connections, publisher_uri: &str) -> bool {
+    matches!(
+        connections.lock().expect(FAILED_TO_LOCK).get(publisher_uri),
+        Some(None)
+    )
+}
+
+/// Removes the publisher's entry if it still refers to the given connection.
+///
+/// A `None` address matches an entry whose connection is still pending.
+fn forget_connection(
+    connections: &Connections,
+    publisher_uri: &str,
+    local_addr: Option<SocketAddr>,
+) {
+    let mut connections = connections.lock().expect(FAILED_TO_LOCK);
+    if refers_to(&connections, publisher_uri, local_addr) {
+        connections.remove(publisher_uri);
+    }
+}
+
+/// Whether the publisher's entry still refers to the given connection.
+fn is_current(
+    connections: &Connections,
+    publisher_uri: &str,
+    local_addr: Option<SocketAddr>,
+) -> bool {
+    refers_to(
+        &connections.lock().expect(FAILED_TO_LOCK),
+        publisher_uri,
+        local_addr,
+    )
+}
+
+fn refers_to(
+    connections: &BTreeMap<String, Option<PublisherConnection>>,
+    publisher_uri: &str,
+    local_addr: Option<SocketAddr>,
+) -> bool {
+    match connections.get(publisher_uri) {
+        Some(Some(connection)) => connection.link.local_addr().ok() == local_addr,
+        Some(None) => local_addr.is_none(),
+        None => false,
+    }
+}
+
+fn write_request<U: std::io::Write>(
     mut stream: &mut U,
+    message_type: &MessageType,
     caller_id: &str,
     topic: &str,
+    compression: bool,
 ) -> Result<()> {
+    let mut fields = request_fields(message_type, caller_id, topic);
+    // Matches the local socket, which has Nagle's algorithm disabled too
+    fields.insert(String::from("tcp_nodelay"), String::from("1"));
+    if compression {
+        fields.insert(
+            String::from(compression::HEADER_FIELD),
+            String::from(compression::LZ4),
+        );
+    }
+    encode(&mut stream, &fields)?;
+    Ok(())
+}
+
+/// Connection header fields that subscribers send over any transport.
+fn request_fields(
+    message_type: &MessageType,
+    caller_id: &str,
+    topic: &str,
+) -> HashMap<String, String> {
     let mut fields = HashMap::<String, String>::new();
-    fields.insert(String::from("message_definition"), T::msg_definition());
+    fields.insert(
+        String::from("message_definition"),
+        message_type.msg_definition.clone(),
+    );
     fields.insert(String::from("callerid"), String::from(caller_id));
     fields.insert(String::from("topic"), String::from(topic));
-    fields.insert(String::from("md5sum"), T::md5sum());
-    fields.insert(String::from("type"), T::msg_type());
-    encode(&mut stream, &fields)?;
-    Ok(())
+    fields.insert(String::from("md5sum"), message_type.md5sum.clone());
+    fields.insert(String::from("type"), message_type.msg_type.clone());
+    fields
 }
 
-fn read_response<T: Message, U: std::io::Read>(mut stream: &mut U) -> Result<Option<String>> {
+fn read_response<U: std::io::Read>(
+    mut stream: &mut U,
+    message_type: &MessageType,
+) -> Result<(Option<String>, bool)> {
     let fields = decode(&mut stream)?;
-    match_field(&fields, "md5sum", &T::md5sum())?;
-    match_field(&fields, "type", &T::msg_type())?;
-    Ok(fields.get("callerid").cloned())
+    check_rejection(&fields)?;
+    check_response_fields(&fields, message_type)?;
+    Ok((
+        fields.get("callerid").cloned(),
+        compression::is_offered(&fields),
+    ))
 }
 
-fn exchange_headers<T, U>(stream: &mut U, caller_id: &str, topic: &str) -> Result<Option<String>>
+fn check_response_fields(
+    fields: &HashMap<String, String>,
+    message_type: &MessageType,
+) -> Result<()> {
+    // Wildcard subscribers, like raw message ones, accept any publisher
+    if !message_type.is_wildcard() {
+        match_field(fields, "md5sum", &message_type.md5sum)?;
+        match_field(fields, "type", &message_type.msg_type)?;
+    }
+    Ok(())
+}
+
+fn exchange_headers<U>(
+    stream: &mut U,
+    message_type: &MessageType,
+    caller_id: &str,
+    topic: &str,
+    compression: bool,
+) -> Result<(Option<String>, bool)>
 where
-    T: Message,
     U: std::io::Write + std::io::Read,
 {
-    write_request::<T, U>(stream, caller_id, topic)?;
-    read_response::<T, U>(stream)
+    write_request(stream, message_type, caller_id, topic, compression)?;
+    read_response(stream, message_type)
 }
 
-#[inline]
-fn package_to_vector<R: std::io::Read>(stream: &mut R) -> std::io::Result<Vec<u8>> {
+/// Whether the first field of the message definition is a standard header.
+fn starts_with_header(definition: &str) -> bool {
+    for line in definition.lines() {
+        // Definitions of dependencies follow the message's own fields
+        if line.starts_with("==") {
+            break;
+        }
+        let line = line.split('#').next().unwrap_or_default().trim();
+        // Constants are not serialized
+        if line.is_empty() || line.contains('=') {
+            continue;
+        }
+        let data_type = line.split_whitespace().next().unwrap_or_default();
+        return data_type == "Header" || data_type == "std_msgs/Header";
+    }
+    false
+}
+
+/// Reads the sequence number and stamp of a header at the start of a message.
+fn read_header_fields(buffer: &[u8]) -> std::io::Result<(u32, Time)> {
+    let mut data = buffer.get(4..).unwrap_or_default();
+    let seq = data.read_u32::<LittleEndian>()?;
+    let stamp = Time::decode(&mut data)?;
+    Ok((seq, stamp))
+}
+
+// Bounds how much is read at once, so huge messages can be aborted while arriving
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reads a message in chunks of at most `chunk_size` bytes.
+///
+/// After every chunk `progress` gets called with the number of bytes read so
+/// far and the total, length prefix included. Returning `false` aborts reading.
+fn package_to_vector_chunked<R, F>(
+    stream: &mut R,
+    chunk_size: usize,
+    mut progress: F,
+) -> std::io::Result<Vec<u8>>
+where
+    R: std::io::Read,
+    F: FnMut(usize, usize) -> bool,
+{
     let length = stream.read_u32::<LittleEndian>()?;
+    check_message_size(length as usize)?;
     let u32_size = std::mem::size_of::<u32>();
     let num_bytes = length as usize + u32_size;
 
@@ -220,7 +1001,19 @@ fn package_to_vector<R: std::io::Read>(stream: &mut R) -> std::io::Result<Vec<u8
 
     // Read data from stream.
     let read_buf = unsafe { std::slice::from_raw_parts_mut(out_ptr as *mut u8, num_bytes) };
-    stream.read_exact(&mut read_buf[u32_size..])?;
+    let chunk_size = chunk_size.max(1);
+    let mut offset = u32_size;
+    while offset < num_bytes {
+        let end = num_bytes.min(offset + chunk_size);
+        stream.read_exact(&mut read_buf[offset..end])?;
+        offset = end;
+        if !progress(offset, num_bytes) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionAborted,
+                "Reading message was aborted",
+            ));
+        }
+    }
 
     // Don't drop the original Vec which has size==0 and instead use
     // its memory to initialize a new Vec with size == capacity == num_bytes.
@@ -233,12 +1026,17 @@ fn package_to_vector<R: std::io::Read>(stream: &mut R) -> std::io::Result<Vec<u8
 #[derive(Clone)]
 struct MessageInfo {
     caller_id: Arc<String>,
+    receipt_time: Time,
     data: Vec<u8>,
 }
 
 impl MessageInfo {
-    fn new(caller_id: Arc<String>, data: Vec<u8>) -> Self {
-        Self { caller_id, data }
+    fn new(caller_id: Arc<String>, receipt_time: Time, data: Vec<u8>) -> Self {
+        Self {
+            caller_id,
+            receipt_time,
+            data,
+        }
     }
 }
 
@@ -249,6 +1047,615 @@ mod tests {
 
     static FAILED_TO_READ_WRITE_VECTOR: &'static str = "Failed to read or write from vector";
 
+    struct FixedClock(Time);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> Time {
+            self.0
+        }
+
+        fn sleep(&self, _d: crate::Duration) {}
+
+        fn wait_until(&self, _t: Time) {}
+    }
+
+    #[test]
+    fn reports_receipt_time_and_publisher_to_callback() {
+        use crate::msg::rosgraph_msgs::Clock as ClockMsg;
+        use std::net::TcpListener;
+        use std::sync::mpsc::channel;
+        use std::sync::Mutex;
+
+        let clock = Arc::new(FixedClock(Time { sec: 12, nsec: 34 }));
+        let (event_tx, event_rx) = channel();
+        let event_tx = Mutex::new(event_tx);
+        let mut subscriber = Subscriber::new::<ClockMsg>(
+            "/listener",
+            "/clock",
+            1,
+            clock,
+            MessageType::of::<ClockMsg>(),
+            None,
+        );
+        subscriber
+            .add_callback(move |_: Arc<ClockMsg>, event: &MessageEvent| {
+                event_tx.lock().unwrap().send(event.clone()).unwrap()
+            })
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        subscriber
+            .connect_to("http://talker:1/", listener.local_addr().unwrap())
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        let (mut stream, _) = listener.accept().unwrap();
+        decode(&mut stream).expect(FAILED_TO_READ_WRITE_VECTOR);
+        let mut fields = HashMap::<String, String>::new();
+        fields.insert(String::from("callerid"), String::from("/talker"));
+        fields.insert(String::from("md5sum"), ClockMsg::md5sum());
+        fields.insert(String::from("type"), ClockMsg::msg_type());
+        encode(&mut stream, &fields).expect(FAILED_TO_READ_WRITE_VECTOR);
+        let message = ClockMsg::default()
+            .encode_vec()
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        std::io::Write::write_all(&mut stream, &message).expect(FAILED_TO_READ_WRITE_VECTOR);
+
+        let event = event_rx
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        assert_eq!("/talker", event.caller_id);
+        assert_eq!(Time { sec: 12, nsec: 34 }, event.receipt_time);
+
+        let connections = subscriber.connections();
+        assert_eq!(1, connections.len());
+        assert_eq!("http://talker:1/", connections[0].peer);
+        assert_eq!(message.len(), connections[0].bytes);
+        assert_eq!(1, connections[0].messages);
+
+        let statistics = subscriber.take_statistics();
+        assert_eq!(1, statistics.len());
+        assert_eq!("/talker", statistics[0].0);
+        assert_eq!(1, statistics[0].1.delivered);
+        assert_eq!(0, subscriber.take_statistics()[0].1.delivered);
+    }
+
+    #[test]
+    fn raw_subscriber_receives_encoded_messages_of_any_type() {
+        use crate::msg::rosgraph_msgs::Clock as ClockMsg;
+        use crate::tcpros::{Publisher, RawMessage};
+        use std::sync::mpsc::channel;
+        use std::time::{Duration, Instant};
+
+        let publisher = Publisher::new(
+            "127.0.0.1:0",
+            "/clock",
+            1,
+            "/talker",
+            MessageType::of::<ClockMsg>(),
+            None,
+        )
+        .expect(FAILED_TO_READ_WRITE_VECTOR);
+        let stream = publisher
+            .stream::<ClockMsg>(1)
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        let (data_tx, data_rx) = channel();
+        let data_tx = Mutex::new(data_tx);
+        let mut subscriber = Subscriber::new::<RawMessage>(
+            "/recorder",
+            "/clock",
+            1,
+            Arc::new(FixedClock(Time::new())),
+            MessageType::of::<RawMessage>(),
+            None,
+        );
+        subscriber
+            .add_callback(move |message: Arc<RawMessage>, _: &MessageEvent| {
+                data_tx.lock().unwrap().send(message).unwrap()
+            })
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        subscriber
+            .connect_to("http://talker:1/", ("127.0.0.1", publisher.port))
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        let start = Instant::now();
+        while stream.subscriber_count() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let message = ClockMsg {
+            clock: Time { sec: 5, nsec: 6 },
+        };
+        stream.send(&message).expect(FAILED_TO_READ_WRITE_VECTOR);
+        let received = data_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        let encoded = message.encode_vec().expect(FAILED_TO_READ_WRITE_VECTOR);
+        assert_eq!(encoded[4..].to_vec(), received.0);
+    }
+
+    #[test]
+    fn callbacks_share_one_decoded_message() {
+        use crate::msg::rosgraph_msgs::Clock as ClockMsg;
+        use crate::tcpros::{Publisher, RawMessage};
+        use std::sync::mpsc::channel;
+        use std::time::{Duration, Instant};
+
+        let publisher = Publisher::new(
+            "127.0.0.1:0",
+            "/clock",
+            1,
+            "/talker",
+            MessageType::of::<ClockMsg>(),
+            None,
+        )
+        .expect(FAILED_TO_READ_WRITE_VECTOR);
+        let stream = publisher
+            .stream::<ClockMsg>(1)
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        let mut subscriber = Subscriber::new::<ClockMsg>(
+            "/listener",
+            "/clock",
+            1,
+            Arc::new(FixedClock(Time::new())),
+            MessageType::of::<ClockMsg>(),
+            None,
+        );
+        let (data_tx, data_rx) = channel();
+        for _ in 0..2 {
+            let data_tx = Mutex::new(data_tx.clone());
+            subscriber
+                .add_callback(move |message: Arc<ClockMsg>, _: &MessageEvent| {
+                    data_tx.lock().unwrap().send(message).unwrap()
+                })
+                .expect(FAILED_TO_READ_WRITE_VECTOR);
+        }
+        subscriber
+            .add_callback(|_: Arc<RawMessage>, _: &MessageEvent| {})
+            .unwrap_err();
+        subscriber
+            .connect_to("http://talker:1/", ("127.0.0.1", publisher.port))
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        let start = Instant::now();
+        while stream.subscriber_count() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let message = ClockMsg {
+            clock: Time { sec: 7, nsec: 8 },
+        };
+        stream.send(&message).expect(FAILED_TO_READ_WRITE_VECTOR);
+        let first = data_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        let second = data_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(message, *first);
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn publisher_count_follows_established_connections() {
+        use crate::msg::rosgraph_msgs::Clock as ClockMsg;
+        use std::net::TcpListener;
+        use std::time::{Duration, Instant};
+
+        let wait_for_count = |subscriber: &Subscriber, count: usize| {
+            let start = Instant::now();
+            while subscriber.publisher_count() != count {
+                assert!(start.elapsed() < Duration::from_secs(5));
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        };
+
+        let mut subscriber = Subscriber::new::<ClockMsg>(
+            "/listener",
+            "/clock",
+            1,
+            Arc::new(FixedClock(Time::new())),
+            MessageType::of::<ClockMsg>(),
+            None,
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        subscriber
+            .connect_to("http://talker:1/", listener.local_addr().unwrap())
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        let (mut stream, _) = listener.accept().unwrap();
+        decode(&mut stream).expect(FAILED_TO_READ_WRITE_VECTOR);
+        assert!(subscriber.is_connected_to("http://talker:1/"));
+        assert_eq!(0, subscriber.publisher_count());
+
+        let mut fields = HashMap::<String, String>::new();
+        fields.insert(String::from("callerid"), String::from("/talker"));
+        fields.insert(String::from("md5sum"), ClockMsg::md5sum());
+        fields.insert(String::from("type"), ClockMsg::msg_type());
+        encode(&mut stream, &fields).expect(FAILED_TO_READ_WRITE_VECTOR);
+        wait_for_count(&subscriber, 1);
+
+        drop(stream);
+        wait_for_count(&subscriber, 0);
+    }
+
+    #[test]
+    fn retries_publisher_that_refused_first_connection() {
+        use crate::msg::rosgraph_msgs::Clock as ClockMsg;
+        use std::net::TcpListener;
+
+        let mut subscriber = Subscriber::new::<ClockMsg>(
+            "/listener",
+            "/clock",
+            1,
+            Arc::new(FixedClock(Time::new())),
+            MessageType::of::<ClockMsg>(),
+            None,
+        );
+        let closed_address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addresses = [closed_address, listener.local_addr().unwrap()];
+        subscriber
+            .connect_to("http://talker:1/", &addresses[..])
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+
+        // Failing addresses do not end the attempt while others are left
+        let (stream, _) = listener.accept().unwrap();
+        assert!(subscriber.is_connected_to("http://talker:1/"));
+        drop(stream);
+
+        let start = Instant::now();
+        let (mut stream, _) = listener.accept().unwrap();
+        assert!(start.elapsed() >= CONNECT_RETRY_INITIAL_DELAY / 2);
+        assert!(subscriber.is_connected_to("http://talker:1/"));
+        decode(&mut stream).expect(FAILED_TO_READ_WRITE_VECTOR);
+        let mut fields = HashMap::<String, String>::new();
+        fields.insert(String::from("callerid"), String::from("/talker"));
+        fields.insert(String::from("md5sum"), ClockMsg::md5sum());
+        fields.insert(String::from("type"), ClockMsg::msg_type());
+        encode(&mut stream, &fields).expect(FAILED_TO_READ_WRITE_VECTOR);
+        while subscriber.publisher_count() != 1 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn removing_callbacks_reports_remaining_count() {
+        use crate::msg::rosgraph_msgs::Clock as ClockMsg;
+
+        let subscriber = Subscriber::new::<ClockMsg>(
+            "/listener",
+            "/clock",
+            1,
+            Arc::new(FixedClock(Time::new())),
+            MessageType::of::<ClockMsg>(),
+            None,
+        );
+        let first = subscriber
+            .add_callback(|_: Arc<ClockMsg>, _: &MessageEvent| {})
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        let second = subscriber
+            .add_callback(|_: Arc<ClockMsg>, _: &MessageEvent| {})
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        assert_ne!(first, second);
+        assert_eq!(1, subscriber.remove_callback(first));
+        assert_eq!(1, subscriber.remove_callback(first));
+        assert_eq!(0, subscriber.remove_callback(second));
+    }
+
+    #[test]
+    fn reads_sequence_and_stamp_of_headers() {
+        use crate::msg::rosgraph_msgs::{Clock as ClockMsg, Log};
+
+        assert!(starts_with_header(&Log::msg_definition()));
+        assert!(!starts_with_header(&ClockMsg::msg_definition()));
+        assert!(starts_with_header(
+            "# comment\nint8 A=1\nstd_msgs/Header h\n"
+        ));
+
+        let mut message = Log::default();
+        message.header.seq = 17;
+        message.header.stamp = Time { sec: 3, nsec: 4 };
+        let buffer = message.encode_vec().expect(FAILED_TO_READ_WRITE_VECTOR);
+        assert_eq!(
+            (17, Time { sec: 3, nsec: 4 }),
+            read_header_fields(&buffer).expect(FAILED_TO_READ_WRITE_VECTOR)
+        );
+        assert!(read_header_fields(&buffer[..10]).is_err());
+    }
+
+    #[test]
+    fn reports_rejection_by_publisher() {
+        use crate::msg::rosgraph_msgs::{Clock as ClockMsg, Log};
+        use crate::tcpros::Publisher;
+
+        let publisher = Publisher::new(
+            "127.0.0.1:0",
+            "/clock",
+            1,
+            "/talker",
+            MessageType::of::<ClockMsg>(),
+            None,
+        )
+        .expect(FAILED_TO_READ_WRITE_VECTOR);
+        let mut stream =
+            TcpStream::connect(("127.0.0.1", publisher.port)).expect(FAILED_TO_READ_WRITE_VECTOR);
+        let err = exchange_headers(
+            &mut stream,
+            &MessageType::of::<Log>(),
+            "/listener",
+            "/clock",
+            false,
+        )
+        .unwrap_err();
+        match *err.kind() {
+            ErrorKind::HandshakeRejected(ref message) => {
+                assert!(message.contains("md5sum"));
+                assert!(message.contains(&ClockMsg::md5sum()));
+                assert!(message.contains(&Log::md5sum()));
+            }
+            ref kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn raw_subscriber_requests_type_given_at_runtime() {
+        use crate::msg::rosgraph_msgs::Clock as ClockMsg;
+        use crate::tcpros::{Publisher, RawMessage};
+        use std::sync::mpsc::channel;
+        use std::time::{Duration, Instant};
+
+        let publisher = Publisher::new(
+            "127.0.0.1:0",
+            "/clock",
+            1,
+            "/talker",
+            MessageType::of::<ClockMsg>(),
+            None,
+        )
+        .expect(FAILED_TO_READ_WRITE_VECTOR);
+        let stream = publisher
+            .stream::<ClockMsg>(1)
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        let (data_tx, data_rx) = channel();
+        let data_tx = Mutex::new(data_tx);
+        let mut subscriber = Subscriber::new::<RawMessage>(
+            "/recorder",
+            "/clock",
+            1,
+            Arc::new(FixedClock(Time::new())),
+            MessageType::of::<ClockMsg>(),
+            None,
+        );
+        assert_eq!("rosgraph_msgs/Clock", subscriber.get_topic().msg_type);
+        subscriber
+            .add_callback(move |message: Arc<RawMessage>, _: &MessageEvent| {
+                data_tx.lock().unwrap().send(message).unwrap()
+            })
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        subscriber
+            .connect_to("http://talker:1/", ("127.0.0.1", publisher.port))
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        let start = Instant::now();
+        while stream.subscriber_count() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let message = ClockMsg {
+            clock: Time { sec: 5, nsec: 6 },
+        };
+        stream.send(&message).expect(FAILED_TO_READ_WRITE_VECTOR);
+        let received = data_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(message, ClockMsg::decode(received.0.as_slice()).unwrap());
+    }
+
+    // Returns the traffic of the connection, relative to the uncompressed message
+    fn transfer_log(publisher_compression: bool, subscriber_compression: bool) -> usize {
+        use crate::msg::rosgraph_msgs::Log;
+        use crate::tcpros::Publisher;
+        use std::sync::mpsc::channel;
+        use std::sync::Mutex;
+        use std::time::{Duration, Instant};
+
+        let publisher = Publisher::new(
+            "127.0.0.1:0",
+            "/rosout",
+            1,
+            "/talker",
+            MessageType::of::<Log>(),
+            None,
+        )
+        .expect(FAILED_TO_READ_WRITE_VECTOR);
+        let stream = publisher
+            .stream::<Log>(1)
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        stream.set_transport_compression(publisher_compression);
+
+        let (message_tx, message_rx) = channel();
+        let message_tx = Mutex::new(message_tx);
+        let mut subscriber = Subscriber::new::<Log>(
+            "/listener",
+            "/rosout",
+            1,
+            Arc::new(FixedClock(Time::new())),
+            MessageType::of::<Log>(),
+            None,
+        );
+        subscriber.set_transport_compression(subscriber_compression);
+        subscriber
+            .add_callback(move |message: Arc<Log>, _: &MessageEvent| {
+                message_tx.lock().unwrap().send((*message).clone()).unwrap()
+            })
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        subscriber
+            .connect_to("http://talker:1/", ("127.0.0.1", publisher.port))
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+
+        let start = Instant::now();
+        while stream.subscriber_count() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let mut message = Log::default();
+        message.msg = "repeated text ".repeat(100);
+        stream.send(&message).expect(FAILED_TO_READ_WRITE_VECTOR);
+
+        assert_eq!(
+            message,
+            message_rx.recv_timeout(Duration::from_secs(5)).unwrap()
+        );
+        let encoded_length = message.encode_vec().unwrap().len();
+        subscriber.connections()[0].bytes * 100 / encoded_length
+    }
+
+    #[test]
+    fn compresses_messages_when_both_sides_offer_it() {
+        assert!(transfer_log(true, true) < 50);
+    }
+
+    #[test]
+    fn falls_back_to_raw_messages_without_compression_support() {
+        assert_eq!(100, transfer_log(true, false));
+        assert_eq!(100, transfer_log(false, true));
+        assert_eq!(100, transfer_log(false, false));
+    }
+
+    #[test]
+    fn offers_compression_in_header_when_enabled() {
+        use crate::msg::rosgraph_msgs::Clock as ClockMsg;
+
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        write_request(
+            &mut cursor,
+            &MessageType::of::<ClockMsg>(),
+            "/listener",
+            "/clock",
+            true,
+        )
+        .expect(FAILED_TO_READ_WRITE_VECTOR);
+        cursor.set_position(0);
+        let fields = decode(&mut cursor).expect(FAILED_TO_READ_WRITE_VECTOR);
+        assert!(compression::is_offered(&fields));
+    }
+
+    #[test]
+    fn connecting_enables_configured_keepalive() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let stream = connect(&address, None).unwrap();
+        assert_eq!(None, keepalive::configured(&stream).unwrap());
+
+        let keepalive = TcpKeepalive::new(Duration::from_secs(30))
+            .with_interval(Duration::from_secs(5))
+            .with_count(3);
+        let stream = connect(&address, Some(&keepalive)).unwrap();
+        assert!(stream.nodelay().unwrap());
+        assert_eq!(Some(keepalive), keepalive::configured(&stream).unwrap());
+    }
+
+    #[test]
+    fn requests_tcp_nodelay_in_header() {
+        use crate::msg::rosgraph_msgs::Clock as ClockMsg;
+
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        write_request(
+            &mut cursor,
+            &MessageType::of::<ClockMsg>(),
+            "/listener",
+            "/clock",
+            false,
+        )
+        .expect(FAILED_TO_READ_WRITE_VECTOR);
+        cursor.set_position(0);
+        let fields = decode(&mut cursor).expect(FAILED_TO_READ_WRITE_VECTOR);
+        assert_eq!(Some(&String::from("1")), fields.get("tcp_nodelay"));
+    }
+
+    fn package_to_vector<R: std::io::Read>(stream: &mut R) -> std::io::Result<Vec<u8>> {
+        package_to_vector_chunked(stream, READ_CHUNK_SIZE, |_, _| true)
+    }
+
+    fn length_prefixed(body: &[u8]) -> Vec<u8> {
+        let mut output = vec![];
+        output
+            .write_u32::<LittleEndian>(body.len() as u32)
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        output.extend_from_slice(body);
+        output
+    }
+
+    #[test]
+    fn shared_pool_keeps_order_of_each_topic() {
+        use crate::msg::rosgraph_msgs::Clock as ClockMsg;
+        use std::collections::HashSet;
+        use std::sync::mpsc::channel;
+        use std::sync::Mutex;
+
+        const TOPICS: usize = 20;
+        const MESSAGES: u32 = 50;
+
+        let pool = Arc::new(WorkerPool::new("test", 2));
+        let (done_tx, done_rx) = channel();
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+        let sinks = (0..TOPICS)
+            .map(|topic| {
+                let (data_tx, data_rx) = lossy_channel(MESSAGES as usize);
+                let callbacks = Arc::new(Callbacks::<ClockMsg>::new());
+                let received = Mutex::new(vec![]);
+                let done_tx = Mutex::new(done_tx.clone());
+                let threads = Arc::clone(&threads);
+                callbacks.add(move |message: Arc<ClockMsg>, _: &MessageEvent| {
+                    threads.lock().unwrap().insert(thread::current().id());
+                    let mut received = received.lock().unwrap();
+                    received.push(message.clock.sec);
+                    if received.len() == MESSAGES as usize {
+                        done_tx
+                            .lock()
+                            .unwrap()
+                            .send((topic, received.clone()))
+                            .unwrap();
+                    }
+                });
+                MessageSink {
+                    wake: start_dispatch(data_rx, callbacks, Some(Arc::clone(&pool))),
+                    queue: data_tx,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let caller_id = Arc::new(String::from("/talker"));
+        let feeders = sinks
+            .into_iter()
+            .map(|sink| {
+                let caller_id = Arc::clone(&caller_id);
+                thread::spawn(move || {
+                    for sec in 0..MESSAGES {
+                        let message = ClockMsg {
+                            clock: Time { sec, nsec: 0 },
+                        };
+                        let data = message.encode_vec().expect(FAILED_TO_READ_WRITE_VECTOR);
+                        let info = MessageInfo::new(Arc::clone(&caller_id), Time::new(), data);
+                        sink.try_send(info).expect(FAILED_TO_READ_WRITE_VECTOR);
+                    }
+                    sink
+                })
+            })
+            .collect::<Vec<_>>();
+        let _sinks = feeders
+            .into_iter()
+            .map(|feeder| feeder.join().unwrap())
+            .collect::<Vec<_>>();
+
+        let mut finished = BTreeSet::new();
+        for _ in 0..TOPICS {
+            let (topic, received) = done_rx
+                .recv_timeout(std::time::Duration::from_secs(5))
+                .unwrap();
+            assert_eq!((0..MESSAGES).collect::<Vec<_>>(), received);
+            finished.insert(topic);
+        }
+        assert_eq!(TOPICS, finished.len());
+        assert!(threads.lock().unwrap().len() <= 2);
+    }
+
     #[test]
     fn package_to_vector_creates_right_buffer_from_reader() {
         let input = [7, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7];
@@ -271,6 +1678,13 @@ mod tests {
         package_to_vector(&mut std::io::Cursor::new(input)).unwrap_err();
     }
 
+    #[test]
+    fn package_to_vector_rejects_oversized_length() {
+        let input = [0xff, 0xff, 0xff, 0xff, 1, 2, 3];
+        let err = package_to_vector(&mut std::io::Cursor::new(input)).unwrap_err();
+        assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+    }
+
     #[test]
     fn package_to_vector_fails_leaves_cursor_at_end_of_reading() {
         let input = [7, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 4, 0, 0, 0, 11, 12, 13, 14];
@@ -280,4 +1694,32 @@ mod tests {
         let data = package_to_vector(&mut cursor).expect(FAILED_TO_READ_WRITE_VECTOR);
         assert_eq!(data, [4, 0, 0, 0, 11, 12, 13, 14]);
     }
+
+    #[test]
+    fn package_to_vector_reassembles_large_message_from_chunks() {
+        let body = (0..100_000u32).map(|v| v as u8).collect::<Vec<u8>>();
+        let input = length_prefixed(&body);
+        let mut reported = vec![];
+        let data =
+            package_to_vector_chunked(&mut std::io::Cursor::new(&input), 4096, |read, total| {
+                reported.push((read, total));
+                true
+            })
+            .expect(FAILED_TO_READ_WRITE_VECTOR);
+        assert_eq!(input, data);
+        assert_eq!(25, reported.len());
+        assert!(reported.iter().all(|&(_, total)| total == input.len()));
+        assert_eq!((4100, input.len()), reported[0]);
+        assert_eq!(input.len(), reported[24].0);
+    }
+
+    #[test]
+    fn package_to_vector_can_be_aborted_between_chunks() {
+        let input = length_prefixed(&[0; 10_000]);
+        let mut cursor = std::io::Cursor::new(&input);
+        let err = package_to_vector_chunked(&mut cursor, 1024, |read, _| read < 3000).unwrap_err();
+        assert_eq!(std::io::ErrorKind::ConnectionAborted, err.kind());
+        // Nothing beyond the chunk that triggered the abort gets read
+        assert_eq!(4 + 3 * 1024, cursor.position() as usize);
+    }
 }
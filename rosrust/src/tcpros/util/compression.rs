@@ -0,0 +1,186 @@
+//! Opt-in compression of topic messages, negotiated between two rosrust nodes.
+//!
+//! This is an extension to TCPROS. Subscribers that support it list the
+//! algorithm in the `transport_compression` field of their connection header,
+//! and publishers that support it too confirm it in their response. Other
+//! ROS clients ignore the field, so their connections stay uncompressed.
+//!
+//! Compressed messages keep the usual length prefix, followed by the length
+//! of the uncompressed message and its data as an LZ4 block.
+
+use crate::rosmsg::check_message_size;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Connection header field used for negotiating compression.
+pub const HEADER_FIELD: &str = "transport_compression";
+
+/// Name of the only supported algorithm.
+pub const LZ4: &str = "lz4";
+
+static TRANSPORT_COMPRESSION: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether publishers and subscribers created afterwards offer compression.
+///
+/// Compression is only used on connections where both sides offer it, which
+/// requires both nodes to use rosrust. It is disabled by default.
+#[inline]
+pub fn set_transport_compression(enabled: bool) {
+    TRANSPORT_COMPRESSION.store(enabled, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn transport_compression() -> bool {
+    TRANSPORT_COMPRESSION.load(Ordering::Relaxed)
+}
+
+/// Whether the peer's connection header offers or confirms compression.
+pub fn is_offered(fields: &HashMap<String, String>) -> bool {
+    fields
+        .get(HEADER_FIELD)
+        .is_some_and(|algorithms| algorithms.split(',').any(|v| v == LZ4))
+}
+
+/// Compresses a message, given with its length prefix.
+pub fn compress(message: &[u8]) -> io::Result<Vec<u8>> {
+    let body = message.get(4..).unwrap_or_default();
+    let data = lz4_flex::block::compress(body);
+    let mut output = Vec::with_capacity(data.len() + 8);
+    output.write_u32::<LittleEndian>((data.len() + 4) as u32)?;
+    output.write_u32::<LittleEndian>(body.len() as u32)?;
+    output.extend_from_slice(&data);
+    Ok(output)
+}
+
+/// Restores a compressed message, given with its length prefix.
+///
+/// The announced uncompressed length is checked against the message size
+/// limit, and decompression cannot produce more data than announced.
+pub fn decompress(message: &[u8]) -> io::Result<Vec<u8>> {
+    let mut data = message.get(4..).unwrap_or_default();
+    let length = data.read_u32::<LittleEndian>()? as usize;
+    check_message_size(length)?;
+    let body = lz4_flex::block::decompress(data, length)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    if body.len() != length {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Decompressed {} bytes instead of the announced {}",
+                body.len(),
+                length
+            ),
+        ));
+    }
+    let mut output = Vec::with_capacity(length + 4);
+    output.write_u32::<LittleEndian>(length as u32)?;
+    output.extend_from_slice(&body);
+    Ok(output)
+}
+
+/// Writer that compresses messages before passing them on.
+///
+/// Written data is split into messages by their length prefixes, and every
+/// message is compressed separately once all of it has been written.
+pub struct Compressor<W> {
+    inner: W,
+    pending: Vec<u8>,
+}
+
+impl<W: io::Write> Compressor<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            pending: Vec::new(),
+        }
+    }
+
+    fn next_message_length(&self) -> Option<usize> {
+        let length = (&self.pending[..]).read_u32::<LittleEndian>().ok()? as usize + 4;
+        if self.pending.len() < length {
+            return None;
+        }
+        Some(length)
+    }
+}
+
+impl<W: io::Write> io::Write for Compressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        while let Some(length) = self.next_message_length() {
+            self.inner.write_all(&compress(&self.pending[..length])?)?;
+            self.pending.drain(..length);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rosmsg::RosMsg;
+    use std::io::Write;
+
+    static FAILED_TO_COMPRESS: &'static str = "Failed to compress";
+
+    #[test]
+    fn restores_compressed_messages() {
+        let message = "repeated text ".repeat(100).encode_vec().unwrap();
+        let compressed = compress(&message).expect(FAILED_TO_COMPRESS);
+        assert!(compressed.len() < message.len());
+        let length = (&compressed[..4]).read_u32::<LittleEndian>().unwrap();
+        assert_eq!(compressed.len() - 4, length as usize);
+        assert_eq!(message, decompress(&compressed).expect(FAILED_TO_COMPRESS));
+
+        let mut output = vec![];
+        Compressor::new(&mut output)
+            .write_all(&message)
+            .expect(FAILED_TO_COMPRESS);
+        assert_eq!(compressed, output);
+    }
+
+    #[test]
+    fn compresses_messages_split_across_writes() {
+        let first = "first message ".repeat(20).encode_vec().unwrap();
+        let second = "second message ".repeat(20).encode_vec().unwrap();
+        let mut data = first.clone();
+        data.extend_from_slice(&second);
+
+        let mut output = vec![];
+        let mut compressor = Compressor::new(&mut output);
+        for chunk in data.chunks(7) {
+            compressor.write_all(chunk).expect(FAILED_TO_COMPRESS);
+        }
+
+        let mut expected = compress(&first).expect(FAILED_TO_COMPRESS);
+        expected.extend(compress(&second).expect(FAILED_TO_COMPRESS));
+        assert_eq!(expected, output);
+    }
+
+    #[test]
+    fn rejects_wrong_announced_length() {
+        let message = "text".repeat(100).encode_vec().unwrap();
+        let mut compressed = compress(&message).expect(FAILED_TO_COMPRESS);
+        compressed[4] -= 1;
+        assert!(decompress(&compressed).is_err());
+        compressed[4] += 2;
+        assert!(decompress(&compressed).is_err());
+        assert!(decompress(&compressed[..6]).is_err());
+    }
+
+    #[test]
+    fn detects_offered_algorithm() {
+        let mut fields = HashMap::new();
+        assert!(!is_offered(&fields));
+        fields.insert(String::from(HEADER_FIELD), String::from("deflate,lz4"));
+        assert!(is_offered(&fields));
+        fields.insert(String::from(HEADER_FIELD), String::from("deflate"));
+        assert!(!is_offered(&fields));
+    }
+}
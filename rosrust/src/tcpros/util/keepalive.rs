@@ -0,0 +1,171 @@
+//! TCP keepalive of topic and service connections.
+//!
+//! Keepalive probes keep idle connections open through NAT and firewall
+//! timeouts, and detect peers that disappeared without closing them.
+
+use net2::TcpStreamExt;
+use std::convert::TryFrom;
+use std::io;
+use std::net::TcpStream;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+/// Keepalive probing of a node's TCP connections.
+///
+/// Probes start once a connection has been idle for the idle time. The
+/// interval between probes and the number of unanswered probes before the
+/// connection is dropped are left to the operating system, unless set.
+/// All durations are counted in whole seconds, and are at least one second.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TcpKeepalive {
+    idle: Duration,
+    interval: Option<Duration>,
+    count: Option<u32>,
+}
+
+impl TcpKeepalive {
+    #[inline]
+    pub fn new(idle: Duration) -> Self {
+        Self {
+            idle: whole_seconds(idle),
+            interval: None,
+            count: None,
+        }
+    }
+
+    /// Sets the time between probes, once they started.
+    #[inline]
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(whole_seconds(interval));
+        self
+    }
+
+    /// Sets how many probes may go unanswered before the connection is dropped.
+    #[inline]
+    pub fn with_count(mut self, count: u32) -> Self {
+        self.count = Some(count.max(1));
+        self
+    }
+
+    #[inline]
+    pub fn idle(&self) -> Duration {
+        self.idle
+    }
+
+    #[inline]
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    #[inline]
+    pub fn count(&self) -> Option<u32> {
+        self.count
+    }
+}
+
+#[inline]
+fn whole_seconds(duration: Duration) -> Duration {
+    Duration::from_secs(duration.as_secs().max(1))
+}
+
+/// Enables keepalive on the connection, if it has been configured.
+pub fn apply(stream: &TcpStream, keepalive: Option<&TcpKeepalive>) -> io::Result<()> {
+    let keepalive = match keepalive {
+        Some(keepalive) => keepalive,
+        None => return Ok(()),
+    };
+    stream.set_keepalive(Some(keepalive.idle))?;
+    if let Some(interval) = keepalive.interval {
+        set_tcp_option(stream, libc::TCP_KEEPINTVL, interval.as_secs())?;
+    }
+    if let Some(count) = keepalive.count {
+        set_tcp_option(stream, libc::TCP_KEEPCNT, u64::from(count))?;
+    }
+    Ok(())
+}
+
+fn set_tcp_option(stream: &TcpStream, option: libc::c_int, value: u64) -> io::Result<()> {
+    let value = libc::c_int::try_from(value).unwrap_or(libc::c_int::MAX);
+    let result = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            option,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Reads back the keepalive settings of a connection, if keepalive is enabled.
+#[cfg(test)]
+pub fn configured(stream: &TcpStream) -> io::Result<Option<TcpKeepalive>> {
+    let idle = match stream.keepalive()? {
+        Some(idle) => idle,
+        None => return Ok(None),
+    };
+    let interval = get_tcp_option(stream, libc::TCP_KEEPINTVL)?;
+    let count = get_tcp_option(stream, libc::TCP_KEEPCNT)?;
+    Ok(Some(
+        TcpKeepalive::new(idle)
+            .with_interval(Duration::from_secs(interval as u64))
+            .with_count(count as u32),
+    ))
+}
+
+#[cfg(test)]
+fn get_tcp_option(stream: &TcpStream, option: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut length = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            option,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn enables_keepalive_on_connected_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        apply(&client, None).unwrap();
+        assert_eq!(None, configured(&client).unwrap());
+
+        let keepalive = TcpKeepalive::new(Duration::from_secs(30))
+            .with_interval(Duration::from_secs(5))
+            .with_count(3);
+        apply(&client, Some(&keepalive)).unwrap();
+        apply(&server, Some(&keepalive)).unwrap();
+        assert_eq!(Some(keepalive), configured(&client).unwrap());
+        assert_eq!(Some(keepalive), configured(&server).unwrap());
+    }
+
+    #[test]
+    fn rounds_durations_to_whole_seconds() {
+        let keepalive = TcpKeepalive::new(Duration::from_millis(1500))
+            .with_interval(Duration::from_millis(200))
+            .with_count(0);
+        assert_eq!(Duration::from_secs(1), keepalive.idle());
+        assert_eq!(Some(Duration::from_secs(1)), keepalive.interval());
+        assert_eq!(Some(1), keepalive.count());
+    }
+}
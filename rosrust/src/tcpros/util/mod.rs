@@ -1,2 +1,6 @@
+pub mod compression;
+pub mod keepalive;
+pub mod stats;
 pub mod streamfork;
 pub mod tcpconnection;
+pub mod workerpool;
@@ -0,0 +1,210 @@
+use crate::time::Time;
+use crate::util::FAILED_TO_LOCK;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+static NEXT_CONNECTION_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Traffic counters of a single topic connection, shared with the thread serving it.
+#[derive(Debug)]
+pub struct ConnectionStats {
+    id: usize,
+    bytes: AtomicUsize,
+    messages: AtomicUsize,
+    timing: Mutex<Timing>,
+}
+
+impl ConnectionStats {
+    pub fn new() -> Self {
+        Self {
+            id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            bytes: AtomicUsize::new(0),
+            messages: AtomicUsize::new(0),
+            timing: Mutex::new(Timing::default()),
+        }
+    }
+
+    /// Adds a received message to the current statistics window.
+    ///
+    /// The sequence number and stamp are taken from the message header, if it has one.
+    pub fn record_receipt(&self, bytes: usize, receipt_time: Time, header: Option<(u32, Time)>) {
+        self.timing
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .record(bytes, receipt_time, header);
+    }
+
+    /// Returns the statistics of the current window, and starts a new one.
+    pub fn take_window(&self) -> WindowStats {
+        let mut timing = self.timing.lock().expect(FAILED_TO_LOCK);
+        std::mem::take(&mut timing.window)
+    }
+
+    /// Counts a message of the given size, including its length prefix.
+    #[inline]
+    pub fn record(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn info(&self, peer: &str) -> ConnectionInfo {
+        ConnectionInfo {
+            id: self.id,
+            peer: peer.into(),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            messages: self.messages.load(Ordering::Relaxed),
+            transport: "TCPROS",
+        }
+    }
+}
+
+impl Default for ConnectionStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Timing {
+    window: WindowStats,
+    last_receipt: Option<Time>,
+    last_seq: Option<u32>,
+}
+
+impl Timing {
+    fn record(&mut self, bytes: usize, receipt_time: Time, header: Option<(u32, Time)>) {
+        let window = &mut self.window;
+        window.delivered += 1;
+        window.traffic += bytes;
+        if let Some(last_receipt) = self.last_receipt {
+            window.period.add((receipt_time - last_receipt).seconds());
+        }
+        self.last_receipt = Some(receipt_time);
+        if let Some((seq, stamp)) = header {
+            if let Some(last_seq) = self.last_seq {
+                let skipped = seq.wrapping_sub(last_seq).wrapping_sub(1);
+                // Sequence numbers going backwards mean the publisher restarted
+                if skipped < u32::MAX / 2 {
+                    window.dropped += skipped as usize;
+                }
+            }
+            self.last_seq = Some(seq);
+            window.age.add((receipt_time - stamp).seconds());
+        }
+    }
+}
+
+/// Messages received on a connection during a statistics window.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WindowStats {
+    pub delivered: usize,
+    /// Messages missing from the header sequence numbers.
+    pub dropped: usize,
+    pub traffic: usize,
+    /// Time between receipts of consecutive messages, in seconds.
+    pub period: Aggregate,
+    /// Time between the header stamp and receipt of messages, in seconds.
+    pub age: Aggregate,
+}
+
+/// Mean, standard deviation and maximum of a series of values.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Aggregate {
+    count: usize,
+    sum: f64,
+    sum_of_squares: f64,
+    max: f64,
+}
+
+impl Aggregate {
+    fn add(&mut self, value: f64) {
+        self.max = if self.count == 0 {
+            value
+        } else {
+            self.max.max(value)
+        };
+        self.count += 1;
+        self.sum += value;
+        self.sum_of_squares += value * value;
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sum / self.count as f64
+    }
+
+    pub fn stddev(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        (self.sum_of_squares / self.count as f64 - mean * mean)
+            .max(0.0)
+            .sqrt()
+    }
+
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+}
+
+/// Snapshot of a live topic connection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionInfo {
+    /// Identifier unique among all connections of the node.
+    pub id: usize,
+    /// Caller ID of a subscriber, or URI of a publisher.
+    pub peer: String,
+    pub bytes: usize,
+    pub messages: usize,
+    /// Protocol the messages are carried over, like `TCPROS`.
+    pub transport: &'static str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_bytes_and_messages() {
+        let stats = ConnectionStats::new();
+        stats.record(12);
+        stats.record(30);
+        let info = stats.info("/listener");
+        assert_eq!("/listener", info.peer);
+        assert_eq!(42, info.bytes);
+        assert_eq!(2, info.messages);
+    }
+
+    #[test]
+    fn aggregates_timing_per_window() {
+        let stats = ConnectionStats::new();
+        let at = |millis: i64| Time::from_nanos(1_000_000_000 + millis * 1_000_000);
+        stats.record_receipt(10, at(0), Some((4, at(-5))));
+        stats.record_receipt(10, at(100), Some((5, at(90))));
+        stats.record_receipt(20, at(300), Some((8, at(290))));
+
+        let window = stats.take_window();
+        assert_eq!(3, window.delivered);
+        assert_eq!(2, window.dropped);
+        assert_eq!(40, window.traffic);
+        assert!((window.period.mean() - 0.15).abs() < 1e-9);
+        assert!((window.period.stddev() - 0.05).abs() < 1e-9);
+        assert!((window.period.max() - 0.2).abs() < 1e-9);
+        assert!((window.age.mean() - 0.025 / 3.0).abs() < 1e-9);
+        assert!((window.age.max() - 0.01).abs() < 1e-9);
+
+        stats.record_receipt(5, at(350), None);
+        let window = stats.take_window();
+        assert_eq!(1, window.delivered);
+        assert!((window.period.mean() - 0.05).abs() < 1e-9);
+        assert_eq!(0.0, window.age.mean());
+    }
+
+    #[test]
+    fn assigns_unique_ids() {
+        assert_ne!(ConnectionStats::new().id, ConnectionStats::new().id);
+    }
+}
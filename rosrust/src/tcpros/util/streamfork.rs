@@ -1,57 +1,109 @@
+use super::stats::{ConnectionInfo, ConnectionStats};
 use crate::util::lossy_channel::{lossy_channel, LossyReceiver, LossySender};
 use crate::util::FAILED_TO_LOCK;
 use crossbeam::channel::{self, unbounded, Receiver, Sender};
-use std::io::Write;
+use log::error;
+use std::io::{self, Write};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
 pub fn fork<T: Write + Send + 'static>(queue_size: usize) -> (TargetList<T>, DataStream) {
     let (streams_sender, streams) = unbounded();
     let (data_sender, data) = lossy_channel(queue_size);
+    let callbacks = ConnectionCallbacks::default();
 
-    let mut fork_thread = ForkThread::new();
+    let mut fork_thread = ForkThread::new(callbacks.clone());
     let target_names = Arc::clone(&fork_thread.target_names);
 
     thread::spawn(move || fork_thread.run(&streams, &data));
 
     (
-        TargetList(streams_sender),
+        TargetList {
+            sender: streams_sender,
+            callbacks: callbacks.clone(),
+        },
         DataStream {
             sender: data_sender,
             target_names,
+            callbacks,
         },
     )
 }
 
+pub type ConnectionCallback = Arc<dyn Fn(&str) + Send + Sync + 'static>;
+
+pub type PublishErrorCallback = Arc<dyn Fn(&str, &io::Error) + Send + Sync + 'static>;
+
+#[derive(Clone, Default)]
+pub struct ConnectionCallbacks {
+    on_connect: Arc<Mutex<Vec<ConnectionCallback>>>,
+    on_disconnect: Arc<Mutex<Vec<ConnectionCallback>>>,
+    on_publish_error: Arc<Mutex<Vec<PublishErrorCallback>>>,
+}
+
+impl ConnectionCallbacks {
+    fn call(callbacks: &Mutex<Vec<ConnectionCallback>>, caller_id: &str) {
+        // Callbacks are copied out so they can freely register more callbacks
+        let callbacks = callbacks.lock().expect(FAILED_TO_LOCK).clone();
+        for callback in callbacks {
+            callback(caller_id);
+        }
+    }
+
+    fn call_publish_error(&self, caller_id: &str, err: &io::Error) {
+        let callbacks = self.on_publish_error.lock().expect(FAILED_TO_LOCK).clone();
+        for callback in callbacks {
+            callback(caller_id, err);
+        }
+    }
+}
+
 struct ForkThread<T: Write + Send + 'static> {
     targets: Vec<SubscriberInfo<T>>,
+    /// Messages written to all targets before flushing them, kept to reuse its allocation.
+    pending: Vec<Arc<Vec<u8>>>,
     target_names: Arc<Mutex<TargetNames>>,
+    callbacks: ConnectionCallbacks,
 }
 
 impl<T: Write + Send + 'static> ForkThread<T> {
-    pub fn new() -> Self {
+    pub fn new(callbacks: ConnectionCallbacks) -> Self {
         Self {
             targets: vec![],
+            pending: vec![],
             target_names: Arc::new(Mutex::new(TargetNames {
                 targets: Vec::new(),
             })),
+            callbacks,
         }
     }
 
-    fn publish_buffer_and_prune_targets(&mut self, buffer: &[u8]) {
+    fn publish_batch_and_prune_targets(&mut self, batch: &[Arc<Vec<u8>>]) {
         let mut dropped_targets = vec![];
         for (idx, target) in self.targets.iter_mut().enumerate() {
-            if target.stream.write_all(&buffer).is_err() {
-                dropped_targets.push(idx);
+            if let Err(err) = target.write_batch(batch) {
+                // Closed connections are the usual way subscribers leave
+                if !is_closed_connection(&err) {
+                    error!(
+                        "Failed to send message to subscriber '{}': {}",
+                        target.caller_id, err
+                    );
+                }
+                dropped_targets.push((idx, err));
             }
         }
 
         if !dropped_targets.is_empty() {
+            let mut dropped = vec![];
             // We reverse the order, to remove bigger indices first.
-            for idx in dropped_targets.into_iter().rev() {
-                self.targets.swap_remove(idx);
+            for (idx, err) in dropped_targets.into_iter().rev() {
+                dropped.push((self.targets.swap_remove(idx).caller_id, err));
             }
             self.update_target_names();
+            for (caller_id, err) in dropped {
+                self.callbacks.call_publish_error(&caller_id, &err);
+                ConnectionCallbacks::call(&self.callbacks.on_disconnect, &caller_id);
+            }
         }
     }
 
@@ -64,7 +116,7 @@ impl<T: Write + Send + 'static> ForkThread<T> {
         let targets = self
             .targets
             .iter()
-            .map(|target| target.caller_id.clone())
+            .map(|target| (target.caller_id.clone(), Arc::clone(&target.stats)))
             .collect();
         *self.target_names.lock().expect(FAILED_TO_LOCK) = TargetNames { targets };
     }
@@ -79,7 +131,18 @@ impl<T: Write + Send + 'static> ForkThread<T> {
                 return msg.and(Err(channel::RecvError));
             }
             recv(data.data_rx) -> msg => {
-                self.publish_buffer_and_prune_targets(&msg?);
+                // Targets that connected before the data was sent should receive it,
+                // which matters for data sent from within connection callbacks
+                for target in streams.try_iter() {
+                    self.add_target(target);
+                }
+                // Messages queued together, like batches, get flushed together
+                let mut pending = std::mem::take(&mut self.pending);
+                pending.push(msg?);
+                pending.extend(data.data_rx.try_iter());
+                self.publish_batch_and_prune_targets(&pending);
+                pending.clear();
+                self.pending = pending;
             }
             recv(streams) -> target => {
                 self.add_target(target?);
@@ -97,27 +160,57 @@ impl<T: Write + Send + 'static> ForkThread<T> {
     }
 }
 
+#[inline]
+pub fn is_closed_connection(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+    )
+}
+
 pub type ForkResult = Result<(), ()>;
 
-pub struct TargetList<T: Write + Send + 'static>(Sender<SubscriberInfo<T>>);
+pub struct TargetList<T: Write + Send + 'static> {
+    sender: Sender<SubscriberInfo<T>>,
+    callbacks: ConnectionCallbacks,
+}
 
 impl<T: Write + Send + 'static> TargetList<T> {
-    pub fn add(&self, caller_id: String, stream: T) -> ForkResult {
-        self.0
-            .send(SubscriberInfo { caller_id, stream })
-            .or(Err(()))
+    pub fn add(&self, caller_id: String, stream: T, stats: ConnectionStats) -> ForkResult {
+        self.sender
+            .send(SubscriberInfo {
+                caller_id: caller_id.clone(),
+                stream,
+                stats: Arc::new(stats),
+            })
+            .or(Err(()))?;
+        ConnectionCallbacks::call(&self.callbacks.on_connect, &caller_id);
+        Ok(())
     }
 }
 
 struct SubscriberInfo<T> {
     caller_id: String,
     stream: T,
+    stats: Arc<ConnectionStats>,
+}
+
+impl<T: Write> SubscriberInfo<T> {
+    /// Writes every message separately, since wrapped streams may expect that.
+    fn write_batch(&mut self, batch: &[Arc<Vec<u8>>]) -> io::Result<()> {
+        for message in batch {
+            self.stream.write_all(message)?;
+            self.stats.record(message.len());
+        }
+        self.stream.flush()
+    }
 }
 
 #[derive(Clone)]
 pub struct DataStream {
     sender: LossySender<Arc<Vec<u8>>>,
     target_names: Arc<Mutex<TargetNames>>,
+    callbacks: ConnectionCallbacks,
 }
 
 impl DataStream {
@@ -125,6 +218,14 @@ impl DataStream {
         self.sender.try_send(data).or(Err(()))
     }
 
+    /// Sends the messages together, each of them taking a place in the queue.
+    pub fn send_batch<I>(&self, batch: I) -> ForkResult
+    where
+        I: IntoIterator<Item = Arc<Vec<u8>>>,
+    {
+        self.sender.try_send_all(batch).or(Err(()))
+    }
+
     #[inline]
     pub fn target_count(&self) -> usize {
         self.target_names.lock().expect(FAILED_TO_LOCK).count()
@@ -135,6 +236,41 @@ impl DataStream {
         self.target_names.lock().expect(FAILED_TO_LOCK).names()
     }
 
+    #[inline]
+    pub fn target_connections(&self) -> Vec<ConnectionInfo> {
+        self.target_names
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .connections()
+    }
+
+    #[inline]
+    pub fn add_connect_callback(&self, callback: ConnectionCallback) {
+        self.callbacks
+            .on_connect
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .push(callback);
+    }
+
+    #[inline]
+    pub fn add_disconnect_callback(&self, callback: ConnectionCallback) {
+        self.callbacks
+            .on_disconnect
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .push(callback);
+    }
+
+    #[inline]
+    pub fn add_publish_error_callback(&self, callback: PublishErrorCallback) {
+        self.callbacks
+            .on_publish_error
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .push(callback);
+    }
+
     #[inline]
     pub fn set_queue_size(&self, queue_size: usize) {
         self.sender.set_queue_size(queue_size);
@@ -148,7 +284,7 @@ impl DataStream {
 
 #[derive(Debug)]
 pub struct TargetNames {
-    targets: Vec<String>,
+    targets: Vec<(String, Arc<ConnectionStats>)>,
 }
 
 impl TargetNames {
@@ -159,6 +295,17 @@ impl TargetNames {
 
     #[inline]
     pub fn names(&self) -> Vec<String> {
-        self.targets.clone()
+        self.targets
+            .iter()
+            .map(|(caller_id, _)| caller_id.clone())
+            .collect()
+    }
+
+    #[inline]
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        self.targets
+            .iter()
+            .map(|(caller_id, stats)| stats.info(caller_id))
+            .collect()
     }
 }
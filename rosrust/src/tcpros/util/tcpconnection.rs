@@ -1,3 +1,4 @@
+use super::keepalive::{self, TcpKeepalive};
 use log::error;
 use std::net::{TcpListener, TcpStream};
 use std::thread;
@@ -8,26 +9,64 @@ pub enum Feedback {
     StopAccepting,
 }
 
-pub fn iterate<F>(listener: TcpListener, tag: String, handler: F)
+/// Hands accepted connections to the handler, with keepalive enabled if given.
+pub fn iterate<F>(listener: TcpListener, tag: String, keepalive: Option<TcpKeepalive>, handler: F)
 where
     F: Fn(TcpStream) -> Feedback + Send + 'static,
 {
-    thread::spawn(move || listener_thread(&listener, &tag, handler));
+    thread::spawn(move || listener_thread(&listener, &tag, keepalive.as_ref(), handler));
 }
 
-fn listener_thread<F>(connections: &TcpListener, tag: &str, handler: F)
-where
+fn listener_thread<F>(
+    connections: &TcpListener,
+    tag: &str,
+    keepalive: Option<&TcpKeepalive>,
+    handler: F,
+) where
     F: Fn(TcpStream) -> Feedback + Send + 'static,
 {
     for stream in connections.incoming() {
         match stream {
-            Ok(stream) => match handler(stream) {
-                Feedback::AcceptNextStream => {}
-                Feedback::StopAccepting => break,
-            },
+            Ok(stream) => {
+                if let Err(err) = keepalive::apply(&stream, keepalive) {
+                    error!("Failed to enable TCP keepalive at {}: {}", tag, err);
+                }
+                match handler(stream) {
+                    Feedback::AcceptNextStream => {}
+                    Feedback::StopAccepting => break,
+                }
+            }
             Err(err) => {
                 error!("TCP connection failed at {}: {}", tag, err);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[test]
+    fn accepted_connections_get_configured_keepalive() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let keepalive = TcpKeepalive::new(Duration::from_secs(30))
+            .with_interval(Duration::from_secs(5))
+            .with_count(3);
+        let (settings_tx, settings_rx) = channel();
+        let settings_tx = Mutex::new(settings_tx);
+        iterate(listener, "test".into(), Some(keepalive), move |stream| {
+            let settings = keepalive::configured(&stream).unwrap();
+            settings_tx.lock().unwrap().send(settings).unwrap();
+            Feedback::StopAccepting
+        });
+
+        let _client = TcpStream::connect(address).unwrap();
+        let settings = settings_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(Some(keepalive), settings);
+    }
+}
@@ -0,0 +1,86 @@
+use crossbeam::channel::{unbounded, Sender};
+use log::error;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Fixed number of threads running queued jobs in order of arrival.
+///
+/// Jobs submitted while all workers are busy wait in the queue. Workers exit
+/// once the pool is dropped and the queue has been drained.
+pub struct WorkerPool {
+    jobs: Sender<Job>,
+}
+
+impl WorkerPool {
+    pub fn new(tag: &str, workers: usize) -> WorkerPool {
+        let (jobs, queue) = unbounded::<Job>();
+        for _ in 0..workers.max(1) {
+            let queue = queue.clone();
+            let tag = String::from(tag);
+            thread::spawn(move || {
+                for job in queue {
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        error!("Job panicked in worker of {}", tag);
+                    }
+                }
+            });
+        }
+        WorkerPool { jobs }
+    }
+
+    pub fn execute<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // Workers only stop receiving after the pool is dropped
+        if self.jobs.send(Box::new(job)).is_err() {
+            error!("All workers of pool have stopped");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn runs_all_jobs_with_bounded_concurrency() {
+        let pool = WorkerPool::new("test", 3);
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (done_tx, done_rx) = channel();
+        for index in 0..10 {
+            let running = Arc::clone(&running);
+            let peak = Arc::clone(&peak);
+            let done_tx = done_tx.clone();
+            pool.execute(move || {
+                let current = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(current, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                done_tx.send(index).unwrap();
+            });
+        }
+        let mut finished = (0..10)
+            .map(|_| done_rx.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect::<Vec<_>>();
+        finished.sort();
+        assert_eq!((0..10).collect::<Vec<_>>(), finished);
+        assert_eq!(3, peak.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn survives_panicking_job() {
+        let pool = WorkerPool::new("test", 1);
+        pool.execute(|| panic!("job failure"));
+        let (done_tx, done_rx) = channel();
+        pool.execute(move || done_tx.send(()).unwrap());
+        done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+}
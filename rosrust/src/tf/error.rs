@@ -0,0 +1,17 @@
+#![allow(deprecated)]
+error_chain! {
+    errors {
+        TransformUnavailable(target: String, source: String) {
+            description("No transform between the frames is available")
+            display("No transform from frame '{}' to frame '{}' is available", source, target)
+        }
+        ExtrapolationIntoFuture(frame: String, requested: f64, latest: f64) {
+            description("Lookup would require extrapolation into the future")
+            display("Lookup of frame '{}' at {:.3}s would require extrapolation into the future, latest data is at {:.3}s", frame, requested, latest)
+        }
+        ExtrapolationIntoPast(frame: String, requested: f64, earliest: f64) {
+            description("Lookup would require extrapolation into the past")
+            display("Lookup of frame '{}' at {:.3}s would require extrapolation into the past, earliest data is at {:.3}s", frame, requested, earliest)
+        }
+    }
+}
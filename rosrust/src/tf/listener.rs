@@ -0,0 +1,433 @@
+use super::error::{ErrorKind, Result};
+use super::msg::{Quaternion, TFMessage, Transform, TransformStamped, Vector3};
+use crate::api::raii::Subscriber;
+use crate::api::Ros;
+use crate::util::FAILED_TO_LOCK;
+use crate::{Duration, Time};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Time-indexed history of the transforms of every frame to its parent.
+///
+/// Dynamic transforms are kept for the cache time, counted back from the
+/// newest transform of each frame. Static transforms are valid at any time.
+pub struct TransformBuffer {
+    cache_time: Duration,
+    dynamic: HashMap<String, Vec<TransformStamped>>,
+    fixed: HashMap<String, TransformStamped>,
+}
+
+impl Default for TransformBuffer {
+    fn default() -> Self {
+        Self::new(Duration::from_seconds(10))
+    }
+}
+
+impl TransformBuffer {
+    pub fn new(cache_time: Duration) -> Self {
+        Self {
+            cache_time,
+            dynamic: HashMap::new(),
+            fixed: HashMap::new(),
+        }
+    }
+
+    /// Stores the transform from its `child_frame_id` to its `frame_id`.
+    ///
+    /// A dynamic transform to a different parent than before drops the
+    /// frame's history, since transforms to different parents don't mix.
+    pub fn set_transform(&mut self, transform: TransformStamped, is_static: bool) {
+        if is_static {
+            self.fixed
+                .insert(transform.child_frame_id.clone(), transform);
+            return;
+        }
+        let cache_time = self.cache_time;
+        let samples = self
+            .dynamic
+            .entry(transform.child_frame_id.clone())
+            .or_default();
+        let parent = &transform.header.frame_id;
+        if samples
+            .first()
+            .is_some_and(|sample| &sample.header.frame_id != parent)
+        {
+            samples.clear();
+        }
+        let stamp = transform.header.stamp;
+        // Samples are sorted by time, and a new sample replaces one of the same time
+        match samples.binary_search_by(|sample| sample.header.stamp.cmp(&stamp)) {
+            Ok(index) => samples[index] = transform,
+            Err(index) => samples.insert(index, transform),
+        }
+        if let Some(oldest) = samples
+            .last()
+            .and_then(|newest| newest.header.stamp.checked_sub(cache_time))
+        {
+            samples.retain(|sample| sample.header.stamp >= oldest);
+        }
+    }
+
+    /// Looks up the transform from the `source` frame to the `target` frame.
+    ///
+    /// A zero `time` requests the latest available transform. Otherwise the
+    /// transform gets interpolated between the two samples closest in time.
+    ///
+    /// Only frames directly connected to each other can be looked up for now.
+    pub fn lookup_transform(
+        &self,
+        target: &str,
+        source: &str,
+        time: Time,
+    ) -> Result<TransformStamped> {
+        if target == source {
+            return Ok(stamped(target, source, time, identity()));
+        }
+        if let Some(transform) = self.parent_transform(source, target, time)? {
+            return Ok(stamped(target, source, transform.0, transform.1));
+        }
+        if let Some(transform) = self.parent_transform(target, source, time)? {
+            return Ok(stamped(target, source, transform.0, invert(&transform.1)));
+        }
+        bail!(ErrorKind::TransformUnavailable(
+            target.into(),
+            source.into()
+        ))
+    }
+
+    fn parent_transform(
+        &self,
+        child: &str,
+        parent: &str,
+        time: Time,
+    ) -> Result<Option<(Time, Transform)>> {
+        if let Some(transform) = self.fixed.get(child) {
+            if transform.header.frame_id == parent {
+                return Ok(Some((time, transform.transform.clone())));
+            }
+        }
+        let samples = match self.dynamic.get(child) {
+            Some(samples) => samples,
+            None => return Ok(None),
+        };
+        let (first, last) = match (samples.first(), samples.last()) {
+            (Some(first), Some(last)) if last.header.frame_id == parent => (first, last),
+            _ => return Ok(None),
+        };
+        if time == Time::new() {
+            return Ok(Some((last.header.stamp, last.transform.clone())));
+        }
+        if time > last.header.stamp {
+            bail!(ErrorKind::ExtrapolationIntoFuture(
+                child.into(),
+                time.seconds(),
+                last.header.stamp.seconds(),
+            ));
+        }
+        if time < first.header.stamp {
+            bail!(ErrorKind::ExtrapolationIntoPast(
+                child.into(),
+                time.seconds(),
+                first.header.stamp.seconds(),
+            ));
+        }
+        let index = match samples.binary_search_by(|sample| sample.header.stamp.cmp(&time)) {
+            Ok(index) => return Ok(Some((time, samples[index].transform.clone()))),
+            Err(index) => index,
+        };
+        let (before, after) = (&samples[index - 1], &samples[index]);
+        let ratio = (time - before.header.stamp).seconds()
+            / (after.header.stamp - before.header.stamp).seconds();
+        Ok(Some((
+            time,
+            interpolate(&before.transform, &after.transform, ratio),
+        )))
+    }
+}
+
+/// Fills a `TransformBuffer` with the transforms published to `/tf` and `/tf_static`.
+///
+/// The subscriptions last as long as the listener.
+pub struct TransformListener {
+    buffer: Arc<Mutex<TransformBuffer>>,
+    _dynamic_subscriber: Subscriber,
+    _static_subscriber: Subscriber,
+}
+
+impl TransformListener {
+    /// Subscribes on the node created by `rosrust::init`.
+    pub fn new() -> crate::api::error::Result<Self> {
+        let buffer = Arc::new(Mutex::new(TransformBuffer::default()));
+        Ok(Self {
+            _dynamic_subscriber: crate::subscribe("/tf", 100, inserter(&buffer, false))?,
+            _static_subscriber: crate::subscribe("/tf_static", 100, inserter(&buffer, true))?,
+            buffer,
+        })
+    }
+
+    /// Subscribes on the given node.
+    pub fn from_ros(ros: &Ros) -> crate::api::error::Result<Self> {
+        let buffer = Arc::new(Mutex::new(TransformBuffer::default()));
+        Ok(Self {
+            _dynamic_subscriber: ros.subscribe("/tf", 100, inserter(&buffer, false))?,
+            _static_subscriber: ros.subscribe("/tf_static", 100, inserter(&buffer, true))?,
+            buffer,
+        })
+    }
+
+    /// Looks up the transform from the `source` frame to the `target` frame.
+    ///
+    /// See `TransformBuffer::lookup_transform` for details.
+    pub fn lookup_transform(
+        &self,
+        target: &str,
+        source: &str,
+        time: Time,
+    ) -> Result<TransformStamped> {
+        self.buffer
+            .lock()
+            .expect(FAILED_TO_LOCK)
+            .lookup_transform(target, source, time)
+    }
+}
+
+fn inserter(
+    buffer: &Arc<Mutex<TransformBuffer>>,
+    is_static: bool,
+) -> impl Fn(TFMessage) + Send + 'static {
+    let buffer = Arc::clone(buffer);
+    move |message: TFMessage| {
+        let mut buffer = buffer.lock().expect(FAILED_TO_LOCK);
+        for transform in message.transforms {
+            buffer.set_transform(transform, is_static);
+        }
+    }
+}
+
+fn stamped(target: &str, source: &str, stamp: Time, transform: Transform) -> TransformStamped {
+    let mut stamped = TransformStamped {
+        child_frame_id: source.into(),
+        transform,
+        ..Default::default()
+    };
+    stamped.header.frame_id = target.into();
+    stamped.header.stamp = stamp;
+    stamped
+}
+
+fn identity() -> Transform {
+    Transform {
+        rotation: Quaternion {
+            w: 1.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn invert(transform: &Transform) -> Transform {
+    let q = &transform.rotation;
+    let rotation = Quaternion {
+        x: -q.x,
+        y: -q.y,
+        z: -q.z,
+        w: q.w,
+    };
+    let t = rotate(&rotation, &transform.translation);
+    Transform {
+        translation: Vector3 {
+            x: -t.x,
+            y: -t.y,
+            z: -t.z,
+        },
+        rotation,
+    }
+}
+
+fn rotate(q: &Quaternion, v: &Vector3) -> Vector3 {
+    // v + 2w(q × v) + 2q × (q × v), with q as the vector part of the quaternion
+    let c = Vector3 {
+        x: q.y * v.z - q.z * v.y,
+        y: q.z * v.x - q.x * v.z,
+        z: q.x * v.y - q.y * v.x,
+    };
+    Vector3 {
+        x: v.x + 2.0 * (q.w * c.x + q.y * c.z - q.z * c.y),
+        y: v.y + 2.0 * (q.w * c.y + q.z * c.x - q.x * c.z),
+        z: v.z + 2.0 * (q.w * c.z + q.x * c.y - q.y * c.x),
+    }
+}
+
+fn interpolate(a: &Transform, b: &Transform, ratio: f64) -> Transform {
+    let lerp = |a: f64, b: f64| a + (b - a) * ratio;
+    Transform {
+        translation: Vector3 {
+            x: lerp(a.translation.x, b.translation.x),
+            y: lerp(a.translation.y, b.translation.y),
+            z: lerp(a.translation.z, b.translation.z),
+        },
+        rotation: slerp(&a.rotation, &b.rotation, ratio),
+    }
+}
+
+fn slerp(a: &Quaternion, b: &Quaternion, ratio: f64) -> Quaternion {
+    let mut dot = a.x * b.x + a.y * b.y + a.z * b.z + a.w * b.w;
+    // Both signs describe the same rotation, and the positive one takes the shorter path
+    let sign = if dot < 0.0 { -1.0 } else { 1.0 };
+    dot *= sign;
+    let (scale_a, scale_b) = if dot > 0.9995 {
+        (1.0 - ratio, ratio)
+    } else {
+        let angle = dot.acos();
+        let sin = angle.sin();
+        (
+            ((1.0 - ratio) * angle).sin() / sin,
+            (ratio * angle).sin() / sin,
+        )
+    };
+    let scale_b = scale_b * sign;
+    let q = Quaternion {
+        x: scale_a * a.x + scale_b * b.x,
+        y: scale_a * a.y + scale_b * b.y,
+        z: scale_a * a.z + scale_b * b.z,
+        w: scale_a * a.w + scale_b * b.w,
+    };
+    let norm = (q.x * q.x + q.y * q.y + q.z * q.z + q.w * q.w).sqrt();
+    Quaternion {
+        x: q.x / norm,
+        y: q.y / norm,
+        z: q.z / norm,
+        w: q.w / norm,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::FRAC_PI_2;
+
+    fn sample(parent: &str, child: &str, sec: u32, x: f64, yaw: f64) -> TransformStamped {
+        let mut transform = stamped(parent, child, Time { sec, nsec: 0 }, identity());
+        transform.transform.translation.x = x;
+        transform.transform.rotation.z = (yaw / 2.0).sin();
+        transform.transform.rotation.w = (yaw / 2.0).cos();
+        transform
+    }
+
+    fn assert_close(expected: f64, actual: f64) {
+        assert!(
+            (expected - actual).abs() < 1e-9,
+            "{} != {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn interpolates_between_nearest_samples() {
+        let mut buffer = TransformBuffer::default();
+        buffer.set_transform(sample("odom", "base_link", 12, 3.0, FRAC_PI_2), false);
+        buffer.set_transform(sample("odom", "base_link", 10, 1.0, 0.0), false);
+        buffer.set_transform(sample("odom", "base_link", 20, 9.0, 0.0), false);
+
+        let time = Time { sec: 11, nsec: 0 };
+        let transform = buffer.lookup_transform("odom", "base_link", time).unwrap();
+        assert_eq!("odom", transform.header.frame_id);
+        assert_eq!("base_link", transform.child_frame_id);
+        assert_eq!(time, transform.header.stamp);
+        assert_close(2.0, transform.transform.translation.x);
+        let yaw = FRAC_PI_2 / 2.0;
+        assert_close((yaw / 2.0).sin(), transform.transform.rotation.z);
+        assert_close((yaw / 2.0).cos(), transform.transform.rotation.w);
+
+        let latest = buffer
+            .lookup_transform("odom", "base_link", Time::new())
+            .unwrap();
+        assert_eq!(20, latest.header.stamp.sec);
+        assert_close(9.0, latest.transform.translation.x);
+    }
+
+    #[test]
+    fn inverts_transform_to_child_frame() {
+        let mut buffer = TransformBuffer::default();
+        buffer.set_transform(sample("odom", "base_link", 10, 1.0, FRAC_PI_2), false);
+
+        let time = Time { sec: 10, nsec: 0 };
+        let transform = buffer.lookup_transform("base_link", "odom", time).unwrap();
+        assert_eq!("base_link", transform.header.frame_id);
+        assert_eq!("odom", transform.child_frame_id);
+        // The odom origin is one meter behind the robot, which is turned to the left
+        assert_close(0.0, transform.transform.translation.x);
+        assert_close(1.0, transform.transform.translation.y);
+        assert_close(-(FRAC_PI_2 / 2.0).sin(), transform.transform.rotation.z);
+    }
+
+    #[test]
+    fn drops_history_when_parent_changes() {
+        let mut buffer = TransformBuffer::default();
+        buffer.set_transform(sample("odom", "base_link", 10, 1.0, 0.0), false);
+        buffer.set_transform(sample("odom", "base_link", 12, 3.0, 0.0), false);
+        buffer.set_transform(sample("map", "base_link", 14, 5.0, 0.0), false);
+
+        let transform = buffer
+            .lookup_transform("map", "base_link", Time::new())
+            .unwrap();
+        assert_close(5.0, transform.transform.translation.x);
+        // Interpolating would mix in the samples relative to odom
+        let err = buffer
+            .lookup_transform("map", "base_link", Time { sec: 13, nsec: 0 })
+            .unwrap_err();
+        match err.kind() {
+            ErrorKind::ExtrapolationIntoPast(..) => {}
+            kind => panic!("Unexpected error: {}", kind),
+        }
+        assert!(buffer
+            .lookup_transform("odom", "base_link", Time { sec: 11, nsec: 0 })
+            .is_err());
+    }
+
+    #[test]
+    fn static_transforms_are_valid_at_any_time() {
+        let mut buffer = TransformBuffer::default();
+        buffer.set_transform(sample("base_link", "laser", 0, 0.2, 0.0), true);
+
+        let time = Time { sec: 1000, nsec: 0 };
+        let transform = buffer.lookup_transform("base_link", "laser", time).unwrap();
+        assert_eq!(time, transform.header.stamp);
+        assert_close(0.2, transform.transform.translation.x);
+    }
+
+    #[test]
+    fn distinguishes_missing_transforms_from_extrapolation() {
+        let mut buffer = TransformBuffer::new(Duration::from_seconds(5));
+        buffer.set_transform(sample("odom", "base_link", 10, 1.0, 0.0), false);
+        buffer.set_transform(sample("odom", "base_link", 20, 2.0, 0.0), false);
+
+        let err = buffer
+            .lookup_transform("map", "base_link", Time { sec: 20, nsec: 0 })
+            .unwrap_err();
+        match err.kind() {
+            ErrorKind::TransformUnavailable(target, source) => {
+                assert_eq!(("map", "base_link"), (target.as_str(), source.as_str()));
+            }
+            kind => panic!("Unexpected error: {}", kind),
+        }
+
+        let err = buffer
+            .lookup_transform("odom", "base_link", Time { sec: 21, nsec: 0 })
+            .unwrap_err();
+        match err.kind() {
+            ErrorKind::ExtrapolationIntoFuture(..) => {}
+            kind => panic!("Unexpected error: {}", kind),
+        }
+
+        // The sample at 10 seconds is older than the cache time
+        let err = buffer
+            .lookup_transform("odom", "base_link", Time { sec: 12, nsec: 0 })
+            .unwrap_err();
+        match err.kind() {
+            ErrorKind::ExtrapolationIntoPast(..) => {}
+            kind => panic!("Unexpected error: {}", kind),
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Helpers for transforms published to `/tf` and `/tf_static`.
+//!
+//! TF drops transforms with an empty frame ID, or with a frame as its own
+//! parent, and only complains about it on the receiving side. Checking them
+//! before publishing turns those silent failures into errors of the publisher:
+//!
+//! ```ignore
+//! publisher.set_validator(|message: &TFMessage| rosrust::check_transforms!(message));
+//! ```
+//!
+//! `TransformListener` collects the published transforms, and looks them up
+//! for any time within its buffer.
+
+pub use self::listener::{TransformBuffer, TransformListener};
+use self::msg::{TFMessage, TransformStamped};
+use crate::api::error::{ErrorKind, Result};
+use crate::api::raii::Publisher;
+
+pub mod error;
+mod listener;
+
+/// Messages used by the transform helpers.
+///
+/// These are generated inside rosrust. Messages generated by a node's own
+/// `rosmsg_include!` are separate types, even for the same message, so
+/// values need to be moved between them field by field.
+pub mod msg {
+    pub use crate::msg::geometry_msgs::{Quaternion, Transform, TransformStamped, Vector3};
+    pub use crate::msg::std_msgs::Header;
+    pub use crate::msg::tf2_msgs::TFMessage;
+}
+
+/// Publishes transforms that never change, on the latched `/tf_static` topic.
+///
+/// Subscribers only receive the last latched message, so every message holds
+/// all transforms sent so far. Sending a transform of the same child frame
+/// again replaces the earlier one.
+pub struct StaticTransformBroadcaster {
+    publisher: Publisher<TFMessage>,
+    transforms: Vec<TransformStamped>,
+}
+
+impl StaticTransformBroadcaster {
+    /// Advertises `/tf_static` on the node created by `rosrust::init`.
+    pub fn new() -> Result<Self> {
+        Ok(Self::from_publisher(crate::publish("/tf_static", 100)?))
+    }
+
+    /// Broadcasts through the given publisher, which gets latched.
+    pub fn from_publisher(mut publisher: Publisher<TFMessage>) -> Self {
+        publisher.set_latching(true);
+        Self {
+            publisher,
+            transforms: vec![],
+        }
+    }
+
+    #[inline]
+    pub fn send_transform(&mut self, transform: TransformStamped) -> Result<()> {
+        self.send_transforms(vec![transform])
+    }
+
+    /// Adds the transforms, and publishes all of them again.
+    ///
+    /// Nothing gets added if any of the transforms has invalid frame IDs.
+    pub fn send_transforms(&mut self, transforms: Vec<TransformStamped>) -> Result<()> {
+        for transform in &transforms {
+            check_frame_ids(&transform.header.frame_id, &transform.child_frame_id)
+                .map_err(ErrorKind::MessageRejected)?;
+        }
+        for transform in transforms {
+            let existing = self
+                .transforms
+                .iter_mut()
+                .find(|existing| existing.child_frame_id == transform.child_frame_id);
+            match existing {
+                Some(existing) => *existing = transform,
+                None => self.transforms.push(transform),
+            }
+        }
+        // Subscribers receive static transforms when connecting, not just the live ones
+        self.publisher
+            .send(TFMessage {
+                transforms: self.transforms.clone(),
+            })
+            .map(|_| ())
+    }
+}
+
+/// Checks the frame IDs of a single transform.
+pub fn check_frame_ids(frame_id: &str, child_frame_id: &str) -> std::result::Result<(), String> {
+    if frame_id.is_empty() {
+        return Err(format!(
+            "Transform to frame '{}' has an empty frame_id",
+            child_frame_id
+        ));
+    }
+    if child_frame_id.is_empty() {
+        return Err(format!(
+            "Transform from frame '{}' has an empty child_frame_id",
+            frame_id
+        ));
+    }
+    if frame_id == child_frame_id {
+        return Err(format!(
+            "Transform of frame '{}' has the frame as its own parent",
+            frame_id
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the frame IDs of every transform in a generated `tf2_msgs/TFMessage`.
+///
+/// Evaluates to the result of `check_frame_ids` for the first failing transform,
+/// which fits the validators of publishers.
+#[macro_export]
+macro_rules! check_transforms {
+    ($message:expr) => {
+        $message.transforms.iter().try_for_each(|transform| {
+            $crate::tf::check_frame_ids(&transform.header.frame_id, &transform.child_frame_id)
+        })
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::msg::Header;
+    use super::*;
+
+    fn transform(frame_id: &str, child_frame_id: &str) -> TransformStamped {
+        TransformStamped {
+            header: Header {
+                frame_id: frame_id.into(),
+                ..Default::default()
+            },
+            child_frame_id: child_frame_id.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rejects_empty_frame_id() {
+        assert_eq!(Ok(()), check_frame_ids("odom", "base_link"));
+        let err = check_frame_ids("", "base_link").unwrap_err();
+        assert!(err.contains("empty frame_id"), "{}", err);
+        let err = check_frame_ids("odom", "").unwrap_err();
+        assert!(err.contains("empty child_frame_id"), "{}", err);
+        assert!(check_frame_ids("odom", "odom").is_err());
+    }
+
+    #[test]
+    fn checks_every_transform_of_message() {
+        let mut message = TFMessage {
+            transforms: vec![transform("map", "odom"), transform("odom", "base_link")],
+        };
+        assert_eq!(Ok(()), check_transforms!(message));
+
+        message.transforms.push(transform("", "laser"));
+        let err = check_transforms!(message).unwrap_err();
+        assert!(err.contains("'laser'"), "{}", err);
+    }
+}
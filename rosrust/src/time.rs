@@ -1,11 +1,18 @@
+use lazy_static::lazy_static;
 use serde_derive::{Deserialize, Serialize};
 use std::cmp;
+use std::convert::TryFrom;
 use std::ops;
 use std::time;
 
 const BILLION: i64 = 1_000_000_000;
 
+lazy_static! {
+    static ref STEADY_ORIGIN: time::Instant = time::Instant::now();
+}
+
 #[derive(Copy, Clone, Default, Serialize, Deserialize, Debug)]
+#[repr(C)]
 pub struct Time {
     pub sec: u32,
     pub nsec: u32,
@@ -25,6 +32,17 @@ impl Time {
         }
     }
 
+    #[inline]
+    fn from_nanos_checked(t: i64) -> Option<Time> {
+        if t < 0 {
+            return None;
+        }
+        Some(Time {
+            sec: u32::try_from(t / BILLION).ok()?,
+            nsec: (t % BILLION) as u32,
+        })
+    }
+
     #[inline]
     pub fn nanos(self) -> i64 {
         i64::from(self.sec) * BILLION + i64::from(self.nsec)
@@ -34,6 +52,18 @@ impl Time {
     pub fn seconds(self) -> f64 {
         f64::from(self.sec) + f64::from(self.nsec) / BILLION as f64
     }
+
+    /// Adds a duration, returning `None` if the result is not representable.
+    #[inline]
+    pub fn checked_add(self, d: Duration) -> Option<Time> {
+        Self::from_nanos_checked(self.nanos().checked_add(d.nanos())?)
+    }
+
+    /// Subtracts a duration, returning `None` if the result is not representable.
+    #[inline]
+    pub fn checked_sub(self, d: Duration) -> Option<Time> {
+        Self::from_nanos_checked(self.nanos().checked_sub(d.nanos())?)
+    }
 }
 
 impl cmp::PartialEq for Time {
@@ -57,6 +87,7 @@ impl cmp::Ord for Time {
 }
 
 #[derive(Copy, Clone, Default, Serialize, Deserialize, Debug)]
+#[repr(C)]
 pub struct Duration {
     pub sec: i32,
     pub nsec: i32,
@@ -81,8 +112,18 @@ impl Duration {
         Duration { sec, nsec: 0 }
     }
 
+    /// Unlike `from_nanos`, negative durations follow the ROS convention
+    /// of keeping `nsec` positive, so -1ns is `sec: -1, nsec: 999_999_999`.
     #[inline]
-    fn nanos(self) -> i64 {
+    fn from_nanos_checked(t: i64) -> Option<Duration> {
+        Some(Duration {
+            sec: i32::try_from(t.div_euclid(BILLION)).ok()?,
+            nsec: t.rem_euclid(BILLION) as i32,
+        })
+    }
+
+    #[inline]
+    pub fn nanos(self) -> i64 {
         i64::from(self.sec) * BILLION + i64::from(self.nsec)
     }
 
@@ -90,6 +131,18 @@ impl Duration {
     pub fn seconds(self) -> f64 {
         f64::from(self.sec) + f64::from(self.nsec) / BILLION as f64
     }
+
+    /// Adds two durations, returning `None` if the result is not representable.
+    #[inline]
+    pub fn checked_add(self, other: Duration) -> Option<Duration> {
+        Self::from_nanos_checked(self.nanos().checked_add(other.nanos())?)
+    }
+
+    /// Subtracts two durations, returning `None` if the result is not representable.
+    #[inline]
+    pub fn checked_sub(self, other: Duration) -> Option<Duration> {
+        Self::from_nanos_checked(self.nanos().checked_sub(other.nanos())?)
+    }
 }
 
 impl cmp::PartialEq for Duration {
@@ -112,6 +165,52 @@ impl cmp::Ord for Duration {
     }
 }
 
+/// Time of a monotonic clock, for measuring elapsed time.
+///
+/// Unlike `Time`, it neither jumps when the system clock gets adjusted, nor
+/// follows simulated time. It counts from an arbitrary point in the life of
+/// the process, so only differences between steady times are meaningful.
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SteadyTime(time::Duration);
+
+impl SteadyTime {
+    #[inline]
+    pub fn now() -> SteadyTime {
+        SteadyTime(STEADY_ORIGIN.elapsed())
+    }
+
+    #[inline]
+    pub fn nanos(self) -> i64 {
+        self.0.as_nanos() as i64
+    }
+
+    #[inline]
+    pub fn seconds(self) -> f64 {
+        self.0.as_secs_f64()
+    }
+
+    /// Time passed since this steady time.
+    #[inline]
+    pub fn elapsed(self) -> Duration {
+        SteadyTime::now() - self
+    }
+}
+
+impl ops::Add<Duration> for SteadyTime {
+    type Output = SteadyTime;
+    fn add(self, rhs: Duration) -> Self::Output {
+        let nanos = (self.nanos() + rhs.nanos()).max(0);
+        SteadyTime(time::Duration::from_nanos(nanos as u64))
+    }
+}
+
+impl ops::Sub<SteadyTime> for SteadyTime {
+    type Output = Duration;
+    fn sub(self, rhs: SteadyTime) -> Self::Output {
+        Duration::from_nanos(self.nanos() - rhs.nanos())
+    }
+}
+
 impl ops::Add<Duration> for Time {
     type Output = Time;
     fn add(self, rhs: Duration) -> Self::Output {
@@ -168,7 +267,7 @@ impl From<time::Duration> for Duration {
 
 #[cfg(test)]
 mod tests {
-    use super::{Duration, Time};
+    use super::{Duration, SteadyTime, Time};
     use std::time;
 
     #[test]
@@ -203,6 +302,65 @@ mod tests {
         assert_eq!(time.nanos(), -123456789987654321);
     }
 
+    #[test]
+    fn checked_time_arithmetic_carries_and_borrows_nanoseconds() {
+        let time = Time {
+            sec: 1,
+            nsec: 999_999_999,
+        };
+        let later = time.checked_add(Duration { sec: 0, nsec: 2 }).unwrap();
+        assert_eq!(later.sec, 2);
+        assert_eq!(later.nsec, 1);
+        let earlier = later.checked_sub(Duration { sec: 0, nsec: 2 }).unwrap();
+        assert_eq!(earlier.sec, 1);
+        assert_eq!(earlier.nsec, 999_999_999);
+    }
+
+    #[test]
+    fn checked_time_arithmetic_detects_overflow() {
+        let time = Time {
+            sec: u32::max_value(),
+            nsec: 999_999_999,
+        };
+        assert!(time.checked_add(Duration { sec: 0, nsec: 1 }).is_none());
+        assert!(time.checked_sub(Duration { sec: 0, nsec: 1 }).is_some());
+        let time = Time { sec: 0, nsec: 1 };
+        assert!(time.checked_sub(Duration { sec: 0, nsec: 2 }).is_none());
+        assert!(time.checked_sub(Duration { sec: 0, nsec: 1 }).is_some());
+    }
+
+    #[test]
+    fn checked_duration_arithmetic_normalizes_nanoseconds() {
+        let duration = Duration {
+            sec: 1,
+            nsec: 999_999_999,
+        }
+        .checked_add(Duration { sec: 0, nsec: 2 })
+        .unwrap();
+        assert_eq!(duration.sec, 2);
+        assert_eq!(duration.nsec, 1);
+        let duration = Duration::default()
+            .checked_sub(Duration { sec: 0, nsec: 1 })
+            .unwrap();
+        assert_eq!(duration.sec, -1);
+        assert_eq!(duration.nsec, 999_999_999);
+    }
+
+    #[test]
+    fn checked_duration_arithmetic_detects_overflow() {
+        let duration = Duration {
+            sec: i32::max_value(),
+            nsec: 999_999_999,
+        };
+        assert!(duration.checked_add(Duration { sec: 0, nsec: 1 }).is_none());
+        let duration = Duration {
+            sec: i32::min_value(),
+            nsec: 0,
+        };
+        assert!(duration.checked_sub(Duration { sec: 0, nsec: 1 }).is_none());
+        assert!(duration.checked_add(Duration { sec: 0, nsec: 1 }).is_some());
+    }
+
     #[test]
     fn convert_works() {
         let std_duration = time::Duration::new(123, 456);
@@ -215,4 +373,25 @@ mod tests {
         assert_eq!(msg_duration2.sec, 9876);
         assert_eq!(msg_duration2.nsec, 54321);
     }
+
+    #[test]
+    fn steady_time_is_monotonic() {
+        let mut last = SteadyTime::now();
+        for _ in 0..1000 {
+            let now = SteadyTime::now();
+            assert!(now >= last);
+            last = now;
+        }
+        std::thread::sleep(time::Duration::from_millis(10));
+        assert!(last.elapsed() >= Duration::from_nanos(10_000_000));
+    }
+
+    #[test]
+    fn steady_time_arithmetic_works() {
+        let start = SteadyTime::now();
+        let later = start + Duration::from_seconds(2);
+        assert_eq!(Duration::from_seconds(2), later - start);
+        assert_eq!(Duration::from_seconds(-2), start - later);
+        assert_eq!(start.nanos() + 2_000_000_000, later.nanos());
+    }
 }
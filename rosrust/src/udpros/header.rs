@@ -0,0 +1,107 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+
+pub const HEADER_LENGTH: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpCode {
+    Data0,
+    DataN,
+    Ping,
+    Err,
+}
+
+impl OpCode {
+    fn from_u8(value: u8) -> io::Result<OpCode> {
+        Ok(match value {
+            0 => OpCode::Data0,
+            1 => OpCode::DataN,
+            2 => OpCode::Ping,
+            3 => OpCode::Err,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown UDPROS op code {}", value),
+                ));
+            }
+        })
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            OpCode::Data0 => 0,
+            OpCode::DataN => 1,
+            OpCode::Ping => 2,
+            OpCode::Err => 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DatagramHeader {
+    pub connection_id: u32,
+    pub op_code: OpCode,
+    pub message_id: u8,
+    /// Total block count for `Data0` datagrams, and the block index for `DataN`.
+    pub block: u16,
+}
+
+impl DatagramHeader {
+    pub fn encode<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_u32::<LittleEndian>(self.connection_id)?;
+        w.write_u8(self.op_code.to_u8())?;
+        w.write_u8(self.message_id)?;
+        w.write_u16::<LittleEndian>(self.block)
+    }
+
+    pub fn decode<R: io::Read>(mut r: R) -> io::Result<DatagramHeader> {
+        Ok(DatagramHeader {
+            connection_id: r.read_u32::<LittleEndian>()?,
+            op_code: OpCode::from_u8(r.read_u8()?)?,
+            message_id: r.read_u8()?,
+            block: r.read_u16::<LittleEndian>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FAILED_TO_ENCODE: &'static str = "Failed to encode";
+    static FAILED_TO_DECODE: &'static str = "Failed to decode";
+
+    #[test]
+    fn writes_header() {
+        let header = DatagramHeader {
+            connection_id: 0x0403_0201,
+            op_code: OpCode::DataN,
+            message_id: 7,
+            block: 0x0201,
+        };
+        let mut cursor = io::Cursor::new(Vec::new());
+        header.encode(&mut cursor).expect(FAILED_TO_ENCODE);
+        assert_eq!(vec![1, 2, 3, 4, 1, 7, 1, 2], cursor.into_inner());
+    }
+
+    #[test]
+    fn reads_header() {
+        let data = vec![1, 2, 3, 4, 0, 7, 3, 0];
+        let header = DatagramHeader::decode(io::Cursor::new(data)).expect(FAILED_TO_DECODE);
+        assert_eq!(
+            DatagramHeader {
+                connection_id: 0x0403_0201,
+                op_code: OpCode::Data0,
+                message_id: 7,
+                block: 3,
+            },
+            header
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_op_code() {
+        let data = vec![1, 2, 3, 4, 9, 7, 3, 0];
+        DatagramHeader::decode(io::Cursor::new(data)).unwrap_err();
+    }
+}
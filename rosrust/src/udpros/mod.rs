@@ -0,0 +1,26 @@
+//! UDPROS transport, for receiving topics over UDP.
+//!
+//! UDPROS sends every serialized message, including its length prefix,
+//! split into blocks that each fit into a single datagram. Every datagram
+//! starts with an eight byte header, described by `DatagramHeader`.
+//!
+//! The first block of a message uses the `Data0` op code and stores the total
+//! number of blocks in its block field. The remaining blocks use `DataN` and
+//! store their index within the message. Datagrams can get lost or reordered,
+//! so a message is only reassembled if all of its blocks arrive in order.
+//! Any gap drops the whole message, and a new `Data0` block abandons
+//! whatever message was in progress.
+//!
+//! Subscribers negotiate UDPROS through `requestTopic`, by offering a bound
+//! socket along with their connection header. Publishers that accept it
+//! answer with a connection ID, which marks the datagrams they send to the
+//! socket. Only the subscriber side is implemented, so topics published by
+//! this crate still go through TCPROS.
+
+pub use self::header::{DatagramHeader, OpCode};
+pub use self::reassembly::{fragment, Reassembler};
+pub use self::subscriber::{offer, receive, Negotiation, MAX_DATAGRAM_SIZE, UDPROS};
+
+mod header;
+mod reassembly;
+mod subscriber;
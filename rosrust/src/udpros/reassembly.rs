@@ -0,0 +1,266 @@
+use super::header::{DatagramHeader, OpCode, HEADER_LENGTH};
+use crate::rosmsg::check_message_size;
+use std::convert::TryFrom;
+use std::io;
+
+/// Splits a serialized message into datagrams of at most `max_datagram_size` bytes.
+pub fn fragment(
+    connection_id: u32,
+    message_id: u8,
+    data: &[u8],
+    max_datagram_size: usize,
+) -> io::Result<Vec<Vec<u8>>> {
+    if max_datagram_size <= HEADER_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Datagram size {} cannot fit a UDPROS header",
+                max_datagram_size
+            ),
+        ));
+    }
+    let block_size = max_datagram_size - HEADER_LENGTH;
+    // Even empty messages need a block to be sent in
+    let block_count = data.len().saturating_sub(1) / block_size + 1;
+    let total_blocks = u16::try_from(block_count).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Message needs {} blocks, which is too many", block_count),
+        )
+    })?;
+
+    (0..block_count)
+        .map(|index| {
+            let header = DatagramHeader {
+                connection_id,
+                op_code: if index == 0 {
+                    OpCode::Data0
+                } else {
+                    OpCode::DataN
+                },
+                message_id,
+                block: if index == 0 {
+                    total_blocks
+                } else {
+                    index as u16
+                },
+            };
+            let start = index * block_size;
+            let end = (start + block_size).min(data.len());
+            let mut datagram = Vec::with_capacity(HEADER_LENGTH + end - start);
+            header.encode(&mut datagram)?;
+            datagram.extend_from_slice(&data[start..end]);
+            Ok(datagram)
+        })
+        .collect()
+}
+
+struct PartialMessage {
+    message_id: u8,
+    block_count: u16,
+    next_block: u16,
+    data: Vec<u8>,
+}
+
+/// Collects the datagrams of a single UDPROS connection back into messages.
+pub struct Reassembler {
+    connection_id: u32,
+    current: Option<PartialMessage>,
+}
+
+impl Reassembler {
+    pub fn new(connection_id: u32) -> Reassembler {
+        Reassembler {
+            connection_id,
+            current: None,
+        }
+    }
+
+    /// Feeds a received datagram, returning the full message once its last block arrives.
+    ///
+    /// Messages that would grow beyond the message size limit are dropped
+    /// with an `InvalidData` error. All blocks but the last one fill whole
+    /// datagrams, so the first block already tells the largest possible size.
+    pub fn push(&mut self, datagram: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let header = DatagramHeader::decode(datagram)?;
+        if header.connection_id != self.connection_id {
+            return Ok(None);
+        }
+        let payload = &datagram[HEADER_LENGTH..];
+        match header.op_code {
+            OpCode::Data0 => {
+                self.current = None;
+                check_message_size(payload.len().saturating_mul(usize::from(header.block)))?;
+                self.current = Some(PartialMessage {
+                    message_id: header.message_id,
+                    block_count: header.block,
+                    next_block: 1,
+                    data: payload.to_vec(),
+                });
+            }
+            OpCode::DataN => match self.current {
+                Some(ref mut message)
+                    if message.message_id == header.message_id
+                        && message.next_block == header.block =>
+                {
+                    if let Err(err) = check_message_size(message.data.len() + payload.len()) {
+                        self.current = None;
+                        return Err(err);
+                    }
+                    message.next_block += 1;
+                    message.data.extend_from_slice(payload);
+                }
+                _ => {
+                    // A block got lost or reordered, so the message cannot be recovered
+                    self.current = None;
+                    return Ok(None);
+                }
+            },
+            OpCode::Ping => return Ok(None),
+            OpCode::Err => {
+                self.current = None;
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "Publisher reported an error on the UDPROS connection",
+                ));
+            }
+        }
+
+        let complete = match self.current {
+            Some(ref message) => message.next_block >= message.block_count,
+            None => false,
+        };
+        if complete {
+            return Ok(self.current.take().map(|message| message.data));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FAILED_TO_FRAGMENT: &'static str = "Failed to fragment";
+    static FAILED_TO_REASSEMBLE: &'static str = "Failed to reassemble";
+
+    fn sample_data() -> Vec<u8> {
+        (0..100).collect()
+    }
+
+    #[test]
+    fn fragments_into_blocks_of_datagram_size() {
+        let datagrams = fragment(5, 1, &sample_data(), 40).expect(FAILED_TO_FRAGMENT);
+        assert_eq!(4, datagrams.len());
+        assert!(datagrams.iter().all(|datagram| datagram.len() <= 40));
+        assert_eq!(
+            DatagramHeader {
+                connection_id: 5,
+                op_code: OpCode::Data0,
+                message_id: 1,
+                block: 4,
+            },
+            DatagramHeader::decode(&datagrams[0][..]).unwrap()
+        );
+        assert_eq!(
+            DatagramHeader {
+                connection_id: 5,
+                op_code: OpCode::DataN,
+                message_id: 1,
+                block: 3,
+            },
+            DatagramHeader::decode(&datagrams[3][..]).unwrap()
+        );
+    }
+
+    #[test]
+    fn reassembles_fragmented_message() {
+        let mut reassembler = Reassembler::new(5);
+        let datagrams = fragment(5, 1, &sample_data(), 40).expect(FAILED_TO_FRAGMENT);
+        let (last, rest) = datagrams.split_last().unwrap();
+        for datagram in rest {
+            assert_eq!(
+                None,
+                reassembler.push(datagram).expect(FAILED_TO_REASSEMBLE)
+            );
+        }
+        assert_eq!(
+            Some(sample_data()),
+            reassembler.push(last).expect(FAILED_TO_REASSEMBLE)
+        );
+    }
+
+    #[test]
+    fn reassembles_single_block_message() {
+        let mut reassembler = Reassembler::new(5);
+        let datagrams = fragment(5, 1, &[1, 2, 3], 40).expect(FAILED_TO_FRAGMENT);
+        assert_eq!(1, datagrams.len());
+        assert_eq!(
+            Some(vec![1, 2, 3]),
+            reassembler.push(&datagrams[0]).expect(FAILED_TO_REASSEMBLE)
+        );
+    }
+
+    #[test]
+    fn drops_message_with_missing_block() {
+        let mut reassembler = Reassembler::new(5);
+        let datagrams = fragment(5, 1, &sample_data(), 40).expect(FAILED_TO_FRAGMENT);
+        for datagram in datagrams
+            .iter()
+            .filter(|datagram| *datagram != &datagrams[1])
+        {
+            assert_eq!(
+                None,
+                reassembler.push(datagram).expect(FAILED_TO_REASSEMBLE)
+            );
+        }
+
+        let datagrams = fragment(5, 2, &[1, 2, 3], 40).expect(FAILED_TO_FRAGMENT);
+        assert_eq!(
+            Some(vec![1, 2, 3]),
+            reassembler.push(&datagrams[0]).expect(FAILED_TO_REASSEMBLE)
+        );
+    }
+
+    #[test]
+    fn rejects_messages_beyond_size_limit() {
+        let mut reassembler = Reassembler::new(5);
+        let mut datagram = vec![];
+        DatagramHeader {
+            connection_id: 5,
+            op_code: OpCode::Data0,
+            message_id: 1,
+            block: u16::MAX,
+        }
+        .encode(&mut datagram)
+        .unwrap();
+        datagram.resize(HEADER_LENGTH + 2000, 0);
+        let err = reassembler.push(&datagram).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        // Blocks larger than the first one cannot sneak past the limit either
+        let datagrams = fragment(5, 2, &[0; 100], 58).expect(FAILED_TO_FRAGMENT);
+        assert_eq!(
+            None,
+            reassembler.push(&datagrams[0]).expect(FAILED_TO_REASSEMBLE)
+        );
+        let mut huge = datagrams[1][..HEADER_LENGTH].to_vec();
+        huge.resize(HEADER_LENGTH + crate::rosmsg::DEFAULT_MAX_MESSAGE_SIZE, 0);
+        let err = reassembler.push(&huge).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(
+            None,
+            reassembler.push(&datagrams[1]).expect(FAILED_TO_REASSEMBLE)
+        );
+    }
+
+    #[test]
+    fn ignores_other_connections() {
+        let mut reassembler = Reassembler::new(5);
+        let datagrams = fragment(6, 1, &[1, 2, 3], 40).expect(FAILED_TO_FRAGMENT);
+        assert_eq!(
+            None,
+            reassembler.push(&datagrams[0]).expect(FAILED_TO_REASSEMBLE)
+        );
+    }
+}
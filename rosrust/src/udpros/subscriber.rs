@@ -0,0 +1,232 @@
+use super::reassembly::Reassembler;
+use crate::rosmsg::RosMsg;
+use byteorder::{LittleEndian, WriteBytesExt};
+use log::error;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
+use std::time::Duration;
+use xml_rpc::Value;
+
+pub const UDPROS: &str = "UDPROS";
+
+/// Largest datagram subscribers ask for, which fits into a typical Ethernet frame.
+pub const MAX_DATAGRAM_SIZE: usize = 1500;
+
+// Publishers may settle on larger datagrams than asked for, up to what UDP allows
+const RECEIVE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// How often a receive loop without incoming datagrams checks whether to stop.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Binds a socket to receive a topic on, and describes it for `requestTopic`.
+///
+/// The socket is bound to the local address that leads to `publisher_host`,
+/// so the publisher can reach it. The offer lists the connection header
+/// `fields`, the address of the socket and `MAX_DATAGRAM_SIZE`.
+pub fn offer(
+    publisher_host: &str,
+    fields: &HashMap<String, String>,
+) -> io::Result<(UdpSocket, Value)> {
+    let probe = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))
+        .or_else(|_| UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)))?;
+    // Connecting a UDP socket sends nothing, it only picks the route
+    probe.connect((publisher_host, 9))?;
+    let ip = match probe.local_addr()?.ip() {
+        IpAddr::V6(ip) => ip.to_ipv4().map_or(IpAddr::V6(ip), IpAddr::V4),
+        ip => ip,
+    };
+    let socket = UdpSocket::bind((ip, 0))?;
+    let mut header = vec![];
+    fields.encode(&mut header)?;
+    // Headers go without the length prefix they have on TCPROS connections
+    header.drain(..4);
+    let offer = Value::Array(vec![
+        Value::String(UDPROS.into()),
+        Value::Base64(header),
+        Value::String(ip.to_string()),
+        Value::Int(i32::from(socket.local_addr()?.port())),
+        Value::Int(MAX_DATAGRAM_SIZE as i32),
+    ]);
+    Ok((socket, offer))
+}
+
+/// Connection parameters of a publisher that accepted a UDPROS offer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Negotiation {
+    pub connection_id: u32,
+    /// Connection header of the publisher.
+    pub fields: HashMap<String, String>,
+}
+
+impl Negotiation {
+    /// Parses the protocol parameters that follow the publisher's host and port.
+    ///
+    /// These are the connection ID, the datagram size the publisher settled
+    /// on, and its connection header.
+    pub fn from_params(params: &[Value]) -> io::Result<Negotiation> {
+        let (connection_id, header) = match params {
+            [Value::Int(connection_id), Value::Int(_), Value::Base64(header), ..] => {
+                (*connection_id, header)
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Malformed UDPROS parameters in requestTopic response",
+                ))
+            }
+        };
+        let length = u32::try_from(header.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Header is too long"))?;
+        let mut data = Vec::with_capacity(header.len() + 4);
+        data.write_u32::<LittleEndian>(length)?;
+        data.extend_from_slice(header);
+        Ok(Negotiation {
+            // IDs are sent as signed integers, but are unsigned in datagrams
+            connection_id: connection_id as u32,
+            fields: RosMsg::decode(&data[..])?,
+        })
+    }
+}
+
+/// Receives messages of a connection, until `keep_going` returns `false`.
+///
+/// Every message is passed on with its length prefix, like messages read
+/// from TCPROS connections. Messages that lose a datagram, or exceed the
+/// message size limit, get dropped. Receiving stops once `on_message`
+/// returns `false`, or once the publisher reports an error.
+pub fn receive<K, F>(
+    socket: &UdpSocket,
+    connection_id: u32,
+    keep_going: K,
+    mut on_message: F,
+) -> io::Result<()>
+where
+    K: Fn() -> bool,
+    F: FnMut(Vec<u8>) -> bool,
+{
+    socket.set_read_timeout(Some(STOP_CHECK_INTERVAL))?;
+    let mut reassembler = Reassembler::new(connection_id);
+    let mut buffer = vec![0; RECEIVE_BUFFER_SIZE];
+    while keep_going() {
+        let length = match socket.recv(&mut buffer) {
+            Ok(length) => length,
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(err) => return Err(err),
+        };
+        match reassembler.push(&buffer[..length]) {
+            Ok(Some(message)) => {
+                if !on_message(message) {
+                    break;
+                }
+            }
+            Ok(None) => {}
+            Err(err) if err.kind() == io::ErrorKind::ConnectionAborted => return Err(err),
+            Err(err) => error!("Dropped UDPROS datagram: {}", err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::reassembly::fragment;
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn header_fields() -> HashMap<String, String> {
+        let mut fields = HashMap::new();
+        fields.insert(String::from("callerid"), String::from("/listener"));
+        fields.insert(String::from("topic"), String::from("/chatter"));
+        fields
+    }
+
+    #[test]
+    fn offers_reachable_socket_with_header() {
+        let (socket, offer) = offer("127.0.0.1", &header_fields()).unwrap();
+        let values = match offer {
+            Value::Array(values) => values,
+            _ => panic!("Offer is not an array"),
+        };
+        assert_eq!(Value::String(UDPROS.into()), values[0]);
+        assert_eq!(Value::String("127.0.0.1".into()), values[2]);
+        let port = i32::from(socket.local_addr().unwrap().port());
+        assert_eq!(Value::Int(port), values[3]);
+        assert_eq!(Value::Int(MAX_DATAGRAM_SIZE as i32), values[4]);
+
+        let header = match values[1] {
+            Value::Base64(ref header) => header.clone(),
+            _ => panic!("Header is not binary"),
+        };
+        let params = [Value::Int(-2), Value::Int(1500), Value::Base64(header)];
+        let negotiation = Negotiation::from_params(&params).unwrap();
+        assert_eq!(u32::MAX - 1, negotiation.connection_id);
+        assert_eq!(header_fields(), negotiation.fields);
+    }
+
+    #[test]
+    fn rejects_malformed_params() {
+        Negotiation::from_params(&[Value::Int(1), Value::Int(1500)]).unwrap_err();
+        let params = [
+            Value::Int(1),
+            Value::Int(1500),
+            // A header row that is not key=value
+            Value::Base64(vec![3, 0, 0, 0, b'a', b'b', b'c']),
+        ];
+        Negotiation::from_params(&params).unwrap_err();
+    }
+
+    #[test]
+    fn receives_fragmented_messages() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let running = Arc::new(AtomicBool::new(true));
+        let keep_going = Arc::clone(&running);
+        let receiver = thread::spawn(move || {
+            let mut messages = vec![];
+            receive(
+                &socket,
+                7,
+                || keep_going.load(Ordering::SeqCst),
+                |message| {
+                    messages.push(message);
+                    messages.len() < 2
+                },
+            )
+            .unwrap();
+            messages
+        });
+
+        let first = (0..=255).collect::<Vec<u8>>();
+        let second = vec![1, 2, 3];
+        for (message_id, data) in [&first, &second].iter().enumerate() {
+            for datagram in fragment(7, message_id as u8, data, 100).unwrap() {
+                sender.send_to(&datagram, address).unwrap();
+            }
+            // Keeps the test from depending on the order of datagrams
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(vec![first, second], receiver.join().unwrap());
+    }
+
+    #[test]
+    fn stops_receiving_when_asked() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let running = Arc::new(AtomicBool::new(true));
+        let keep_going = Arc::clone(&running);
+        let receiver = thread::spawn(move || {
+            receive(&socket, 7, || keep_going.load(Ordering::SeqCst), |_| true)
+        });
+        running.store(false, Ordering::SeqCst);
+        receiver.join().unwrap().unwrap();
+    }
+}
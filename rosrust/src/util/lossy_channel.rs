@@ -1,7 +1,7 @@
 use crate::util::killable_channel::{channel, KillMode, Killer, Receiver, SendMode, Sender};
 use crate::util::FAILED_TO_LOCK;
 use crossbeam::channel;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
 #[allow(clippy::mutex_atomic)]
@@ -15,6 +15,7 @@ pub fn lossy_channel<T>(queue_size: usize) -> (LossySender<T>, LossyReceiver<T>)
         killer,
         is_open,
         queue_size,
+        dropped: Arc::new(AtomicUsize::new(0)),
     };
     (sender, receiver)
 }
@@ -26,6 +27,7 @@ pub struct LossySender<T> {
     killer: Killer,
     is_open: Arc<AtomicBool>,
     pub queue_size: Arc<Mutex<usize>>,
+    dropped: Arc<AtomicUsize>,
 }
 
 impl<T> LossySender<T> {
@@ -38,11 +40,32 @@ impl<T> LossySender<T> {
         Ok(())
     }
 
+    /// Sends all messages, only dropping excess data once all of them are queued.
+    pub fn try_send_all<I>(&self, msgs: I) -> Result<(), channel::TrySendError<T>>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        for msg in msgs {
+            if !self.is_open.load(Ordering::SeqCst) {
+                return Err(channel::TrySendError::Disconnected(msg));
+            }
+            self.data_tx.try_send(msg)?;
+        }
+        self.remove_extra_data();
+        Ok(())
+    }
+
     pub fn close(&mut self) -> Result<(), channel::SendError<()>> {
         self.is_open.store(false, Ordering::SeqCst);
         self.killer.send()
     }
 
+    /// Whether the receiving side has not been closed yet.
+    #[inline]
+    pub fn is_open(&self) -> bool {
+        self.is_open.load(Ordering::SeqCst)
+    }
+
     fn remove_extra_data(&self) {
         let queue_size: usize = *self.queue_size.lock().expect(FAILED_TO_LOCK);
         while self.data_rx.len() > queue_size {
@@ -50,9 +73,15 @@ impl<T> LossySender<T> {
                 log::error!("Failed to remove excess data from message queue");
                 break;
             }
+            self.dropped.fetch_add(1, Ordering::SeqCst);
         }
     }
 
+    /// Number of messages discarded so far because the queue was full.
+    pub fn dropped_count(&self) -> usize {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
     pub fn set_queue_size(&self, queue_size: usize) {
         *self.queue_size.lock().expect(FAILED_TO_LOCK) = queue_size;
     }
@@ -64,3 +93,38 @@ impl<T> LossySender<T> {
 }
 
 pub type LossyReceiver<T> = Receiver<T>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_oldest_data_beyond_queue_size() {
+        let (sender, receiver) = lossy_channel(2);
+        for i in 0..5 {
+            sender.try_send(i).unwrap();
+        }
+        assert_eq!(3, sender.dropped_count());
+        assert_eq!(Ok(3), receiver.data_rx.try_recv());
+        assert_eq!(Ok(4), receiver.data_rx.try_recv());
+    }
+
+    #[test]
+    fn counts_every_message_of_a_batch() {
+        let (sender, receiver) = lossy_channel(3);
+        sender.try_send_all(0..5).unwrap();
+        assert_eq!(2, sender.dropped_count());
+        let received = receiver.data_rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(vec![2, 3, 4], received);
+    }
+
+    #[test]
+    fn does_not_drop_data_within_queue_size() {
+        let (sender, receiver) = lossy_channel(2);
+        for i in 0..4 {
+            sender.try_send(i).unwrap();
+            assert_eq!(Ok(i), receiver.data_rx.try_recv());
+        }
+        assert_eq!(0, sender.dropped_count());
+    }
+}
@@ -1,5 +1,6 @@
 pub mod kill;
 pub mod killable_channel;
 pub mod lossy_channel;
+pub mod ports;
 
 pub static FAILED_TO_LOCK: &'static str = "Failed to acquire lock";
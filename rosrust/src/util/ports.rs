@@ -0,0 +1,103 @@
+use std::fmt;
+use std::io;
+use std::ops::RangeInclusive;
+
+/// Lets the operating system pick any free port.
+pub const ANY_PORT: RangeInclusive<u16> = 0..=0;
+
+/// Errors that tell whether binding failed because the port is taken.
+pub trait AddrInUse {
+    fn is_addr_in_use(&self) -> bool;
+}
+
+impl AddrInUse for io::Error {
+    #[inline]
+    fn is_addr_in_use(&self) -> bool {
+        self.kind() == io::ErrorKind::AddrInUse
+    }
+}
+
+/// Calls `bind` with each port of the range, until one of them succeeds.
+///
+/// Only ports that are taken move on to the next one, any other error is
+/// returned straight away. With `ANY_PORT` the error of the single attempt
+/// is returned unchanged. Otherwise running out of ports results in an
+/// `AddrInUse` error naming the range, along with the error of the last attempt.
+pub fn bind_in_range<T, E, F>(ports: &RangeInclusive<u16>, mut bind: F) -> Result<T, E>
+where
+    E: From<io::Error> + fmt::Display + AddrInUse,
+    F: FnMut(u16) -> Result<T, E>,
+{
+    if *ports == ANY_PORT {
+        return bind(0);
+    }
+    let mut last_error = None;
+    for port in ports.clone() {
+        match bind(port) {
+            Ok(bound) => return Ok(bound),
+            Err(err) if err.is_addr_in_use() => last_error = Some(err),
+            Err(err) => return Err(err),
+        }
+    }
+    let mut message = format!("No free port between {} and {}", ports.start(), ports.end());
+    if let Some(err) = last_error {
+        message = format!("{}, last attempt failed with: {}", message, err);
+    }
+    Err(io::Error::new(io::ErrorKind::AddrInUse, message).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bind_only(free: u16) -> impl FnMut(u16) -> io::Result<u16> {
+        move |port| {
+            if port == free {
+                Ok(port)
+            } else {
+                Err(io::Error::new(io::ErrorKind::AddrInUse, "Port taken"))
+            }
+        }
+    }
+
+    #[test]
+    fn binds_first_free_port_of_range() {
+        assert_eq!(
+            40003,
+            bind_in_range(&(40000..=40005), bind_only(40003)).unwrap()
+        );
+        assert_eq!(0, bind_in_range(&ANY_PORT, bind_only(0)).unwrap());
+    }
+
+    #[test]
+    fn reports_exhausted_range() {
+        let err = bind_in_range(&(40000..=40002), bind_only(40003)).unwrap_err();
+        assert_eq!(io::ErrorKind::AddrInUse, err.kind());
+        let message = err.to_string();
+        assert!(message.contains("40000 and 40002"), "{}", message);
+        assert!(message.contains("Port taken"), "{}", message);
+    }
+
+    #[test]
+    fn returns_other_errors_straight_away() {
+        let mut attempts = vec![];
+        let err = bind_in_range(&(40000..=40005), |port| -> io::Result<u16> {
+            attempts.push(port);
+            let kind = if port < 40002 {
+                io::ErrorKind::AddrInUse
+            } else {
+                io::ErrorKind::PermissionDenied
+            };
+            Err(io::Error::new(kind, "Failed to bind"))
+        })
+        .unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        assert_eq!(vec![40000, 40001, 40002], attempts);
+    }
+
+    #[test]
+    fn keeps_error_of_any_port_unchanged() {
+        let err = bind_in_range(&ANY_PORT, bind_only(1)).unwrap_err();
+        assert_eq!("Port taken", err.to_string());
+    }
+}
@@ -12,7 +12,8 @@ mod msg {
     rosrust::rosmsg_include!(
         roscpp_tutorials / TwoInts,
         rospy_tutorials / AddTwoInts,
-        std_msgs / String
+        std_msgs / String,
+        geometry_msgs / Point
     );
 }
 
@@ -337,8 +338,69 @@ fn call_service(criterion: &mut Criterion) {
     );
 }
 
+fn encode_byte_array(criterion: &mut Criterion) {
+    use rosrust::rosmsg::{
+        decode_byte_vec, decode_variable_vec, encode_byte_slice, encode_variable_slice,
+    };
+
+    let data = (0..10_000_000).map(|v| v as u8).collect::<Vec<u8>>();
+    let mut encoded = Vec::with_capacity(data.len() + 4);
+    encode_byte_slice(&data, &mut encoded).unwrap();
+
+    let input = data.clone();
+    criterion.bench_function("encode 10 MB byte array element-wise", move |b| {
+        let mut output = Vec::with_capacity(input.len() + 4);
+        b.iter(|| {
+            output.clear();
+            encode_variable_slice(&input, &mut output).unwrap();
+        });
+    });
+    let input = data;
+    criterion.bench_function("encode 10 MB byte array in bulk", move |b| {
+        let mut output = Vec::with_capacity(input.len() + 4);
+        b.iter(|| {
+            output.clear();
+            encode_byte_slice(&input, &mut output).unwrap();
+        });
+    });
+    let input = encoded.clone();
+    criterion.bench_function("decode 10 MB byte array element-wise", move |b| {
+        b.iter(|| decode_variable_vec::<_, u8>(input.as_slice()).unwrap());
+    });
+    let input = encoded;
+    criterion.bench_function("decode 10 MB byte array in bulk", move |b| {
+        b.iter(|| decode_byte_vec(input.as_slice()).unwrap());
+    });
+}
+
+fn decode_point_array(criterion: &mut Criterion) {
+    use msg::geometry_msgs::Point;
+    use rosrust::rosmsg::{decode_variable_fixed_size_vec, decode_variable_vec};
+
+    let data = (0..1_000_000)
+        .map(|v| Point {
+            x: f64::from(v),
+            y: f64::from(v) * 2.0,
+            z: f64::from(v) * 3.0,
+        })
+        .collect::<Vec<Point>>();
+    let mut encoded = Vec::new();
+    rosrust::rosmsg::encode_variable_slice(&data, &mut encoded).unwrap();
+
+    let input = encoded.clone();
+    criterion.bench_function("decode 1M point array element-wise", move |b| {
+        b.iter(|| decode_variable_vec::<_, Point>(input.as_slice()).unwrap());
+    });
+    let input = encoded;
+    criterion.bench_function("decode 1M point array in bulk", move |b| {
+        b.iter(|| decode_variable_fixed_size_vec::<_, Point>(input.as_slice()).unwrap());
+    });
+}
+
 criterion_group!(
     benches,
+    encode_byte_array,
+    decode_point_array,
     subscribe_publish_directly,
     subscribe_publish_relayed,
     call_service
@@ -0,0 +1,45 @@
+use crossbeam::channel::unbounded;
+use rosrust;
+
+mod util;
+
+mod msg {
+    rosrust::rosmsg_include!(rosgraph_msgs / Log);
+}
+
+#[test]
+fn can_read_log_crate_records_from_rosout() {
+    let _roscore = util::run_roscore_for(util::Language::None, util::Feature::Log);
+
+    rosrust::init("log_crate_listener");
+    rosrust::init_log().unwrap();
+
+    let (tx, rx) = unbounded();
+
+    let _subscriber =
+        rosrust::subscribe::<msg::rosgraph_msgs::Log, _>("/rosout", 100, move |data| {
+            tx.send(data).unwrap();
+        })
+        .unwrap();
+
+    let rate = rosrust::rate(1.0);
+
+    for _ in 0..10 {
+        for item in rx.try_iter() {
+            println!("Received message at level {}: {}", item.level, item.msg);
+            if item.msg == "info record 42" {
+                assert_eq!(msg::rosgraph_msgs::Log::INFO, item.level);
+                assert_eq!("/log_crate_listener", item.name);
+                assert!(item.file.ends_with(file!()), "{}", item.file);
+                return;
+            }
+            assert_ne!("debug record", item.msg);
+        }
+
+        log::debug!("debug record");
+        log::info!("info record {}", 42);
+        rate.sleep();
+    }
+
+    panic!("Failed to receive log crate record on /rosout");
+}
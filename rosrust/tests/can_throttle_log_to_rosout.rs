@@ -0,0 +1,72 @@
+use crossbeam::channel::unbounded;
+use msg::rosgraph_msgs::Log;
+use rosrust;
+use std::collections::BTreeMap;
+
+mod util;
+
+mod msg {
+    rosrust::rosmsg_include!(rosgraph_msgs / Log);
+}
+
+#[test]
+fn can_throttle_log_to_rosout() {
+    let _roscore = util::run_roscore_for(util::Language::None, util::Feature::Log);
+
+    rosrust::init("throttled_logger");
+
+    let (tx, rx) = unbounded();
+
+    let _subscriber =
+        rosrust::subscribe::<msg::rosgraph_msgs::Log, _>("/rosout", 100, move |data| {
+            tx.send((data.level, data.msg)).unwrap();
+        })
+        .unwrap();
+
+    let rate = rosrust::rate(1.0);
+    let mut connected = false;
+    for _ in 0..10 {
+        if rx.try_iter().any(|(_, msg)| msg == "connected") {
+            connected = true;
+            break;
+        }
+        rosrust::ros_info!("connected");
+        rate.sleep();
+    }
+    assert!(connected, "Failed to receive data on /rosout");
+
+    // Three seconds of logging every 100ms
+    let rate = rosrust::rate(10.0);
+    for value in 0..30 {
+        rosrust::ros_debug_throttle!(0.5, "throttled {}", value);
+        rosrust::ros_info_throttle!(1.0, "throttled {}", value);
+        rosrust::ros_warn_throttle!(2.0, "throttled {}", value);
+        rosrust::ros_err_throttle!(3.0, "throttled {}", value);
+        rosrust::ros_fatal_throttle!(4.0, "throttled");
+        rate.sleep();
+    }
+    rosrust::sleep(rosrust::Duration::from_seconds(1));
+
+    let mut counts = BTreeMap::new();
+    for (level, msg) in rx.try_iter() {
+        if msg.starts_with("throttled") {
+            *counts.entry(level).or_insert(0) += 1;
+        }
+    }
+    println!("Received throttled messages per level: {:?}", counts);
+    for &(level, max_count) in &[
+        (Log::DEBUG, 7),
+        (Log::INFO, 4),
+        (Log::WARN, 2),
+        (Log::ERROR, 2),
+        (Log::FATAL, 1),
+    ] {
+        let count = counts.get(&level).cloned().unwrap_or(0);
+        assert!(
+            (1..=max_count).contains(&count),
+            "Got {} messages at level {}",
+            count,
+            level
+        );
+    }
+}
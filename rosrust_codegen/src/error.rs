@@ -10,5 +10,9 @@ error_chain! {
             description("message not found in provided directories")
             display("message {} not found in provided directories\nDirectories:\n{}", msg, folders)
         }
+        BuilderConflict(msg: String, reason: String) {
+            description("message builder would clash with generated code")
+            display("cannot generate builder for message {}: {}", msg, reason)
+        }
     }
 }
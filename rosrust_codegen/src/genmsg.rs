@@ -4,15 +4,16 @@ use crate::output_layout;
 use std::collections::HashSet;
 
 pub fn depend_on_messages(folders: &[&str], messages: &[&str]) -> Result<output_layout::Layout> {
-    let mut output = output_layout::Layout {
-        packages: Vec::new(),
-    };
     let mut message_pairs = Vec::<(&str, &str)>::new();
     for message in messages {
         message_pairs.push(string_into_pair(message)?);
     }
     let message_map = helpers::get_message_map(folders, &message_pairs)?;
     let hashes = helpers::calculate_md5(&message_map)?;
+    let mut output = output_layout::Layout {
+        packages: Vec::new(),
+        fixed_size: helpers::find_fixed_size_messages(&message_map),
+    };
     let packages = message_map
         .messages
         .iter()
@@ -69,10 +70,13 @@ pub fn depend_on_messages(folders: &[&str], messages: &[&str]) -> Result<output_
                 .expect("Internal implementation contains mismatch in map keys")
                 .clone();
             let msg_type = format!("{}/{}", package, name);
+            let msg_definition =
+                helpers::generate_service_definition(&message_map.messages, &package, name)?;
             package_data.services.push(output_layout::Service {
                 name: name.clone(),
                 md5sum,
                 msg_type,
+                msg_definition,
             })
         }
         output.packages.push(package_data);
@@ -48,6 +48,26 @@ pub fn calculate_md5(message_map: &MessageMap) -> Result<HashMap<(String, String
     Ok(hashes)
 }
 
+/// Finds the messages that always encode to the same number of bytes.
+///
+/// Messages qualify once all of their dependencies do, so the search repeats
+/// until no more messages get added.
+pub fn find_fixed_size_messages(message_map: &MessageMap) -> HashSet<(String, String)> {
+    let mut fixed_size = HashSet::new();
+    loop {
+        let found = message_map
+            .messages
+            .iter()
+            .filter(|&(key, value)| !fixed_size.contains(key) && value.is_fixed_size(&fixed_size))
+            .map(|(key, _value)| key.clone())
+            .collect::<Vec<_>>();
+        if found.is_empty() {
+            return fixed_size;
+        }
+        fixed_size.extend(found);
+    }
+}
+
 fn calculate_md5_from_representation(v: &str) -> String {
     use md5::{Digest, Md5};
     let mut hasher = Md5::new();
@@ -86,6 +106,22 @@ pub fn generate_message_definition<S: std::hash::BuildHasher>(
     Ok(result)
 }
 
+/// Definition of a service, made of its request and response sources, as in its `.srv` file.
+pub fn generate_service_definition<S: std::hash::BuildHasher>(
+    message_map: &HashMap<(String, String), Msg, S>,
+    package: &str,
+    name: &str,
+) -> Result<String> {
+    let source = |suffix: &str| -> Result<String> {
+        let key = (String::from(package), format!("{}{}", name, suffix));
+        match message_map.get(&key) {
+            Some(msg) => Ok(msg.source.clone()),
+            None => bail!("Message map does not contain all needed elements"),
+        }
+    };
+    Ok(format!("{}\n---\n{}\n", source("Req")?, source("Res")?))
+}
+
 pub struct MessageMap {
     pub messages: HashMap<(String, String), Msg>,
     pub services: HashSet<(String, String)>,
@@ -120,6 +156,14 @@ pub fn get_message_map(folders: &[&str], messages: &[(&str, &str)]) -> Result<Me
                     msgs.insert((package.clone(), res.name.clone()), res);
                     srvs.insert((package, service_name));
                 }
+                MessageCase::Action(messages) => {
+                    for message in messages {
+                        for dependency in &message.dependencies() {
+                            pending.push(dependency.clone());
+                        }
+                        msgs.insert((package.clone(), message.name.clone()), message);
+                    }
+                }
             };
         }
     }
@@ -132,6 +176,7 @@ pub fn get_message_map(folders: &[&str], messages: &[(&str, &str)]) -> Result<Me
 enum MessageCase {
     Message(Msg),
     Service(String, Msg, Msg),
+    Action(Vec<Msg>),
 }
 
 lazy_static! {
@@ -149,10 +194,42 @@ fn generate_in_memory_messages() -> HashMap<&'static str, &'static str> {
         "rosgraph_msgs/Log",
         include_str!("msg_examples/rosgraph_msgs/msg/Log.msg"),
     );
+    output.insert(
+        "rosgraph_msgs/TopicStatistics",
+        include_str!("msg_examples/rosgraph_msgs/msg/TopicStatistics.msg"),
+    );
+    output.insert(
+        "actionlib_msgs/GoalID",
+        include_str!("msg_examples/actionlib_msgs/msg/GoalID.msg"),
+    );
+    output.insert(
+        "actionlib_msgs/GoalStatus",
+        include_str!("msg_examples/actionlib_msgs/msg/GoalStatus.msg"),
+    );
     output.insert(
         "std_msgs/Header",
         include_str!("msg_examples/std_msgs/msg/Header.msg"),
     );
+    output.insert(
+        "geometry_msgs/Quaternion",
+        include_str!("msg_examples/geometry_msgs/msg/Quaternion.msg"),
+    );
+    output.insert(
+        "geometry_msgs/Transform",
+        include_str!("msg_examples/geometry_msgs/msg/Transform.msg"),
+    );
+    output.insert(
+        "geometry_msgs/TransformStamped",
+        include_str!("msg_examples/geometry_msgs/msg/TransformStamped.msg"),
+    );
+    output.insert(
+        "geometry_msgs/Vector3",
+        include_str!("msg_examples/geometry_msgs/msg/Vector3.msg"),
+    );
+    output.insert(
+        "tf2_msgs/TFMessage",
+        include_str!("msg_examples/tf2_msgs/msg/TFMessage.msg"),
+    );
     output
 }
 
@@ -197,6 +274,17 @@ fn get_message(folders: &[&str], package: &str, name: &str) -> Result<MessageCas
             let res = Msg::new(package, &format!("{}Res", name), res)?;
             return Ok(MessageCase::Service(name.into(), req, res));
         }
+        let full_path = Path::new(&folder)
+            .join(package)
+            .join("action")
+            .join(name)
+            .with_extension("action");
+        if let Ok(mut f) = File::open(&full_path) {
+            let mut contents = String::new();
+            f.read_to_string(&mut contents)
+                .chain_err(|| "Failed to read file to string!")?;
+            return action_messages(package, name, &contents).map(MessageCase::Action);
+        }
     }
     if let Some(contents) = IN_MEMORY_MESSAGES.get(format!("{}/{}", package, name).as_str()) {
         return Msg::new(package, name, contents).map(MessageCase::Message);
@@ -207,6 +295,62 @@ fn get_message(folders: &[&str], package: &str, name: &str) -> Result<MessageCas
     ));
 }
 
+const ACTION_HEADER: &str =
+    "# ====== DO NOT MODIFY! AUTOGENERATED FROM AN ACTION DEFINITION ======\n";
+
+/// Generates the seven messages that actionlib derives from an action definition.
+fn action_messages(package: &str, name: &str, contents: &str) -> Result<Vec<Msg>> {
+    let re = RegexBuilder::new("^---$").multi_line(true).build()?;
+    let parts = re.split(contents).collect::<Vec<&str>>();
+    if parts.len() != 3 {
+        bail!("Action needs to have goal, result and feedback sections");
+    }
+    let (goal, result, feedback) = (parts[0], parts[1], parts[2]);
+    let action = format!(
+        "{}\n{name}ActionGoal action_goal\n{name}ActionResult action_result\n\
+         {name}ActionFeedback action_feedback\n",
+        ACTION_HEADER,
+        name = name
+    );
+    let action_goal = format!(
+        "{}\nHeader header\nactionlib_msgs/GoalID goal_id\n{}Goal goal\n",
+        ACTION_HEADER, name
+    );
+    let action_result = format!(
+        "{}\nHeader header\nactionlib_msgs/GoalStatus status\n{}Result result\n",
+        ACTION_HEADER, name
+    );
+    let action_feedback = format!(
+        "{}\nHeader header\nactionlib_msgs/GoalStatus status\n{}Feedback feedback\n",
+        ACTION_HEADER, name
+    );
+    Ok(vec![
+        Msg::new(package, &format!("{}Action", name), &action)?,
+        Msg::new(package, &format!("{}ActionGoal", name), &action_goal)?,
+        Msg::new(package, &format!("{}ActionResult", name), &action_result)?,
+        Msg::new(
+            package,
+            &format!("{}ActionFeedback", name),
+            &action_feedback,
+        )?,
+        Msg::new(
+            package,
+            &format!("{}Goal", name),
+            &format!("{}{}", ACTION_HEADER, goal),
+        )?,
+        Msg::new(
+            package,
+            &format!("{}Result", name),
+            &format!("{}{}", ACTION_HEADER, result),
+        )?,
+        Msg::new(
+            package,
+            &format!("{}Feedback", name),
+            &format!("{}{}", ACTION_HEADER, feedback),
+        )?,
+    ])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -271,6 +415,25 @@ mod tests {
         assert!(message_map.contains_key(&("rosgraph_msgs".into(), "Log".into()),));
     }
 
+    #[test]
+    fn finds_fixed_size_messages() {
+        let message_map = get_message_map(
+            &[FILEPATH],
+            &[("geometry_msgs", "PoseStamped"), ("rosgraph_msgs", "Clock")],
+        )
+        .unwrap();
+        let expected = [
+            ("geometry_msgs", "Point"),
+            ("geometry_msgs", "Pose"),
+            ("geometry_msgs", "Quaternion"),
+            ("rosgraph_msgs", "Clock"),
+        ]
+        .iter()
+        .map(|&(package, name)| (package.into(), name.into()))
+        .collect::<HashSet<(String, String)>>();
+        assert_eq!(expected, find_fixed_size_messages(&message_map));
+    }
+
     #[test]
     fn calculate_md5_works() {
         let message_map = get_message_map(
@@ -335,6 +498,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn in_memory_topic_statistics_md5_matches() {
+        let message_map =
+            get_message_map(&[FILEPATH], &[("rosgraph_msgs", "TopicStatistics")]).unwrap();
+        let hashes = calculate_md5(&message_map).unwrap();
+        assert_eq!(
+            *hashes
+                .get(&("rosgraph_msgs".into(), "TopicStatistics".into()))
+                .unwrap(),
+            "10152ed868c5097a5e2e4a89d7daa710".to_owned()
+        );
+    }
+
+    #[test]
+    fn in_memory_tf_message_md5_matches() {
+        let message_map = get_message_map(&[], &[("tf2_msgs", "TFMessage")]).unwrap();
+        let hashes = calculate_md5(&message_map).unwrap();
+        assert_eq!(
+            *hashes
+                .get(&("geometry_msgs".into(), "TransformStamped".into()))
+                .unwrap(),
+            "b5764a33bfeb3588febc2682852579b0".to_owned()
+        );
+        assert_eq!(
+            *hashes
+                .get(&("tf2_msgs".into(), "TFMessage".into()))
+                .unwrap(),
+            "94810edda583a504dfda3829e70d7eec".to_owned()
+        );
+    }
+
     #[test]
     fn generate_message_definition_works() {
         let message_map = get_message_map(&[FILEPATH], &[("geometry_msgs", "Vector3")])
@@ -441,6 +635,29 @@ float64 w\n\
         );
     }
 
+    #[test]
+    fn generate_service_definition_joins_request_and_response() {
+        let message_map = get_message_map(
+            &[FILEPATH],
+            &[
+                ("empty_req_srv", "EmptyRequest"),
+                ("simple_srv", "Something"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            "\n---\nstring message\n",
+            generate_service_definition(&message_map.messages, "empty_req_srv", "EmptyRequest")
+                .unwrap()
+        );
+        let definition =
+            generate_service_definition(&message_map.messages, "simple_srv", "Something").unwrap();
+        assert!(definition.contains("\n---\n"));
+        assert!(
+            generate_service_definition(&message_map.messages, "simple_srv", "Missing").is_err()
+        );
+    }
+
     #[test]
     fn parse_tricky_srv_files() {
         get_message_map(
@@ -453,4 +670,113 @@ float64 w\n\
         )
         .unwrap();
     }
+
+    #[test]
+    fn get_message_map_derives_action_messages() {
+        let message_map = get_message_map(&[FILEPATH], &[("actionlib_tutorials", "Fibonacci")])
+            .unwrap()
+            .messages;
+        assert_eq!(message_map.len(), 10);
+        let field_names = |name: &str| {
+            message_map
+                .get(&("actionlib_tutorials".into(), name.into()))
+                .unwrap()
+                .fields
+                .iter()
+                .map(|v| v.name.clone())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            field_names("FibonacciAction"),
+            vec!["action_goal", "action_result", "action_feedback"],
+        );
+        assert_eq!(
+            field_names("FibonacciActionGoal"),
+            vec!["header", "goal_id", "goal"],
+        );
+        assert_eq!(
+            field_names("FibonacciActionResult"),
+            vec!["header", "status", "result"],
+        );
+        assert_eq!(
+            field_names("FibonacciActionFeedback"),
+            vec!["header", "status", "feedback"],
+        );
+        assert_eq!(field_names("FibonacciGoal"), vec!["order"]);
+        assert_eq!(field_names("FibonacciResult"), vec!["sequence"]);
+        assert_eq!(field_names("FibonacciFeedback"), vec!["sequence"]);
+        assert!(message_map.contains_key(&("actionlib_msgs".into(), "GoalID".into()),));
+        assert!(message_map.contains_key(&("actionlib_msgs".into(), "GoalStatus".into()),));
+        assert!(message_map.contains_key(&("std_msgs".into(), "Header".into()),));
+    }
+
+    #[test]
+    fn get_message_map_allows_empty_action_sections() {
+        let message_map = get_message_map(&[FILEPATH], &[("empty_action", "Trigger")])
+            .unwrap()
+            .messages;
+        let fields = |name: &str| {
+            message_map
+                .get(&("empty_action".into(), name.into()))
+                .unwrap()
+                .fields
+                .len()
+        };
+        assert_eq!(fields("TriggerGoal"), 0);
+        assert_eq!(fields("TriggerResult"), 1);
+        assert_eq!(fields("TriggerFeedback"), 0);
+        assert_eq!(fields("TriggerActionGoal"), 3);
+    }
+
+    #[test]
+    fn action_messages_need_three_sections() {
+        assert!(action_messages("pkg", "Broken", "int32 order\n---\nint32 result\n").is_err());
+        assert!(action_messages("pkg", "Broken", "---\n---\n---\n").is_err());
+    }
+
+    #[test]
+    fn calculate_md5_works_for_actions() {
+        let message_map =
+            get_message_map(&[FILEPATH], &[("actionlib_tutorials", "Fibonacci")]).unwrap();
+        let hashes = calculate_md5(&message_map).unwrap();
+        let md5 = |package: &str, name: &str| {
+            hashes.get(&(package.into(), name.into())).unwrap().as_str()
+        };
+        assert_eq!(
+            md5("actionlib_msgs", "GoalID"),
+            "302881f31927c1df708a2dbab0e80ee8"
+        );
+        assert_eq!(
+            md5("actionlib_msgs", "GoalStatus"),
+            "d388f9b87b3c471f784434d671988d4a"
+        );
+        assert_eq!(
+            md5("actionlib_tutorials", "FibonacciGoal"),
+            "6889063349a00b249bd1661df429d822"
+        );
+        assert_eq!(
+            md5("actionlib_tutorials", "FibonacciResult"),
+            "b81e37d2a31925a0e8ae261a8699cb79"
+        );
+        assert_eq!(
+            md5("actionlib_tutorials", "FibonacciFeedback"),
+            "b81e37d2a31925a0e8ae261a8699cb79"
+        );
+        assert_eq!(
+            md5("actionlib_tutorials", "FibonacciActionGoal"),
+            "006871c7fa1d0e3d5fe2226bf17b2a94"
+        );
+        assert_eq!(
+            md5("actionlib_tutorials", "FibonacciActionResult"),
+            "bee73a9fe29ae25e966e105f5553dd03"
+        );
+        assert_eq!(
+            md5("actionlib_tutorials", "FibonacciActionFeedback"),
+            "73b8497a9f629a31c0020900e4148f07"
+        );
+        assert_eq!(
+            md5("actionlib_tutorials", "FibonacciAction"),
+            "f59df5767bf7634684781c92598b2406"
+        );
+    }
 }
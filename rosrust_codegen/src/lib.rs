@@ -26,10 +26,17 @@ pub fn rosmsg_include(input: TokenStream) -> TokenStream {
             s => next_item += s,
         }
     }
-    let is_internal = next_item == "INTERNAL";
-    if !is_internal && next_item != "" {
+    if next_item != "" {
         messages.push(next_item);
     }
+    let is_internal = take_option(&mut messages, "INTERNAL");
+    let with_builders = take_option(&mut messages, "BUILDERS");
     let message_refs = messages.iter().map(String::as_str).collect::<Vec<&str>>();
-    rosmsg_include::depend_on_messages(&message_refs, is_internal)
+    rosmsg_include::depend_on_messages(&message_refs, is_internal, with_builders)
+}
+
+fn take_option(items: &mut Vec<String>, option: &str) -> bool {
+    let count = items.len();
+    items.retain(|item| item != option);
+    items.len() != count
 }
@@ -1,9 +1,9 @@
-use crate::error::{Result, ResultExt};
+use crate::error::{ErrorKind, Result, ResultExt};
 use lazy_static::lazy_static;
 use proc_macro2::{Literal, Span};
 use quote::{quote, ToTokens};
 use regex::Regex;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use syn::Ident;
 
 #[derive(Clone)]
@@ -29,8 +29,18 @@ impl Msg {
         Ident::new(&self.name, Span::call_site())
     }
 
-    pub fn token_stream<T: ToTokens>(&self, crate_prefix: &T) -> impl ToTokens {
+    pub fn token_stream<T: ToTokens>(
+        &self,
+        crate_prefix: &T,
+        fixed_size: &HashSet<(String, String)>,
+    ) -> impl ToTokens {
         let name = self.name_ident();
+        // Keeping the declared field order lets arrays be copied as a whole
+        let repr = if self.is_fixed_size(fixed_size) {
+            quote! { #[repr(C)] }
+        } else {
+            quote! {}
+        };
         let fields = self
             .fields
             .iter()
@@ -62,6 +72,7 @@ impl Msg {
         quote! {
             #[allow(dead_code, non_camel_case_types, non_snake_case)]
             #[derive(Clone)]
+            #repr
             pub struct #name {
                 #(#fields)*
             }
@@ -94,11 +105,72 @@ impl Msg {
         }
     }
 
-    pub fn token_stream_encode<T: ToTokens>(&self, crate_prefix: &T) -> impl ToTokens {
+    /// Checks that the builder's names are free, given the package's type names.
+    pub fn check_builder(&self, type_names: &HashSet<String>) -> Result<()> {
+        let builder = format!("{}Builder", self.name);
+        if type_names.contains(&builder) {
+            bail!(ErrorKind::BuilderConflict(
+                self.get_type(),
+                format!("the package already has a type called {}", builder),
+            ));
+        }
+        if self
+            .fields
+            .iter()
+            .any(|v| !v.is_constant() && v.name == "build")
+        {
+            bail!(ErrorKind::BuilderConflict(
+                self.get_type(),
+                String::from("the setter of field `build` would clash with finishing the message"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Builder that starts from the default message and sets fields by name.
+    ///
+    /// It is generated on request, after `check_builder` passes.
+    pub fn builder_token_stream<T: ToTokens>(&self, crate_prefix: &T) -> impl ToTokens {
+        let name = self.name_ident();
+        let builder = Ident::new(&format!("{}Builder", self.name), Span::call_site());
+        let setters = self
+            .fields
+            .iter()
+            .map(|v| v.field_setter_token_stream(crate_prefix))
+            .collect::<Vec<_>>();
+        quote! {
+            impl #name {
+                pub fn builder() -> #builder {
+                    #builder::default()
+                }
+            }
+
+            #[allow(dead_code, non_camel_case_types, non_snake_case)]
+            #[derive(Clone, Debug, Default, PartialEq)]
+            pub struct #builder {
+                message: #name,
+            }
+
+            #[allow(dead_code, non_snake_case)]
+            impl #builder {
+                #(#setters)*
+
+                pub fn build(self) -> #name {
+                    self.message
+                }
+            }
+        }
+    }
+
+    pub fn token_stream_encode<T: ToTokens>(
+        &self,
+        crate_prefix: &T,
+        fixed_size: &HashSet<(String, String)>,
+    ) -> impl ToTokens {
         let fields = self
             .fields
             .iter()
-            .map(|v| v.field_token_stream_encode(crate_prefix))
+            .map(|v| v.field_token_stream_encode(crate_prefix, &self.package, fixed_size))
             .collect::<Vec<_>>();
         quote! {
             #(#fields)*
@@ -106,11 +178,15 @@ impl Msg {
         }
     }
 
-    pub fn token_stream_decode<T: ToTokens>(&self, crate_prefix: &T) -> impl ToTokens {
+    pub fn token_stream_decode<T: ToTokens>(
+        &self,
+        crate_prefix: &T,
+        fixed_size: &HashSet<(String, String)>,
+    ) -> impl ToTokens {
         let fields = self
             .fields
             .iter()
-            .map(|v| v.field_token_stream_decode(crate_prefix))
+            .map(|v| v.field_token_stream_decode(crate_prefix, &self.package, fixed_size))
             .collect::<Vec<_>>();
         quote! {
             Ok(Self {
@@ -170,6 +246,66 @@ impl Msg {
         Ok(representation)
     }
 
+    /// Whether every field has a fixed encoded length, given the messages known to have one.
+    pub fn is_fixed_size(&self, fixed_size: &HashSet<(String, String)>) -> bool {
+        self.fields.iter().all(|field| match field.case {
+            FieldCase::Const(_) => true,
+            FieldCase::Vector => false,
+            FieldCase::Unit | FieldCase::Array(_) => {
+                field.datatype.is_fixed_size(&self.package, fixed_size)
+            }
+        })
+    }
+
+    pub fn fixed_size_token_stream<T: ToTokens>(
+        &self,
+        crate_prefix: &T,
+        fixed_size: &HashSet<(String, String)>,
+    ) -> impl ToTokens {
+        if !self.is_fixed_size(fixed_size) {
+            return quote! {};
+        }
+        let name = self.name_ident();
+        let (lengths, layouts): (Vec<_>, Vec<_>) = self
+            .fields
+            .iter()
+            .filter_map(|field| {
+                let datatype = field.datatype.token_stream(crate_prefix);
+                let count = match field.case {
+                    FieldCase::Unit => 1,
+                    FieldCase::Array(l) => l,
+                    FieldCase::Vector | FieldCase::Const(_) => return None,
+                };
+                let fixed_size = quote! { <#datatype as #crate_prefix rosmsg::FixedSize> };
+                Some((
+                    quote! { #fixed_size::ENCODED_LEN * #count },
+                    quote! { #fixed_size::MATCHES_MEMORY_LAYOUT },
+                ))
+            })
+            .unzip();
+        quote! {
+            unsafe impl #crate_prefix rosmsg::FixedSize for #name {
+                const ENCODED_LEN: usize = 0 #(+ #lengths)*;
+                const MATCHES_MEMORY_LAYOUT: bool = true #(&& #layouts)*
+                    && ::std::mem::size_of::<Self>() == Self::ENCODED_LEN;
+            }
+        }
+    }
+
+    pub fn min_encoded_len_token_stream<T: ToTokens>(&self, crate_prefix: &T) -> impl ToTokens {
+        let lengths = self.fields.iter().filter_map(|field| {
+            let datatype = field.datatype.token_stream(crate_prefix);
+            let min_len = quote! { <#datatype as #crate_prefix rosmsg::RosMsg>::MIN_ENCODED_LEN };
+            match field.case {
+                FieldCase::Unit => Some(min_len),
+                FieldCase::Array(l) => Some(quote! { #min_len * #l }),
+                FieldCase::Vector => Some(quote! { 4 }),
+                FieldCase::Const(_) => None,
+            }
+        });
+        quote! { 0 #(+ #lengths)* }
+    }
+
     pub fn has_header(&self) -> bool {
         self.fields.iter().any(FieldInfo::is_header)
     }
@@ -295,7 +431,7 @@ fn match_const_string(data: &str) -> Option<(FieldLine, String)> {
 fn match_const_numeric(data: &str) -> Option<(FieldLine, String)> {
     lazy_static! {
         static ref MATCHER: String = format!(
-            r"^{}{}{}{}={}(-?[0-9]+)$",
+            r"^{}{}{}{}={}(-?[0-9]*\.?[0-9]+(?:[eE][-+]?[0-9]+)?)$",
             FIELD_TYPE, ANY_WHITESPACE, FIELD_NAME, IGNORE_WHITESPACE, IGNORE_WHITESPACE
         );
         static ref RE: Regex = Regex::new(&MATCHER).unwrap();
@@ -313,6 +449,32 @@ fn match_const_numeric(data: &str) -> Option<(FieldLine, String)> {
     ))
 }
 
+fn match_default_field(data: &str) -> Option<(FieldLine, FieldCase, String)> {
+    lazy_static! {
+        static ref MATCHER: String = format!(
+            r"^{}{}(\[\s*([0-9]*)\s*\])?{}{}{}([^=\s].*)$",
+            FIELD_TYPE, IGNORE_WHITESPACE, ANY_WHITESPACE, FIELD_NAME, ANY_WHITESPACE
+        );
+        static ref RE: Regex = Regex::new(&MATCHER).unwrap();
+    }
+    let captures = RE.captures(data)?;
+    let case = match (captures.get(2), captures.get(3)) {
+        (None, _) => FieldCase::Unit,
+        (Some(_), Some(count)) if !count.as_str().is_empty() => {
+            FieldCase::Array(count.as_str().parse().unwrap())
+        }
+        (Some(_), _) => FieldCase::Vector,
+    };
+    Some((
+        FieldLine {
+            field_type: captures.get(1).unwrap().as_str().into(),
+            field_name: captures.get(4).unwrap().as_str().into(),
+        },
+        case,
+        captures.get(5).unwrap().as_str().into(),
+    ))
+}
+
 fn match_line(data: &str) -> Option<Result<FieldInfo>> {
     if let Some((info, data)) = match_const_string(data.trim()) {
         return Some(FieldInfo::new(
@@ -351,19 +513,34 @@ fn match_line(data: &str) -> Option<Result<FieldInfo>> {
         ));
     }
     if let Some((info, data)) = match_const_numeric(data) {
-        return Some(FieldInfo::new(
-            &info.field_type,
-            &info.field_name,
-            FieldCase::Const(data),
-        ));
+        return Some(
+            FieldInfo::new(
+                &info.field_type,
+                &info.field_name,
+                FieldCase::Const(data.clone()),
+            )
+            .and_then(|field| {
+                if field.datatype.is_numeric() {
+                    parse_default_item(&field.datatype, &data)
+                        .chain_err(|| format!("Invalid value for constant {}", field.name))?;
+                }
+                Ok(field)
+            }),
+        );
+    }
+    if let Some((info, case, value)) = match_default_field(data) {
+        return Some(
+            FieldInfo::new(&info.field_type, &info.field_name, case)
+                .and_then(|field| field.with_default(&value)),
+        );
     }
     Some(Err(format!("Unsupported content of line: {}", data).into()))
 }
 
 #[inline]
 fn strip_useless(data: &str) -> Result<&str> {
-    Ok(data
-        .splitn(2, '#')
+    Ok(split_unquoted(data, '#')
+        .into_iter()
         .next()
         .ok_or_else(|| {
             format!(
@@ -374,6 +551,125 @@ fn strip_useless(data: &str) -> Result<&str> {
         .trim())
 }
 
+fn split_unquoted(data: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, c) in data.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == separator => {
+                parts.push(&data[start..idx]);
+                start = idx + c.len_utf8();
+            }
+            None => {}
+        }
+    }
+    parts.push(&data[start..]);
+    parts
+}
+
+fn parse_default(datatype: &DataType, case: &FieldCase, data: &str) -> Result<DefaultValue> {
+    match *case {
+        FieldCase::Unit => Ok(DefaultValue::Single(parse_default_item(datatype, data)?)),
+        FieldCase::Vector | FieldCase::Array(_) => {
+            let inner = data
+                .strip_prefix('[')
+                .and_then(|v| v.strip_suffix(']'))
+                .ok_or_else(|| format!("Array default must be enclosed in brackets: {}", data))?;
+            let items = if inner.trim().is_empty() {
+                vec![]
+            } else {
+                split_unquoted(inner, ',')
+                    .into_iter()
+                    .map(|item| parse_default_item(datatype, item.trim()))
+                    .collect::<Result<Vec<_>>>()?
+            };
+            if let FieldCase::Array(l) = *case {
+                if items.len() != l {
+                    bail!("Expected {} default values, but got {}", l, items.len());
+                }
+            }
+            Ok(DefaultValue::List(items))
+        }
+        FieldCase::Const(_) => bail!("Constants cannot have default values"),
+    }
+}
+
+fn parse_default_item(datatype: &DataType, data: &str) -> Result<String> {
+    match *datatype {
+        DataType::Bool => match data {
+            "true" | "True" | "1" => Ok("true".into()),
+            "false" | "False" | "0" => Ok("false".into()),
+            _ => bail!("Default value {} is not a valid bool", data),
+        },
+        DataType::I8(_) => check_default_integer::<i8>(data),
+        DataType::I16 => check_default_integer::<i16>(data),
+        DataType::I32 => check_default_integer::<i32>(data),
+        DataType::I64 => check_default_integer::<i64>(data),
+        DataType::U8(_) => check_default_integer::<u8>(data),
+        DataType::U16 => check_default_integer::<u16>(data),
+        DataType::U32 => check_default_integer::<u32>(data),
+        DataType::U64 => check_default_integer::<u64>(data),
+        DataType::F32 => match data.parse::<f32>() {
+            Ok(v) if v.is_finite() => Ok(data.into()),
+            _ => bail!("Default value {} is not a valid f32", data),
+        },
+        DataType::F64 => match data.parse::<f64>() {
+            Ok(v) if v.is_finite() => Ok(data.into()),
+            _ => bail!("Default value {} is not a valid f64", data),
+        },
+        DataType::String => unquote_default(data),
+        DataType::Time
+        | DataType::Duration
+        | DataType::LocalStruct(..)
+        | DataType::RemoteStruct(..) => {
+            bail!("Default values are only supported for primitive types")
+        }
+    }
+}
+
+fn check_default_integer<T: std::str::FromStr>(data: &str) -> Result<String> {
+    match data.parse::<T>() {
+        Ok(_) => Ok(data.into()),
+        Err(_) => bail!(
+            "Default value {} is not a valid {}",
+            data,
+            std::any::type_name::<T>()
+        ),
+    }
+}
+
+fn unquote_default(data: &str) -> Result<String> {
+    let quote = match data.chars().next() {
+        Some(c) if c == '"' || c == '\'' => c,
+        _ => return Ok(data.into()),
+    };
+    if data.len() < 2 || !data.ends_with(quote) {
+        bail!("Unterminated string default: {}", data);
+    }
+    let mut output = String::new();
+    let mut chars = data[1..data.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        output.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some(c) => c,
+            None => bail!("Unterminated string default: {}", data),
+        });
+    }
+    Ok(output)
+}
+
 #[inline]
 fn match_lines(data: &str) -> Result<Vec<FieldInfo>> {
     data.split('\n')
@@ -396,11 +692,18 @@ pub enum FieldCase {
     Const(String),
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum DefaultValue {
+    Single(String),
+    List(Vec<String>),
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct FieldInfo {
     pub datatype: DataType,
     pub name: String,
     pub case: FieldCase,
+    pub default: Option<DefaultValue>,
 }
 
 impl FieldInfo {
@@ -430,6 +733,23 @@ impl FieldInfo {
         }
     }
 
+    pub fn field_setter_token_stream<T: ToTokens>(&self, crate_prefix: &T) -> impl ToTokens {
+        let datatype = self.datatype.token_stream(crate_prefix);
+        let name = self.create_identifier(Span::call_site());
+        let datatype = match self.case {
+            FieldCase::Unit => quote! { #datatype },
+            FieldCase::Vector => quote! { Vec<#datatype> },
+            FieldCase::Array(l) => quote! { [#datatype; #l] },
+            FieldCase::Const(_) => return quote! {},
+        };
+        quote! {
+            pub fn #name<V: Into<#datatype>>(mut self, value: V) -> Self {
+                self.message.#name = value.into();
+                self
+            }
+        }
+    }
+
     pub fn field_name_eq_and_debug_token_stream(
         &self,
     ) -> Option<(impl ToTokens, impl ToTokens, impl ToTokens)> {
@@ -449,18 +769,47 @@ impl FieldInfo {
 
     pub fn field_default_token_stream<T: ToTokens>(&self, _crate_prefix: &T) -> impl ToTokens {
         let name = self.create_identifier(Span::call_site());
-        match self.case {
-            FieldCase::Unit | FieldCase::Vector => quote! { #name: Default::default(), },
-            FieldCase::Array(l) => quote! { #name: [Default::default(); #l], },
-            FieldCase::Const(_) => quote! {},
+        match (&self.case, &self.default) {
+            (&FieldCase::Unit, &Some(DefaultValue::Single(ref value))) => {
+                let value = self.datatype.default_value_token_stream(value);
+                quote! { #name: #value, }
+            }
+            (&FieldCase::Vector, &Some(DefaultValue::List(ref values))) => {
+                let values = values
+                    .iter()
+                    .map(|v| self.datatype.default_value_token_stream(v));
+                quote! { #name: vec![#(#values),*], }
+            }
+            (&FieldCase::Array(_), &Some(DefaultValue::List(ref values))) => {
+                let values = values
+                    .iter()
+                    .map(|v| self.datatype.default_value_token_stream(v));
+                quote! { #name: [#(#values),*], }
+            }
+            (&FieldCase::Array(l), _) => quote! { #name: [Default::default(); #l], },
+            (&FieldCase::Const(_), _) => quote! {},
+            _ => quote! { #name: Default::default(), },
         }
     }
 
-    pub fn field_token_stream_encode<T: ToTokens>(&self, crate_prefix: &T) -> impl ToTokens {
+    pub fn field_token_stream_encode<T: ToTokens>(
+        &self,
+        crate_prefix: &T,
+        package: &str,
+        fixed_size: &HashSet<(String, String)>,
+    ) -> impl ToTokens {
         let name = self.create_identifier(Span::call_site());
         match self.case {
             FieldCase::Unit => quote! { self.#name.encode(w.by_ref())?; },
             FieldCase::Vector => match self.datatype {
+                DataType::Time
+                | DataType::Duration
+                | DataType::LocalStruct(_)
+                | DataType::RemoteStruct(_, _)
+                    if self.datatype.is_fixed_size(package, fixed_size) =>
+                {
+                    quote! { #crate_prefix rosmsg::encode_variable_fixed_size_slice(&self.#name, w.by_ref())?; }
+                }
                 DataType::String
                 | DataType::Time
                 | DataType::Duration
@@ -468,6 +817,9 @@ impl FieldInfo {
                 | DataType::RemoteStruct(_, _) => {
                     quote! { #crate_prefix rosmsg::encode_variable_slice(&self.#name, w.by_ref())?; }
                 }
+                DataType::U8(_) => {
+                    quote! { #crate_prefix rosmsg::encode_byte_slice(&self.#name, w.by_ref())?; }
+                }
                 _ => {
                     quote! { #crate_prefix rosmsg::encode_variable_primitive_slice(&self.#name, w.by_ref())?; }
                 }
@@ -479,11 +831,24 @@ impl FieldInfo {
         }
     }
 
-    pub fn field_token_stream_decode<T: ToTokens>(&self, crate_prefix: &T) -> impl ToTokens {
+    pub fn field_token_stream_decode<T: ToTokens>(
+        &self,
+        crate_prefix: &T,
+        package: &str,
+        fixed_size: &HashSet<(String, String)>,
+    ) -> impl ToTokens {
         let name = self.create_identifier(Span::call_site());
         match self.case {
             FieldCase::Unit => quote! { #name: #crate_prefix rosmsg::RosMsg::decode(r.by_ref())?, },
             FieldCase::Vector => match self.datatype {
+                DataType::Time
+                | DataType::Duration
+                | DataType::LocalStruct(_)
+                | DataType::RemoteStruct(_, _)
+                    if self.datatype.is_fixed_size(package, fixed_size) =>
+                {
+                    quote! { #name: #crate_prefix rosmsg::decode_variable_fixed_size_vec(r.by_ref())?, }
+                }
                 DataType::String
                 | DataType::Time
                 | DataType::Duration
@@ -491,6 +856,9 @@ impl FieldInfo {
                 | DataType::RemoteStruct(_, _) => {
                     quote! { #name: #crate_prefix rosmsg::decode_variable_vec(r.by_ref())?, }
                 }
+                DataType::U8(_) => {
+                    quote! { #name: #crate_prefix rosmsg::decode_byte_vec(r.by_ref())?, }
+                }
                 _ => {
                     quote! { #name: #crate_prefix rosmsg::decode_variable_primitive_vec(r.by_ref())?, }
                 }
@@ -518,7 +886,7 @@ impl FieldInfo {
                 } else {
                     quote! { false }
                 };
-                quote! { #name; bool = #bool_value }
+                quote! { #name: bool = #bool_value }
             }
             DataType::String => quote! { #name: &'static str = #value },
             DataType::Time
@@ -598,8 +966,16 @@ impl FieldInfo {
                 .ok_or_else(|| format!("Unsupported datatype: {}", datatype))?,
             name: name.to_owned(),
             case,
+            default: None,
         })
     }
+
+    fn with_default(mut self, value: &str) -> Result<FieldInfo> {
+        let default = parse_default(&self.datatype, &self.case, value)
+            .chain_err(|| format!("Invalid default value for field {}", self.name))?;
+        self.default = Some(default);
+        Ok(self)
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -651,6 +1027,55 @@ impl DataType {
         }
     }
 
+    fn default_value_token_stream(&self, value: &str) -> proc_macro2::TokenStream {
+        match *self {
+            DataType::Bool => match value {
+                "true" => quote! { true },
+                _ => quote! { false },
+            },
+            DataType::I8(_) => Literal::i8_suffixed(value.parse().unwrap()).into_token_stream(),
+            DataType::I16 => Literal::i16_suffixed(value.parse().unwrap()).into_token_stream(),
+            DataType::I32 => Literal::i32_suffixed(value.parse().unwrap()).into_token_stream(),
+            DataType::I64 => Literal::i64_suffixed(value.parse().unwrap()).into_token_stream(),
+            DataType::U8(_) => Literal::u8_suffixed(value.parse().unwrap()).into_token_stream(),
+            DataType::U16 => Literal::u16_suffixed(value.parse().unwrap()).into_token_stream(),
+            DataType::U32 => Literal::u32_suffixed(value.parse().unwrap()).into_token_stream(),
+            DataType::U64 => Literal::u64_suffixed(value.parse().unwrap()).into_token_stream(),
+            DataType::F32 => Literal::f32_suffixed(value.parse().unwrap()).into_token_stream(),
+            DataType::F64 => Literal::f64_suffixed(value.parse().unwrap()).into_token_stream(),
+            DataType::String => quote! { ::std::string::String::from(#value) },
+            DataType::Time
+            | DataType::Duration
+            | DataType::LocalStruct(..)
+            | DataType::RemoteStruct(..) => quote! { Default::default() },
+        }
+    }
+
+    fn is_fixed_size(&self, package: &str, fixed_size: &HashSet<(String, String)>) -> bool {
+        match *self {
+            DataType::String => false,
+            DataType::LocalStruct(ref name) => {
+                fixed_size.contains(&(package.to_owned(), name.clone()))
+            }
+            DataType::RemoteStruct(ref pkg, ref name) => {
+                fixed_size.contains(&(pkg.clone(), name.clone()))
+            }
+            _ => true,
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        !matches!(
+            *self,
+            DataType::Bool
+                | DataType::String
+                | DataType::Time
+                | DataType::Duration
+                | DataType::LocalStruct(_)
+                | DataType::RemoteStruct(_, _)
+        )
+    }
+
     fn is_builtin(&self) -> bool {
         match *self {
             DataType::Bool
@@ -916,6 +1341,139 @@ mod tests {
         );
     }
 
+    #[test]
+    fn message_md5_matches_ros_table() {
+        let diagnostic_status = "byte OK=0\nbyte WARN=1\nbyte ERROR=2\nbyte STALE=3\n\
+                                 byte level\nstring name\nstring message\n\
+                                 string hardware_id\nKeyValue[] values";
+        let table = [
+            (
+                "std_msgs",
+                "Header",
+                include_str!("msg_examples/std_msgs/msg/Header.msg"),
+                "2176decaecbce78abc3b96ef049fabed",
+            ),
+            (
+                "std_msgs",
+                "ColorRGBA",
+                include_str!("msg_examples/std_msgs/msg/ColorRGBA.msg"),
+                "a29a96539573343b1310c73607334b00",
+            ),
+            (
+                "geometry_msgs",
+                "Vector3",
+                include_str!("msg_examples/geometry_msgs/msg/Vector3.msg"),
+                "4a842b65f413084dc2b10fb484ea7f17",
+            ),
+            (
+                "geometry_msgs",
+                "Twist",
+                "Vector3 linear\nVector3 angular",
+                "9f195f881246fdfa2798d1d3eebca84a",
+            ),
+            (
+                "geometry_msgs",
+                "TwistWithCovariance",
+                include_str!("msg_examples/geometry_msgs/msg/TwistWithCovariance.msg"),
+                "1fe8a28e6890a4cc3ae4c3ca5c7d82e6",
+            ),
+            (
+                "geometry_msgs",
+                "PoseStamped",
+                include_str!("msg_examples/geometry_msgs/msg/PoseStamped.msg"),
+                "d3812c3cbc69362b77dc0b19b345f8f5",
+            ),
+            (
+                "rosgraph_msgs",
+                "Clock",
+                include_str!("msg_examples/rosgraph_msgs/msg/Clock.msg"),
+                "a9c97c1d230cfc112e270351a944ee47",
+            ),
+            (
+                "rosgraph_msgs",
+                "Log",
+                include_str!("msg_examples/rosgraph_msgs/msg/Log.msg"),
+                "acffd30cd6b6de30f120938c17c593fb",
+            ),
+            (
+                "sensor_msgs",
+                "Image",
+                include_str!("msg_examples/sensor_msgs/msg/Image.msg"),
+                "060021388200f6f0f447d0fcd9c64743",
+            ),
+            (
+                "sensor_msgs",
+                "Imu",
+                include_str!("msg_examples/sensor_msgs/msg/Imu.msg"),
+                "6a62c6daae103f4ff57a132d6f95cec2",
+            ),
+            (
+                "diagnostic_msgs",
+                "KeyValue",
+                "string key\nstring value",
+                "cf57fdc6617a881a88c16e768132149c",
+            ),
+            (
+                "diagnostic_msgs",
+                "DiagnosticStatus",
+                diagnostic_status,
+                "d0ce08bc6e5ba34c7754f563a9cabaf1",
+            ),
+        ];
+        let mut hashes = HashMap::new();
+        for &(package, name, source, md5) in &table {
+            let message = Msg::new(package, name, source).unwrap();
+            assert_eq!(
+                md5,
+                message.calculate_md5(&hashes).unwrap(),
+                "md5sum mismatch for {}/{}",
+                package,
+                name
+            );
+            hashes.insert((package.into(), name.into()), md5.into());
+            if (package, name) == ("geometry_msgs", "Vector3") {
+                hashes.insert(("geometry_msgs".into(), "Point".into()), md5.into());
+                hashes.insert(
+                    ("geometry_msgs".into(), "Quaternion".into()),
+                    "a779879fadf0160734f906b8c19c7004".into(),
+                );
+                hashes.insert(
+                    ("geometry_msgs".into(), "Pose".into()),
+                    "e45d45a5a1ce597b249e23fb30fc871f".into(),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn message_md5_hoists_interleaved_constants() {
+        let hashes = HashMap::new();
+        let interleaved = Msg::new(
+            "p1",
+            "Mixed",
+            "int32 a\nint32 FIRST=1\nfloat64[] b\nstring SECOND=two words\nuint8[4] c",
+        )
+        .unwrap();
+        assert_eq!(
+            "int32 FIRST=1\nstring SECOND=two words\nint32 a\nfloat64[] b\nuint8[4] c",
+            interleaved.get_md5_representation(&hashes).unwrap()
+        );
+
+        let mut hashes = HashMap::new();
+        hashes.insert(("p1".into(), "Item".into()), "0123".into());
+        hashes.insert(("p2".into(), "Other".into()), "4567".into());
+        let nested = Msg::new(
+            "p1",
+            "Nested",
+            "Item[] items\nbyte KIND=3\np2/Other[2] others\nItem single",
+        )
+        .unwrap();
+        assert_eq!(
+            "byte KIND=3\n0123 items\n4567 others\n0123 single",
+            nested.get_md5_representation(&hashes).unwrap()
+        );
+    }
+
     #[test]
     fn match_field_matches_legal_field() {
         assert_eq!(
@@ -980,6 +1538,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn match_line_validates_numeric_constants() {
+        assert_eq!(
+            FieldInfo {
+                datatype: DataType::F64,
+                name: "PI".into(),
+                case: FieldCase::Const("3.14159".into()),
+                default: None,
+            },
+            match_line("float64 PI = 3.14159 # approximately")
+                .unwrap()
+                .unwrap()
+        );
+        assert!(match_line("float32 SMALL=1e-3").unwrap().is_ok());
+        assert!(match_line("int32 HALF=0.5").unwrap().is_err());
+        assert!(match_line("uint8 BIG=256").unwrap().is_err());
+    }
+
     #[test]
     fn match_line_works_on_legal_data() {
         assert!(match_line("#just a comment").is_none());
@@ -991,6 +1567,7 @@ mod tests {
                 datatype: DataType::RemoteStruct("geom_msgs".into(), "Twist".into()),
                 name: "myname".into(),
                 case: FieldCase::Unit,
+                default: None,
             },
             match_line("  geom_msgs/Twist   myname    # this clearly should succeed",)
                 .unwrap()
@@ -1002,6 +1579,7 @@ mod tests {
                 datatype: DataType::RemoteStruct("geom_msgs".into(), "Twist".into()),
                 name: "myname".into(),
                 case: FieldCase::Vector,
+                default: None,
             },
             match_line("  geom_msgs/Twist [  ]   myname  # ...")
                 .unwrap()
@@ -1013,6 +1591,7 @@ mod tests {
                 datatype: DataType::U8(false),
                 name: "myname".into(),
                 case: FieldCase::Array(127),
+                default: None,
             },
             match_line("  char   [   127 ]   myname# comment")
                 .unwrap()
@@ -1023,6 +1602,7 @@ mod tests {
                 datatype: DataType::String,
                 name: "myname".into(),
                 case: FieldCase::Const("this is # data".into()),
+                default: None,
             },
             match_line("  string  myname =   this is # data  ")
                 .unwrap()
@@ -1033,6 +1613,7 @@ mod tests {
                 datatype: DataType::RemoteStruct("geom_msgs".into(), "Twist".into()),
                 name: "myname".into(),
                 case: FieldCase::Const("-444".into()),
+                default: None,
             },
             match_line("  geom_msgs/Twist  myname =   -444 # data  ")
                 .unwrap()
@@ -1040,6 +1621,366 @@ mod tests {
         );
     }
 
+    #[test]
+    fn match_line_parses_default_values() {
+        assert_eq!(
+            FieldInfo {
+                datatype: DataType::U8(true),
+                name: "level".into(),
+                case: FieldCase::Unit,
+                default: Some(DefaultValue::Single("20".into())),
+            },
+            match_line("  uint8 level   20 # warn").unwrap().unwrap()
+        );
+        assert_eq!(
+            FieldInfo {
+                datatype: DataType::String,
+                name: "label".into(),
+                case: FieldCase::Unit,
+                default: Some(DefaultValue::Single("say \"hi\" # now".into())),
+            },
+            match_line(r#"string label "say \"hi\" # now" # comment"#)
+                .unwrap()
+                .unwrap()
+        );
+        assert_eq!(
+            FieldInfo {
+                datatype: DataType::String,
+                name: "names".into(),
+                case: FieldCase::Vector,
+                default: Some(DefaultValue::List(vec!["a, b".into(), "c".into()])),
+            },
+            match_line("string[] names ['a, b', c]").unwrap().unwrap()
+        );
+        assert_eq!(
+            FieldInfo {
+                datatype: DataType::F32,
+                name: "gains".into(),
+                case: FieldCase::Array(3),
+                default: Some(DefaultValue::List(vec![
+                    "1.5".into(),
+                    "-2".into(),
+                    "0".into()
+                ])),
+            },
+            match_line("float32[3] gains [1.5, -2, 0]")
+                .unwrap()
+                .unwrap()
+        );
+        assert_eq!(
+            FieldInfo {
+                datatype: DataType::Bool,
+                name: "enabled".into(),
+                case: FieldCase::Unit,
+                default: Some(DefaultValue::Single("true".into())),
+            },
+            match_line("bool enabled True").unwrap().unwrap()
+        );
+    }
+
+    #[test]
+    fn match_line_rejects_invalid_default_values() {
+        assert!(match_line("uint8 level 256").unwrap().is_err());
+        assert!(match_line("int8 level -129").unwrap().is_err());
+        assert!(match_line("uint32 level -1").unwrap().is_err());
+        assert!(match_line("float32 gain 1e39").unwrap().is_err());
+        assert!(match_line("bool enabled yes").unwrap().is_err());
+        assert!(match_line("string label \"open").unwrap().is_err());
+        assert!(match_line("int32[2] values [1, 2, 3]").unwrap().is_err());
+        assert!(match_line("int32[] values 1, 2").unwrap().is_err());
+        assert!(match_line("time stamp 0").unwrap().is_err());
+    }
+
+    #[test]
+    fn default_impl_uses_declared_defaults() {
+        let message = Msg::new(
+            "p1",
+            "Defaults",
+            "uint8 level 20\nint16 offset -3\nfloat64 ratio 0.5\nbool enabled true\n\
+             string label \"a \\\"b\\\"\"\nint32[] values [1, 2]\nuint8[2] pair [3, 4]\n\
+             string[] empty []\nint32 plain\nint32 LIMIT=5\n",
+        )
+        .unwrap();
+        let tokens = message.token_stream(&quote! { rosrust:: }, &HashSet::new());
+        let code = quote! { #tokens }.to_string().replace(' ', "");
+        for expected in &[
+            "level:20u8,",
+            "offset:-3i16,",
+            "ratio:0.5f64,",
+            "enabled:true,",
+            r#"label:::std::string::String::from("a\"b\""),"#,
+            "values:vec![1i32,2i32],",
+            "pair:[3u8,4u8],",
+            "empty:vec![],",
+            "plain:Default::default(),",
+        ] {
+            assert!(code.contains(expected), "{} not in {}", expected, code);
+        }
+        assert!(!code.contains("LIMIT:Default"));
+    }
+
+    #[test]
+    fn builder_sets_fields_by_name() {
+        let message = Msg::new(
+            "p1",
+            "Pose",
+            "int32 x\nstring[] names\nuint8[2] pair\nint32 A=1\n",
+        )
+        .unwrap();
+        let tokens = message.token_stream(&quote! { rosrust:: }, &HashSet::new());
+        let code = quote! { #tokens }.to_string();
+        assert!(!code.contains("PoseBuilder"), "{}", code);
+
+        let tokens = message.builder_token_stream(&quote! { rosrust:: });
+        let code = quote! { #tokens }.to_string().replace(' ', "");
+        for expected in &[
+            "pubfnbuilder()->PoseBuilder{",
+            "pubfnx<V:Into<i32>>(mutself,value:V)->Self{self.message.x=value.into();",
+            "pubfnnames<V:Into<Vec<::std::string::String>>>",
+            "pubfnpair<V:Into<[u8;2usize]>>",
+            "pubfnbuild(self)->Pose{",
+        ] {
+            assert!(code.contains(expected), "{} not in {}", expected, code);
+        }
+        assert!(!code.contains("pubfnA<"));
+
+        let mut type_names = HashSet::new();
+        type_names.insert(String::from("Pose"));
+        assert!(message.check_builder(&type_names).is_ok());
+    }
+
+    #[test]
+    fn builder_reports_name_conflicts() {
+        let type_names = HashSet::new();
+        let field = Msg::new("p1", "Job", "string build\n").unwrap();
+        let err = field.check_builder(&type_names).unwrap_err().to_string();
+        assert!(err.contains("p1/Job"), "{}", err);
+        assert!(err.contains("`build`"), "{}", err);
+
+        let constant = Msg::new("p1", "Job", "int32 build=1\n").unwrap();
+        assert!(constant.check_builder(&type_names).is_ok());
+
+        let mut type_names = HashSet::new();
+        type_names.insert(String::from("Job"));
+        type_names.insert(String::from("JobBuilder"));
+        let message = Msg::new("p1", "Job", "string name\n").unwrap();
+        let err = message.check_builder(&type_names).unwrap_err().to_string();
+        assert!(err.contains("JobBuilder"), "{}", err);
+    }
+
+    #[test]
+    fn time_and_duration_map_to_rosrust_types() {
+        let message = Msg::new("p1", "Timing", "time stamp\nduration timeout\n").unwrap();
+        let prefix = quote! { rosrust:: };
+        let tokens = message.token_stream(&prefix, &HashSet::new());
+        let code = quote! { #tokens }.to_string().replace(' ', "");
+        assert!(code.contains("pubstamp:rosrust::Time,"), "{}", code);
+        assert!(code.contains("pubtimeout:rosrust::Duration,"), "{}", code);
+
+        let tokens = message.token_stream_encode(&prefix, &HashSet::new());
+        let code = quote! { #tokens }.to_string().replace(' ', "");
+        assert!(code.contains("self.stamp.encode(w.by_ref())?;"), "{}", code);
+        assert!(
+            code.contains("self.timeout.encode(w.by_ref())?;"),
+            "{}",
+            code
+        );
+        assert!(message.is_fixed_size(&HashSet::new()));
+    }
+
+    #[test]
+    fn encode_and_decode_visit_fields_in_declared_order() {
+        let message = Msg::new(
+            "p1",
+            "Layout",
+            "int32 alpha\nstring[] bravo\nint32 LIMIT=5\nuint8[3] charlie\n\
+             p2/Other delta\ntime echo\n",
+        )
+        .unwrap();
+        let prefix = quote! { rosrust:: };
+        let fields = ["alpha", "bravo", "charlie", "delta", "echo"];
+
+        let tokens = message.token_stream_encode(&prefix, &HashSet::new());
+        let code = quote! { #tokens }.to_string().replace(' ', "");
+        let positions = fields
+            .iter()
+            .map(|field| {
+                let pattern = format!("self.{}", field);
+                assert_eq!(1, code.matches(&pattern).count(), "{} in {}", pattern, code);
+                code.find(&pattern).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!(positions.windows(2).all(|v| v[0] < v[1]), "{}", code);
+        assert!(!code.contains("LIMIT"));
+
+        let tokens = message.token_stream_decode(&prefix, &HashSet::new());
+        let code = quote! { #tokens }.to_string().replace(' ', "");
+        let positions = fields
+            .iter()
+            .map(|field| {
+                let pattern = format!("{}:", field);
+                assert_eq!(1, code.matches(&pattern).count(), "{} in {}", pattern, code);
+                code.find(&pattern).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!(positions.windows(2).all(|v| v[0] < v[1]), "{}", code);
+        assert!(!code.contains("LIMIT"));
+    }
+
+    #[test]
+    fn default_values_do_not_change_md5() {
+        let hashes = HashMap::new();
+        let plain = Msg::new("p1", "Plain", "uint8 level\nstring[] names\n").unwrap();
+        let defaulted =
+            Msg::new("p1", "Plain", "uint8 level 20\nstring[] names [\"a\"]\n").unwrap();
+        assert_eq!(
+            plain.get_md5_representation(&hashes),
+            defaulted.get_md5_representation(&hashes)
+        );
+    }
+
+    #[test]
+    fn byte_arrays_use_bulk_encoding() {
+        let message = Msg::new("p1", "Blob", "uint8[] data\nchar[] text\nint8[] values\n").unwrap();
+        let prefix = quote! { rosrust:: };
+        let tokens = message.token_stream(&prefix, &HashSet::new());
+        let encode = message.token_stream_encode(&prefix, &HashSet::new());
+        let decode = message.token_stream_decode(&prefix, &HashSet::new());
+        let code = quote! { #tokens #encode #decode }
+            .to_string()
+            .replace(' ', "");
+        for expected in &[
+            "pubdata:Vec<u8>,",
+            "rosrust::rosmsg::encode_byte_slice(&self.data,w.by_ref())?;",
+            "rosrust::rosmsg::encode_byte_slice(&self.text,w.by_ref())?;",
+            "data:rosrust::rosmsg::decode_byte_vec(r.by_ref())?,",
+            "values:rosrust::rosmsg::decode_variable_primitive_vec(r.by_ref())?,",
+        ] {
+            assert!(code.contains(expected), "{} not in {}", expected, code);
+        }
+    }
+
+    fn fixed_size_set(messages: &[(&str, &str)]) -> HashSet<(String, String)> {
+        messages
+            .iter()
+            .map(|&(package, name)| (package.into(), name.into()))
+            .collect()
+    }
+
+    #[test]
+    fn detects_fixed_size_messages() {
+        let fixed_size = fixed_size_set(&[("p2", "Pose")]);
+        let fixed = Msg::new(
+            "p1",
+            "Sample",
+            "float64 x\nint32 LIMIT=3\nuint8[4] data\ntime stamp\nbool flag\np2/Pose pose\n",
+        )
+        .unwrap();
+        assert!(fixed.is_fixed_size(&fixed_size));
+        for source in &[
+            "float64 x\nstring label\n",
+            "float64 x\nfloat64[] values\n",
+            "float64 x\np2/Other other\n",
+            "float64 x\nPose[2] poses\n",
+        ] {
+            let message = Msg::new("p1", "Sample", source).unwrap();
+            assert!(!message.is_fixed_size(&fixed_size), "{}", source);
+        }
+    }
+
+    #[test]
+    fn fixed_size_messages_implement_marker_trait() {
+        let fixed_size = fixed_size_set(&[("p2", "Pose")]);
+        let prefix = quote! { rosrust:: };
+        let message = Msg::new("p1", "Sample", "float64 x\nuint8[4] data\np2/Pose pose\n").unwrap();
+        let tokens = message.token_stream(&prefix, &fixed_size);
+        let fixed = message.fixed_size_token_stream(&prefix, &fixed_size);
+        let code = quote! { #tokens #fixed }.to_string().replace(' ', "");
+        for expected in &[
+            "#[repr(C)]pubstructSample",
+            "unsafeimplrosrust::rosmsg::FixedSizeforSample",
+            "<f64asrosrust::rosmsg::FixedSize>::ENCODED_LEN*1usize",
+            "<u8asrosrust::rosmsg::FixedSize>::ENCODED_LEN*4usize",
+            "<super::p2::Poseasrosrust::rosmsg::FixedSize>::MATCHES_MEMORY_LAYOUT",
+            "::std::mem::size_of::<Self>()==Self::ENCODED_LEN",
+        ] {
+            assert!(code.contains(expected), "{} not in {}", expected, code);
+        }
+
+        let message = Msg::new("p1", "Sample", "float64 x\nstring label\n").unwrap();
+        let tokens = message.token_stream(&prefix, &fixed_size);
+        let fixed = message.fixed_size_token_stream(&prefix, &fixed_size);
+        let code = quote! { #tokens #fixed }.to_string().replace(' ', "");
+        assert!(!code.contains("repr(C)"));
+        assert!(!code.contains("FixedSize"));
+    }
+
+    #[test]
+    fn sums_min_encoded_len_of_fields() {
+        let message = Msg::new(
+            "p1",
+            "Sample",
+            "int32 FOO=1\nfloat64 x\nuint8[4] data\nstring[] labels\np2/Pose pose\n",
+        )
+        .unwrap();
+        let min_len = message.min_encoded_len_token_stream(&quote! { rosrust:: });
+        let code = quote! { #min_len }.to_string().replace(' ', "");
+        assert_eq!(
+            "0+<f64asrosrust::rosmsg::RosMsg>::MIN_ENCODED_LEN\
+             +<u8asrosrust::rosmsg::RosMsg>::MIN_ENCODED_LEN*4usize\
+             +4\
+             +<super::p2::Poseasrosrust::rosmsg::RosMsg>::MIN_ENCODED_LEN",
+            code
+        );
+    }
+
+    #[test]
+    fn fixed_size_arrays_use_bulk_encoding() {
+        let fixed_size = fixed_size_set(&[("p1", "Point"), ("p2", "Pose")]);
+        let message = Msg::new(
+            "p1",
+            "Path",
+            "Point[] points\np2/Pose[] poses\np2/Other[] others\ntime[] stamps\n",
+        )
+        .unwrap();
+        let prefix = quote! { rosrust:: };
+        let encode = message.token_stream_encode(&prefix, &fixed_size);
+        let decode = message.token_stream_decode(&prefix, &fixed_size);
+        let code = quote! { #encode #decode }.to_string().replace(' ', "");
+        for expected in &[
+            "rosrust::rosmsg::encode_variable_fixed_size_slice(&self.points,w.by_ref())?;",
+            "rosrust::rosmsg::encode_variable_fixed_size_slice(&self.poses,w.by_ref())?;",
+            "rosrust::rosmsg::encode_variable_slice(&self.others,w.by_ref())?;",
+            "stamps:rosrust::rosmsg::decode_variable_fixed_size_vec(r.by_ref())?,",
+            "poses:rosrust::rosmsg::decode_variable_fixed_size_vec(r.by_ref())?,",
+            "others:rosrust::rosmsg::decode_variable_vec(r.by_ref())?,",
+        ] {
+            assert!(code.contains(expected), "{} not in {}", expected, code);
+        }
+    }
+
+    #[test]
+    fn constants_become_associated_consts() {
+        let message = Msg::new(
+            "p1",
+            "Constants",
+            "int32 FOO=1\nint8 NEGATIVE = -4\nbool FLAG=1\n\
+             string GREETING=  hello # world  \nfloat64 value\n",
+        )
+        .unwrap();
+        let tokens = message.token_stream(&quote! { rosrust:: }, &HashSet::new());
+        let code = quote! { #tokens }.to_string().replace(' ', "");
+        for expected in &[
+            "pubconstFOO:i32=1i32asi32;",
+            "pubconstNEGATIVE:i8=-4i8asi8;",
+            "pubconstFLAG:bool=true;",
+            r#"pubconstGREETING:&'staticstr="hello#world";"#,
+        ] {
+            assert!(code.contains(expected), "{} not in {}", expected, code);
+        }
+        assert!(!code.contains("pubFOO:"));
+    }
+
     #[test]
     fn match_lines_parses_real_messages() {
         let data = match_lines(include_str!(
@@ -1053,11 +1994,13 @@ mod tests {
                     datatype: DataType::LocalStruct("Twist".into()),
                     name: "twist".into(),
                     case: FieldCase::Unit,
+                    default: None,
                 },
                 FieldInfo {
                     datatype: DataType::F64,
                     name: "covariance".into(),
                     case: FieldCase::Array(36),
+                    default: None,
                 },
             ],
             data
@@ -1073,11 +2016,13 @@ mod tests {
                     datatype: DataType::RemoteStruct("std_msgs".into(), "Header".into()),
                     name: "header".into(),
                     case: FieldCase::Unit,
+                    default: None,
                 },
                 FieldInfo {
                     datatype: DataType::LocalStruct("Pose".into()),
                     name: "pose".into(),
                     case: FieldCase::Unit,
+                    default: None,
                 },
             ],
             data
@@ -1105,11 +2050,13 @@ mod tests {
                     datatype: DataType::LocalStruct("Twist".into()),
                     name: "twist".into(),
                     case: FieldCase::Unit,
+                    default: None,
                 },
                 FieldInfo {
                     datatype: DataType::F64,
                     name: "covariance".into(),
                     case: FieldCase::Array(36),
+                    default: None,
                 },
             ]
         );
@@ -1132,11 +2079,13 @@ mod tests {
                     datatype: DataType::RemoteStruct("std_msgs".into(), "Header".into()),
                     name: "header".into(),
                     case: FieldCase::Unit,
+                    default: None,
                 },
                 FieldInfo {
                     datatype: DataType::LocalStruct("Pose".into()),
                     name: "pose".into(),
                     case: FieldCase::Unit,
+                    default: None,
                 },
             ]
         );
@@ -1160,36 +2109,43 @@ mod tests {
                     datatype: DataType::RemoteStruct("std_msgs".into(), "Header".into()),
                     name: "header".into(),
                     case: FieldCase::Unit,
+                    default: None,
                 },
                 FieldInfo {
                     datatype: DataType::RemoteStruct("geometry_msgs".into(), "Quaternion".into()),
                     name: "orientation".into(),
                     case: FieldCase::Unit,
+                    default: None,
                 },
                 FieldInfo {
                     datatype: DataType::F64,
                     name: "orientation_covariance".into(),
                     case: FieldCase::Array(9),
+                    default: None,
                 },
                 FieldInfo {
                     datatype: DataType::RemoteStruct("geometry_msgs".into(), "Vector3".into()),
                     name: "angular_velocity".into(),
                     case: FieldCase::Unit,
+                    default: None,
                 },
                 FieldInfo {
                     datatype: DataType::F64,
                     name: "angular_velocity_covariance".into(),
                     case: FieldCase::Array(9),
+                    default: None,
                 },
                 FieldInfo {
                     datatype: DataType::RemoteStruct("geometry_msgs".into(), "Vector3".into()),
                     name: "linear_acceleration".into(),
                     case: FieldCase::Unit,
+                    default: None,
                 },
                 FieldInfo {
                     datatype: DataType::F64,
                     name: "linear_acceleration_covariance".into(),
                     case: FieldCase::Array(9),
+                    default: None,
                 },
             ]
         );
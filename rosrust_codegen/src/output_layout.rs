@@ -1,24 +1,44 @@
+use crate::error::Result;
 use crate::msg::Msg;
 use proc_macro2::Span;
 use quote::quote;
 use quote::ToTokens;
+use std::collections::HashSet;
 use syn::Ident;
 
 pub struct Layout {
     pub packages: Vec<Package>,
+    /// Messages that always encode to the same number of bytes.
+    pub fixed_size: HashSet<(String, String)>,
 }
 
 impl Layout {
-    pub fn token_stream<T: ToTokens>(&self, crate_prefix: &T) -> impl ToTokens {
+    pub fn token_stream<T: ToTokens>(&self, crate_prefix: &T, builders: bool) -> impl ToTokens {
         let packages = self
             .packages
             .iter()
-            .map(|v| v.token_stream(crate_prefix))
+            .map(|v| v.token_stream(crate_prefix, &self.fixed_size, builders))
             .collect::<Vec<_>>();
         quote! {
             #(#packages)*
         }
     }
+
+    /// Checks that builders can be generated for all messages.
+    pub fn check_builders(&self) -> Result<()> {
+        for package in &self.packages {
+            let type_names = package
+                .messages
+                .iter()
+                .map(|v| v.message.name.clone())
+                .chain(package.services.iter().map(|v| v.name.clone()))
+                .collect::<HashSet<String>>();
+            for message in &package.messages {
+                message.message.check_builder(&type_names)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 pub struct Package {
@@ -28,12 +48,17 @@ pub struct Package {
 }
 
 impl Package {
-    pub fn token_stream<T: ToTokens>(&self, crate_prefix: &T) -> impl ToTokens {
+    pub fn token_stream<T: ToTokens>(
+        &self,
+        crate_prefix: &T,
+        fixed_size: &HashSet<(String, String)>,
+        builders: bool,
+    ) -> impl ToTokens {
         let name = Ident::new(&self.name, Span::call_site());
         let messages = self
             .messages
             .iter()
-            .map(|v| v.token_stream(crate_prefix))
+            .map(|v| v.token_stream(crate_prefix, fixed_size, builders))
             .collect::<Vec<_>>();
         let services = self
             .services
@@ -57,21 +82,35 @@ pub struct Message {
 }
 
 impl Message {
-    pub fn token_stream<T: ToTokens>(&self, crate_prefix: &T) -> impl ToTokens {
+    pub fn token_stream<T: ToTokens>(
+        &self,
+        crate_prefix: &T,
+        fixed_size: &HashSet<(String, String)>,
+        builders: bool,
+    ) -> impl ToTokens {
         let Message {
             message,
             msg_definition,
             md5sum,
             msg_type,
         } = self;
-        let base_message = message.token_stream(crate_prefix);
-        let encode_message = message.token_stream_encode(crate_prefix);
-        let decode_message = message.token_stream_decode(crate_prefix);
+        let base_message = message.token_stream(crate_prefix, fixed_size);
+        let encode_message = message.token_stream_encode(crate_prefix, fixed_size);
+        let decode_message = message.token_stream_decode(crate_prefix, fixed_size);
+        let fixed_size_tokens = message.fixed_size_token_stream(crate_prefix, fixed_size);
+        let min_encoded_len = message.min_encoded_len_token_stream(crate_prefix);
         let name = message.name_ident();
         let header_tokens = message.header_token_stream(crate_prefix);
+        let builder = if builders {
+            Some(message.builder_token_stream(crate_prefix))
+        } else {
+            None
+        };
         quote! {
             #base_message
 
+            #builder
+
             impl #crate_prefix Message for #name {
                 #[inline]
                 fn msg_definition() -> ::std::string::String {
@@ -92,6 +131,8 @@ impl Message {
             }
 
             impl #crate_prefix rosmsg::RosMsg for #name {
+                const MIN_ENCODED_LEN: usize = #min_encoded_len;
+
                 fn encode<W: ::std::io::Write>(&self, mut w: W) -> ::std::io::Result<()> {
                     #encode_message
                 }
@@ -100,6 +141,8 @@ impl Message {
                     #decode_message
                 }
             }
+
+            #fixed_size_tokens
         }
     }
 }
@@ -108,6 +151,7 @@ pub struct Service {
     pub name: String,
     pub md5sum: String,
     pub msg_type: String,
+    pub msg_definition: String,
 }
 
 impl Service {
@@ -116,6 +160,7 @@ impl Service {
             name,
             md5sum,
             msg_type,
+            msg_definition,
         } = self;
         let name_ident = Ident::new(&name, Span::call_site());
         let req_ident = Ident::new(&format!("{}Req", name), Span::call_site());
@@ -129,7 +174,7 @@ impl Service {
             impl #crate_prefix Message for #name_ident {
                 #[inline]
                 fn msg_definition() -> ::std::string::String {
-                    String::new()
+                    #msg_definition.into()
                 }
 
                 #[inline]
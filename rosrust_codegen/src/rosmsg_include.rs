@@ -4,7 +4,7 @@ use quote::quote;
 use std::env;
 use std::path::Path;
 
-pub fn depend_on_messages(messages: &[&str], internal: bool) -> TokenStream {
+pub fn depend_on_messages(messages: &[&str], internal: bool, builders: bool) -> TokenStream {
     let cmake_paths = env::var("CMAKE_PREFIX_PATH")
         .unwrap_or_default()
         .split(':')
@@ -26,13 +26,17 @@ pub fn depend_on_messages(messages: &[&str], internal: bool) -> TokenStream {
         .chain(extra_paths.iter())
         .map(String::as_str)
         .collect::<Vec<&str>>();
-    let output = genmsg::depend_on_messages(paths.as_slice(), messages)
-        .unwrap_or_else(|r| panic!("{}", r))
-        .token_stream(&if internal {
-            quote! { crate:: }
-        } else {
-            quote! { rosrust:: }
-        });
+    let layout =
+        genmsg::depend_on_messages(paths.as_slice(), messages).unwrap_or_else(|r| panic!("{}", r));
+    if builders {
+        layout.check_builders().unwrap_or_else(|r| panic!("{}", r));
+    }
+    let crate_prefix = if internal {
+        quote! { crate:: }
+    } else {
+        quote! { rosrust:: }
+    };
+    let output = layout.token_stream(&crate_prefix, builders);
     (quote! {#output}).into()
 }
 